@@ -0,0 +1,508 @@
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+
+use crate::backup::ImportSummary;
+use crate::error::{CoreError, Result};
+use crate::filter::Filter;
+use crate::relay::AuthPolicy;
+use crate::retention::RetentionPolicy;
+use crate::types::{CoreEvent, RelayConfig, Scope};
+use crate::CoreHandle;
+
+/// コマンドの結果を返すチャネル
+pub type Reply<T> = oneshot::Sender<Result<T>>;
+
+/// CoreActorへのコマンド
+///
+/// 結果は`reply`で返す。UIの状態に関わる結果は`CoreEvent`でも通知する。
+#[derive(Debug)]
+pub enum Command {
+    /// 定期処理（続けて届いたものは1回にまとめる）
+    Tick,
+    ConnectAll(Reply<()>),
+    GetPublicKey(Reply<Option<String>>),
+    OpenChannel { channel_id: String, reply: Reply<()> },
+    OpenDm { peer: String, reply: Reply<()> },
+    CloseScope { scope: Scope, reply: Reply<()> },
+    /// 会話の保持中のイベントを`CoreEvent::ConversationLoaded`で返す
+    LoadConversation(Scope),
+    LoadOlder { scope: Scope, limit: u32, reply: Reply<usize> },
+    CreateChannel { name: String, about: String, picture: String, reply: Reply<String> },
+    SendPublic { channel_id: String, content: String, reply: Reply<String> },
+    SendDm { peer: String, plaintext: String, reply: Reply<String> },
+    Relays(oneshot::Sender<Vec<RelayConfig>>),
+    AddRelay { url: String, reply: Reply<()> },
+    RemoveRelay { url: String, reply: Reply<()> },
+    SetRelayPolicy { url: String, read: bool, write: bool, reply: Reply<()> },
+    SetAuthPolicy { url: String, policy: AuthPolicy, reply: Reply<()> },
+    ApproveAuth { url: String, reply: Reply<()> },
+    DenyAuth(String),
+    SetMaxSubscriptions { url: String, max: Option<usize>, reply: Reply<()> },
+    SetPublishQuorum(usize),
+    /// NIP-45 COUNTを送り、購読IDを返す（結果は`CoreEvent::CountUpdated`で通知する）
+    RequestCount { filter: Filter, reply: Reply<String> },
+    PublishRelayList(Reply<String>),
+    SetRetentionPolicy(RetentionPolicy),
+    /// バックアップ（JSONL）を書き出す
+    ExportBackup(Reply<Vec<u8>>),
+    /// バックアップを復元し、Relay設定を`CoreEvent::RelaysChanged`で通知する
+    ImportBackup { data: Vec<u8>, reply: Reply<ImportSummary> },
+}
+
+/// CoreHandleを単一のタスクで動かすアクター
+///
+/// コマンドを届いた順に1つずつ処理するため、UIが借用の競合で操作を落とすことはない。
+/// `CoreClient`がすべてドロップされると`run`は終わる。
+pub struct CoreActor {
+    core: CoreHandle,
+    commands: UnboundedReceiver<Command>,
+    events: UnboundedSender<CoreEvent>,
+}
+
+/// CoreActorにコマンドを送るハンドル（複製して複数の場所から使える）
+#[derive(Debug, Clone)]
+pub struct CoreClient {
+    commands: UnboundedSender<Command>,
+}
+
+/// CoreActorからの通知を受け取る
+pub type CoreEvents = UnboundedReceiver<CoreEvent>;
+
+/// CoreActor側のチャネルの端
+pub struct ActorMailbox {
+    commands: UnboundedReceiver<Command>,
+    events: UnboundedSender<CoreEvent>,
+}
+
+/// CoreActorとやり取りするチャネルを作る
+///
+/// CoreHandleの初期化前に作っておけば、初期化中に送ったコマンドも初期化後に順に処理される。
+pub fn channel() -> (CoreClient, CoreEvents, ActorMailbox) {
+    let (command_tx, command_rx) = mpsc::unbounded_channel();
+    let (event_tx, event_rx) = mpsc::unbounded_channel();
+    let mailbox = ActorMailbox {
+        commands: command_rx,
+        events: event_tx,
+    };
+    (CoreClient { commands: command_tx }, event_rx, mailbox)
+}
+
+impl CoreHandle {
+    /// アクターに変換する。`CoreActor::run`を`spawn_local`等で動かす
+    pub fn into_actor(self, mailbox: ActorMailbox) -> CoreActor {
+        CoreActor {
+            core: self,
+            commands: mailbox.commands,
+            events: mailbox.events,
+        }
+    }
+}
+
+impl CoreActor {
+    /// コマンドを処理し続ける
+    pub async fn run(mut self) {
+        self.notify(CoreEvent::RelaysChanged(self.core.relays()));
+
+        let mut pending = None;
+        loop {
+            let command = match pending.take() {
+                Some(command) => command,
+                None => match self.commands.recv().await {
+                    Some(command) => command,
+                    None => break,
+                },
+            };
+            match command {
+                Command::Tick => {
+                    // 続けて届いたtickは1回にまとめる
+                    pending = loop {
+                        match self.commands.try_recv() {
+                            Ok(Command::Tick) => {}
+                            next => break next.ok(),
+                        }
+                    };
+                    if let Err(e) = self.core.tick().await {
+                        log::error!("Tick error: {:?}", e);
+                    }
+                }
+                command => self.handle(command).await,
+            }
+            self.forward_events();
+        }
+    }
+
+    async fn handle(&mut self, command: Command) {
+        let core = &mut self.core;
+        // 呼び出し側が結果を待たずにドロップしていても処理は行う
+        match command {
+            Command::Tick => {}
+            Command::ConnectAll(reply) => {
+                let _ = reply.send(core.connect_all().await);
+            }
+            Command::GetPublicKey(reply) => {
+                let _ = reply.send(core.get_public_key().await);
+            }
+            Command::OpenChannel { channel_id, reply } => {
+                let _ = reply.send(core.open_channel(&channel_id).await);
+            }
+            Command::OpenDm { peer, reply } => {
+                let _ = reply.send(core.open_dm(&peer).await);
+            }
+            Command::CloseScope { scope, reply } => {
+                let _ = reply.send(core.close_scope(&scope).await);
+            }
+            Command::LoadConversation(scope) => {
+                let rows = core.conversation(&scope);
+                let history = core.history_state(&scope);
+                self.notify(CoreEvent::ConversationLoaded { scope, rows, history });
+            }
+            Command::LoadOlder { scope, limit, reply } => {
+                let _ = reply.send(core.load_older(&scope, limit).await);
+            }
+            Command::CreateChannel { name, about, picture, reply } => {
+                let _ = reply.send(core.create_channel(&name, &about, &picture).await);
+            }
+            Command::SendPublic { channel_id, content, reply } => {
+                let _ = reply.send(core.send_public(&channel_id, &content).await);
+            }
+            Command::SendDm { peer, plaintext, reply } => {
+                let _ = reply.send(core.send_dm(&peer, &plaintext).await);
+            }
+            Command::Relays(reply) => {
+                let _ = reply.send(core.relays());
+            }
+            Command::AddRelay { url, reply } => {
+                let result = core.add_relay(&url).await;
+                self.reply_relays_changed(reply, result);
+            }
+            Command::RemoveRelay { url, reply } => {
+                let result = core.remove_relay(&url).await;
+                self.reply_relays_changed(reply, result);
+            }
+            Command::SetRelayPolicy { url, read, write, reply } => {
+                let result = core.set_relay_policy(&url, read, write).await;
+                self.reply_relays_changed(reply, result);
+            }
+            Command::SetAuthPolicy { url, policy, reply } => {
+                let result = core.set_auth_policy(&url, policy).await;
+                self.reply_relays_changed(reply, result);
+            }
+            Command::ApproveAuth { url, reply } => {
+                let _ = reply.send(core.approve_auth(&url).await);
+            }
+            Command::DenyAuth(url) => {
+                if let Err(e) = core.deny_auth(&url).await {
+                    log::error!("Failed to deny AUTH for {}: {:?}", url, e);
+                }
+            }
+            Command::SetMaxSubscriptions { url, max, reply } => {
+                let result = core.set_max_subscriptions(&url, max).await;
+                self.reply_relays_changed(reply, result);
+            }
+            Command::SetPublishQuorum(quorum) => core.set_publish_quorum(quorum),
+            Command::RequestCount { filter, reply } => {
+                let _ = reply.send(core.request_count(&filter).await);
+            }
+            Command::PublishRelayList(reply) => {
+                let _ = reply.send(core.publish_relay_list().await);
+            }
+            Command::SetRetentionPolicy(policy) => core.set_retention_policy(policy),
+            Command::ExportBackup(reply) => {
+                let mut data = Vec::new();
+                let _ = reply.send(core.export_backup(&mut data).await.map(|_| data));
+            }
+            Command::ImportBackup { data, reply } => {
+                let result = core.import_backup(data.as_slice()).await;
+                if result.is_ok() {
+                    self.notify(CoreEvent::RelaysChanged(self.core.relays()));
+                }
+                let _ = reply.send(result);
+            }
+        }
+    }
+
+    /// Relay設定を変えるコマンドの結果を返し、成功したら新しい設定を通知する
+    fn reply_relays_changed(&mut self, reply: Reply<()>, result: Result<()>) {
+        if result.is_ok() {
+            self.notify(CoreEvent::RelaysChanged(self.core.relays()));
+        }
+        let _ = reply.send(result);
+    }
+
+    /// CoreHandleに溜まった通知を送る
+    fn forward_events(&mut self) {
+        for event in self.core.poll_events(u32::MAX) {
+            self.notify(event);
+        }
+    }
+
+    fn notify(&self, event: CoreEvent) {
+        // 受け取り側がいなくなっていれば捨てる
+        let _ = self.events.send(event);
+    }
+}
+
+impl CoreClient {
+    /// 定期処理を要求（結果は通知で届く）
+    pub fn tick(&self) {
+        let _ = self.send(Command::Tick);
+    }
+
+    /// 会話の保持中のイベントを要求（`CoreEvent::ConversationLoaded`で届く）
+    pub fn load_conversation(&self, scope: Scope) -> Result<()> {
+        self.send(Command::LoadConversation(scope))
+    }
+
+    pub async fn connect_all(&self) -> Result<()> {
+        self.request(Command::ConnectAll).await
+    }
+
+    pub async fn get_public_key(&self) -> Result<Option<String>> {
+        self.request(Command::GetPublicKey).await
+    }
+
+    pub async fn open_channel(&self, channel_id: &str) -> Result<()> {
+        let channel_id = channel_id.to_string();
+        self.request(|reply| Command::OpenChannel { channel_id, reply }).await
+    }
+
+    pub async fn open_dm(&self, peer: &str) -> Result<()> {
+        let peer = peer.to_string();
+        self.request(|reply| Command::OpenDm { peer, reply }).await
+    }
+
+    pub async fn close_scope(&self, scope: &Scope) -> Result<()> {
+        let scope = scope.clone();
+        self.request(|reply| Command::CloseScope { scope, reply }).await
+    }
+
+    pub async fn load_older(&self, scope: &Scope, limit: u32) -> Result<usize> {
+        let scope = scope.clone();
+        self.request(|reply| Command::LoadOlder { scope, limit, reply }).await
+    }
+
+    pub async fn create_channel(&self, name: &str, about: &str, picture: &str) -> Result<String> {
+        let (name, about, picture) = (name.to_string(), about.to_string(), picture.to_string());
+        self.request(|reply| Command::CreateChannel { name, about, picture, reply }).await
+    }
+
+    pub async fn send_public(&self, channel_id: &str, content: &str) -> Result<String> {
+        let (channel_id, content) = (channel_id.to_string(), content.to_string());
+        self.request(|reply| Command::SendPublic { channel_id, content, reply }).await
+    }
+
+    pub async fn send_dm(&self, peer: &str, plaintext: &str) -> Result<String> {
+        let (peer, plaintext) = (peer.to_string(), plaintext.to_string());
+        self.request(|reply| Command::SendDm { peer, plaintext, reply }).await
+    }
+
+    pub async fn relays(&self) -> Result<Vec<RelayConfig>> {
+        let (reply, rx) = oneshot::channel();
+        self.send(Command::Relays(reply))?;
+        rx.await.map_err(|_| stopped())
+    }
+
+    pub async fn add_relay(&self, url: &str) -> Result<()> {
+        let url = url.to_string();
+        self.request(|reply| Command::AddRelay { url, reply }).await
+    }
+
+    pub async fn remove_relay(&self, url: &str) -> Result<()> {
+        let url = url.to_string();
+        self.request(|reply| Command::RemoveRelay { url, reply }).await
+    }
+
+    pub async fn set_relay_policy(&self, url: &str, read: bool, write: bool) -> Result<()> {
+        let url = url.to_string();
+        self.request(|reply| Command::SetRelayPolicy { url, read, write, reply }).await
+    }
+
+    pub async fn set_auth_policy(&self, url: &str, policy: AuthPolicy) -> Result<()> {
+        let url = url.to_string();
+        self.request(|reply| Command::SetAuthPolicy { url, policy, reply }).await
+    }
+
+    pub async fn approve_auth(&self, url: &str) -> Result<()> {
+        let url = url.to_string();
+        self.request(|reply| Command::ApproveAuth { url, reply }).await
+    }
+
+    pub fn deny_auth(&self, url: &str) -> Result<()> {
+        self.send(Command::DenyAuth(url.to_string()))
+    }
+
+    /// Relayが同時に開ける購読数の上限を設定（NIP-11 の`limitation.max_subscriptions`）
+    pub async fn set_max_subscriptions(&self, url: &str, max: Option<usize>) -> Result<()> {
+        let url = url.to_string();
+        self.request(|reply| Command::SetMaxSubscriptions { url, max, reply }).await
+    }
+
+    /// 配送済みとするのに必要な受理Relay数を設定
+    pub fn set_publish_quorum(&self, quorum: usize) -> Result<()> {
+        self.send(Command::SetPublishQuorum(quorum))
+    }
+
+    /// NIP-45: イベント数を問い合わせ、購読IDを返す（結果は`CoreEvent::CountUpdated`で届く）
+    pub async fn request_count(&self, filter: &Filter) -> Result<String> {
+        let filter = filter.clone();
+        self.request(|reply| Command::RequestCount { filter, reply }).await
+    }
+
+    pub async fn publish_relay_list(&self) -> Result<String> {
+        self.request(Command::PublishRelayList).await
+    }
+
+    /// Storageに残すイベントの上限を設定（次の定期処理で適用する）
+    pub fn set_retention_policy(&self, policy: RetentionPolicy) -> Result<()> {
+        self.send(Command::SetRetentionPolicy(policy))
+    }
+
+    /// バックアップ（JSONL）を書き出す
+    pub async fn export_backup(&self) -> Result<Vec<u8>> {
+        self.request(Command::ExportBackup).await
+    }
+
+    /// バックアップを復元する（同じIDのレコードは重複させない）
+    pub async fn import_backup(&self, data: Vec<u8>) -> Result<ImportSummary> {
+        self.request(|reply| Command::ImportBackup { data, reply }).await
+    }
+
+    fn send(&self, command: Command) -> Result<()> {
+        self.commands.send(command).map_err(|_| stopped())
+    }
+
+    /// コマンドを送り、結果を待つ
+    async fn request<T>(&self, command: impl FnOnce(Reply<T>) -> Command) -> Result<T> {
+        let (reply, rx) = oneshot::channel();
+        self.send(command(reply))?;
+        rx.await.map_err(|_| stopped())?
+    }
+}
+
+fn stopped() -> CoreError {
+    CoreError::Other("Core actor has stopped".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::storage::mock::MockStorage;
+    use crate::transport::mock::MockTransport;
+    use crate::verify::signed_event_json;
+
+    const URL: &str = "wss://relay.example";
+
+    async fn actor(transport: &MockTransport) -> (CoreActor, CoreClient, CoreEvents) {
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let storage = Arc::new(MockStorage::new());
+        let core = CoreHandle::init_with(vec![URL.to_string()], storage, Arc::new(transport.clone()), clock)
+            .await
+            .unwrap();
+        let (client, events, mailbox) = channel();
+        (core.into_actor(mailbox), client, events)
+    }
+
+    fn drain(events: &mut CoreEvents) -> Vec<CoreEvent> {
+        std::iter::from_fn(|| events.try_recv().ok()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_commands_are_processed_in_order() {
+        let transport = MockTransport::new();
+        let (actor, client, mut events) = actor(&transport).await;
+
+        let ui = async move {
+            client.connect_all().await.unwrap();
+            transport.open(URL);
+            // 結果を待たずに続けて送ったコマンドも落ちない
+            client.tick();
+            let open = client.open_channel("chan");
+            let tick = async { client.tick() };
+            let (opened, ()) = tokio::join!(open, tick);
+            opened.unwrap();
+            let sent = transport.take_sent(URL);
+            assert_eq!(sent.len(), 1);
+            assert!(sent[0].starts_with(r#"["REQ","channel_chan","#));
+
+            let event = signed_event_json(&nostr::Keys::generate(), 42, &[&["e", "chan"]], "hi", 1_700_000_000);
+            transport.receive(URL, &format!(r#"["EVENT","channel_chan",{}]"#, event));
+            client.tick();
+            client.load_conversation(Scope::Channel("chan".to_string())).unwrap();
+            assert_eq!(client.relays().await.unwrap().len(), 1);
+            assert!(client.remove_relay("wss://unknown.example").await.is_err());
+        };
+        tokio::join!(actor.run(), ui);
+
+        let events = drain(&mut events);
+        assert!(matches!(&events[0], CoreEvent::RelaysChanged(relays) if relays.len() == 1));
+        assert!(events.iter().any(|e| matches!(e, CoreEvent::EventReceived(row) if row.content == "hi")));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            CoreEvent::ConversationLoaded { rows, .. } if rows.len() == 1
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_relay_limits_and_count_commands() {
+        let transport = MockTransport::new();
+        let (actor, client, mut events) = actor(&transport).await;
+
+        let ui = async move {
+            client.connect_all().await.unwrap();
+            transport.open(URL);
+            client.tick();
+            client.set_publish_quorum(2).unwrap();
+            client.set_max_subscriptions(URL, Some(10)).await.unwrap();
+            assert_eq!(client.relays().await.unwrap()[0].max_subscriptions, Some(10));
+
+            let sub_id = client.request_count(&Filter::new().kinds([42])).await.unwrap();
+            let sent = transport.take_sent(URL);
+            assert_eq!(sent.last().unwrap(), &format!(r#"["COUNT","{}",{{"kinds":[42]}}]"#, sub_id));
+            transport.receive(URL, &format!(r#"["COUNT","{}",{{"count":7}}]"#, sub_id));
+            client.tick();
+            client.relays().await.unwrap();
+        };
+        tokio::join!(actor.run(), ui);
+
+        let events = drain(&mut events);
+        assert!(events.iter().any(|e| matches!(
+            e,
+            CoreEvent::RelaysChanged(relays) if relays[0].max_subscriptions == Some(10)
+        )));
+        assert!(events.iter().any(|e| matches!(e, CoreEvent::CountUpdated { count: 7, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_commands_sent_before_init_are_kept() {
+        let (client, mut events, mailbox) = channel();
+        client.tick();
+        client.load_conversation(Scope::Dm("peer".to_string())).unwrap();
+
+        let transport = MockTransport::new();
+        let core = CoreHandle::init_with(
+            vec![URL.to_string()],
+            Arc::new(MockStorage::new()),
+            Arc::new(transport.clone()),
+            Arc::new(MockClock::new(1_700_000_000)),
+        )
+        .await
+        .unwrap();
+        drop(client);
+        core.into_actor(mailbox).run().await;
+
+        assert!(drain(&mut events)
+            .iter()
+            .any(|e| matches!(e, CoreEvent::ConversationLoaded { scope: Scope::Dm(peer), .. } if peer == "peer")));
+    }
+
+    #[tokio::test]
+    async fn test_client_fails_after_actor_stops() {
+        let transport = MockTransport::new();
+        let (actor, client, _events) = actor(&transport).await;
+        drop(actor);
+        assert!(client.open_channel("chan").await.is_err());
+    }
+}
@@ -0,0 +1,312 @@
+use std::io::{BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{CoreError, Result};
+use crate::filter::Filter;
+use crate::storage::Storage;
+use crate::types::{DmThread, OutboxItem, RelayConfig, StoredEvent};
+
+/// バックアップの形式名（先頭行の`format`）
+pub const BACKUP_FORMAT: &str = "rustr-backup";
+
+/// バックアップの形式のバージョン（読めるのはこれ以下）
+pub const BACKUP_VERSION: u32 = 1;
+
+/// イベントを書き出すときに1回で読む件数
+const EXPORT_BATCH: u32 = 500;
+
+/// JSONLバックアップの1行
+///
+/// 先頭行は`Header`で、以降は各テーブルのレコードが1行ずつ続く。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackupRecord {
+    Header { format: String, version: u32, exported_at: i64 },
+    Event(StoredEvent),
+    DmThread(DmThread),
+    LastSeen { scope: String, ts: i64 },
+    Outbox(OutboxItem),
+    Relay(RelayConfig),
+    /// 暗号化済みの鍵ペア（内蔵Signer用）
+    Keypair { data: Vec<u8> },
+}
+
+/// 復元の結果
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// 追加・更新したレコード数
+    pub imported: usize,
+    /// 既に同じか新しいものがあったレコード数
+    pub skipped: usize,
+}
+
+/// Storageの全テーブルをJSONLで書き出す（書き出したレコード数を返す）
+///
+/// イベントは新しい方から`EXPORT_BATCH`件ずつ読んで書く。
+pub async fn export(storage: &dyn Storage, exported_at: i64, out: &mut impl Write) -> Result<usize> {
+    let mut count = 0;
+    let mut write = |record: &BackupRecord| -> Result<()> {
+        serde_json::to_writer(&mut *out, record)?;
+        out.write_all(b"\n").map_err(io_error)?;
+        count += 1;
+        Ok(())
+    };
+
+    write(&BackupRecord::Header { format: BACKUP_FORMAT.to_string(), version: BACKUP_VERSION, exported_at })?;
+
+    let mut until: Option<i64> = None;
+    loop {
+        let filter = Filter::new().limit(EXPORT_BATCH);
+        let batch = storage.get_events(&until.map_or(filter.clone(), |until| filter.until(until))).await?;
+        let Some(oldest) = batch.last().map(|e| e.created_at) else {
+            break;
+        };
+        // 最古の時刻のイベントは件数に関わらずまとめて書き、次はその前から読む
+        for event in batch.iter().filter(|e| e.created_at > oldest) {
+            write(&BackupRecord::Event(event.clone()))?;
+        }
+        for event in storage.get_events(&Filter::new().since(oldest).until(oldest)).await? {
+            write(&BackupRecord::Event(event))?;
+        }
+        if batch.len() < EXPORT_BATCH as usize {
+            break;
+        }
+        until = Some(oldest - 1);
+    }
+
+    for thread in storage.get_dm_threads().await? {
+        write(&BackupRecord::DmThread(thread))?;
+    }
+    for (scope, ts) in storage.get_all_last_seen().await? {
+        write(&BackupRecord::LastSeen { scope, ts })?;
+    }
+    for item in storage.get_all_outbox().await? {
+        write(&BackupRecord::Outbox(item))?;
+    }
+    for relay in storage.get_relays().await? {
+        write(&BackupRecord::Relay(relay))?;
+    }
+    if let Some(data) = storage.get_keypair().await? {
+        write(&BackupRecord::Keypair { data })?;
+    }
+    Ok(count)
+}
+
+/// JSONLのバックアップをStorageに復元する
+///
+/// IDなどが同じレコードは重複させず、今あるものを優先する。
+/// イベントは受信Relayの記録だけ足し、既読位置とDMスレッドは新しい方を残す。
+/// 鍵ペアは今ないときだけ復元する。
+pub async fn import(storage: &dyn Storage, input: impl BufRead) -> Result<ImportSummary> {
+    let mut lines = input.lines().enumerate().filter(|(_, line)| !line.as_ref().is_ok_and(|l| l.trim().is_empty()));
+    let parse = |(number, line): (usize, std::io::Result<String>)| -> Result<BackupRecord> {
+        let line = line.map_err(io_error)?;
+        serde_json::from_str(&line)
+            .map_err(|e| CoreError::Other(format!("Invalid backup line {}: {}", number + 1, e)))
+    };
+
+    match lines.next().map(parse).transpose()? {
+        Some(BackupRecord::Header { format, version, .. }) if format == BACKUP_FORMAT => {
+            if version > BACKUP_VERSION {
+                return Err(CoreError::Other(format!("Unsupported backup version: {}", version)));
+            }
+        }
+        _ => return Err(CoreError::Other("Not a backup file".to_string())),
+    }
+
+    let threads = storage.get_dm_threads().await?;
+    let outbox = storage.get_all_outbox().await?;
+    let relays = storage.get_relays().await?;
+    let mut summary = ImportSummary::default();
+    for line in lines {
+        let imported = match parse(line)? {
+            BackupRecord::Header { .. } => return Err(CoreError::Other("Unexpected backup header".to_string())),
+            BackupRecord::Event(event) => match storage.get_event(&event.id).await? {
+                Some(existing) => {
+                    let new_relays: Vec<&String> = event.seen_on.iter().filter(|r| !existing.seen_on.contains(r)).collect();
+                    for relay_url in &new_relays {
+                        storage.add_seen_on(&event.id, relay_url).await?;
+                    }
+                    !new_relays.is_empty()
+                }
+                None => {
+                    storage.insert_event(&event).await?;
+                    true
+                }
+            },
+            BackupRecord::DmThread(thread) => {
+                let newer = threads
+                    .iter()
+                    .find(|t| t.peer == thread.peer)
+                    .is_none_or(|t| t.last_msg_at < thread.last_msg_at);
+                if newer {
+                    storage.upsert_dm_thread(&thread.peer, thread.last_msg_at).await?;
+                }
+                newer
+            }
+            BackupRecord::LastSeen { scope, ts } => {
+                let newer = storage.get_last_seen(&scope).await? < ts;
+                if newer {
+                    storage.set_last_seen(&scope, ts).await?;
+                }
+                newer
+            }
+            BackupRecord::Outbox(item) => {
+                let missing = !outbox.iter().any(|i| i.req_id == item.req_id);
+                if missing {
+                    storage.enqueue_outbox(item).await?;
+                }
+                missing
+            }
+            BackupRecord::Relay(relay) => {
+                let missing = !relays.iter().any(|r| r.url == relay.url);
+                if missing {
+                    storage.upsert_relay(&relay).await?;
+                }
+                missing
+            }
+            BackupRecord::Keypair { data } => {
+                let missing = storage.get_keypair().await?.is_none();
+                if missing {
+                    storage.save_keypair(&data).await?;
+                } else {
+                    log::warn!("Backup keypair ignored: a keypair already exists");
+                }
+                missing
+            }
+        };
+        if imported {
+            summary.imported += 1;
+        } else {
+            summary.skipped += 1;
+        }
+    }
+    Ok(summary)
+}
+
+fn io_error(error: std::io::Error) -> CoreError {
+    CoreError::Other(format!("Backup I/O error: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::NostrEvent;
+    use crate::storage::mock::MockStorage;
+    use crate::types::OutboxStatus;
+
+    fn event(n: u64, created_at: i64) -> NostrEvent {
+        NostrEvent {
+            id: format!("{:064x}", n),
+            pubkey: "b".repeat(64),
+            created_at,
+            kind: 42,
+            tags: vec![vec!["e".to_string(), "chan".to_string()]],
+            content: format!("message {}", n),
+            sig: "c".repeat(128),
+        }
+    }
+
+    fn outbox_item(req_id: &str, n: u64) -> OutboxItem {
+        let event = event(n, 100);
+        OutboxItem {
+            req_id: req_id.to_string(),
+            event_id: event.id.clone(),
+            event,
+            status: OutboxStatus::Ok,
+            last_try_at: 0,
+            retry_count: 0,
+            error: None,
+            receipts: Vec::new(),
+        }
+    }
+
+    async fn filled_storage() -> MockStorage {
+        let storage = MockStorage::new();
+        // 同じ時刻が`EXPORT_BATCH`の境界をまたぐ
+        for n in 0..(EXPORT_BATCH as u64 + 10) {
+            storage.save_event(&event(n, 1_000 + (n as i64 % 300))).await.unwrap();
+        }
+        storage.add_seen_on(&event(1, 0).id, "wss://a").await.unwrap();
+        storage.upsert_dm_thread("alice", 100).await.unwrap();
+        storage.set_last_seen("channel:chan", 500).await.unwrap();
+        storage.enqueue_outbox(outbox_item("req_1", 1)).await.unwrap();
+        storage.upsert_relay(&RelayConfig::new("wss://a")).await.unwrap();
+        storage.save_keypair(&[1, 2, 3]).await.unwrap();
+        storage
+    }
+
+    async fn export_to_string(storage: &MockStorage) -> String {
+        let mut out = Vec::new();
+        export(storage, 42, &mut out).await.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_import_roundtrip() {
+        let source = filled_storage().await;
+        let backup = export_to_string(&source).await;
+        let lines: Vec<&str> = backup.lines().collect();
+        assert!(lines[0].starts_with(r#"{"type":"header","format":"rustr-backup","version":1"#));
+        // ヘッダー + イベント + 各テーブル1件ずつ
+        assert_eq!(lines.len(), 1 + EXPORT_BATCH as usize + 10 + 5);
+
+        let target = MockStorage::new();
+        let summary = import(&target, backup.as_bytes()).await.unwrap();
+        assert_eq!(summary, ImportSummary { imported: lines.len() - 1, skipped: 0 });
+
+        let events = target.get_events(&Filter::new()).await.unwrap();
+        assert_eq!(events.len(), EXPORT_BATCH as usize + 10);
+        assert_eq!(
+            events.iter().map(|e| &e.id).collect::<Vec<_>>(),
+            source.get_events(&Filter::new()).await.unwrap().iter().map(|e| &e.id).collect::<Vec<_>>()
+        );
+        assert_eq!(target.get_event(&event(1, 0).id).await.unwrap().unwrap().seen_on, vec!["wss://a"]);
+        assert_eq!(target.get_dm_threads().await.unwrap()[0].last_msg_at, 100);
+        assert_eq!(target.get_last_seen("channel:chan").await.unwrap(), 500);
+        assert_eq!(target.get_all_outbox().await.unwrap()[0].req_id, "req_1");
+        assert_eq!(target.get_relays().await.unwrap()[0].url, "wss://a");
+        assert_eq!(target.get_keypair().await.unwrap(), Some(vec![1, 2, 3]));
+
+        // 2回目はすべて既にある
+        let summary = import(&target, backup.as_bytes()).await.unwrap();
+        assert_eq!(summary, ImportSummary { imported: 0, skipped: lines.len() - 1 });
+        assert_eq!(target.get_events(&Filter::new()).await.unwrap().len(), EXPORT_BATCH as usize + 10);
+    }
+
+    #[tokio::test]
+    async fn test_import_keeps_newer_local_data() {
+        let source = filled_storage().await;
+        let backup = export_to_string(&source).await;
+
+        let target = MockStorage::new();
+        target.save_event(&event(1, 1_001)).await.unwrap();
+        target.add_seen_on(&event(1, 0).id, "wss://b").await.unwrap();
+        target.upsert_dm_thread("alice", 900).await.unwrap();
+        target.set_last_seen("channel:chan", 900).await.unwrap();
+        target.save_keypair(&[9]).await.unwrap();
+        import(&target, backup.as_bytes()).await.unwrap();
+
+        // 受信Relayの記録は足す
+        assert_eq!(target.get_event(&event(1, 0).id).await.unwrap().unwrap().seen_on, vec!["wss://b", "wss://a"]);
+        assert_eq!(target.get_dm_threads().await.unwrap()[0].last_msg_at, 900);
+        assert_eq!(target.get_last_seen("channel:chan").await.unwrap(), 900);
+        assert_eq!(target.get_keypair().await.unwrap(), Some(vec![9]));
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_unknown_files() {
+        let storage = MockStorage::new();
+        let newer = r#"{"type":"header","format":"rustr-backup","version":2,"exported_at":0}"#;
+        for input in ["", "{}", r#"{"type":"relay","url":"wss://a","read":true,"write":true}"#, newer] {
+            assert!(import(&storage, input.as_bytes()).await.is_err(), "{}", input);
+        }
+
+        let broken = format!("{}\n{{\n", r#"{"type":"header","format":"rustr-backup","version":1,"exported_at":0}"#);
+        match import(&storage, broken.as_bytes()).await {
+            Err(CoreError::Other(message)) => assert!(message.starts_with("Invalid backup line 2"), "{}", message),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::Mutex;
+
+/// 時刻取得の抽象trait
+///
+/// WASMでは`js_sys::Date::now`、ネイティブでは`SystemTime`を使う。
+/// テストでは`MockClock`を注入して時間を進められるようにする。
+pub trait Clock {
+    /// 現在のUNIXタイムスタンプ（ミリ秒）
+    fn now_millis(&self) -> i64;
+
+    /// 現在のUNIXタイムスタンプ（秒）
+    fn now(&self) -> i64 {
+        self.now_millis() / 1000
+    }
+}
+
+/// 実時間のClock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[cfg(target_arch = "wasm32")]
+    fn now_millis(&self) -> i64 {
+        js_sys::Date::now() as i64
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn now_millis(&self) -> i64 {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0)
+    }
+}
+
+/// テスト用の手動で進めるClock
+#[derive(Debug, Default)]
+pub struct MockClock {
+    millis: Mutex<i64>,
+}
+
+impl MockClock {
+    pub fn new(now_secs: i64) -> Self {
+        Self {
+            millis: Mutex::new(now_secs * 1000),
+        }
+    }
+
+    /// 時刻を設定（秒）
+    pub fn set(&self, now_secs: i64) {
+        *self.millis.lock().unwrap() = now_secs * 1000;
+    }
+
+    /// 時刻を進める（秒）
+    pub fn advance(&self, secs: i64) {
+        *self.millis.lock().unwrap() += secs * 1000;
+    }
+
+    /// 時刻を進める（ミリ秒）
+    pub fn advance_millis(&self, millis: i64) {
+        *self.millis.lock().unwrap() += millis;
+    }
+}
+
+impl Clock for MockClock {
+    fn now_millis(&self) -> i64 {
+        *self.millis.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new(1_000);
+        assert_eq!(clock.now(), 1_000);
+
+        clock.advance(5);
+        assert_eq!(clock.now(), 1_005);
+
+        clock.advance_millis(1_500);
+        assert_eq!(clock.now_millis(), 1_006_500);
+        assert_eq!(clock.now(), 1_006);
+    }
+
+    #[test]
+    fn test_system_clock_is_recent() {
+        // 2023-01-01以降であること
+        assert!(SystemClock.now() > 1_672_531_200);
+    }
+}
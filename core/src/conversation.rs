@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+
+use crate::types::{Scope, UiRow};
+
+/// 1つの会話で保持する最大件数
+pub const MAX_ROWS_PER_SCOPE: usize = 1000;
+
+/// 会話ごとの表示用イベント一覧
+///
+/// 各会話は新しい順（`created_at`の降順、同時刻はID順）に並べ、IDで重複を除く。
+#[derive(Debug, Clone, Default)]
+pub struct Conversations {
+    scopes: HashMap<Scope, Vec<UiRow>>,
+}
+
+impl Conversations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 行を追加。会話に属さない行や既にある行は無視し、追加したらtrueを返す
+    pub fn insert(&mut self, row: UiRow) -> bool {
+        let Some(scope) = row.scope.clone() else {
+            return false;
+        };
+        let rows = self.scopes.entry(scope).or_default();
+        if rows.iter().any(|r| r.id == row.id) {
+            return false;
+        }
+
+        let pos = rows.partition_point(|r| {
+            r.created_at > row.created_at || (r.created_at == row.created_at && r.id < row.id)
+        });
+        if pos >= MAX_ROWS_PER_SCOPE {
+            return false;
+        }
+        rows.insert(pos, row);
+        rows.truncate(MAX_ROWS_PER_SCOPE);
+        true
+    }
+
+    /// 会話のイベント一覧（新しい順）
+    pub fn rows(&self, scope: &Scope) -> &[UiRow] {
+        self.scopes.get(scope).map(|rows| rows.as_slice()).unwrap_or(&[])
+    }
+
+    /// 会話に指定IDの行があるか
+    pub fn contains(&self, scope: &Scope, id: &str) -> bool {
+        self.rows(scope).iter().any(|r| r.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(id: &str, created_at: i64, scope: Option<Scope>) -> UiRow {
+        UiRow {
+            id: id.to_string(),
+            kind: 42,
+            pubkey: "pk".to_string(),
+            created_at,
+            content: String::new(),
+            image_url: None,
+            scope,
+        }
+    }
+
+    #[test]
+    fn test_orders_and_dedups_per_scope() {
+        let a = Scope::Channel("a".to_string());
+        let b = Scope::Dm("peer".to_string());
+        let mut conversations = Conversations::new();
+
+        assert!(conversations.insert(row("e2", 20, Some(a.clone()))));
+        assert!(conversations.insert(row("e1", 10, Some(a.clone()))));
+        assert!(conversations.insert(row("e3", 30, Some(a.clone()))));
+        assert!(!conversations.insert(row("e1", 10, Some(a.clone()))));
+        assert!(conversations.insert(row("d1", 15, Some(b.clone()))));
+        assert!(!conversations.insert(row("x", 15, None)));
+
+        let ids: Vec<&str> = conversations.rows(&a).iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["e3", "e2", "e1"]);
+        assert_eq!(conversations.rows(&b).len(), 1);
+        assert!(conversations.rows(&Scope::Channel("none".to_string())).is_empty());
+    }
+}
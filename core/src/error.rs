@@ -18,6 +18,9 @@ pub enum CoreError {
     #[error("Parse error: {0}")]
     ParseError(String),
     
+    #[error("Invalid event: {0}")]
+    InvalidEvent(String),
+    
     #[error("{0}")]
     Other(String),
 }
@@ -50,6 +53,13 @@ impl From<rexie::Error> for CoreError {
     }
 }
 
+#[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
+impl From<rusqlite::Error> for CoreError {
+    fn from(error: rusqlite::Error) -> Self {
+        CoreError::StorageError(error.to_string())
+    }
+}
+
 impl From<serde_wasm_bindgen::Error> for CoreError {
     fn from(error: serde_wasm_bindgen::Error) -> Self {
         CoreError::ParseError(error.to_string())
@@ -0,0 +1,273 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{CoreError, Result};
+
+/// NIP-01 のイベント
+///
+/// Relayから受け取ったときに一度だけパースし、各フィールドの形式を検証する。
+/// 以降はStorage、Outbox、UIまでこの型で受け渡す（ID・署名の検証は`verify::verify_event`）。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NostrEvent {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: i64,
+    pub kind: u16,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+
+impl NostrEvent {
+    /// JSON文字列からパース
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|e| CoreError::InvalidEvent(format!("Malformed JSON: {}", e)))?;
+        Self::from_value(&value)
+    }
+
+    /// JSONの値からパース
+    pub fn from_value(value: &Value) -> Result<Self> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| CoreError::InvalidEvent("Event is not an object".to_string()))?;
+        let field = |name: &str| {
+            object
+                .get(name)
+                .ok_or_else(|| CoreError::InvalidEvent(format!("Missing field: {}", name)))
+        };
+
+        let created_at = field("created_at")?
+            .as_u64()
+            .and_then(|t| i64::try_from(t).ok())
+            .ok_or_else(|| invalid("created_at", "not a non-negative integer"))?;
+        let kind = field("kind")?
+            .as_u64()
+            .and_then(|k| u16::try_from(k).ok())
+            .ok_or_else(|| invalid("kind", "not an integer between 0 and 65535"))?;
+        let content = field("content")?
+            .as_str()
+            .ok_or_else(|| invalid("content", "not a string"))?
+            .to_string();
+
+        let tags = field("tags")?
+            .as_array()
+            .ok_or_else(|| invalid("tags", "not an array"))?
+            .iter()
+            .enumerate()
+            .map(|(i, tag)| {
+                tag.as_array()
+                    .ok_or_else(|| invalid("tags", &format!("tag {} is not an array", i)))?
+                    .iter()
+                    .map(|v| v.as_str().map(String::from))
+                    .collect::<Option<Vec<String>>>()
+                    .ok_or_else(|| invalid("tags", &format!("tag {} has a non-string value", i)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            id: hex_field(field("id")?, "id", 64)?,
+            pubkey: hex_field(field("pubkey")?, "pubkey", 64)?,
+            created_at,
+            kind,
+            tags,
+            content,
+            sig: hex_field(field("sig")?, "sig", 128)?,
+        })
+    }
+
+    /// JSON文字列に変換（REQ/EVENTに載せる形）
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    /// 指定した名前のタグ
+    pub fn tags_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [String]> + 'a {
+        self.tags
+            .iter()
+            .filter(move |tag| tag.first().is_some_and(|n| n == name))
+            .map(|tag| tag.as_slice())
+    }
+
+    /// 指定した名前のタグの値（2番目の要素）
+    pub fn tag_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.tags_named(name).filter_map(|tag| tag.get(1).map(|v| v.as_str()))
+    }
+
+    /// NIP-28 のチャンネルID（`channel_root`）
+    pub fn channel_root(&self) -> Option<&str> {
+        channel_root(&self.tags)
+    }
+
+    /// 新しい版で置き換えるイベントのアドレス（`replace_key`）
+    pub fn replace_key(&self) -> Option<String> {
+        replace_key(self.kind, &self.pubkey, &self.tags)
+    }
+}
+
+/// NIP-01 の置き換え可能なイベント（kindと著者ごとに最新の版だけ残す）
+pub fn is_replaceable_kind(kind: u16) -> bool {
+    matches!(kind, 0 | 3 | 10000..=19999)
+}
+
+/// NIP-01 のアドレス指定可能なイベント（kindと著者と`d`タグごとに最新の版だけ残す）
+pub fn is_addressable_kind(kind: u16) -> bool {
+    (30000..=39999).contains(&kind)
+}
+
+/// NIP-01 の一時的なイベント（保存しない）
+pub fn is_ephemeral_kind(kind: u16) -> bool {
+    (20000..=29999).contains(&kind)
+}
+
+/// NIP-28 のチャンネルID（rootマーカー付きのeタグ、なければ最初のeタグ）
+pub fn channel_root(tags: &[Vec<String>]) -> Option<&str> {
+    let e_tags: Vec<&Vec<String>> = tags.iter().filter(|tag| tag.first().is_some_and(|n| n == "e")).collect();
+    let root = e_tags
+        .iter()
+        .find(|tag| tag.get(3).is_some_and(|m| m == "root"))
+        .or_else(|| e_tags.first())?;
+    root.get(1).map(|id| id.as_str())
+}
+
+/// 新しい版で置き換えるイベントのアドレス（`<kind>:<pubkey>:<d>`、置き換えないイベントはNone）
+///
+/// NIP-28 のチャンネル情報（kind 41）もチャンネルごとに置き換える。
+pub fn replace_key(kind: u16, pubkey: &str, tags: &[Vec<String>]) -> Option<String> {
+    let d = if is_replaceable_kind(kind) {
+        ""
+    } else if is_addressable_kind(kind) {
+        tags.iter()
+            .find(|tag| tag.first().is_some_and(|n| n == "d"))
+            .and_then(|tag| tag.get(1))
+            .map_or("", |d| d.as_str())
+    } else if kind == 41 {
+        channel_root(tags)?
+    } else {
+        return None;
+    };
+    Some(format!("{}:{}:{}", kind, pubkey, d))
+}
+
+impl From<nostr::Event> for NostrEvent {
+    fn from(event: nostr::Event) -> Self {
+        Self {
+            id: event.id.to_hex(),
+            pubkey: event.pubkey.to_hex(),
+            created_at: event.created_at.as_u64() as i64,
+            kind: event.kind.as_u16(),
+            tags: event.tags.iter().map(|t| t.clone().to_vec()).collect(),
+            content: event.content,
+            sig: event.sig.to_string(),
+        }
+    }
+}
+
+fn invalid(field: &str, reason: &str) -> CoreError {
+    CoreError::InvalidEvent(format!("Invalid {}: {}", field, reason))
+}
+
+/// 小文字16進数で指定の長さの文字列フィールド
+fn hex_field(value: &Value, name: &str, len: usize) -> Result<String> {
+    let s = value.as_str().ok_or_else(|| invalid(name, "not a string"))?;
+    if s.len() != len || !s.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+        return Err(invalid(name, &format!("expected {} lowercase hex characters", len)));
+    }
+    Ok(s.to_string())
+}
+
+/// イベントをJSON文字列として保存するためのserdeヘルパー（`#[serde(with = "...")]`用）
+pub mod json_string {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use super::NostrEvent;
+
+    pub fn serialize<S: Serializer>(event: &NostrEvent, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&event.to_json())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NostrEvent, D::Error> {
+        let json = String::deserialize(deserializer)?;
+        NostrEvent::from_json(&json).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn valid() -> Value {
+        json!({
+            "id": "a".repeat(64),
+            "pubkey": "b".repeat(64),
+            "created_at": 100,
+            "kind": 42,
+            "tags": [["e", "chan", "", "root"], ["p", "peer"]],
+            "content": "hi",
+            "sig": "c".repeat(128),
+        })
+    }
+
+    fn error(value: Value) -> String {
+        match NostrEvent::from_value(&value) {
+            Err(CoreError::InvalidEvent(message)) => message,
+            other => panic!("expected InvalidEvent, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_and_roundtrip() {
+        let event = NostrEvent::from_value(&valid()).unwrap();
+        assert_eq!(event.kind, 42);
+        assert_eq!(event.tag_values("e").collect::<Vec<_>>(), vec!["chan"]);
+        assert_eq!(event.tags_named("p").count(), 1);
+        assert_eq!(NostrEvent::from_json(&event.to_json()).unwrap(), event);
+    }
+
+    #[test]
+    fn test_rejects_malformed_events() {
+        let with = |key: &str, value: Value| {
+            let mut event = valid();
+            event[key] = value;
+            event
+        };
+        let without = |key: &str| {
+            let mut event = valid();
+            event.as_object_mut().unwrap().remove(key);
+            event
+        };
+
+        assert_eq!(error(json!([])), "Event is not an object");
+        assert_eq!(error(without("sig")), "Missing field: sig");
+        assert!(error(with("id", json!("e1"))).starts_with("Invalid id"));
+        assert!(error(with("pubkey", json!("B".repeat(64)))).starts_with("Invalid pubkey"));
+        assert!(error(with("kind", json!(70000))).starts_with("Invalid kind"));
+        assert!(error(with("created_at", json!(-1))).starts_with("Invalid created_at"));
+        assert!(error(with("content", json!(1))).starts_with("Invalid content"));
+        assert_eq!(error(with("tags", json!([["e", 1]]))), "Invalid tags: tag 0 has a non-string value");
+        assert!(matches!(NostrEvent::from_json("{"), Err(CoreError::InvalidEvent(_))));
+    }
+
+    #[test]
+    fn test_replace_key() {
+        let tags = |tags: &[&[&str]]| -> Vec<Vec<String>> {
+            tags.iter().map(|t| t.iter().map(|s| s.to_string()).collect()).collect()
+        };
+        assert_eq!(replace_key(0, "pk", &[]).as_deref(), Some("0:pk:"));
+        assert_eq!(replace_key(10002, "pk", &tags(&[&["d", "x"]])).as_deref(), Some("10002:pk:"));
+        assert_eq!(replace_key(30023, "pk", &tags(&[&["d", "post"]])).as_deref(), Some("30023:pk:post"));
+        // dタグがなければ空文字列
+        assert_eq!(replace_key(30023, "pk", &tags(&[&["d"]])).as_deref(), Some("30023:pk:"));
+        assert_eq!(replace_key(30023, "pk", &[]).as_deref(), Some("30023:pk:"));
+        // チャンネル情報はチャンネルごと
+        let metadata = tags(&[&["e", "reply"], &["e", "chan", "", "root"]]);
+        assert_eq!(replace_key(41, "pk", &metadata).as_deref(), Some("41:pk:chan"));
+        assert_eq!(replace_key(41, "pk", &[]), None);
+
+        for kind in [1, 4, 40, 42, 20001] {
+            assert_eq!(replace_key(kind, "pk", &tags(&[&["e", "chan"]])), None, "kind {}", kind);
+        }
+        assert!(is_ephemeral_kind(20000) && is_ephemeral_kind(29999) && !is_ephemeral_kind(30000));
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::de::{self, Deserializer};
+use serde::ser::{SerializeMap, Serializer};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::event::NostrEvent;
+use crate::types::StoredEvent;
+
+/// NIP-01 の購読フィルター
+///
+/// REQのフィルターJSONと相互に変換でき、Storageの検索にも使う。
+/// 汎用タグ条件は`#e`のように1文字のタグ名をキーとする。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub ids: Option<Vec<String>>,
+    pub authors: Option<Vec<String>>,
+    pub kinds: Option<Vec<u16>>,
+    /// タグ名（`#`なし） -> 値
+    pub tags: BTreeMap<char, Vec<String>>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: Option<u32>,
+    /// NIP-50 の全文検索
+    pub search: Option<String>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ids<I, S>(mut self, ids: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.ids = Some(ids.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn authors<I, S>(mut self, authors: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.authors = Some(authors.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn kinds<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+        self.kinds = Some(kinds.into_iter().collect());
+        self
+    }
+
+    /// `#<name>`のタグ条件
+    pub fn tag<I, S>(mut self, name: char, values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tags.insert(name, values.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn since(mut self, since: i64) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    pub fn until(mut self, until: i64) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn search(mut self, search: impl Into<String>) -> Self {
+        self.search = Some(search.into());
+        self
+    }
+
+    /// `#<name>`のタグ条件の値
+    pub fn tag_values(&self, name: char) -> Option<&[String]> {
+        self.tags.get(&name).map(|v| v.as_slice())
+    }
+
+    /// JSON文字列から作成
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// REQに載せるJSON文字列
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    /// 2つのフィルターを1つにまとめる
+    ///
+    /// 結果はどちらかに一致するイベントすべてに一致する（それ以外にも一致することがある）。
+    /// 片方にしかない条件は外す。
+    pub fn merge(&self, other: &Filter) -> Filter {
+        Filter {
+            ids: merge_lists(&self.ids, &other.ids),
+            authors: merge_lists(&self.authors, &other.authors),
+            kinds: merge_lists(&self.kinds, &other.kinds),
+            tags: self
+                .tags
+                .iter()
+                .filter_map(|(name, values)| {
+                    let other_values = other.tags.get(name)?;
+                    let merged = merge_lists(&Some(values.clone()), &Some(other_values.clone()))?;
+                    Some((*name, merged))
+                })
+                .collect(),
+            since: self.since.zip(other.since).map(|(a, b)| a.min(b)),
+            until: self.until.zip(other.until).map(|(a, b)| a.max(b)),
+            limit: self.limit.zip(other.limit).map(|(a, b)| a.max(b)),
+            search: self.search.clone().filter(|s| other.search.as_ref() == Some(s)),
+        }
+    }
+
+    /// 保存済みのイベントがフィルターに一致するか（`limit`は見ない）
+    pub fn matches(&self, event: &StoredEvent) -> bool {
+        self.matches_fields(&event.id, &event.pubkey, event.kind, event.created_at, &event.tags, &event.content)
+    }
+
+    /// イベントがフィルターに一致するか（`limit`は見ない）
+    pub fn matches_event(&self, event: &NostrEvent) -> bool {
+        self.matches_fields(&event.id, &event.pubkey, event.kind, event.created_at, &event.tags, &event.content)
+    }
+
+    fn matches_fields(
+        &self,
+        id: &String,
+        pubkey: &String,
+        kind: u16,
+        created_at: i64,
+        tags: &[Vec<String>],
+        content: &str,
+    ) -> bool {
+        if self.ids.as_ref().is_some_and(|ids| !ids.contains(id)) {
+            return false;
+        }
+        if self.authors.as_ref().is_some_and(|authors| !authors.contains(pubkey)) {
+            return false;
+        }
+        if self.kinds.as_ref().is_some_and(|kinds| !kinds.contains(&kind)) {
+            return false;
+        }
+        if self.since.is_some_and(|since| created_at < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| created_at > until) {
+            return false;
+        }
+        for (name, values) in &self.tags {
+            let name = name.to_string();
+            let found = tags.iter().any(|tag| {
+                tag.first() == Some(&name) && tag.get(1).is_some_and(|v| values.contains(v))
+            });
+            if !found {
+                return false;
+            }
+        }
+        if let Some(search) = &self.search {
+            if !content.to_lowercase().contains(&search.to_lowercase()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// リスト条件をまとめる（片方が無条件なら無条件）
+fn merge_lists<T: Clone + Ord>(a: &Option<Vec<T>>, b: &Option<Vec<T>>) -> Option<Vec<T>> {
+    let (a, b) = (a.as_ref()?, b.as_ref()?);
+    let merged: BTreeSet<T> = a.iter().chain(b.iter()).cloned().collect();
+    Some(merged.into_iter().collect())
+}
+
+impl Serialize for Filter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        if let Some(ids) = &self.ids {
+            map.serialize_entry("ids", ids)?;
+        }
+        if let Some(authors) = &self.authors {
+            map.serialize_entry("authors", authors)?;
+        }
+        if let Some(kinds) = &self.kinds {
+            map.serialize_entry("kinds", kinds)?;
+        }
+        for (name, values) in &self.tags {
+            map.serialize_entry(&format!("#{}", name), values)?;
+        }
+        if let Some(since) = self.since {
+            map.serialize_entry("since", &since)?;
+        }
+        if let Some(until) = self.until {
+            map.serialize_entry("until", &until)?;
+        }
+        if let Some(limit) = self.limit {
+            map.serialize_entry("limit", &limit)?;
+        }
+        if let Some(search) = &self.search {
+            map.serialize_entry("search", search)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Filter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = serde_json::Map::<String, Value>::deserialize(deserializer)?;
+        let mut filter = Filter::new();
+        for (key, value) in fields {
+            match key.as_str() {
+                "ids" => filter.ids = Some(serde_json::from_value(value).map_err(de::Error::custom)?),
+                "authors" => filter.authors = Some(serde_json::from_value(value).map_err(de::Error::custom)?),
+                "kinds" => filter.kinds = Some(serde_json::from_value(value).map_err(de::Error::custom)?),
+                "since" => filter.since = Some(serde_json::from_value(value).map_err(de::Error::custom)?),
+                "until" => filter.until = Some(serde_json::from_value(value).map_err(de::Error::custom)?),
+                "limit" => filter.limit = Some(serde_json::from_value(value).map_err(de::Error::custom)?),
+                "search" => filter.search = Some(serde_json::from_value(value).map_err(de::Error::custom)?),
+                _ => {
+                    // `#`+1文字のキーだけをタグ条件とし、それ以外は無視する
+                    let mut chars = key.chars();
+                    if let (Some('#'), Some(name), None) = (chars.next(), chars.next(), chars.next()) {
+                        let values = serde_json::from_value(value).map_err(de::Error::custom)?;
+                        filter.tags.insert(name, values);
+                    }
+                }
+            }
+        }
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: u16, created_at: i64, tags: &[&[&str]], content: &str) -> StoredEvent {
+        StoredEvent {
+            id: "id1".to_string(),
+            kind,
+            pubkey: "alice".to_string(),
+            created_at,
+            content: content.to_string(),
+            tags: tags.iter().map(|t| t.iter().map(|s| s.to_string()).collect()).collect(),
+            sig: String::new(),
+            relay_hint: None,
+            inserted_at: 0,
+            seen_on: Vec::new(),
+            tag_keys: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let filter = Filter::new().kinds([42]).tag('e', ["chan"]).since(100).limit(20);
+        assert_eq!(filter.to_json(), r##"{"kinds":[42],"#e":["chan"],"since":100,"limit":20}"##);
+
+        let parsed = Filter::from_json(&filter.to_json()).unwrap();
+        assert_eq!(parsed, filter);
+
+        let parsed = Filter::from_json(r##"{"ids":["a"],"authors":["b"],"#p":["c"],"until":5,"search":"hi","#long":["x"]}"##).unwrap();
+        assert_eq!(parsed.ids, Some(vec!["a".to_string()]));
+        assert_eq!(parsed.tag_values('p'), Some(&["c".to_string()][..]));
+        assert_eq!(parsed.until, Some(5));
+        assert_eq!(parsed.search.as_deref(), Some("hi"));
+        assert_eq!(parsed.tags.len(), 1);
+
+        assert!(Filter::from_json(r#"{"kinds":"x"}"#).is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let filter = Filter::new().kinds([42]).authors(["alice"]).tag('e', ["chan"]).since(100).until(200);
+        assert!(filter.matches(&event(42, 150, &[&["e", "chan", "", "root"]], "")));
+        assert!(!filter.matches(&event(42, 150, &[&["e", "other"]], "")));
+        assert!(!filter.matches(&event(42, 150, &[&["p", "chan"]], "")));
+        assert!(!filter.matches(&event(4, 150, &[&["e", "chan"]], "")));
+        assert!(!filter.matches(&event(42, 99, &[&["e", "chan"]], "")));
+        assert!(!filter.matches(&event(42, 201, &[&["e", "chan"]], "")));
+
+        let search = Filter::new().search("Hello");
+        assert!(search.matches(&event(1, 0, &[], "well, hello there")));
+        assert!(!search.matches(&event(1, 0, &[], "goodbye")));
+
+        let event = event(42, 150, &[&["e", "chan"]], "").event();
+        assert!(filter.matches_event(&event));
+        assert!(!filter.matches_event(&NostrEvent { kind: 4, ..event }));
+    }
+
+    #[test]
+    fn test_merge() {
+        let a = Filter::new().kinds([42]).tag('e', ["a"]).since(100).limit(10);
+        let b = Filter::new().kinds([42, 40]).tag('e', ["b"]).since(50).limit(20).authors(["x"]);
+        let merged = a.merge(&b);
+        assert_eq!(merged.kinds, Some(vec![40, 42]));
+        assert_eq!(merged.tag_values('e'), Some(&["a".to_string(), "b".to_string()][..]));
+        assert_eq!(merged.since, Some(50));
+        assert_eq!(merged.limit, Some(20));
+        // 片方にしかない条件は外す
+        assert_eq!(merged.authors, None);
+    }
+}
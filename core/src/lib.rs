@@ -1,22 +1,56 @@
 pub mod types;
+pub mod clock;
 pub mod storage;
+pub mod transport;
 pub mod relay;
+pub mod supervisor;
+pub mod filter;
 pub mod subscription;
 pub mod outbox;
+pub mod conversation;
+pub mod nip65;
+pub mod seen;
 pub mod signer;
+pub mod verify;
 pub mod error;
+pub mod event;
+pub mod actor;
+pub mod retention;
+pub mod backup;
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, Write};
 use std::sync::Arc;
 
 pub use error::{CoreError, Result};
+pub use actor::{CoreActor, CoreClient, CoreEvents};
 
+use crate::clock::{Clock, SystemClock};
 use crate::storage::Storage;
-use crate::relay::{RelayConnection, RelayMessage};
-use crate::subscription::SubscriptionManager;
+use crate::transport::Transport;
+use crate::relay::{AuthPolicy, ReasonPrefix, RelayConnection, RelayMessage};
+use crate::filter::Filter;
+use crate::subscription::{FinishedHistory, HistoryState, SubscriptionManager};
 use crate::outbox::OutboxQueue;
+use crate::conversation::Conversations;
+use crate::nip65::{RelayList, RelayListCache, RELAY_LIST_KIND};
+use crate::seen::SeenCache;
+use crate::retention::{Candidate, RetentionPolicy, StoredTotals};
+use crate::backup::ImportSummary;
 use crate::signer::Signer;
-use crate::types::UiRow;
+use crate::event::{is_ephemeral_kind, NostrEvent};
+use crate::verify::verify_event;
+use crate::types::{CoreEvent, DeliverySummary, RelayConfig, Scope, StoredEvent, UiRow};
+use crate::relay::ConnectionState;
+
+/// 一定時間内（`RelayConnection::invalid_event_count`）にこの数のイベント検証に失敗したRelayは読み込みに使わない
+const MAX_INVALID_EVENTS: u32 = 5;
+
+/// 会話を開いたときにStorageから読み込む件数
+const CACHED_HISTORY_LIMIT: u32 = 50;
+
+/// 保存方針を適用する間隔（秒）
+const RETENTION_INTERVAL_SECS: i64 = 10 * 60;
 
 /// CoreHandle: UIから使用されるメインAPI
 pub struct CoreHandle {
@@ -25,19 +59,64 @@ pub struct CoreHandle {
     outbox: OutboxQueue,
     storage: Arc<dyn Storage>,
     signer: Option<Arc<dyn Signer>>,
-    event_buffer: VecDeque<UiRow>,
+    event_buffer: VecDeque<CoreEvent>,
+    /// 前回通知したRelayの接続状態
+    relay_states: HashMap<String, ConnectionState>,
+    /// 前回通知した配送状況
+    delivery_states: HashMap<String, DeliverySummary>,
+    conversations: Conversations,
+    seen: SeenCache,
+    counts: HashMap<String, u64>,
+    next_count_id: u64,
+    relay_lists: RelayListCache,
+    next_relay_list_id: u64,
+    /// NIP-65で見つけた、設定に含まれないRelay
+    discovered: HashSet<String>,
+    /// 不正なイベントを送ってきたため読み込みに使わないRelay（設定は変えず、再起動で解除）
+    demoted: HashSet<String>,
+    retention: RetentionPolicy,
+    /// 次に保存方針を適用する時刻
+    next_retention_at: i64,
+    /// 保存済みイベントの見積もり（最初に保存方針を適用するときに数える）
+    stored_totals: Option<StoredTotals>,
+    transport: Arc<dyn Transport>,
+    clock: Arc<dyn Clock>,
 }
 
 impl CoreHandle {
-    /// 初期化
+    /// 初期化（ターゲットのデフォルトTransportと実時間Clockを使用）
     pub async fn init(relay_urls: Vec<String>, storage: Arc<dyn Storage>) -> Result<Self> {
-        let relays = relay_urls
-            .into_iter()
-            .map(|url| RelayConnection::new(url))
+        Self::init_with(relay_urls, storage, transport::default_transport(), Arc::new(SystemClock)).await
+    }
+
+    /// TransportとClockを指定して初期化
+    ///
+    /// Storageに保存済みのRelay一覧があればそれを使い、なければ`relay_urls`を初期値として保存する。
+    pub async fn init_with(
+        relay_urls: Vec<String>,
+        storage: Arc<dyn Storage>,
+        transport: Arc<dyn Transport>,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self> {
+        let mut configs = storage.get_relays().await?;
+        if configs.is_empty() {
+            configs = relay_urls.into_iter().map(RelayConfig::new).collect();
+            for config in &configs {
+                storage.upsert_relay(config).await?;
+            }
+        }
+
+        let relays = configs
+            .iter()
+            .map(|config| {
+                let mut relay = RelayConnection::new(config.url.clone(), transport.clone(), clock.clone());
+                relay.apply_config(config);
+                relay
+            })
             .collect();
 
-        let sub_mgr = SubscriptionManager::new();
-        let outbox = OutboxQueue::new(storage.clone());
+        let sub_mgr = SubscriptionManager::with_clock(clock.clone());
+        let outbox = OutboxQueue::with_clock(storage.clone(), clock.clone());
 
         Ok(Self {
             relays,
@@ -46,12 +125,110 @@ impl CoreHandle {
             storage,
             signer: None,
             event_buffer: VecDeque::new(),
+            relay_states: HashMap::new(),
+            delivery_states: HashMap::new(),
+            conversations: Conversations::new(),
+            seen: SeenCache::default(),
+            counts: HashMap::new(),
+            next_count_id: 0,
+            relay_lists: RelayListCache::new(),
+            next_relay_list_id: 0,
+            discovered: HashSet::new(),
+            demoted: HashSet::new(),
+            retention: RetentionPolicy::default(),
+            next_retention_at: 0,
+            stored_totals: None,
+            transport,
+            clock,
         })
     }
 
+    /// Relay設定一覧
+    pub fn relays(&self) -> Vec<RelayConfig> {
+        self.relays
+            .iter()
+            .filter(|r| !self.discovered.contains(&r.url))
+            .map(|r| r.config())
+            .collect()
+    }
+
+    /// Relayの接続状態
+    pub fn relay_state(&self, url: &str) -> Option<ConnectionState> {
+        self.relays.iter().find(|r| r.url == url).map(|r| r.state())
+    }
+
+    /// Relayを追加して接続する
+    pub async fn add_relay(&mut self, url: &str) -> Result<()> {
+        let url = normalize_relay_url(url)?;
+        let promoted = self.discovered.remove(&url);
+        if !promoted && self.relays.iter().any(|r| r.url == url) {
+            return Err(CoreError::RelayError(format!("Relay already exists: {}", url)));
+        }
+
+        let config = RelayConfig::new(url.clone());
+        self.storage.upsert_relay(&config).await?;
+
+        if promoted {
+            // 接続済みの一時的なRelayを設定に昇格
+            if let Some(relay) = self.relay_mut(&url) {
+                relay.apply_config(&config);
+            }
+        } else {
+            let mut relay = RelayConnection::new(url, self.transport.clone(), self.clock.clone());
+            if let Err(e) = relay.connect().await {
+                log::error!("Failed to connect to {}: {:?}", relay.url, e);
+            }
+            self.relays.push(relay);
+        }
+
+        // 開いている購読は接続時に送られる
+        let subs: Vec<(String, Filter)> = self.sub_mgr.get_active_subs()
+            .into_iter()
+            .map(|sub| (sub.sub_id.clone(), sub.filter.clone()))
+            .collect();
+        for (sub_id, filter) in subs {
+            self.subscribe_routed(&sub_id, &filter).await;
+        }
+        Ok(())
+    }
+
+    /// Relayを切断して削除する
+    pub async fn remove_relay(&mut self, url: &str) -> Result<()> {
+        let index = self.relays.iter().position(|r| r.url == url)
+            .ok_or_else(|| CoreError::RelayError(format!("Unknown relay: {}", url)))?;
+        let mut relay = self.relays.remove(index);
+        relay.disconnect();
+        self.discovered.remove(url);
+        self.storage.remove_relay(url).await?;
+        Ok(())
+    }
+
+    /// Relayの読み書きフラグを設定
+    pub async fn set_relay_policy(&mut self, url: &str, read: bool, write: bool) -> Result<()> {
+        if read {
+            // 手動で読み込みを有効にしたら降格を解除
+            self.demoted.remove(url);
+        }
+        let relay = self.relay_mut(url)
+            .ok_or_else(|| CoreError::RelayError(format!("Unknown relay: {}", url)))?;
+        relay.set_policy(read, write);
+        if read {
+            relay.reset_invalid_events();
+        }
+        let config = relay.config();
+        self.storage.upsert_relay(&config).await
+    }
+
+    /// 不正なイベントにより読み込みから外されたRelayか
+    pub fn is_demoted(&self, url: &str) -> bool {
+        self.demoted.contains(url)
+    }
+
     /// Signerを設定
     pub fn set_signer(&mut self, signer: Arc<dyn Signer>) {
         self.signer = Some(signer);
+        // 残す自分のイベントが変わるので数え直す
+        self.stored_totals = None;
     }
 
     /// 公開鍵を取得
@@ -75,14 +252,16 @@ impl CoreHandle {
 
     /// チャンネルを開く
     pub async fn open_channel(&mut self, channel_id: &str) -> Result<()> {
+        // 保存済みの履歴をすぐ表示する
+        let scope = Scope::Channel(channel_id.to_string());
+        self.load_from_storage(&scope, "", None, CACHED_HISTORY_LIMIT).await?;
+        self.mark_viewed(&scope).await?;
+
         let filters = self.sub_mgr.open_channel(channel_id);
         
-        // 全Relayに購読リクエスト送信
-        for (sub_id, filter_json) in filters {
-            let req = format!(r#"["REQ","{}",{}]"#, sub_id, filter_json);
-            for relay in &self.relays {
-                let _ = relay.send(&req).await;
-            }
+        // 読み込み用Relayに購読リクエスト送信
+        for (sub_id, filter) in filters {
+            self.subscribe_routed(&sub_id, &filter).await;
         }
         Ok(())
     }
@@ -95,18 +274,347 @@ impl CoreHandle {
             return Err(CoreError::Other("No signer available".to_string()));
         };
         
+        let scope = Scope::Dm(peer.to_string());
+        self.load_from_storage(&scope, &self_pubkey, None, CACHED_HISTORY_LIMIT).await?;
+        self.mark_viewed(&scope).await?;
+
+        // 相手のRelayリストが届いたら購読先を追加する
+        self.fetch_relay_lists(&[peer.to_string()]).await;
+
         let filters = self.sub_mgr.open_dm(peer, &self_pubkey);
         
-        // 全Relayに購読リクエスト送信
-        for (sub_id, filter_json) in filters {
-            let req = format!(r#"["REQ","{}",{}]"#, sub_id, filter_json);
-            for relay in &self.relays {
-                let _ = relay.send(&req).await;
+        // 著者の書き込み用Relay（不明なら自分の読み込み用Relay）に購読リクエスト送信
+        for (sub_id, filter) in filters {
+            self.subscribe_routed(&sub_id, &filter).await;
+        }
+        Ok(())
+    }
+
+    /// 会話の購読を閉じる（他に使っている画面がなければRelayに`CLOSE`を送る）
+    pub async fn close_scope(&mut self, scope: &Scope) -> Result<()> {
+        self.mark_viewed(scope).await?;
+        for sub_id in self.sub_mgr.close_scope(scope) {
+            for relay in &mut self.relays {
+                relay.unsubscribe(&sub_id).await?;
             }
         }
         Ok(())
     }
 
+    /// Storageの全テーブルをJSONLのバックアップとして書き出す（`backup::export`）
+    pub async fn export_backup(&self, out: &mut impl Write) -> Result<usize> {
+        backup::export(self.storage.as_ref(), self.clock.now(), out).await
+    }
+
+    /// JSONLのバックアップから復元する（`backup::import`）
+    ///
+    /// 復元したRelayには接続し、未送信のOutboxアイテムは送信キューに入れる。
+    pub async fn import_backup(&mut self, input: impl BufRead) -> Result<ImportSummary> {
+        let summary = backup::import(self.storage.as_ref(), input).await?;
+        self.stored_totals = None;
+
+        self.outbox.load_pending().await?;
+        let mut added = false;
+        for config in self.storage.get_relays().await? {
+            if self.discovered.remove(&config.url) {
+                // 接続済みの一時的なRelayを設定に昇格
+                if let Some(relay) = self.relay_mut(&config.url) {
+                    relay.apply_config(&config);
+                }
+            } else if !self.relays.iter().any(|r| r.url == config.url) {
+                let mut relay = RelayConnection::new(config.url.clone(), self.transport.clone(), self.clock.clone());
+                relay.apply_config(&config);
+                if let Err(e) = relay.connect().await {
+                    log::error!("Failed to connect to {}: {:?}", relay.url, e);
+                }
+                self.relays.push(relay);
+            } else {
+                continue;
+            }
+            added = true;
+        }
+
+        // 開いている購読は接続時に送られる
+        if added {
+            let subs: Vec<(String, Filter)> = self.sub_mgr.get_active_subs()
+                .into_iter()
+                .map(|sub| (sub.sub_id.clone(), sub.filter.clone()))
+                .collect();
+            for (sub_id, filter) in subs {
+                self.subscribe_routed(&sub_id, &filter).await;
+            }
+        }
+        Ok(summary)
+    }
+
+    /// 会話を今まで見ていたことを記録する（保存方針で最後に見た会話を残すのに使う）
+    async fn mark_viewed(&self, scope: &Scope) -> Result<()> {
+        self.storage.set_last_seen(&scope.storage_key(), self.clock.now()).await
+    }
+
+    /// Storageに残すイベントの上限を設定（次の`tick`で適用する）
+    pub fn set_retention_policy(&mut self, policy: RetentionPolicy) {
+        self.retention = policy;
+        self.next_retention_at = 0;
+    }
+
+    /// 保存方針を超えたイベントをStorageから消す
+    ///
+    /// 自分のイベント、DM、置き換え可能なメタデータは残す。
+    /// 件数と合計サイズは見積もり（`StoredTotals`）で判定し、期限切れの分と上限を超えた会話、
+    /// 合計サイズが上限を超えていれば最後に見たのが古い会話から消す分だけを読む
+    /// （全件を読むのは最初の見積もりのときだけ）。消したイベントの数を返す。
+    pub async fn enforce_retention(&mut self) -> Result<usize> {
+        let self_pubkey = self.get_public_key().await.ok().flatten();
+        let self_pubkey = self_pubkey.as_deref();
+        let now = self.clock.now();
+        let mut totals = match self.stored_totals.take() {
+            Some(totals) => totals,
+            None => self.measure_stored(self_pubkey).await?,
+        };
+        let usage = self.storage.estimate_usage().await?;
+        let excess = self.retention.excess_bytes(totals.bytes, usage);
+        let cutoff = self.retention.max_age_secs.map(|max_age| now - max_age);
+        let max_events = self.retention.max_events_per_scope;
+
+        let last_seen: HashMap<String, i64> = self.storage.get_all_last_seen().await?.into_iter().collect();
+        let last_viewed = |scope: &Option<Scope>| {
+            scope.as_ref().and_then(|s| last_seen.get(&s.storage_key()).copied()).unwrap_or(0)
+        };
+        let mut candidates: HashMap<String, Candidate> = HashMap::new();
+        let mut add = |events: Vec<(StoredEvent, Option<Scope>)>, viewed: i64| -> u64 {
+            let mut bytes = 0;
+            for (event, scope) in events {
+                let size = retention::event_size(&event);
+                bytes += size;
+                candidates.entry(event.id.clone()).or_insert(Candidate {
+                    id: event.id,
+                    scope,
+                    created_at: event.created_at,
+                    size,
+                    last_viewed: viewed,
+                });
+            }
+            bytes
+        };
+
+        // 上限を超えた会話は丸ごと、それ以外は期限切れの分だけ時刻の範囲で読む
+        let mut scopes: Vec<Option<Scope>> = totals.per_scope.keys().cloned().map(Some).collect();
+        if !totals.unscoped.is_empty() {
+            scopes.push(None);
+        }
+        let mut found = 0;
+        for scope in &scopes {
+            let count = match scope {
+                Some(scope) => totals.per_scope[scope],
+                None => totals.unscoped.len(),
+            };
+            let until = match max_events.is_some_and(|max| count > max) {
+                true => None,
+                false => match cutoff {
+                    Some(cutoff) => Some(cutoff - 1),
+                    None => continue,
+                },
+            };
+            let events = self.load_candidates(scope, until, self_pubkey, &totals).await?;
+            found += add(events, last_viewed(scope));
+        }
+        if found == 0 && excess == 0 {
+            self.stored_totals = Some(totals);
+            return Ok(0);
+        }
+        // 最後に見たのが古い会話から、消す分に足りるまで読む（`RetentionPolicy::plan`と同じ順）
+        if excess > 0 {
+            scopes.sort_by_cached_key(|scope| (last_viewed(scope), scope.as_ref().map(Scope::storage_key).unwrap_or_default()));
+            let mut loaded = 0;
+            for scope in &scopes {
+                if loaded >= excess {
+                    break;
+                }
+                let events = self.load_candidates(scope, None, self_pubkey, &totals).await?;
+                loaded += add(events, last_viewed(scope));
+            }
+        }
+
+        let candidates: Vec<Candidate> = candidates.into_values().collect();
+        let evicted = self.retention.plan(&candidates, totals.bytes, usage, now);
+        if !evicted.is_empty() {
+            log::info!("Retention: evicting {} of {} loaded events", evicted.len(), candidates.len());
+            self.storage.delete_events(&evicted).await?;
+            let evicted: HashSet<&String> = evicted.iter().collect();
+            for candidate in candidates.iter().filter(|c| evicted.contains(&c.id)) {
+                totals.remove(candidate);
+            }
+        }
+        self.stored_totals = Some(totals);
+        Ok(evicted.len())
+    }
+
+    /// 保存済みのイベントを全件読んで見積もりを作る（起動後の最初の一度だけ）
+    async fn measure_stored(&self, self_pubkey: Option<&str>) -> Result<StoredTotals> {
+        let mut totals = StoredTotals::default();
+        for event in self.storage.get_events(&Filter::new()).await? {
+            let protected = retention::is_protected(&event, self_pubkey);
+            let scope = event_scope(&event.event(), self_pubkey);
+            totals.add(&event, scope, protected);
+        }
+        Ok(totals)
+    }
+
+    /// 会話の`until`以前の消してよいイベントを読む
+    ///
+    /// 会話の分からないイベントは見積もりに覚えているIDで引く。
+    async fn load_candidates(
+        &self,
+        scope: &Option<Scope>,
+        until: Option<i64>,
+        self_pubkey: Option<&str>,
+        totals: &StoredTotals,
+    ) -> Result<Vec<(StoredEvent, Option<Scope>)>> {
+        let filters = match scope {
+            Some(scope) => SubscriptionManager::scope_filters(scope, self_pubkey.unwrap_or_default()),
+            None => {
+                let ids: Vec<&str> = totals.unscoped
+                    .iter()
+                    .filter(|(_, created_at)| until.is_none_or(|until| **created_at <= until))
+                    .map(|(id, _)| id.as_str())
+                    .collect();
+                if ids.is_empty() {
+                    return Ok(Vec::new());
+                }
+                vec![Filter::new().ids(ids)]
+            }
+        };
+        let mut events = Vec::new();
+        for mut filter in filters {
+            if let Some(until) = until {
+                filter = filter.until(until);
+            }
+            for event in self.storage.get_events(&filter).await? {
+                // 別の会話に返信しているイベントもタグで一致するので、会話を求め直す
+                let event_scope = event_scope(&event.event(), self_pubkey);
+                if !retention::is_protected(&event, self_pubkey) && event_scope == *scope {
+                    events.push((event, event_scope));
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    /// 保存したイベントを見積もりに加える
+    async fn count_stored(&mut self, event: &NostrEvent) {
+        if self.stored_totals.is_none() || is_ephemeral_kind(event.kind) {
+            return;
+        }
+        let self_pubkey = self.get_public_key().await.ok().flatten();
+        let stored = StoredEvent::new(event.clone(), self.clock.now());
+        let protected = retention::is_protected(&stored, self_pubkey.as_deref());
+        let scope = event_scope(event, self_pubkey.as_deref());
+        if let Some(totals) = &mut self.stored_totals {
+            totals.add(&stored, scope, protected);
+        }
+    }
+
+    /// Relayが同時に開ける購読数の上限を設定（超えた購読は空きができるまで待つ）
+    ///
+    /// NIP-11 のRelay情報は取得しないので、`limitation.max_subscriptions`の値をここで与える。
+    /// キープアライブのping用に1つ空けておく。
+    pub async fn set_max_subscriptions(&mut self, url: &str, max: Option<usize>) -> Result<()> {
+        let relay = self.relay_mut(url)
+            .ok_or_else(|| CoreError::RelayError(format!("Unknown relay: {}", url)))?;
+        relay.set_max_subscriptions(max);
+        let config = relay.config();
+        self.storage.upsert_relay(&config).await
+    }
+
+    /// 会話の過去ログを読み込む（表示中の最古のイベントより前を`limit`件）
+    ///
+    /// まずStorageから補い、足りなければ`until`+`limit`のREQをRelayに送る。
+    /// Relayからの結果は`poll_events`で届き、取得の進み具合は`CoreEvent::History*`で通知する。
+    /// Storageから読み込んだ件数を返す。
+    pub async fn load_older(&mut self, scope: &Scope, limit: u32) -> Result<usize> {
+        let state = self.sub_mgr.history_state(scope);
+        if state.loading || state.reached_start {
+            return Ok(0);
+        }
+        let self_pubkey = match scope {
+            Scope::Channel(_) => String::new(),
+            Scope::Dm(_) => self
+                .get_public_key()
+                .await?
+                .ok_or_else(|| CoreError::Other("No signer available".to_string()))?,
+        };
+
+        // Storageにあるものを先に使う
+        let until = self.oldest_created_at(scope);
+        let filled = self.load_from_storage(scope, &self_pubkey, Some(until), limit).await?;
+        if filled >= limit as usize {
+            return Ok(filled);
+        }
+
+        let until = self.oldest_created_at(scope);
+        let requests = self.sub_mgr.open_history(scope, &self_pubkey, until, limit - filled as u32);
+        if !requests.is_empty() {
+            self.event_buffer.push_back(CoreEvent::HistoryLoading(scope.clone()));
+        }
+        for (sub_id, filter) in requests {
+            let relays = self.subscribe_routed(&sub_id, &filter).await;
+            let finished = self.sub_mgr.on_history_sent(&sub_id, relays);
+            self.on_history_finished(finished);
+        }
+        Ok(filled)
+    }
+
+    /// Storageにある会話のイベントを新しい方から`limit`件読み込み、新しく加えた件数を返す
+    ///
+    /// DMは送信と受信のフィルターごとに`limit`件ずつ読むので、まとめて新しい`limit`件だけ加える。
+    /// 片方だけ加えると、もう片方の読み残しが次の`until`より新しくなり二度と読まれない。
+    async fn load_from_storage(
+        &mut self,
+        scope: &Scope,
+        self_pubkey: &str,
+        until: Option<i64>,
+        limit: u32,
+    ) -> Result<usize> {
+        let mut events = Vec::new();
+        for filter in SubscriptionManager::scope_filters(scope, self_pubkey) {
+            let filter = match until {
+                Some(until) => filter.until(until),
+                None => filter,
+            };
+            events.extend(self.storage.get_events(&filter.limit(limit)).await?);
+        }
+        storage::sort_and_limit(&mut events, None);
+        events.dedup_by(|a, b| a.id == b.id);
+        // `until`ちょうどの読み込み済みの行で枠を埋めない
+        events.retain(|event| !self.conversations.contains(scope, &event.id));
+        events.truncate(limit as usize);
+
+        let mut added = 0;
+        for event in events {
+            let row = UiRow::from_stored(&event, Some(scope.clone()));
+            if self.conversations.insert(row.clone()) {
+                self.event_buffer.push_back(CoreEvent::EventReceived(row));
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    /// 会話の過去ログ取得の状態
+    pub fn history_state(&self, scope: &Scope) -> HistoryState {
+        self.sub_mgr.history_state(scope)
+    }
+
+    /// 会話で保持している最古のイベント時刻（なければ現在時刻）
+    fn oldest_created_at(&self, scope: &Scope) -> i64 {
+        self.conversations
+            .rows(scope)
+            .last()
+            .map(|row| row.created_at)
+            .unwrap_or_else(|| self.clock.now())
+    }
+
     /// チャンネル作成 (NIP-28)
     pub async fn create_channel(&mut self, name: &str, about: &str, picture: &str) -> Result<String> {
         let signer = self.signer.as_ref()
@@ -123,15 +631,14 @@ impl CoreHandle {
             kind: 40,
             content,
             tags: vec![],
-            created_at: (js_sys::Date::now() / 1000.0) as i64,
+            created_at: self.clock.now(),
         };
         
         let signed_event = signer.sign_event(unsigned_event).await?;
         let event_id = signed_event.id.clone();
-        let event_json = signed_event.to_json();
         
         // Outboxキューに追加
-        self.outbox.enqueue(event_json).await?;
+        self.outbox.enqueue(signed_event).await?;
         
         Ok(event_id)
     }
@@ -143,23 +650,27 @@ impl CoreHandle {
         
         // NIP-28: チャンネルメッセージ (kind 42)
         // eタグ: ["e", <32-bytes lowercase hex of the id of another event>, <recommended relay URL, optional>]
-        let tags = vec![
-            vec!["e".to_string(), channel_id.to_string()],
-        ];
+        let tags = match self.relay_hint(channel_id).await? {
+            Some(hint) => vec![
+                vec!["e".to_string(), channel_id.to_string(), hint, "root".to_string()],
+            ],
+            None => vec![
+                vec!["e".to_string(), channel_id.to_string()],
+            ],
+        };
         
         let unsigned_event = crate::signer::UnsignedEvent {
             kind: 42,
             content: content.to_string(),
             tags,
-            created_at: (js_sys::Date::now() / 1000.0) as i64,
+            created_at: self.clock.now(),
         };
         
         let signed_event = signer.sign_event(unsigned_event).await?;
         let event_id = signed_event.id.clone();
-        let event_json = signed_event.to_json();
         
         // Outboxキューに追加
-        self.outbox.enqueue(event_json).await?;
+        self.outbox.enqueue(signed_event).await?;
         
         Ok(event_id)
     }
@@ -181,25 +692,117 @@ impl CoreHandle {
             kind: 4,
             content: encrypted,
             tags,
-            created_at: (js_sys::Date::now() / 1000.0) as i64,
+            created_at: self.clock.now(),
         };
         
         let signed_event = signer.sign_event(unsigned_event).await?;
         let event_id = signed_event.id.clone();
-        let event_json = signed_event.to_json();
         
         // Outboxキューに追加
-        self.outbox.enqueue(event_json).await?;
+        self.outbox.enqueue(signed_event).await?;
         
         Ok(event_id)
     }
 
-    /// UIイベントをポーリング
-    pub fn poll_events(&mut self, max: u32) -> Vec<UiRow> {
+    /// NIP-65: 自分のRelay設定をkind 10002として公開
+    pub async fn publish_relay_list(&mut self) -> Result<String> {
+        let signer = self.signer.as_ref()
+            .ok_or_else(|| CoreError::Other("No signer available".to_string()))?;
+
+        let list = RelayList::from_configs(&self.relays(), self.clock.now());
+        let unsigned_event = crate::signer::UnsignedEvent {
+            kind: RELAY_LIST_KIND,
+            content: String::new(),
+            tags: list.to_tags(),
+            created_at: list.created_at,
+        };
+
+        let signed_event = signer.sign_event(unsigned_event).await?;
+        let event_id = signed_event.id.clone();
+        self.relay_lists.update(&signed_event.pubkey, list);
+        self.outbox.enqueue(signed_event).await?;
+
+        Ok(event_id)
+    }
+
+    /// NIP-65: 未取得の著者のRelayリストを要求
+    pub async fn fetch_relay_lists(&mut self, pubkeys: &[String]) {
+        let missing: Vec<&String> = pubkeys.iter().filter(|pk| !self.relay_lists.contains(pk)).collect();
+        if missing.is_empty() {
+            return;
+        }
+
+        self.next_relay_list_id += 1;
+        let sub_id = format!("relay_list_{}", self.next_relay_list_id);
+        let filter = Filter::new().kinds([RELAY_LIST_KIND]).authors(missing.into_iter().cloned());
+        let demoted = &self.demoted;
+        for relay in self.relays.iter_mut().filter(|r| r.is_read() && !demoted.contains(&r.url)) {
+            let _ = relay.subscribe(&sub_id, &filter).await;
+        }
+    }
+
+    /// キャッシュ済みのRelayリスト
+    pub fn relay_list(&self, pubkey: &str) -> Option<&RelayList> {
+        self.relay_lists.get(pubkey)
+    }
+
+    /// NIP-45: イベント数を問い合わせる。結果は`count_result`で取得する
+    pub async fn request_count(&mut self, filter: &Filter) -> Result<String> {
+        self.next_count_id += 1;
+        let sub_id = format!("count_{}", self.next_count_id);
+        let msg = format!(r#"["COUNT","{}",{}]"#, sub_id, filter.to_json());
+        for relay in self.relays.iter().filter(|r| r.is_read() && !self.demoted.contains(&r.url)) {
+            if let Err(e) = relay.send(&msg).await {
+                log::error!("Failed to send COUNT to {}: {:?}", relay.url, e);
+            }
+        }
+        Ok(sub_id)
+    }
+
+    /// COUNTの結果（応答したRelayの最大値）
+    pub fn count_result(&self, sub_id: &str) -> Option<u64> {
+        self.counts.get(sub_id).copied()
+    }
+
+    /// イベントを受信したRelay（受信順）
+    pub async fn seen_on(&self, event_id: &str) -> Result<Vec<String>> {
+        if let Some(relays) = self.seen.seen_on(event_id) {
+            return Ok(relays.to_vec());
+        }
+        Ok(self.storage.get_event(event_id).await?.map(|e| e.seen_on).unwrap_or_default())
+    }
+
+    /// NIP-19やリプライに使うRelayヒント（最初に受信したRelay）
+    pub async fn relay_hint(&self, event_id: &str) -> Result<Option<String>> {
+        Ok(self.seen_on(event_id).await?.into_iter().next())
+    }
+
+    /// 配送済みとするのに必要な受理Relay数を設定
+    pub fn set_publish_quorum(&mut self, quorum: usize) {
+        self.outbox.set_quorum(quorum);
+    }
+
+    /// 送信したイベントのRelayごとの配送状況
+    pub fn delivery(&self, event_id: &str) -> Option<DeliverySummary> {
+        self.outbox.delivery(event_id)
+    }
+
+    /// 送信したイベントの配送状況一覧（イベントID, 配送状況）
+    pub fn deliveries(&self) -> Vec<(String, DeliverySummary)> {
+        self.outbox.deliveries()
+    }
+
+    /// 会話のイベント一覧（新しい順）
+    pub fn conversation(&self, scope: &Scope) -> Vec<UiRow> {
+        self.conversations.rows(scope).to_vec()
+    }
+
+    /// UIへの通知をポーリング
+    pub fn poll_events(&mut self, max: u32) -> Vec<CoreEvent> {
         let mut result = Vec::new();
         for _ in 0..max {
-            if let Some(row) = self.event_buffer.pop_front() {
-                result.push(row);
+            if let Some(event) = self.event_buffer.pop_front() {
+                result.push(event);
             } else {
                 break;
             }
@@ -211,110 +814,1551 @@ impl CoreHandle {
     pub async fn tick(&mut self) -> Result<()> {
         // Relay再接続チェック
         for relay in &mut self.relays {
+            relay.poll();
             if relay.needs_reconnect() {
                 let _ = relay.connect().await;
             }
+
+            // 接続が（再）確立したRelayにはOK待ちのイベントを再送
+            // （購読はRelayConnectionが接続時に再送済み）
+            if relay.take_opened() && relay.is_write() {
+                for event in self.outbox.unacknowledged_for(&relay.url) {
+                    let msg = format!(r#"["EVENT",{}]"#, event.to_json());
+                    if let Err(e) = relay.send(&msg).await {
+                        log::error!("Failed to resend to relay {}: {:?}", relay.url, e);
+                    } else {
+                        self.outbox.mark_sent(&event.id, &relay.url).await?;
+                    }
+                }
+            }
+        }
+
+        // 拒否またはタイムアウトしたRelayにだけ再送
+        for (url, event) in self.outbox.due_retries() {
+            let Some(relay) = self.relays.iter_mut().find(|r| r.url == url) else {
+                continue;
+            };
+            log::info!("Retrying event {} on {}", event.id, url);
+            if let Err(e) = relay.send_or_queue(&format!(r#"["EVENT",{}]"#, event.to_json())).await {
+                log::error!("Failed to retry on relay {}: {:?}", url, e);
+            } else {
+                self.outbox.mark_sent(&event.id, &url).await?;
+            }
+        }
+
+        // 保存方針の適用（保存に失敗したあとはすぐ）
+        if self.clock.now() >= self.next_retention_at {
+            self.next_retention_at = self.clock.now() + RETENTION_INTERVAL_SECS;
+            if let Err(e) = self.enforce_retention().await {
+                log::error!("tick: Error enforcing retention: {:?}", e);
+            }
         }
 
         // 受信メッセージ処理
         let mut all_messages = Vec::new();
         for relay in &mut self.relays {
-            all_messages.extend(relay.drain_messages());
+            let url = relay.url.clone();
+            all_messages.extend(relay.drain_messages().into_iter().map(|msg| (url.clone(), msg)));
         }
-        for msg in all_messages {
-            self.process_relay_message(msg).await
-                .map_err(|e| {
-                    log::error!("tick: Error processing relay message: {:?}", e);
-                    e
-                })?;
+        // 1件の失敗で残りのメッセージや送信を止めない
+        for (url, msg) in all_messages {
+            if let Err(e) = self.process_relay_message(&url, msg).await {
+                log::error!("tick: Error processing relay message: {:?}", e);
+                self.event_buffer.push_back(CoreEvent::Error(e.to_string()));
+            }
+        }
+
+        // 検証に失敗し続けるRelayは読み込みから外す
+        let failing: Vec<String> = self.relays
+            .iter()
+            .filter(|r| r.invalid_event_count() >= MAX_INVALID_EVENTS && !self.demoted.contains(&r.url))
+            .map(|r| r.url.clone())
+            .collect();
+        for url in failing {
+            self.demote_relay(&url).await?;
         }
 
         // Outbox処理（送信キューからイベントを取り出して送信）
         match self.outbox.dequeue().await {
-            Ok(Some(event_json)) => {
-                let msg = format!(r#"["EVENT",{}]"#, event_json);
+            Ok(Some(event)) => {
+                let msg = format!(r#"["EVENT",{}]"#, event.to_json());
                 log::info!("Sending EVENT to relays: {}", msg);
                 
-                for relay in &self.relays {
+                // 未接続のRelayには接続時に送る
+                for relay in self.relays.iter().filter(|r| r.is_write() && r.is_connected()) {
                     if let Err(e) = relay.send(&msg).await {
                         log::error!("Failed to send to relay {}: {:?}", relay.url, e);
                     } else {
                         log::info!("Sent to relay: {}", relay.url);
+                        self.outbox.mark_sent(&event.id, &relay.url).await?;
                     }
                 }
+                self.send_to_inboxes(&event, None).await;
             }
             Ok(None) => {
                 // キューが空の場合は何もしない
             }
             Err(e) => {
                 log::error!("tick: Error in outbox.dequeue(): {:?}", e);
+                self.event_buffer.push_back(CoreEvent::Error(e.to_string()));
                 return Err(e);
             }
         }
 
+        self.emit_state_changes();
+        Ok(())
+    }
+
+    /// Relayの接続状態と配送状況の変化を通知する
+    fn emit_state_changes(&mut self) {
+        for relay in &self.relays {
+            let state = relay.state();
+            if self.relay_states.insert(relay.url.clone(), state) != Some(state) {
+                let reason = match state {
+                    ConnectionState::Disconnected => relay.last_disconnect_reason().cloned(),
+                    _ => None,
+                };
+                self.event_buffer.push_back(CoreEvent::RelayStateChanged { url: relay.url.clone(), state, reason });
+            }
+        }
+        self.relay_states.retain(|url, _| self.relays.iter().any(|r| &r.url == url));
+
+        let deliveries = self.outbox.deliveries();
+        for (event_id, summary) in &deliveries {
+            if self.delivery_states.get(event_id) != Some(summary) {
+                self.event_buffer.push_back(CoreEvent::OutboxUpdated {
+                    event_id: event_id.clone(),
+                    summary: summary.clone(),
+                });
+            }
+        }
+        self.delivery_states = deliveries.into_iter().collect();
+    }
+
+    /// 過去ログ取得で得た行を会話に加え、終了を通知する
+    fn on_history_finished(&mut self, finished: Option<FinishedHistory>) {
+        let Some(FinishedHistory { scope, state, rows }) = finished else {
+            return;
+        };
+        for row in rows {
+            if self.conversations.insert(row.clone()) {
+                self.event_buffer.push_back(CoreEvent::EventReceived(row));
+            }
+        }
+        if state.reached_start {
+            self.event_buffer.push_back(CoreEvent::HistoryExhausted(scope.clone()));
+        }
+        self.event_buffer.push_back(CoreEvent::HistoryLoaded(scope));
+    }
+
+    /// Relayの認証ポリシーを設定
+    pub async fn set_auth_policy(&mut self, url: &str, policy: AuthPolicy) -> Result<()> {
+        let relay = self.relay_mut(url)
+            .ok_or_else(|| CoreError::RelayError(format!("Unknown relay: {}", url)))?;
+        relay.set_auth_policy(policy);
+        let config = relay.config();
+        self.storage.upsert_relay(&config).await
+    }
+
+    /// ユーザーの確認待ちのAUTHチャレンジがあるRelay一覧
+    pub fn pending_auth_requests(&self) -> Vec<String> {
+        self.relays
+            .iter()
+            .filter(|r| r.auth_policy() == AuthPolicy::Ask && r.auth_challenge().is_some())
+            .map(|r| r.url.clone())
+            .collect()
+    }
+
+    /// 確認待ちのAUTHを承認して認証する
+    pub async fn approve_auth(&mut self, url: &str) -> Result<()> {
+        self.authenticate(url).await
+    }
+
+    /// 確認待ちのAUTHを拒否（認証待ちのイベントはこのRelayには送らない）
+    pub async fn deny_auth(&mut self, url: &str) -> Result<()> {
+        if let Some(relay) = self.relay_mut(url) {
+            relay.deny_auth();
+        }
+        self.outbox.on_auth_result(url, false, "auth-required: denied by user").await
+    }
+
+    /// NIP-42: kind 22242 のイベントに署名してAUTHを送る
+    async fn authenticate(&mut self, url: &str) -> Result<()> {
+        let signer = self.signer.clone()
+            .ok_or_else(|| CoreError::Other("No signer available".to_string()))?;
+        let challenge = self.relay_mut(url)
+            .and_then(|r| r.auth_challenge().map(|c| c.to_string()))
+            .ok_or_else(|| CoreError::RelayError(format!("No AUTH challenge from {}", url)))?;
+
+        let unsigned_event = crate::signer::UnsignedEvent {
+            kind: 22242,
+            content: String::new(),
+            tags: vec![
+                vec!["relay".to_string(), url.to_string()],
+                vec!["challenge".to_string(), challenge],
+            ],
+            created_at: self.clock.now(),
+        };
+        let signed_event = signer.sign_event(unsigned_event).await?;
+
+        if let Some(relay) = self.relay_mut(url) {
+            log::info!("Sending AUTH to {}", url);
+            relay.send_auth(&signed_event).await?;
+        }
+        Ok(())
+    }
+
+    /// AUTHチャレンジを受け取った
+    async fn on_auth_challenge(&mut self, url: &str, challenge: String) -> Result<()> {
+        let Some(relay) = self.relay_mut(url) else {
+            return Ok(());
+        };
+        relay.set_auth_challenge(challenge);
+        match relay.auth_policy() {
+            AuthPolicy::Always => self.authenticate(url).await?,
+            AuthPolicy::Ask => {
+                log::info!("AUTH requested by {}, waiting for user approval", url);
+                self.event_buffer.push_back(CoreEvent::AuthRequested { url: url.to_string() });
+            }
+            AuthPolicy::Never => log::info!("Ignoring AUTH request from {}", url),
+        }
+        Ok(())
+    }
+
+    /// 購読を読み込み先のRelayに送る（同じフィルターで購読済みのRelayは除く）。送り先を返す
+    async fn subscribe_routed(&mut self, sub_id: &str, filter: &Filter) -> Vec<String> {
+        let self_pubkey = self.get_public_key().await.ok().flatten();
+        let targets = self.read_targets(filter, self_pubkey.as_deref());
+        for url in &targets {
+            let index = self.ensure_relay(url).await;
+            let relay = &mut self.relays[index];
+            if relay.subscriptions().get(sub_id) == Some(filter) {
+                continue;
+            }
+            let _ = relay.subscribe(sub_id, filter).await;
+        }
+        targets
+    }
+
+    /// 購読の送り先
+    ///
+    /// `authors`があれば各著者の書き込み用Relay、Relayリストが不明な著者がいれば自分の読み込み用Relayも使う。
+    fn read_targets(&self, filter: &Filter, self_pubkey: Option<&str>) -> Vec<String> {
+        let own_read: Vec<String> = self.relays.iter().filter(|r| r.is_read()).map(|r| r.url.clone()).collect();
+        let own_write: Vec<String> = self.relays.iter().filter(|r| r.is_write()).map(|r| r.url.clone()).collect();
+
+        let authors = filter.authors.clone().unwrap_or_default();
+
+        let mut targets = Vec::new();
+        let mut fallback = authors.is_empty();
+        for author in &authors {
+            let urls = if Some(author.as_str()) == self_pubkey {
+                Some(own_write.clone())
+            } else {
+                self.relay_lists.write_relays(author)
+            };
+            match urls {
+                Some(urls) if !urls.is_empty() => push_unique(&mut targets, urls),
+                _ => fallback = true,
+            }
+        }
+        if fallback {
+            push_unique(&mut targets, own_read);
+        }
+        targets.retain(|url| !self.demoted.contains(url));
+        targets
+    }
+
+    /// DMを受信者の読み込み用Relayに送る（自分の書き込み用Relayには送信済み）
+    async fn send_to_inboxes(&mut self, event: &NostrEvent, only_to: Option<&str>) {
+        if event.kind != 4 {
+            return;
+        }
+
+        let recipients: Vec<String> = event
+            .tag_values("p")
+            .filter(|p| only_to.is_none_or(|only| only == *p))
+            .map(String::from)
+            .collect();
+
+        let msg = format!(r#"["EVENT",{}]"#, event.to_json());
+        let event_id = event.id.as_str();
+        for recipient in recipients {
+            for url in self.relay_lists.read_relays(&recipient).unwrap_or_default() {
+                let index = self.ensure_relay(&url).await;
+                let relay = &mut self.relays[index];
+                if relay.is_write() {
+                    continue;
+                }
+                if let Err(e) = relay.send_or_queue(&msg).await {
+                    log::error!("Failed to send DM to inbox {}: {:?}", url, e);
+                } else if let Err(e) = self.outbox.mark_sent(event_id, &url).await {
+                    log::error!("Failed to record delivery to {}: {:?}", url, e);
+                }
+            }
+        }
+    }
+
+    /// Relayリストを受け取った
+    async fn on_relay_list(&mut self, event: &NostrEvent) {
+        let pubkey = event.pubkey.as_str();
+        let Some(list) = RelayList::from_event(event) else {
+            return;
+        };
+        if !self.relay_lists.update(pubkey, list) {
+            return;
+        }
+        log::info!("Relay list updated for {}", pubkey);
+
+        // この著者の購読を書き込み用Relayにも送る
+        let subs: Vec<(String, Filter)> = self.sub_mgr.get_active_subs()
+            .into_iter()
+            .filter(|sub| sub.filter.authors.as_ref().is_some_and(|a| a.iter().any(|x| x == pubkey)))
+            .map(|sub| (sub.sub_id.clone(), sub.filter.clone()))
+            .collect();
+        for (sub_id, filter) in subs {
+            self.subscribe_routed(&sub_id, &filter).await;
+        }
+
+        // OK待ちのDMを受信者の読み込み用Relayに送る
+        for event in self.outbox.unacknowledged() {
+            self.send_to_inboxes(&event, Some(pubkey)).await;
+        }
+    }
+
+    /// Relayを読み込みから外し、購読を終了する（Relay設定は変えない）
+    async fn demote_relay(&mut self, url: &str) -> Result<()> {
+        log::warn!("Demoting relay {} after repeated invalid events", url);
+        self.demoted.insert(url.to_string());
+        self.event_buffer.push_back(CoreEvent::RelayDemoted { url: url.to_string() });
+        if let Some(relay) = self.relay_mut(url) {
+            relay.unsubscribe_all().await?;
+        }
         Ok(())
     }
 
+    /// Relayを探し、なければ一時的なRelayとして接続する
+    async fn ensure_relay(&mut self, url: &str) -> usize {
+        if let Some(index) = self.relays.iter().position(|r| r.url == url) {
+            return index;
+        }
+        let mut relay = RelayConnection::new(url.to_string(), self.transport.clone(), self.clock.clone());
+        relay.set_policy(false, false);
+        if let Err(e) = relay.connect().await {
+            log::error!("Failed to connect to {}: {:?}", url, e);
+        }
+        self.discovered.insert(url.to_string());
+        self.relays.push(relay);
+        self.relays.len() - 1
+    }
+
+    /// URLからRelayを探す
+    fn relay_mut(&mut self, url: &str) -> Option<&mut RelayConnection> {
+        self.relays.iter_mut().find(|r| r.url == url)
+    }
+
     /// Relayメッセージを処理
-    async fn process_relay_message(&mut self, msg: RelayMessage) -> Result<()> {
+    async fn process_relay_message(&mut self, url: &str, msg: RelayMessage) -> Result<()> {
         match msg {
-            RelayMessage::Event { sub_id: _, event_json } => {
-                // イベントをパース
-                let event: serde_json::Value = serde_json::from_str(&event_json)
-                    .map_err(|e| CoreError::ParseError(e.to_string()))?;
-                
-                // ストレージに保存
-                let event_id = event["id"].as_str().unwrap_or("");
-                self.storage.save_event(event_id, &event_json).await?;
-                
-                // UIバッファに追加
-                let kind = event["kind"].as_u64().unwrap_or(0) as u16;
-                let content = event["content"].as_str().unwrap_or("").to_string();
-                let created_at = event["created_at"].as_i64().unwrap_or(0);
-                let pubkey = event["pubkey"].as_str().unwrap_or("").to_string();
-                
-                let ui_row = UiRow {
-                    id: event_id.to_string(),
-                    kind,
-                    pubkey,
-                    created_at,
-                    content,
-                    image_url: None,
+            RelayMessage::Event { sub_id, event } => {
+                let event_id = event.id.as_str();
+
+                // 同じイベントは一度だけ検証・処理し、2回目以降は受信したRelayだけ記録する
+                if self.seen.contains(event_id) {
+                    // 検証済みのコピーと違えば検証する（IDだけ真似たRelayを受信元として記録しない）
+                    if !self.seen.matches(&event) {
+                        if let Err(e) = verify_event(&event) {
+                            if let Some(relay) = self.relay_mut(url) {
+                                relay.record_invalid_event();
+                            }
+                            log::warn!("Dropping forged copy of {} from {}: {}", event_id, url, e);
+                            return Ok(());
+                        }
+                    }
+                    if let Some(relay) = self.relay_mut(url) {
+                        relay.record_event_time(&sub_id, event.created_at);
+                    }
+                    if self.seen.add_relay(event_id, url) {
+                        self.storage.add_seen_on(event_id, url).await?;
+                    }
+                    return Ok(());
+                }
+
+                // 検証できないイベントは保存も表示もしない
+                let verified = verify_event(&event);
+                if let Some(relay) = self.relay_mut(url) {
+                    match &verified {
+                        Ok(()) => relay.record_event_time(&sub_id, event.created_at),
+                        Err(_) => relay.record_invalid_event(),
+                    }
+                }
+                if let Err(e) = verified {
+                    log::warn!("Dropping event from {}: {}", url, e);
+                    return Ok(());
+                }
+
+                // キャッシュから外れていてもStorageにあれば保存し直さない
+                let mut seen_on = match self.storage.get_event(event_id).await? {
+                    Some(stored) => stored.seen_on,
+                    None => match self.storage.save_event(&event).await {
+                        Ok(()) => {
+                            self.count_stored(&event).await;
+                            Vec::new()
+                        }
+                        Err(e) => {
+                            // 表示は続ける。容量不足かもしれないので、次のtickで保存方針を適用する
+                            log::error!("Failed to save event {} from {}: {:?}", event_id, url, e);
+                            self.next_retention_at = 0;
+                            // 保存されていないので受信Relayは記録しない
+                            vec![url.to_string()]
+                        }
+                    },
                 };
-                
-                self.event_buffer.push_back(ui_row);
+                if !seen_on.iter().any(|r| r == url) {
+                    seen_on.push(url.to_string());
+                    self.storage.add_seen_on(event_id, url).await?;
+                }
+                self.seen.insert(&event, seen_on);
+
+                // NIP-65 のRelayリストはルーティングに使い、UIには流さない
+                if event.kind == RELAY_LIST_KIND {
+                    self.on_relay_list(&event).await;
+                    return Ok(());
+                }
+
+                // 購読IDから会話を決め、分からなければイベントのタグから求める
+                let scope = match self.sub_mgr.scope_of(&sub_id) {
+                    Some(scope) => Some(scope),
+                    None => {
+                        let self_pubkey = self.get_public_key().await.ok().flatten();
+                        event_scope(&event, self_pubkey.as_deref())
+                    }
+                };
+                let ui_row = UiRow::from_event(&event, scope);
+
+                if self.sub_mgr.is_history(&sub_id) {
+                    // 過去ログは取得が終わってから新しい分だけ会話に加える
+                    if let Some(scope) = &ui_row.scope {
+                        if !self.conversations.contains(scope, &ui_row.id) {
+                            self.sub_mgr.on_history_event(&sub_id, ui_row);
+                        }
+                    }
+                } else if ui_row.scope.is_none() || self.conversations.insert(ui_row.clone()) {
+                    // 会話に既にあるもの（Storageから読み込み済み）は流さない
+                    self.event_buffer.push_back(CoreEvent::EventReceived(ui_row));
+                }
+            }
+            RelayMessage::Eose { sub_id } if self.sub_mgr.is_history(&sub_id) => {
+                // 過去ログは1回分ずつ取得する
+                if let Some(relay) = self.relay_mut(url) {
+                    relay.unsubscribe(&sub_id).await?;
+                }
+                let finished = self.sub_mgr.on_history_done(&sub_id, url);
+                self.on_history_finished(finished);
+            }
+            RelayMessage::Eose { sub_id } if sub_id.starts_with("relay_list_") => {
+                // Relayリストの取得は一度きり
+                if let Some(relay) = self.relay_mut(url) {
+                    relay.unsubscribe(&sub_id).await?;
+                }
             }
             RelayMessage::Eose { sub_id } => {
                 self.sub_mgr.mark_eose(&sub_id);
-                
-                // ウィンドウ拡張が必要か確認
-                if self.sub_mgr.needs_extension(&sub_id) {
-                    if let Some(filters) = self.sub_mgr.extend_window(&sub_id) {
-                        for (new_sub_id, filter_json) in filters {
-                            let req = format!(r#"["REQ","{}",{}]"#, new_sub_id, filter_json);
-                            for relay in &self.relays {
-                                let _ = relay.send(&req).await;
-                            }
-                        }
-                    }
+                if let Some(scope) = self.sub_mgr.scope_of(&sub_id) {
+                    self.event_buffer.push_back(CoreEvent::EndOfStoredEvents { url: url.to_string(), scope });
                 }
             }
             RelayMessage::Ok { event_id, accepted, message } => {
-                if accepted {
-                    log::info!("Event {} accepted", event_id);
-                    // Outboxから削除
-                    self.outbox.on_ok(&event_id, true, "").await?;
-                } else {
-                    log::warn!("Event {} rejected: {}", event_id, message);
-                    // エラーステータスに変更
-                    self.outbox.on_ok(&event_id, false, &message).await?;
+                // AUTHへの応答（認証待ちだったイベントは再送されるのでOK待ちに戻す）
+                if let Some(relay) = self.relay_mut(url).filter(|r| r.is_auth_event(&event_id)) {
+                    relay.on_auth_result(accepted, &message).await;
+                    return self.outbox.on_auth_result(url, accepted, &message.message).await;
                 }
+
+                if !accepted && message.is(ReasonPrefix::AuthRequired) {
+                    // 認証後に再送する（それまではタイムアウトさせない）
+                    log::info!("Event {} requires AUTH on {}", event_id, url);
+                    if let Some(event) = self.outbox.find_event(&event_id) {
+                        if let Some(relay) = self.relay_mut(url) {
+                            relay.queue_auth_retry(format!(r#"["EVENT",{}]"#, event.to_json()));
+                        }
+                        self.outbox.await_auth(&event_id, url).await?;
+                    }
+                    return Ok(());
+                }
+
+                // Relayごとの配送記録を更新（必要数のRelayに受理されたら配送済み）
+                self.outbox.on_ok(&event_id, url, accepted, &message.message).await?;
             }
             RelayMessage::Notice { message } => {
                 log::info!("Relay notice: {}", message);
+                self.event_buffer.push_back(CoreEvent::Notice { url: url.to_string(), message });
+            }
+            RelayMessage::Auth { challenge } => {
+                self.on_auth_challenge(url, challenge).await?;
+            }
+            RelayMessage::Closed { sub_id, message } => {
+                log::info!("Subscription {} closed by {}: {}", sub_id, url, message);
+                let history = self.sub_mgr.is_history(&sub_id);
+                let Some(relay) = self.relay_mut(url) else {
+                    return Ok(());
+                };
+                match message.prefix {
+                    // 認証後に再送
+                    Some(ReasonPrefix::AuthRequired) => relay.queue_auth_retry_subscription(&sub_id),
+                    // 一時的な理由ならバックオフ後に再送する（過去ログ取得は打ち切る）
+                    Some(ReasonPrefix::RateLimited) | Some(ReasonPrefix::Error) if !history => {
+                        relay.retry_subscription_later(&sub_id)
+                    }
+                    _ => {
+                        relay.remove_subscription(&sub_id);
+                        self.sub_mgr.on_closed(&sub_id, url);
+                        let finished = self.sub_mgr.on_history_done(&sub_id, url);
+                        self.on_history_finished(finished);
+                    }
+                }
+            }
+            RelayMessage::Count { sub_id, count, approximate } => {
+                log::info!("COUNT {} from {}: {} (approximate: {})", sub_id, url, count, approximate);
+                // 複数Relayの結果は最大値を採用
+                let previous = self.counts.get(&sub_id).copied();
+                let count = previous.map_or(count, |p| p.max(count));
+                if previous != Some(count) {
+                    self.counts.insert(sub_id.clone(), count);
+                    self.event_buffer.push_back(CoreEvent::CountUpdated { sub_id, count });
+                }
             }
         }
         Ok(())
     }
 }
 
+
+/// イベントのタグから会話を求める
+fn event_scope(event: &NostrEvent, self_pubkey: Option<&str>) -> Option<Scope> {
+    match event.kind {
+        // NIP-28: rootマーカー付きのeタグ、なければ最初のeタグがチャンネル
+        42 => event.channel_root().map(|id| Scope::Channel(id.to_string())),
+        // NIP-04: 自分が送ったものはpタグの相手、受け取ったものは送信者
+        4 => {
+            if Some(event.pubkey.as_str()) == self_pubkey {
+                event.tag_values("p").next().map(|peer| Scope::Dm(peer.to_string()))
+            } else {
+                Some(Scope::Dm(event.pubkey.clone()))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// 重複を除いて追加
+fn push_unique(targets: &mut Vec<String>, urls: Vec<String>) {
+    for url in urls {
+        if !targets.contains(&url) {
+            targets.push(url);
+        }
+    }
+}
+
+/// Relay URLを検証して正規化（末尾のスラッシュを除去）
+fn normalize_relay_url(url: &str) -> Result<String> {
+    let url = url.trim().trim_end_matches('/');
+    let valid = ["wss://", "ws://"]
+        .iter()
+        .any(|scheme| url.len() > scheme.len() && url.starts_with(scheme));
+    if !valid || url.contains(char::is_whitespace) {
+        return Err(CoreError::RelayError(format!("Invalid relay URL: {}", url)));
+    }
+    Ok(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::signer::internal::InternalSigner;
+    use crate::storage::mock::MockStorage;
+    use crate::supervisor::DisconnectReason;
+    use crate::transport::mock::MockTransport;
+    use crate::types::{DeliveryStatus, StorageUsage};
+    use crate::verify::signed_event_json;
+
+    const URL: &str = "wss://relay.example";
+
+    /// 接続済みのCoreHandleを作る
+    async fn connected_core(transport: &MockTransport) -> CoreHandle {
+        connected_core_with_clock(transport).await.0
+    }
+
+    /// 接続済みのCoreHandleと、その時計を作る
+    async fn connected_core_with_clock(transport: &MockTransport) -> (CoreHandle, Arc<MockClock>) {
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let storage = Arc::new(MockStorage::new());
+        let mut core = CoreHandle::init_with(vec![URL.to_string()], storage, Arc::new(transport.clone()), clock.clone())
+            .await
+            .unwrap();
+        core.set_signer(Arc::new(InternalSigner::generate("").await.unwrap()));
+        core.connect_all().await.unwrap();
+        transport.open(URL);
+        core.tick().await.unwrap();
+        (core, clock)
+    }
+
+    /// 通知から受信したイベントだけを取り出す
+    fn received_rows(core: &mut CoreHandle) -> Vec<UiRow> {
+        core.poll_events(100)
+            .into_iter()
+            .filter_map(|event| match event {
+                CoreEvent::EventReceived(row) => Some(row),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// 送信済みメッセージから指定タイプのものを取り出す
+    fn sent_of_type(sent: &[String], msg_type: &str) -> Vec<Vec<serde_json::Value>> {
+        sent.iter()
+            .map(|m| serde_json::from_str::<Vec<serde_json::Value>>(m).unwrap())
+            .filter(|m| m[0] == msg_type)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_core_handle_runs_natively() {
+        let transport = MockTransport::new();
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let storage = Arc::new(MockStorage::new());
+        let mut core = CoreHandle::init_with(vec![URL.to_string()], storage, Arc::new(transport.clone()), clock)
+            .await
+            .unwrap();
+
+        core.connect_all().await.unwrap();
+        transport.open(URL);
+        core.tick().await.unwrap();
+
+        core.open_channel("chan").await.unwrap();
+        let sent = transport.take_sent(URL);
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].starts_with(r#"["REQ","channel_chan","#));
+
+        let event = signed_event_json(&nostr::Keys::generate(), 42, &[&["e", "chan"]], "hi", 1_700_000_000);
+        transport.receive(URL, &format!(r#"["EVENT","channel_chan",{}]"#, event));
+        core.tick().await.unwrap();
+
+        let rows = received_rows(&mut core);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].content, "hi");
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_replays_subscriptions_and_outbox() {
+        let transport = MockTransport::new();
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let storage = Arc::new(MockStorage::new());
+        let mut core = CoreHandle::init_with(vec![URL.to_string()], storage, Arc::new(transport.clone()), clock.clone())
+            .await
+            .unwrap();
+
+        core.connect_all().await.unwrap();
+        core.open_channel("chan").await.unwrap();
+        transport.open(URL);
+        core.tick().await.unwrap();
+        assert_eq!(transport.take_sent(URL).len(), 1);
+
+        // OK待ちのイベント
+        let event = NostrEvent::from_json(&signed_event_json(&nostr::Keys::generate(), 42, &[], "hi", 0)).unwrap();
+        let message = format!(r#"["EVENT",{}]"#, event.to_json());
+        core.outbox.enqueue(event).await.unwrap();
+        core.tick().await.unwrap();
+        assert_eq!(transport.take_sent(URL), vec![message.clone()]);
+
+        // 切断 -> バックオフ経過後に再接続
+        transport.close(URL);
+        core.tick().await.unwrap();
+        clock.advance(2);
+        core.tick().await.unwrap();
+        assert_eq!(transport.connect_count(URL), 2);
+        transport.open(URL);
+        core.tick().await.unwrap();
+
+        let sent = transport.take_sent(URL);
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].starts_with(r#"["REQ","channel_chan","#));
+        assert_eq!(sent[1], message);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_subscription_is_resent_after_backoff() {
+        let transport = MockTransport::new();
+        let (mut core, clock) = connected_core_with_clock(&transport).await;
+        core.open_channel("chan").await.unwrap();
+        transport.take_sent(URL);
+
+        transport.receive(URL, r#"["CLOSED","channel_chan","rate-limited: slow down"]"#);
+        core.tick().await.unwrap();
+        assert!(sent_of_type(&transport.take_sent(URL), "REQ").is_empty());
+
+        // 1秒後に再送し、また終了されたら次は2秒待つ
+        clock.advance(1);
+        core.tick().await.unwrap();
+        let reqs = sent_of_type(&transport.take_sent(URL), "REQ");
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0][1], "channel_chan");
+
+        transport.receive(URL, r#"["CLOSED","channel_chan","error: busy"]"#);
+        core.tick().await.unwrap();
+        clock.advance(1);
+        core.tick().await.unwrap();
+        assert!(sent_of_type(&transport.take_sent(URL), "REQ").is_empty());
+        clock.advance(1);
+        core.tick().await.unwrap();
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "REQ").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_auth_always_signs_and_retries_closed_req() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        core.set_auth_policy(URL, AuthPolicy::Always).await.unwrap();
+        core.open_channel("chan").await.unwrap();
+        transport.take_sent(URL);
+
+        transport.receive(URL, r#"["AUTH","challenge-123"]"#);
+        transport.receive(URL, r#"["CLOSED","channel_chan","auth-required: members only"]"#);
+        core.tick().await.unwrap();
+
+        let sent = transport.take_sent(URL);
+        let auth = sent_of_type(&sent, "AUTH");
+        assert_eq!(auth.len(), 1);
+        let event = &auth[0][1];
+        assert_eq!(event["kind"], 22242);
+        let tags = event["tags"].as_array().unwrap();
+        assert!(tags.contains(&serde_json::json!(["relay", URL])));
+        assert!(tags.contains(&serde_json::json!(["challenge", "challenge-123"])));
+
+        // AUTH成功後に購読を再送
+        let auth_id = event["id"].as_str().unwrap();
+        transport.receive(URL, &format!(r#"["OK","{}",true,""]"#, auth_id));
+        core.tick().await.unwrap();
+        let reqs = sent_of_type(&transport.take_sent(URL), "REQ");
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0][1], "channel_chan");
+    }
+
+    #[tokio::test]
+    async fn test_auth_result_only_counts_from_the_challenging_relay() {
+        const SECOND: &str = "wss://second.example";
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        core.add_relay(SECOND).await.unwrap();
+        transport.open(SECOND);
+        core.tick().await.unwrap();
+        core.set_auth_policy(URL, AuthPolicy::Always).await.unwrap();
+        transport.take_sent(URL);
+
+        transport.receive(URL, r#"["AUTH","c1"]"#);
+        core.tick().await.unwrap();
+        let auth = sent_of_type(&transport.take_sent(URL), "AUTH");
+        let auth_id = auth[0][1]["id"].as_str().unwrap();
+
+        // 別のRelayが同じIDのOKを返しても認証済みにしない
+        transport.receive(SECOND, &format!(r#"["OK","{}",true,""]"#, auth_id));
+        core.tick().await.unwrap();
+        let relay = |core: &CoreHandle| core.relays.iter().find(|r| r.url == URL).unwrap().is_authenticated();
+        assert!(!relay(&core));
+
+        transport.receive(URL, &format!(r#"["OK","{}",true,""]"#, auth_id));
+        core.tick().await.unwrap();
+        assert!(relay(&core));
+    }
+
+    #[tokio::test]
+    async fn test_auth_ask_waits_for_approval() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+
+        transport.receive(URL, r#"["AUTH","c1"]"#);
+        core.tick().await.unwrap();
+        assert_eq!(core.pending_auth_requests(), vec![URL.to_string()]);
+        assert!(sent_of_type(&transport.take_sent(URL), "AUTH").is_empty());
+
+        core.approve_auth(URL).await.unwrap();
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "AUTH").len(), 1);
+
+        transport.receive(URL, r#"["AUTH","c2"]"#);
+        core.tick().await.unwrap();
+        core.deny_auth(URL).await.unwrap();
+        assert!(core.pending_auth_requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_auth_never_ignores_challenge() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        core.set_auth_policy(URL, AuthPolicy::Never).await.unwrap();
+
+        transport.receive(URL, r#"["AUTH","c1"]"#);
+        core.tick().await.unwrap();
+        assert!(core.pending_auth_requests().is_empty());
+        assert!(sent_of_type(&transport.take_sent(URL), "AUTH").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_auth_required_event_is_resent_after_auth() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        core.set_auth_policy(URL, AuthPolicy::Always).await.unwrap();
+
+        let event_id = core.send_public("chan", "hello").await.unwrap();
+        core.tick().await.unwrap();
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "EVENT").len(), 1);
+
+        transport.receive(URL, &format!(r#"["OK","{}",false,"auth-required: sign in first"]"#, event_id));
+        transport.receive(URL, r#"["AUTH","c1"]"#);
+        core.tick().await.unwrap();
+        let sent = transport.take_sent(URL);
+        let auth_id = sent_of_type(&sent, "AUTH")[0][1]["id"].as_str().unwrap().to_string();
+
+        transport.receive(URL, &format!(r#"["OK","{}",true,""]"#, auth_id));
+        core.tick().await.unwrap();
+        let events = sent_of_type(&transport.take_sent(URL), "EVENT");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0][1]["id"], event_id.as_str());
+        assert_eq!(core.outbox.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_auth_required_event_waits_for_slow_approval() {
+        let transport = MockTransport::new();
+        let (mut core, clock) = connected_core_with_clock(&transport).await;
+
+        let event_id = core.send_public("chan", "hello").await.unwrap();
+        core.tick().await.unwrap();
+        transport.take_sent(URL);
+        transport.receive(URL, &format!(r#"["OK","{}",false,"auth-required: sign in first"]"#, event_id));
+        transport.receive(URL, r#"["AUTH","c1"]"#);
+        core.tick().await.unwrap();
+
+        // 承認を待つ間はタイムアウトも再送もしない
+        for _ in 0..10 {
+            clock.advance(30);
+            transport.receive(URL, &format!(r#"["EOSE","{}"]"#, crate::supervisor::PING_SUB_ID));
+            core.tick().await.unwrap();
+        }
+        assert!(sent_of_type(&transport.take_sent(URL), "EVENT").is_empty());
+        assert_eq!(core.delivery(&event_id).unwrap().receipts[0].status, DeliveryStatus::AwaitingAuth);
+
+        core.approve_auth(URL).await.unwrap();
+        let auth_id = sent_of_type(&transport.take_sent(URL), "AUTH")[0][1]["id"].as_str().unwrap().to_string();
+        transport.receive(URL, &format!(r#"["OK","{}",true,""]"#, auth_id));
+        core.tick().await.unwrap();
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "EVENT").len(), 1);
+        let receipt = &core.delivery(&event_id).unwrap().receipts[0];
+        assert_eq!((&receipt.status, receipt.attempts), (&DeliveryStatus::Pending, 2));
+    }
+
+    #[tokio::test]
+    async fn test_closed_drops_subscription_unless_transient() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        core.open_channel("a").await.unwrap();
+        core.open_channel("b").await.unwrap();
+
+        transport.receive(URL, r#"["CLOSED","channel_a","blocked: not allowed"]"#);
+        transport.receive(URL, r#"["CLOSED","channel_b","rate-limited: slow down"]"#);
+        core.tick().await.unwrap();
+
+        assert_eq!(core.sub_mgr.closed_relays("channel_a"), [URL.to_string()]);
+        assert!(core.sub_mgr.closed_relays("channel_b").is_empty());
+        let relay = &core.relays[0];
+        assert!(!relay.subscriptions().contains_key("channel_a"));
+        assert!(relay.subscriptions().contains_key("channel_b"));
+    }
+
+    #[tokio::test]
+    async fn test_count_request_and_result() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+
+        let sub_id = core.request_count(&Filter::new().kinds([42])).await.unwrap();
+        let sent = sent_of_type(&transport.take_sent(URL), "COUNT");
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0][1], sub_id.as_str());
+        assert_eq!(core.count_result(&sub_id), None);
+
+        transport.receive(URL, &format!(r#"["COUNT","{}",{{"count":7}}]"#, sub_id));
+        core.tick().await.unwrap();
+        assert_eq!(core.count_result(&sub_id), Some(7));
+    }
+
+    #[tokio::test]
+    async fn test_relay_list_is_persisted_and_restored() {
+        let transport = MockTransport::new();
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let storage = Arc::new(MockStorage::new());
+        let mut core = CoreHandle::init_with(vec![URL.to_string()], storage.clone(), Arc::new(transport.clone()), clock.clone())
+            .await
+            .unwrap();
+
+        assert!(core.add_relay("ftp://bad").await.is_err());
+        assert!(core.add_relay(URL).await.is_err());
+        core.add_relay("wss://second.example/").await.unwrap();
+        core.set_relay_policy("wss://second.example", true, false).await.unwrap();
+        core.set_auth_policy("wss://second.example", AuthPolicy::Always).await.unwrap();
+        core.remove_relay(URL).await.unwrap();
+
+        // 保存済みの一覧があれば初期値は使わない
+        let restored = CoreHandle::init_with(vec![URL.to_string()], storage, Arc::new(transport), clock)
+            .await
+            .unwrap();
+        let configs = restored.relays();
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0].url, "wss://second.example");
+        assert!(configs[0].read);
+        assert!(!configs[0].write);
+        assert_eq!(configs[0].auth, AuthPolicy::Always);
+    }
+
+    #[tokio::test]
+    async fn test_read_write_flags_route_messages() {
+        const WRITE_ONLY: &str = "wss://write.example";
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        core.open_channel("chan").await.unwrap();
+
+        // 追加したRelayには既存の購読が送られる
+        core.add_relay(WRITE_ONLY).await.unwrap();
+        transport.open(WRITE_ONLY);
+        core.tick().await.unwrap();
+        assert_eq!(sent_of_type(&transport.take_sent(WRITE_ONLY), "REQ").len(), 1);
+
+        core.set_relay_policy(WRITE_ONLY, false, true).await.unwrap();
+        core.set_relay_policy(URL, true, false).await.unwrap();
+        transport.take_sent(URL);
+
+        core.open_channel("other").await.unwrap();
+        core.send_public("chan", "hello").await.unwrap();
+        core.tick().await.unwrap();
+
+        let read_sent = transport.take_sent(URL);
+        let write_sent = transport.take_sent(WRITE_ONLY);
+        assert_eq!(sent_of_type(&read_sent, "REQ").len(), 1);
+        assert!(sent_of_type(&read_sent, "EVENT").is_empty());
+        assert!(sent_of_type(&write_sent, "REQ").is_empty());
+        assert_eq!(sent_of_type(&write_sent, "EVENT").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dm_routes_through_relay_lists() {
+        const PEER_OUT: &str = "wss://peer-out.example";
+        const PEER_IN: &str = "wss://peer-in.example";
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        let peer_keys = nostr::Keys::generate();
+        let peer = peer_keys.public_key().to_hex();
+
+        core.open_dm(&peer).await.unwrap();
+        let reqs = sent_of_type(&transport.take_sent(URL), "REQ");
+        assert_eq!(reqs[0][1], "relay_list_1");
+        assert_eq!(reqs[0][2]["authors"], serde_json::json!([peer]));
+
+        let relay_list = signed_event_json(
+            &peer_keys,
+            10002,
+            &[&["r", PEER_OUT, "write"], &["r", PEER_IN, "read"]],
+            "",
+            1_700_000_000,
+        );
+        transport.receive(URL, &format!(r#"["EVENT","relay_list_1",{}]"#, relay_list));
+        transport.receive(URL, r#"["EOSE","relay_list_1"]"#);
+        core.tick().await.unwrap();
+        assert!(received_rows(&mut core).is_empty());
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "CLOSE")[0][1], "relay_list_1");
+
+        // 相手の投稿は相手の書き込み用Relayから読む
+        transport.open(PEER_OUT);
+        core.tick().await.unwrap();
+        let reqs = sent_of_type(&transport.take_sent(PEER_OUT), "REQ");
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0][1], format!("dm_from_{}", peer));
+
+        // DMは自分の書き込み用Relayと相手の読み込み用Relayへ
+        core.send_dm(&peer, "hi").await.unwrap();
+        core.tick().await.unwrap();
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "EVENT").len(), 1);
+        transport.open(PEER_IN);
+        core.tick().await.unwrap();
+        assert_eq!(sent_of_type(&transport.take_sent(PEER_IN), "EVENT").len(), 1);
+
+        // 一時的なRelayは設定一覧に出ない
+        assert_eq!(core.relays().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_publish_relay_list() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        core.add_relay("wss://read.example").await.unwrap();
+        core.set_relay_policy("wss://read.example", true, false).await.unwrap();
+
+        core.publish_relay_list().await.unwrap();
+        core.tick().await.unwrap();
+        let events = sent_of_type(&transport.take_sent(URL), "EVENT");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0][1]["kind"], 10002);
+        assert_eq!(
+            events[0][1]["tags"],
+            serde_json::json!([["r", URL], ["r", "wss://read.example", "read"]])
+        );
+
+        let pubkey = core.get_public_key().await.unwrap().unwrap();
+        assert_eq!(core.relay_list(&pubkey).unwrap().write_relays(), vec![URL.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_delivery_receipts_and_targeted_retry() {
+        const SECOND: &str = "wss://second.example";
+        let transport = MockTransport::new();
+        let (mut core, clock) = connected_core_with_clock(&transport).await;
+        core.add_relay(SECOND).await.unwrap();
+        transport.open(SECOND);
+        core.tick().await.unwrap();
+        core.set_publish_quorum(2);
+
+        let event_id = core.send_public("chan", "hello").await.unwrap();
+        core.tick().await.unwrap();
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "EVENT").len(), 1);
+        assert_eq!(sent_of_type(&transport.take_sent(SECOND), "EVENT").len(), 1);
+
+        transport.receive(URL, &format!(r#"["OK","{}",true,""]"#, event_id));
+        transport.receive(SECOND, &format!(r#"["OK","{}",false,"rate-limited: slow down"]"#, event_id));
+        core.tick().await.unwrap();
+        let delivery = core.delivery(&event_id).unwrap();
+        assert_eq!((delivery.accepted, delivery.total, delivery.delivered), (1, 2, false));
+
+        // 拒否したRelayにだけ再送
+        clock.advance(5);
+        core.tick().await.unwrap();
+        assert!(sent_of_type(&transport.take_sent(URL), "EVENT").is_empty());
+        assert_eq!(sent_of_type(&transport.take_sent(SECOND), "EVENT").len(), 1);
+
+        transport.receive(SECOND, &format!(r#"["OK","{}",true,""]"#, event_id));
+        core.tick().await.unwrap();
+        let delivery = core.delivery(&event_id).unwrap();
+        assert_eq!((delivery.accepted, delivery.total, delivery.delivered), (2, 2, true));
+    }
+
+    #[tokio::test]
+    async fn test_forged_events_are_dropped_and_relay_demoted() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        core.open_channel("chan").await.unwrap();
+        transport.take_sent(URL);
+
+        let event = signed_event_json(&nostr::Keys::generate(), 42, &[&["e", "chan"]], "hi", 1_700_000_000);
+        let forged = event.replace(r#""hi""#, r#""forged""#);
+        for _ in 0..MAX_INVALID_EVENTS {
+            transport.receive(URL, &format!(r#"["EVENT","channel_chan",{}]"#, forged));
+        }
+        core.tick().await.unwrap();
+
+        assert!(core.poll_events(100).contains(&CoreEvent::RelayDemoted { url: URL.to_string() }));
+        assert!(core.is_demoted(URL));
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "CLOSE")[0][1], "channel_chan");
+
+        // 設定は変えない（再起動すれば解除される）
+        assert!(core.relays()[0].read);
+        assert!(core.storage.get_relays().await.unwrap()[0].read);
+
+        // 新しい購読は送られない
+        core.open_channel("other").await.unwrap();
+        assert!(sent_of_type(&transport.take_sent(URL), "REQ").is_empty());
+
+        // 手動で読み込みを戻せば解除
+        core.set_relay_policy(URL, true, true).await.unwrap();
+        assert!(!core.is_demoted(URL));
+    }
+
+    #[tokio::test]
+    async fn test_occasional_invalid_events_do_not_demote() {
+        let transport = MockTransport::new();
+        let (mut core, clock) = connected_core_with_clock(&transport).await;
+        let event = signed_event_json(&nostr::Keys::generate(), 42, &[&["e", "chan"]], "hi", 1_700_000_000);
+        let forged = event.replace(r#""hi""#, r#""forged""#);
+
+        // 期間を空けて届く不正なイベントは数えない
+        for _ in 0..MAX_INVALID_EVENTS * 2 {
+            transport.receive(URL, &format!(r#"["EVENT","channel_chan",{}]"#, forged));
+            core.tick().await.unwrap();
+            clock.advance(5 * 60);
+        }
+        assert!(!core.is_demoted(URL));
+    }
+
+    #[tokio::test]
+    async fn test_events_deduplicated_across_relays() {
+        const SECOND: &str = "wss://second.example";
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        core.add_relay(SECOND).await.unwrap();
+        transport.open(SECOND);
+        core.tick().await.unwrap();
+        core.open_channel("chan").await.unwrap();
+
+        let event = signed_event_json(&nostr::Keys::generate(), 42, &[&["e", "chan"]], "hi", 1_700_000_000);
+        let event_id = serde_json::from_str::<serde_json::Value>(&event).unwrap()["id"].as_str().unwrap().to_string();
+        let msg = format!(r#"["EVENT","channel_chan",{}]"#, event);
+        transport.receive(SECOND, &msg);
+        transport.receive(URL, &msg);
+        transport.receive(URL, &msg);
+        core.tick().await.unwrap();
+
+        assert_eq!(received_rows(&mut core).len(), 1);
+        assert_eq!(core.seen_on(&event_id).await.unwrap().len(), 2);
+
+        let stored = core.storage.get_event(&event_id).await.unwrap().unwrap();
+        assert_eq!(stored.seen_on.len(), 2);
+        assert_eq!(stored.relay_hint, core.relay_hint(&event_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_forged_duplicate_is_not_recorded() {
+        const SECOND: &str = "wss://second.example";
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        core.add_relay(SECOND).await.unwrap();
+        transport.open(SECOND);
+        core.tick().await.unwrap();
+        core.open_channel("chan").await.unwrap();
+
+        let event = signed_event_json(&nostr::Keys::generate(), 42, &[&["e", "chan"]], "hi", 1_700_000_000);
+        let mut forged: serde_json::Value = serde_json::from_str(&event).unwrap();
+        let event_id = forged["id"].as_str().unwrap().to_string();
+        forged["sig"] = serde_json::json!("0".repeat(128));
+        forged["created_at"] = serde_json::json!(1_800_000_000);
+        transport.receive(URL, &format!(r#"["EVENT","channel_chan",{}]"#, event));
+        transport.receive(SECOND, &format!(r#"["EVENT","channel_chan",{}]"#, forged));
+        core.tick().await.unwrap();
+
+        // IDだけ同じで検証できないコピーを送ったRelayは受信元にしない
+        assert_eq!(core.seen_on(&event_id).await.unwrap(), vec![URL.to_string()]);
+        assert_eq!(core.storage.get_event(&event_id).await.unwrap().unwrap().seen_on, vec![URL.to_string()]);
+        let second = core.relays.iter().find(|r| r.url == SECOND).unwrap();
+        assert_eq!(second.invalid_event_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_channel_message_uses_relay_hint() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        let channel = signed_event_json(&nostr::Keys::generate(), 40, &[], "{}", 1_700_000_000);
+        let channel_id = serde_json::from_str::<serde_json::Value>(&channel).unwrap()["id"].as_str().unwrap().to_string();
+        transport.receive(URL, &format!(r#"["EVENT","meta",{}]"#, channel));
+        core.tick().await.unwrap();
+
+        core.send_public(&channel_id, "hello").await.unwrap();
+        core.tick().await.unwrap();
+        let events = sent_of_type(&transport.take_sent(URL), "EVENT");
+        assert_eq!(events[0][1]["tags"][0], serde_json::json!(["e", channel_id, URL, "root"]));
+    }
+
+    #[tokio::test]
+    async fn test_events_are_routed_to_their_scope() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        core.open_channel("a").await.unwrap();
+        core.open_channel("b").await.unwrap();
+
+        let keys = nostr::Keys::generate();
+        let older = signed_event_json(&keys, 42, &[&["e", "a"]], "older", 1_700_000_000);
+        let newer = signed_event_json(&keys, 42, &[&["e", "a"]], "newer", 1_700_000_010);
+        let other = signed_event_json(&keys, 42, &[&["e", "b"]], "other", 1_700_000_005);
+        transport.receive(URL, &format!(r#"["EVENT","channel_a",{}]"#, newer));
+        transport.receive(URL, &format!(r#"["EVENT","channel_a",{}]"#, older));
+        transport.receive(URL, &format!(r#"["EVENT","channel_b",{}]"#, other));
+        // 購読IDで分からなければタグから求める
+        let peer = nostr::Keys::generate();
+        let dm = signed_event_json(&peer, 4, &[&["p", "me"]], "secret", 1_700_000_000);
+        transport.receive(URL, &format!(r#"["EVENT","unknown",{}]"#, dm));
+        core.tick().await.unwrap();
+
+        let rows = received_rows(&mut core);
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[2].scope, Some(Scope::Channel("b".to_string())));
+
+        let a: Vec<String> = core.conversation(&Scope::Channel("a".to_string())).into_iter().map(|r| r.content).collect();
+        assert_eq!(a, vec!["newer", "older"]);
+        let b = core.conversation(&Scope::Channel("b".to_string()));
+        assert_eq!(b.len(), 1);
+        assert_eq!(b[0].content, "other");
+        let dm_rows = core.conversation(&Scope::Dm(peer.public_key().to_hex()));
+        assert_eq!(dm_rows.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_load_older_fills_from_storage_then_relays() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        let scope = Scope::Channel("chan".to_string());
+        core.open_channel("chan").await.unwrap();
+        transport.take_sent(URL);
+
+        let keys = nostr::Keys::generate();
+        let live = signed_event_json(&keys, 42, &[&["e", "chan"]], "live", 1_700_000_000);
+        transport.receive(URL, &format!(r#"["EVENT","channel_chan",{}]"#, live));
+        core.tick().await.unwrap();
+        let stored = signed_event_json(&keys, 42, &[&["e", "chan"]], "stored", 1_699_999_000);
+        core.storage.save_event(&NostrEvent::from_json(&stored).unwrap()).await.unwrap();
+
+        // Storageで足りない分をRelayに問い合わせる
+        assert_eq!(core.load_older(&scope, 2).await.unwrap(), 1);
+        let reqs = sent_of_type(&transport.take_sent(URL), "REQ");
+        assert_eq!(reqs.len(), 1);
+        let sub_id = reqs[0][1].as_str().unwrap().to_string();
+        assert_eq!(reqs[0][2]["until"], 1_699_999_000);
+        assert_eq!(reqs[0][2]["limit"], 1);
+        assert!(core.history_state(&scope).loading);
+
+        let older = signed_event_json(&keys, 42, &[&["e", "chan"]], "older", 1_699_998_000);
+        transport.receive(URL, &format!(r#"["EVENT","{}",{}]"#, sub_id, older));
+        transport.receive(URL, &format!(r#"["EOSE","{}"]"#, sub_id));
+        core.tick().await.unwrap();
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "CLOSE").len(), 1);
+        assert_eq!(core.history_state(&scope), HistoryState { loading: false, reached_start: false });
+        let contents: Vec<String> = core.conversation(&scope).into_iter().map(|r| r.content).collect();
+        assert_eq!(contents, vec!["live", "stored", "older"]);
+
+        // 何も返らなければ最初まで取得した
+        core.load_older(&scope, 2).await.unwrap();
+        let reqs = sent_of_type(&transport.take_sent(URL), "REQ");
+        assert_eq!(reqs[0][2]["until"], 1_699_998_000);
+        transport.receive(URL, &format!(r#"["EOSE","{}"]"#, reqs[0][1].as_str().unwrap()));
+        core.tick().await.unwrap();
+        assert!(core.history_state(&scope).reached_start);
+        assert_eq!(core.load_older(&scope, 2).await.unwrap(), 0);
+        assert!(sent_of_type(&transport.take_sent(URL), "REQ").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_load_older_dm_keeps_newest_across_directions() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        let my_keys = nostr::Keys::generate();
+        core.set_signer(Arc::new(InternalSigner::from_secret_key(&my_keys.secret_key().to_secret_bytes()).unwrap()));
+        let me = my_keys.public_key().to_hex();
+        let peer_keys = nostr::Keys::generate();
+        let peer = peer_keys.public_key().to_hex();
+        let scope = Scope::Dm(peer.clone());
+
+        // 送信ばかり続き、受信は古い1件だけ
+        for (keys, p, content, created_at) in [
+            (&my_keys, peer.as_str(), "sent3", 1_699_999_400),
+            (&my_keys, peer.as_str(), "sent2", 1_699_999_300),
+            (&my_keys, peer.as_str(), "sent1", 1_699_999_200),
+            (&peer_keys, me.as_str(), "received", 1_699_999_100),
+        ] {
+            let json = signed_event_json(keys, 4, &[&["p", p]], content, created_at);
+            core.storage.save_event(&NostrEvent::from_json(&json).unwrap()).await.unwrap();
+        }
+
+        // 受信側の古い1件を先に加えると、送信側の読み残しを飛ばしてしまう
+        assert_eq!(core.load_older(&scope, 2).await.unwrap(), 2);
+        let contents: Vec<String> = core.conversation(&scope).into_iter().map(|r| r.content).collect();
+        assert_eq!(contents, vec!["sent3", "sent2"]);
+
+        assert_eq!(core.load_older(&scope, 2).await.unwrap(), 2);
+        let contents: Vec<String> = core.conversation(&scope).into_iter().map(|r| r.content).collect();
+        assert_eq!(contents, vec!["sent3", "sent2", "sent1", "received"]);
+        assert!(sent_of_type(&transport.take_sent(URL), "REQ").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_open_channel_shows_cached_history() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        let keys = nostr::Keys::generate();
+        for (tags, content, created_at) in [
+            (&[&["e", "chan"][..]][..], "first", 1_700_000_000),
+            (&[&["e", "chan"][..]][..], "second", 1_700_000_100),
+            (&[&["e", "other"][..]][..], "elsewhere", 1_700_000_200),
+        ] {
+            let json = signed_event_json(&keys, 42, tags, content, created_at);
+            core.storage.save_event(&NostrEvent::from_json(&json).unwrap()).await.unwrap();
+        }
+
+        // Relayの応答を待たずにStorageの分が届く
+        core.open_channel("chan").await.unwrap();
+        let contents: Vec<String> = received_rows(&mut core).into_iter().map(|r| r.content).collect();
+        assert_eq!(contents, vec!["second", "first"]);
+        assert_eq!(core.conversation(&Scope::Channel("chan".to_string())).len(), 2);
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "REQ").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retention_evicts_least_recently_viewed_first() {
+        let transport = MockTransport::new();
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let storage = Arc::new(MockStorage::new());
+        let mut core = CoreHandle::init_with(vec![URL.to_string()], storage.clone(), Arc::new(transport.clone()), clock.clone())
+            .await
+            .unwrap();
+        core.set_signer(Arc::new(InternalSigner::generate("").await.unwrap()));
+        let me = core.get_public_key().await.unwrap().unwrap();
+        let keys = nostr::Keys::generate();
+        let next = |kind: u16, tags: &[&[&str]], content: &str| {
+            let json = signed_event_json(&keys, kind, tags, content, clock.now());
+            clock.advance(1);
+            NostrEvent::from_json(&json).unwrap()
+        };
+        let events = [
+            next(42, &[&["e", "stale"]], "stale 1"),
+            next(42, &[&["e", "stale"]], "stale 2"),
+            next(42, &[&["e", "fresh"]], "fresh 1"),
+            next(0, &[], "{}"),
+            next(4, &[&["p", &me]], "secret"),
+        ];
+        let own = NostrEvent { pubkey: me.clone(), id: "f".repeat(64), ..events[0].clone() };
+        for event in events.iter().chain([&own]) {
+            storage.save_event(event).await.unwrap();
+        }
+        core.open_channel("stale").await.unwrap();
+        clock.advance(60);
+        core.open_channel("fresh").await.unwrap();
+
+        // 上限をわずかに超えたら、最後に見たのが古い会話の古いイベントだけ消す
+        storage.set_usage(Some(StorageUsage { usage: 8_001, quota: Some(10_000) }));
+        core.tick().await.unwrap();
+        assert_eq!(storage.get_event(&events[0].id).await.unwrap().map(|e| e.id), None);
+        assert!(storage.get_event(&events[1].id).await.unwrap().is_some());
+
+        // 間隔が空くまでは適用しない
+        storage.set_usage(Some(StorageUsage { usage: 10_000, quota: Some(10_000) }));
+        core.tick().await.unwrap();
+        assert!(storage.get_event(&events[1].id).await.unwrap().is_some());
+
+        // 自分のイベント、DM、メタデータは残す
+        clock.advance(RETENTION_INTERVAL_SECS);
+        core.tick().await.unwrap();
+        let mut remaining: Vec<String> = storage.get_events(&Filter::new()).await.unwrap().into_iter().map(|e| e.id).collect();
+        remaining.sort();
+        let mut expected = vec![events[3].id.clone(), events[4].id.clone(), own.id.clone()];
+        expected.sort();
+        assert_eq!(remaining, expected);
+    }
+
+    #[tokio::test]
+    async fn test_retention_counts_received_events_incrementally() {
+        let transport = MockTransport::new();
+        let (mut core, clock) = connected_core_with_clock(&transport).await;
+        core.set_retention_policy(RetentionPolicy {
+            max_events_per_scope: Some(2),
+            max_age_secs: None,
+            max_bytes: None,
+            max_quota_ratio: 1.0,
+        });
+        core.tick().await.unwrap();
+        assert_eq!(core.stored_totals, Some(StoredTotals::default()));
+
+        core.open_channel("a").await.unwrap();
+        core.open_channel("b").await.unwrap();
+        let keys = nostr::Keys::generate();
+        let mut ids = Vec::new();
+        for (channel, created_at) in [("a", 1), ("a", 2), ("a", 3), ("b", 1)] {
+            let json = signed_event_json(&keys, 42, &[&["e", channel]], "hi", created_at);
+            ids.push(NostrEvent::from_json(&json).unwrap().id);
+            transport.receive(URL, &format!(r#"["EVENT","channel_{}",{}]"#, channel, json));
+        }
+        core.tick().await.unwrap();
+        let totals = core.stored_totals.clone().unwrap();
+        assert_eq!(totals.per_scope[&Scope::Channel("a".to_string())], 3);
+        assert_eq!(totals.per_scope[&Scope::Channel("b".to_string())], 1);
+
+        // 上限を超えた会話の古いものだけ消し、見積もりからも差し引く
+        clock.advance(RETENTION_INTERVAL_SECS);
+        core.tick().await.unwrap();
+        let mut remaining: Vec<String> = core.storage.get_events(&Filter::new()).await.unwrap().into_iter().map(|e| e.id).collect();
+        remaining.sort();
+        let mut expected = ids[1..].to_vec();
+        expected.sort();
+        assert_eq!(remaining, expected);
+        assert_eq!(core.stored_totals.as_ref().unwrap().per_scope[&Scope::Channel("a".to_string())], 2);
+    }
+
+    #[tokio::test]
+    async fn test_save_failure_keeps_processing_the_tick() {
+        let transport = MockTransport::new();
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let storage = Arc::new(MockStorage::new());
+        let mut core = CoreHandle::init_with(vec![URL.to_string()], storage.clone(), Arc::new(transport.clone()), clock.clone())
+            .await
+            .unwrap();
+        core.set_signer(Arc::new(InternalSigner::generate("").await.unwrap()));
+        core.connect_all().await.unwrap();
+        transport.open(URL);
+        core.tick().await.unwrap();
+        core.open_channel("chan").await.unwrap();
+        core.outbox.enqueue(NostrEvent::from_json(&signed_event_json(&nostr::Keys::generate(), 1, &[], "out", 0)).unwrap())
+            .await
+            .unwrap();
+        transport.take_sent(URL);
+        received_rows(&mut core);
+
+        storage.set_fail_saves(true);
+        let keys = nostr::Keys::generate();
+        let first = signed_event_json(&keys, 42, &[&["e", "chan"]], "first", 1_700_000_000);
+        let second = signed_event_json(&keys, 42, &[&["e", "chan"]], "second", 1_700_000_001);
+        transport.receive(URL, &format!(r#"["EVENT","channel_chan",{}]"#, first));
+        transport.receive(URL, &format!(r#"["EVENT","channel_chan",{}]"#, second));
+        core.tick().await.unwrap();
+
+        // 保存できなくても同じtickの残りのメッセージを表示し、送信も続ける
+        let contents: Vec<String> = received_rows(&mut core).into_iter().map(|row| row.content).collect();
+        assert_eq!(contents, vec!["first", "second"]);
+        assert!(storage.get_events(&Filter::new()).await.unwrap().is_empty());
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "EVENT").len(), 1);
+        assert_eq!(core.next_retention_at, 0);
+
+        // 既に表示したイベントは流さない
+        transport.receive(URL, &format!(r#"["EVENT","channel_chan",{}]"#, first));
+        core.tick().await.unwrap();
+        assert!(received_rows(&mut core).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_backup_restores_relays_and_outbox() {
+        const SECOND: &str = "wss://second.example";
+        let transport = MockTransport::new();
+        let mut source = connected_core(&transport).await;
+        source.add_relay(SECOND).await.unwrap();
+        source.open_channel("chan").await.unwrap();
+        let event = NostrEvent::from_json(&signed_event_json(&nostr::Keys::generate(), 42, &[&["e", "chan"]], "hi", 0)).unwrap();
+        source.storage.save_event(&event).await.unwrap();
+        source.outbox.enqueue(event.clone()).await.unwrap();
+
+        let mut archive = Vec::new();
+        assert!(source.export_backup(&mut archive).await.unwrap() > 0);
+
+        let mut target = connected_core(&transport).await;
+        target.open_channel("chan").await.unwrap();
+        let summary = target.import_backup(archive.as_slice()).await.unwrap();
+        assert!(summary.imported > 0);
+        assert!(target.storage.get_event(&event.id).await.unwrap().is_some());
+        let urls: Vec<String> = target.relays().into_iter().map(|r| r.url).collect();
+        assert_eq!(urls, vec![URL.to_string(), SECOND.to_string()]);
+
+        // 復元したRelayには購読と未送信のイベントを送る
+        transport.take_sent(SECOND);
+        transport.open(SECOND);
+        target.tick().await.unwrap();
+        let sent = transport.take_sent(SECOND);
+        assert!(sent.iter().any(|m| m.starts_with(r#"["REQ","channel_chan","#)));
+        assert!(sent.contains(&format!(r#"["EVENT",{}]"#, event.to_json())));
+
+        // 2回目は何も増えない
+        let mut archive = Vec::new();
+        source.export_backup(&mut archive).await.unwrap();
+        let again = target.import_backup(archive.as_slice()).await.unwrap();
+        assert_eq!(again.imported, 0);
+        assert_eq!(target.relays().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_close_scope_sends_close_for_last_user() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        let scope = Scope::Channel("chan".to_string());
+        core.open_channel("chan").await.unwrap();
+        core.open_channel("chan").await.unwrap();
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "REQ").len(), 1);
+
+        core.close_scope(&scope).await.unwrap();
+        assert!(transport.take_sent(URL).is_empty());
+        core.close_scope(&scope).await.unwrap();
+        assert_eq!(sent_of_type(&transport.take_sent(URL), "CLOSE")[0][1], "channel_chan");
+        assert!(core.relays[0].subscriptions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions_wait_for_relay_limit() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        core.set_max_subscriptions(URL, Some(1)).await.unwrap();
+        assert_eq!(core.relays()[0].max_subscriptions, Some(1));
+
+        core.open_channel("a").await.unwrap();
+        core.open_channel("b").await.unwrap();
+        let reqs = sent_of_type(&transport.take_sent(URL), "REQ");
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0][1], "channel_a");
+        assert!(core.relays[0].is_subscription_queued("channel_b"));
+
+        // 空きができたら待っていた購読を送る（CLOSEが先）
+        core.close_scope(&Scope::Channel("a".to_string())).await.unwrap();
+        let sent = transport.take_sent(URL);
+        assert!(sent[0].starts_with(r#"["CLOSE","channel_a"]"#));
+        assert!(sent[1].starts_with(r#"["REQ","channel_b","#));
+        assert!(!core.relays[0].is_subscription_queued("channel_b"));
+    }
+
+    #[tokio::test]
+    async fn test_core_events_report_state_changes() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        assert!(core.poll_events(10).contains(&CoreEvent::RelayStateChanged {
+            url: URL.to_string(),
+            state: ConnectionState::Connected,
+            reason: None,
+        }));
+
+        core.open_channel("chan").await.unwrap();
+        transport.receive(URL, r#"["NOTICE","slow down"]"#);
+        transport.receive(URL, r#"["EOSE","channel_chan"]"#);
+        core.send_public("chan", "hello").await.unwrap();
+        core.tick().await.unwrap();
+        let events = core.poll_events(10);
+        assert!(events.contains(&CoreEvent::Notice { url: URL.to_string(), message: "slow down".to_string() }));
+        assert!(events.contains(&CoreEvent::EndOfStoredEvents {
+            url: URL.to_string(),
+            scope: Scope::Channel("chan".to_string()),
+        }));
+        let event_id = events
+            .iter()
+            .find_map(|e| match e {
+                CoreEvent::OutboxUpdated { event_id, summary } if summary.accepted == 0 => Some(event_id.clone()),
+                _ => None,
+            })
+            .unwrap();
+
+        transport.receive(URL, &format!(r#"["OK","{}",true,""]"#, event_id));
+        transport.close(URL);
+        core.tick().await.unwrap();
+        let events = core.poll_events(10);
+        assert!(events.iter().any(|e| matches!(e, CoreEvent::OutboxUpdated { summary, .. } if summary.delivered)));
+        assert!(events.contains(&CoreEvent::RelayStateChanged {
+            url: URL.to_string(),
+            state: ConnectionState::Disconnected,
+            reason: Some(DisconnectReason::Closed { code: 1006, reason: String::new() }),
+        }));
+        // 変化がなければ通知しない
+        core.tick().await.unwrap();
+        assert!(core.poll_events(10).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_history_exhausted_is_reported() {
+        let transport = MockTransport::new();
+        let mut core = connected_core(&transport).await;
+        let scope = Scope::Channel("chan".to_string());
+        core.poll_events(10);
+
+        core.load_older(&scope, 10).await.unwrap();
+        assert_eq!(core.poll_events(10), vec![CoreEvent::HistoryLoading(scope.clone())]);
+        let reqs = sent_of_type(&transport.take_sent(URL), "REQ");
+        transport.receive(URL, &format!(r#"["EOSE","{}"]"#, reqs[0][1].as_str().unwrap()));
+        core.tick().await.unwrap();
+        assert_eq!(
+            core.poll_events(10),
+            vec![CoreEvent::HistoryExhausted(scope.clone()), CoreEvent::HistoryLoaded(scope)]
+        );
+    }
+}
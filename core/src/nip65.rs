@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use crate::event::NostrEvent;
+use crate::types::RelayConfig;
+
+/// NIP-65 のRelayリストのkind
+pub const RELAY_LIST_KIND: u16 = 10002;
+
+/// 1人の著者について使うRelayの最大数
+pub const MAX_RELAYS_PER_AUTHOR: usize = 3;
+
+/// Relayリストの1エントリ（`["r", url, "read"|"write"]`）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayListEntry {
+    pub url: String,
+    pub read: bool,
+    pub write: bool,
+}
+
+/// NIP-65 のRelayリスト（kind 10002）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayList {
+    pub created_at: i64,
+    pub relays: Vec<RelayListEntry>,
+}
+
+impl RelayList {
+    /// kind 10002 のイベントから作成。不正なURLのエントリは無視する
+    pub fn from_event(event: &NostrEvent) -> Option<Self> {
+        if event.kind != RELAY_LIST_KIND {
+            return None;
+        }
+
+        let mut relays: Vec<RelayListEntry> = Vec::new();
+        for tag in event.tags_named("r") {
+            let Some(url) = tag.get(1) else {
+                continue;
+            };
+            let Ok(url) = crate::normalize_relay_url(url) else {
+                continue;
+            };
+            // マーカーなしは読み書き両方
+            let (read, write) = match tag.get(2).map(|m| m.as_str()) {
+                Some("read") => (true, false),
+                Some("write") => (false, true),
+                _ => (true, true),
+            };
+            match relays.iter_mut().find(|r| r.url == url) {
+                Some(entry) => {
+                    entry.read |= read;
+                    entry.write |= write;
+                }
+                None => relays.push(RelayListEntry { url, read, write }),
+            }
+        }
+
+        Some(Self { created_at: event.created_at, relays })
+    }
+
+    /// 自分のRelay設定から作成
+    pub fn from_configs(configs: &[RelayConfig], created_at: i64) -> Self {
+        let relays = configs
+            .iter()
+            .filter(|c| c.read || c.write)
+            .map(|c| RelayListEntry {
+                url: c.url.clone(),
+                read: c.read,
+                write: c.write,
+            })
+            .collect();
+        Self { created_at, relays }
+    }
+
+    /// イベントのタグに変換
+    pub fn to_tags(&self) -> Vec<Vec<String>> {
+        self.relays
+            .iter()
+            .map(|r| {
+                let mut tag = vec!["r".to_string(), r.url.clone()];
+                match (r.read, r.write) {
+                    (true, false) => tag.push("read".to_string()),
+                    (false, true) => tag.push("write".to_string()),
+                    _ => {}
+                }
+                tag
+            })
+            .collect()
+    }
+
+    /// 読み込み用Relay（この人宛てのイベントを受け取る）
+    pub fn read_relays(&self) -> Vec<String> {
+        self.relays.iter().filter(|r| r.read).map(|r| r.url.clone()).collect()
+    }
+
+    /// 書き込み用Relay（この人が自分のイベントを送る）
+    pub fn write_relays(&self) -> Vec<String> {
+        self.relays.iter().filter(|r| r.write).map(|r| r.url.clone()).collect()
+    }
+}
+
+/// 取得したRelayリストのキャッシュ（pubkey -> 最新のRelayリスト）
+#[derive(Debug, Clone, Default)]
+pub struct RelayListCache {
+    lists: HashMap<String, RelayList>,
+}
+
+impl RelayListCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Relayリストを記録。既存より新しい場合のみ更新し、更新したらtrueを返す
+    pub fn update(&mut self, pubkey: &str, list: RelayList) -> bool {
+        if let Some(existing) = self.lists.get(pubkey) {
+            if existing.created_at >= list.created_at {
+                return false;
+            }
+        }
+        self.lists.insert(pubkey.to_string(), list);
+        true
+    }
+
+    pub fn get(&self, pubkey: &str) -> Option<&RelayList> {
+        self.lists.get(pubkey)
+    }
+
+    pub fn contains(&self, pubkey: &str) -> bool {
+        self.lists.contains_key(pubkey)
+    }
+
+    /// 著者の書き込み用Relay（最大`MAX_RELAYS_PER_AUTHOR`件）
+    pub fn write_relays(&self, pubkey: &str) -> Option<Vec<String>> {
+        self.get(pubkey)
+            .map(|list| list.write_relays().into_iter().take(MAX_RELAYS_PER_AUTHOR).collect())
+    }
+
+    /// 受信者の読み込み用Relay（最大`MAX_RELAYS_PER_AUTHOR`件）
+    pub fn read_relays(&self, pubkey: &str) -> Option<Vec<String>> {
+        self.get(pubkey)
+            .map(|list| list.read_relays().into_iter().take(MAX_RELAYS_PER_AUTHOR).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: u16, tags: Vec<Vec<String>>) -> NostrEvent {
+        NostrEvent {
+            id: String::new(),
+            pubkey: String::new(),
+            created_at: 100,
+            kind,
+            tags,
+            content: String::new(),
+            sig: String::new(),
+        }
+    }
+
+    fn tags(tags: &[&[&str]]) -> Vec<Vec<String>> {
+        tags.iter().map(|t| t.iter().map(|s| s.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn test_parse_relay_list_event() {
+        let list = RelayList::from_event(&event(10002, tags(&[
+            &["r", "wss://both.example/"],
+            &["r", "wss://read.example", "read"],
+            &["r", "wss://write.example", "write"],
+            &["r", "https://not-a-relay.example"],
+            &["p", "abc"],
+        ])))
+        .unwrap();
+        assert_eq!(list.read_relays(), vec!["wss://both.example", "wss://read.example"]);
+        assert_eq!(list.write_relays(), vec!["wss://both.example", "wss://write.example"]);
+
+        // タグへの往復
+        let roundtrip = RelayList::from_event(&event(10002, list.to_tags())).unwrap();
+        assert_eq!(roundtrip, list);
+
+        assert!(RelayList::from_event(&event(1, Vec::new())).is_none());
+    }
+
+    #[test]
+    fn test_cache_keeps_newest() {
+        let mut cache = RelayListCache::new();
+        let list = |created_at, url: &str| RelayList {
+            created_at,
+            relays: vec![RelayListEntry { url: url.to_string(), read: true, write: true }],
+        };
+
+        assert!(cache.update("pk", list(10, "wss://a.example")));
+        assert!(!cache.update("pk", list(5, "wss://old.example")));
+        assert!(cache.update("pk", list(20, "wss://b.example")));
+        assert_eq!(cache.write_relays("pk"), Some(vec!["wss://b.example".to_string()]));
+        assert_eq!(cache.read_relays("unknown"), None);
+    }
+}
@@ -1,40 +1,71 @@
 use std::collections::VecDeque;
 use std::sync::Arc;
 
+use crate::clock::{Clock, SystemClock};
 use crate::storage::Storage;
-use crate::types::{OutboxItem, OutboxStatus};
-use crate::relay::RelayConnection;
+use crate::types::{DeliveryStatus, DeliverySummary, OutboxItem, OutboxStatus, RelayReceipt};
+use crate::relay::{ReasonPrefix, RelayReason};
 use crate::error::Result;
+use crate::event::NostrEvent;
 
 const MAX_RETRY_COUNT: u32 = 5;
 const RETRY_DELAY_SECONDS: i64 = 5;
+/// OKを待つ時間（秒）
+const OK_TIMEOUT_SECONDS: i64 = 10;
+/// 配送が終わっても配送記録を残しておく件数
+const MAX_FINISHED: usize = 200;
 
 /// 送信キュー
+///
+/// Relayごとに配送状態を記録し、`quorum`個のRelayに受理されたら配送済みとする。
 pub struct OutboxQueue {
     storage: Arc<dyn Storage>,
     pending: VecDeque<OutboxItem>,
+    /// 配送が終わった（配送済みまたは失敗した）アイテム
+    finished: VecDeque<OutboxItem>,
+    quorum: usize,
+    clock: Arc<dyn Clock>,
 }
 
 impl OutboxQueue {
     pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self::with_clock(storage, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(storage: Arc<dyn Storage>, clock: Arc<dyn Clock>) -> Self {
         Self {
             storage,
             pending: VecDeque::new(),
+            finished: VecDeque::new(),
+            quorum: 1,
+            clock,
         }
     }
 
+    /// 配送済みとするのに必要な受理Relay数
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    /// 配送済みとするのに必要な受理Relay数を設定（最小1）
+    pub fn set_quorum(&mut self, quorum: usize) {
+        self.quorum = quorum.max(1);
+    }
+
     /// イベントをキューに追加
-    pub async fn enqueue(&mut self, event_json: String) -> Result<String> {
-        let req_id = generate_req_id();
-        let now = current_timestamp();
+    pub async fn enqueue(&mut self, event: NostrEvent) -> Result<String> {
+        let req_id = generate_req_id(self.clock.now_millis());
+        let now = self.clock.now();
 
         let item = OutboxItem {
             req_id: req_id.clone(),
-            event_json,
+            event_id: event.id.clone(),
+            event,
             status: OutboxStatus::Queued,
             last_try_at: now,
             retry_count: 0,
             error: None,
+            receipts: Vec::new(),
         };
 
         self.storage.enqueue_outbox(item.clone()).await?;
@@ -43,141 +74,222 @@ impl OutboxQueue {
         Ok(req_id)
     }
 
-    /// 保留中のアイテムをStorageから読み込み
+    /// 保留中のアイテムをStorageから読み込み（キューにあるものは読み飛ばす）
     pub async fn load_pending(&mut self) -> Result<()> {
-        let items = self.storage.get_pending_outbox().await?;
+        let mut items = self.storage.get_pending_outbox().await?;
+        items.retain(|item| !self.pending.iter().any(|p| p.req_id == item.req_id));
+        for item in &mut items {
+            if item.event_id.is_empty() {
+                item.event_id = item.event.id.clone();
+            }
+        }
         self.pending.extend(items);
         Ok(())
     }
 
-    /// キューから1つ取り出す（送信用）
-    pub async fn dequeue(&mut self) -> Result<Option<String>> {
-        if let Some(item) = self.pending.front() {
-            if item.status == OutboxStatus::Queued {
-                let event_json = item.event_json.clone();
-                let req_id = item.req_id.clone();
-                
-                // 送信済みステータスに変更（OKレスポンス待ち）
-                if let Some(item) = self.pending.front_mut() {
-                    item.status = OutboxStatus::Sent;
-                    item.last_try_at = current_timestamp();
-                }
-                
-                // IndexedDBも更新
-                self.storage.update_outbox_status(&req_id, OutboxStatus::Sent).await
-                    .map_err(|e| {
-                        log::error!("dequeue: Failed to update outbox status for {}: {:?}", req_id, e);
-                        e
-                    })?;
-                
-                return Ok(Some(event_json));
-            }
-        }
-        Ok(None)
-    }
+    /// 未送信のアイテムを古い順に1つ取り出す（送信用）
+    ///
+    /// OK待ちのアイテムがあっても、後から追加されたアイテムは待たせない。
+    pub async fn dequeue(&mut self) -> Result<Option<NostrEvent>> {
+        let Some(item) = self.pending.iter_mut().find(|item| item.status == OutboxStatus::Queued) else {
+            return Ok(None);
+        };
 
-    /// キューを処理（Relayに送信）
-    pub async fn process(&mut self, relays: &[RelayConnection]) -> Result<()> {
-        let now = current_timestamp();
-        let mut to_retry = Vec::new();
+        // 送信済みステータスに変更（OKレスポンス待ち）
+        item.status = OutboxStatus::Sent;
+        item.last_try_at = self.clock.now();
+        let event = item.event.clone();
+        let req_id = item.req_id.clone();
 
-        while let Some(mut item) = self.pending.pop_front() {
-            // 再送待ち時間チェック
-            if item.status == OutboxStatus::Sent {
-                let elapsed = now - item.last_try_at;
-                if elapsed < RETRY_DELAY_SECONDS {
-                    to_retry.push(item);
-                    continue;
-                }
-            }
+        // IndexedDBも更新
+        self.storage.update_outbox_status(&req_id, OutboxStatus::Sent).await
+            .map_err(|e| {
+                log::error!("dequeue: Failed to update outbox status for {}: {:?}", req_id, e);
+                e
+            })?;
 
-            // 最大再送回数チェック
-            if item.retry_count >= MAX_RETRY_COUNT {
-                item.status = OutboxStatus::Error;
-                item.error = Some("Max retry count exceeded".to_string());
-                self.storage.update_outbox_status(&item.req_id, OutboxStatus::Error).await?;
-                continue;
-            }
+        Ok(Some(event))
+    }
 
-            // 接続済みのRelayに送信
-            let mut sent_count = 0;
-            for relay in relays {
-                if relay.is_connected() {
-                    if let Err(e) = relay.send(&item.event_json).await {
-                        log::warn!("Failed to send to {}: {}", relay.url, e);
-                    } else {
-                        sent_count += 1;
-                    }
-                }
-            }
+    /// イベントIDからキュー内のイベントを探す
+    pub fn find_event(&self, event_id: &str) -> Option<NostrEvent> {
+        self.pending
+            .iter()
+            .find(|item| item.event_id == event_id)
+            .map(|item| item.event.clone())
+    }
 
-            if sent_count > 0 {
-                item.status = OutboxStatus::Sent;
-                item.last_try_at = now;
-                item.retry_count += 1;
-                self.storage.update_outbox_status(&item.req_id, OutboxStatus::Sent).await?;
-                to_retry.push(item);
-            } else {
-                // 接続済みのRelayがない場合は再度キューに戻す
-                to_retry.push(item);
-            }
-        }
+    /// 送信済みでOK待ちのイベント（再接続時の再送用）
+    pub fn unacknowledged(&self) -> Vec<NostrEvent> {
+        self.pending
+            .iter()
+            .filter(|item| item.status == OutboxStatus::Sent)
+            .map(|item| item.event.clone())
+            .collect()
+    }
 
-        // 再送待ちのアイテムをキューに戻す
-        self.pending.extend(to_retry);
+    /// 指定Relayにまだ受理されていない送信済みイベント（再接続時の再送用）
+    pub fn unacknowledged_for(&self, relay_url: &str) -> Vec<NostrEvent> {
+        self.pending
+            .iter()
+            .filter(|item| item.status != OutboxStatus::Queued)
+            .filter(|item| item.receipt(relay_url).is_none_or(can_still_accept))
+            .map(|item| item.event.clone())
+            .collect()
+    }
 
-        Ok(())
+    /// Relayに送信したことを記録
+    pub async fn mark_sent(&mut self, event_id: &str, relay_url: &str) -> Result<()> {
+        let now = self.clock.now();
+        let Some(item) = self.pending.iter_mut().find(|item| item.event_id == event_id) else {
+            return Ok(());
+        };
+        let attempts = item.receipt(relay_url).map_or(0, |r| r.attempts) + 1;
+        set_receipt(item, relay_url, DeliveryStatus::Pending, now, attempts);
+        item.status = OutboxStatus::Sent;
+        item.last_try_at = now;
+        self.storage.update_outbox_item(item).await
     }
 
-    /// NIP-20 OK受信時の処理
-    pub async fn on_ok(&mut self, event_id: &str, accepted: bool, message: &str) -> Result<()> {
-        // event_idからreq_idを探す（簡易実装: event_jsonをパースして確認）
-        let mut found_req_id = None;
-        
-        for item in &self.pending {
-            if item.event_json.contains(event_id) {
-                found_req_id = Some(item.req_id.clone());
-                break;
-            }
-        }
+    /// `auth-required:`で拒否されたことを記録（認証が終わるまでタイムアウトも再送もしない）
+    pub async fn await_auth(&mut self, event_id: &str, relay_url: &str) -> Result<()> {
+        let now = self.clock.now();
+        let Some(item) = self.pending.iter_mut().find(|item| item.event_id == event_id) else {
+            return Ok(());
+        };
+        let attempts = item.receipt(relay_url).map_or(1, |r| r.attempts);
+        set_receipt(item, relay_url, DeliveryStatus::AwaitingAuth, now, attempts);
+        self.storage.update_outbox_item(item).await
+    }
 
-        if let Some(req_id) = found_req_id {
+    /// Relayの認証結果を反映
+    ///
+    /// 成功したら認証待ちのイベントは再送済み（OK待ち）に戻す。
+    /// 失敗または拒否したら、そのRelayへの配送は諦める。
+    pub async fn on_auth_result(&mut self, relay_url: &str, accepted: bool, message: &str) -> Result<()> {
+        let now = self.clock.now();
+        for item in &mut self.pending {
+            let Some(receipt) = item.receipt(relay_url).filter(|r| r.status == DeliveryStatus::AwaitingAuth) else {
+                continue;
+            };
+            let attempts = receipt.attempts;
             if accepted {
-                // 成功: ストレージから削除してからキューから削除
-                // 順序を逆にしてエラーを防ぐ
-                if let Err(e) = self.storage.update_outbox_status(&req_id, OutboxStatus::Ok).await {
-                    log::warn!("Failed to update outbox status: {:?}", e);
-                }
-                self.pending.retain(|item| item.req_id != req_id);
-                log::info!("Event {} accepted", event_id);
+                set_receipt(item, relay_url, DeliveryStatus::Pending, now, attempts + 1);
             } else {
-                // 拒否: エラーとしてマーク
-                if let Some(item) = self.pending.iter_mut().find(|i| i.req_id == req_id) {
-                    item.status = OutboxStatus::Error;
-                    item.error = Some(message.to_string());
+                let status = DeliveryStatus::Rejected { reason: message.to_string(), permanent: true };
+                set_receipt(item, relay_url, status, now, attempts);
+            }
+            update_status(item, self.quorum);
+            self.storage.update_outbox_item(item).await?;
+        }
+        self.settle_finished();
+        Ok(())
+    }
+
+    /// 再送すべき（Relay URL, イベント）
+    ///
+    /// OKが返らないまま`OK_TIMEOUT_SECONDS`経ったRelayはタイムアウトとし、
+    /// 一時的な拒否やタイムアウトのRelayにだけ再送する。認証待ちのRelayは対象外。
+    pub fn due_retries(&mut self) -> Vec<(String, NostrEvent)> {
+        let now = self.clock.now();
+        let mut retries = Vec::new();
+        for item in &mut self.pending {
+            for receipt in &mut item.receipts {
+                if receipt.status == DeliveryStatus::Pending && now - receipt.sent_at >= OK_TIMEOUT_SECONDS {
+                    log::warn!("Event {} timed out on {}", item.event_id, receipt.relay_url);
+                    receipt.status = DeliveryStatus::TimedOut;
                 }
-                if let Err(e) = self.storage.update_outbox_status(&req_id, OutboxStatus::Error).await {
-                    log::warn!("Failed to update outbox status: {:?}", e);
+                let waiting = matches!(
+                    receipt.status,
+                    DeliveryStatus::TimedOut | DeliveryStatus::Rejected { permanent: false, .. }
+                );
+                let delay = RETRY_DELAY_SECONDS * receipt.attempts as i64;
+                if waiting && can_still_accept(receipt) && now - receipt.sent_at >= delay {
+                    retries.push((receipt.relay_url.clone(), item.event.clone()));
                 }
-                log::warn!("Event {} rejected: {}", event_id, message);
             }
+            update_status(item, self.quorum);
         }
+        self.settle_finished();
+        retries
+    }
 
-        Ok(())
+    /// 配送状況（配送が終わったものも直近`MAX_FINISHED`件まで）
+    pub fn delivery(&self, event_id: &str) -> Option<DeliverySummary> {
+        self.pending
+            .iter()
+            .chain(&self.finished)
+            .find(|item| item.event_id == event_id)
+            .map(summarize)
     }
 
-    /// 失敗したアイテムを再送
-    pub async fn retry_failed(&mut self) -> Result<()> {
-        let now = current_timestamp();
+    /// 全ての配送状況（イベントID, 配送状況）
+    pub fn deliveries(&self) -> Vec<(String, DeliverySummary)> {
+        self.pending
+            .iter()
+            .chain(&self.finished)
+            .map(|item| (item.event_id.clone(), summarize(item)))
+            .collect()
+    }
 
-        for item in &mut self.pending {
-            if item.status == OutboxStatus::Error && item.retry_count < MAX_RETRY_COUNT {
-                let elapsed = now - item.last_try_at;
-                if elapsed >= RETRY_DELAY_SECONDS * (item.retry_count as i64) {
-                    item.status = OutboxStatus::Queued;
-                    item.error = None;
-                }
+    /// 配送済みまたは再送の見込みがなくなったアイテムを送信キューから外す
+    fn settle_finished(&mut self) {
+        let (finished, pending): (VecDeque<_>, VecDeque<_>) =
+            std::mem::take(&mut self.pending).into_iter().partition(is_finished);
+        self.pending = pending;
+        for item in finished {
+            self.push_finished(item);
+        }
+    }
+
+    /// 配送が終わったアイテムを配送記録のために一定数残す
+    fn push_finished(&mut self, item: OutboxItem) {
+        self.finished.push_back(item);
+        if self.finished.len() > MAX_FINISHED {
+            self.finished.pop_front();
+        }
+    }
+
+    /// NIP-20 OK受信時の処理
+    pub async fn on_ok(&mut self, event_id: &str, relay_url: &str, accepted: bool, message: &str) -> Result<()> {
+        let now = self.clock.now();
+        let reason = RelayReason::parse(message);
+        // duplicate: はRelayが既に持っているので成功扱い
+        let accepted = accepted || reason.is(ReasonPrefix::Duplicate);
+        let permanent = reason.prefix.is_some_and(|p| p.is_permanent());
+        let status = if accepted {
+            DeliveryStatus::Accepted
+        } else {
+            DeliveryStatus::Rejected { reason: message.to_string(), permanent }
+        };
+
+        let Some(index) = self.pending.iter().position(|item| item.event_id == event_id) else {
+            // 配送が終わったイベントへの遅れたOKは記録だけ更新
+            if let Some(item) = self.finished.iter_mut().find(|item| item.event_id == event_id) {
+                let attempts = item.receipt(relay_url).map_or(1, |r| r.attempts);
+                set_receipt(item, relay_url, status, now, attempts);
             }
+            return Ok(());
+        };
+
+        let item = &mut self.pending[index];
+        let attempts = item.receipt(relay_url).map_or(1, |r| r.attempts);
+        set_receipt(item, relay_url, status, now, attempts);
+        if accepted {
+            log::info!("Event {} accepted by {} ({}/{})", event_id, relay_url, item.accepted_count(), self.quorum);
+        } else {
+            item.error = Some(message.to_string());
+            log::warn!("Event {} rejected by {}: {}", event_id, relay_url, message);
+        }
+        update_status(item, self.quorum);
+        if let Err(e) = self.storage.update_outbox_item(item).await {
+            log::warn!("Failed to update outbox item: {:?}", e);
+        }
+
+        // 配送済み、または全てのRelayに恒久的に拒否されたら送信キューから外す
+        if is_finished(item) {
+            let item = self.pending.remove(index).unwrap();
+            self.push_finished(item);
         }
 
         Ok(())
@@ -194,60 +306,137 @@ impl OutboxQueue {
     }
 }
 
-/// リクエストID生成
-fn generate_req_id() -> String {
-    use js_sys::Math;
-    let random = Math::random();
-    let timestamp = js_sys::Date::now();
-    format!("req_{}_{}", timestamp as u64, (random * 1000000.0) as u64)
+/// Relayの配送記録を更新（なければ追加）
+fn set_receipt(item: &mut OutboxItem, relay_url: &str, status: DeliveryStatus, now: i64, attempts: u32) {
+    match item.receipts.iter_mut().find(|r| r.relay_url == relay_url) {
+        Some(receipt) => {
+            // OK待ちに戻すときだけ送信時刻を更新
+            if status == DeliveryStatus::Pending {
+                receipt.sent_at = now;
+            }
+            receipt.status = status;
+            receipt.attempts = attempts;
+        }
+        None => item.receipts.push(RelayReceipt {
+            relay_url: relay_url.to_string(),
+            status,
+            sent_at: now,
+            attempts,
+        }),
+    }
+}
+
+/// このRelayに今後受理される可能性があるか
+fn can_still_accept(receipt: &RelayReceipt) -> bool {
+    match &receipt.status {
+        DeliveryStatus::Accepted => false,
+        DeliveryStatus::Rejected { permanent: true, .. } => false,
+        DeliveryStatus::Pending | DeliveryStatus::AwaitingAuth => true,
+        _ => receipt.attempts < MAX_RETRY_COUNT,
+    }
+}
+
+/// 配送記録からアイテムのステータスを決める
+///
+/// `quorum`個のRelayに受理されたら配送済み。これ以上受理されうるRelayがなければ、
+/// 1つでも受理されていれば配送済み、なければエラー。
+fn update_status(item: &mut OutboxItem, quorum: usize) {
+    let accepted = item.accepted_count();
+    let open = item.receipts.iter().any(can_still_accept);
+    let waiting = item
+        .receipts
+        .iter()
+        .any(|r| matches!(r.status, DeliveryStatus::Pending | DeliveryStatus::AwaitingAuth));
+
+    if accepted >= quorum || (accepted > 0 && !open) {
+        item.status = OutboxStatus::Ok;
+        item.error = None;
+    } else if !waiting && !item.receipts.is_empty() {
+        item.status = OutboxStatus::Error;
+    } else if waiting {
+        item.status = OutboxStatus::Sent;
+    }
+}
+
+/// 配送が終わったか（配送済み、または受理されうるRelayが残っていない）
+fn is_finished(item: &OutboxItem) -> bool {
+    match item.status {
+        OutboxStatus::Ok => true,
+        OutboxStatus::Error => !item.receipts.is_empty() && !item.receipts.iter().any(can_still_accept),
+        _ => false,
+    }
 }
 
-/// 現在のUNIXタイムスタンプ（秒）
-fn current_timestamp() -> i64 {
-    (js_sys::Date::now() / 1000.0) as i64
+/// 配送状況をまとめる
+fn summarize(item: &OutboxItem) -> DeliverySummary {
+    DeliverySummary {
+        accepted: item.accepted_count(),
+        total: item.receipts.len(),
+        delivered: item.status == OutboxStatus::Ok,
+        receipts: item.receipts.clone(),
+    }
+}
+
+/// リクエストID生成
+fn generate_req_id(timestamp_millis: i64) -> String {
+    let mut buf = [0u8; 4];
+    let random = match getrandom::getrandom(&mut buf) {
+        Ok(()) => u32::from_le_bytes(buf) % 1_000_000,
+        Err(_) => 0,
+    };
+    format!("req_{}_{}", timestamp_millis, random)
 }
 
-#[cfg(all(test, target_arch = "wasm32"))]
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
     use crate::storage::mock::MockStorage;
-    use wasm_bindgen_test::*;
 
-    wasm_bindgen_test_configure!(run_in_browser);
+    const RELAY: &str = "wss://relay.example";
+
+    fn event(id: &str) -> NostrEvent {
+        NostrEvent {
+            id: id.to_string(),
+            pubkey: String::new(),
+            created_at: 0,
+            kind: 1,
+            tags: Vec::new(),
+            content: String::new(),
+            sig: String::new(),
+        }
+    }
 
-    #[wasm_bindgen_test]
+    #[tokio::test]
     async fn test_enqueue() {
         let storage = Arc::new(MockStorage::new());
         let mut queue = OutboxQueue::new(storage);
 
-        let event_json = r#"{"id":"test","kind":1}"#.to_string();
-        let req_id = queue.enqueue(event_json).await.unwrap();
+        let req_id = queue.enqueue(event("test")).await.unwrap();
 
         assert!(!req_id.is_empty());
         assert_eq!(queue.len(), 1);
     }
 
-    #[wasm_bindgen_test]
+    #[tokio::test]
     async fn test_on_ok_accepted() {
         let storage = Arc::new(MockStorage::new());
         let mut queue = OutboxQueue::new(storage);
 
-        let event_json = r#"{"id":"event123","kind":1}"#.to_string();
-        queue.enqueue(event_json).await.unwrap();
+        queue.enqueue(event("event123")).await.unwrap();
 
-        queue.on_ok("event123", true, "").await.unwrap();
+        queue.on_ok("event123", RELAY, true, "").await.unwrap();
         assert_eq!(queue.len(), 0);
     }
 
-    #[wasm_bindgen_test]
+    #[tokio::test]
     async fn test_on_ok_rejected() {
         let storage = Arc::new(MockStorage::new());
         let mut queue = OutboxQueue::new(storage);
 
-        let event_json = r#"{"id":"event123","kind":1}"#.to_string();
-        queue.enqueue(event_json).await.unwrap();
+        queue.enqueue(event("event123")).await.unwrap();
 
-        queue.on_ok("event123", false, "duplicate").await.unwrap();
+        queue.on_ok("event123", RELAY, false, "duplicate").await.unwrap();
         
         // エラーステータスになっているが、キューには残っている
         assert_eq!(queue.len(), 1);
@@ -255,5 +444,123 @@ mod tests {
         assert_eq!(item.status, OutboxStatus::Error);
         assert!(item.error.is_some());
     }
-}
 
+    #[tokio::test]
+    async fn test_on_ok_duplicate_counts_as_accepted() {
+        let storage = Arc::new(MockStorage::new());
+        let mut queue = OutboxQueue::new(storage);
+
+        queue.enqueue(event("event123")).await.unwrap();
+        queue.on_ok("event123", RELAY, false, "duplicate: already have this event").await.unwrap();
+        assert!(queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_on_ok_permanent_rejection_is_not_retried() {
+        let storage = Arc::new(MockStorage::new());
+        let mut queue = OutboxQueue::new(storage);
+
+        queue.enqueue(event("pow1")).await.unwrap();
+        queue.enqueue(event("rate1")).await.unwrap();
+        queue.on_ok("pow1", RELAY, false, "pow: difficulty 10 is less than 20").await.unwrap();
+        queue.on_ok("rate1", RELAY, false, "rate-limited: slow down").await.unwrap();
+
+        // 恒久的に拒否されたものはキューから外し、配送記録だけ残す
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.pending[0].event_id, "rate1");
+        assert_eq!(queue.pending[0].retry_count, 0);
+        let delivery = queue.delivery("pow1").unwrap();
+        assert!(!delivery.delivered);
+        assert_eq!(delivery.total, 1);
+    }
+
+    #[tokio::test]
+    async fn test_quorum_and_receipts() {
+        let storage = Arc::new(MockStorage::new());
+        let mut queue = OutboxQueue::new(storage);
+        queue.set_quorum(2);
+
+        queue.enqueue(event("ev")).await.unwrap();
+        queue.dequeue().await.unwrap();
+        for relay in ["wss://a", "wss://b", "wss://c"] {
+            queue.mark_sent("ev", relay).await.unwrap();
+        }
+
+        queue.on_ok("ev", "wss://a", true, "").await.unwrap();
+        assert_eq!(queue.len(), 1);
+        queue.on_ok("ev", "wss://b", false, "blocked: no").await.unwrap();
+        assert_eq!(queue.len(), 1);
+        queue.on_ok("ev", "wss://c", true, "").await.unwrap();
+        assert!(queue.is_empty());
+
+        let delivery = queue.delivery("ev").unwrap();
+        assert!(delivery.delivered);
+        assert_eq!((delivery.accepted, delivery.total), (2, 3));
+    }
+
+    #[tokio::test]
+    async fn test_targeted_retry_after_rejection_and_timeout() {
+        let storage = Arc::new(MockStorage::new());
+        let clock = Arc::new(MockClock::new(1_000));
+        let mut queue = OutboxQueue::with_clock(storage, clock.clone());
+        queue.set_quorum(3);
+
+        queue.enqueue(event("ev")).await.unwrap();
+        queue.dequeue().await.unwrap();
+        for relay in ["wss://a", "wss://b", "wss://c"] {
+            queue.mark_sent("ev", relay).await.unwrap();
+        }
+        queue.on_ok("ev", "wss://a", true, "").await.unwrap();
+        queue.on_ok("ev", "wss://b", false, "rate-limited: slow down").await.unwrap();
+        assert!(queue.due_retries().is_empty());
+
+        // 拒否したRelayとOKを返さないRelayにだけ再送
+        clock.advance(OK_TIMEOUT_SECONDS);
+        let mut retries: Vec<String> = queue.due_retries().into_iter().map(|(url, _)| url).collect();
+        retries.sort();
+        assert_eq!(retries, vec!["wss://b", "wss://c"]);
+        assert_eq!(
+            queue.delivery("ev").unwrap().receipts[2].status,
+            DeliveryStatus::TimedOut
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unacknowledged_event_does_not_block_later_ones() {
+        let storage = Arc::new(MockStorage::new());
+        let mut queue = OutboxQueue::new(storage);
+        queue.set_quorum(3);
+
+        queue.enqueue(event("first")).await.unwrap();
+        queue.enqueue(event("second")).await.unwrap();
+        assert_eq!(queue.dequeue().await.unwrap().unwrap().id, "first");
+        for relay in ["wss://a", "wss://b", "wss://c"] {
+            queue.mark_sent("first", relay).await.unwrap();
+        }
+        queue.on_ok("first", "wss://a", true, "").await.unwrap();
+        queue.on_ok("first", "wss://b", true, "").await.unwrap();
+
+        // 1つのRelayがOKを返さなくても次のイベントは送る
+        assert_eq!(queue.dequeue().await.unwrap().unwrap().id, "second");
+        assert!(queue.dequeue().await.unwrap().is_none());
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_delivered_with_fewer_relays_than_quorum() {
+        let storage = Arc::new(MockStorage::new());
+        let mut queue = OutboxQueue::new(storage);
+        queue.set_quorum(3);
+
+        queue.enqueue(event("ev")).await.unwrap();
+        queue.dequeue().await.unwrap();
+        queue.mark_sent("ev", "wss://a").await.unwrap();
+        queue.mark_sent("ev", "wss://b").await.unwrap();
+        queue.on_ok("ev", "wss://a", true, "").await.unwrap();
+        queue.on_ok("ev", "wss://b", false, "invalid: bad").await.unwrap();
+
+        // これ以上受理されうるRelayがないので配送済み
+        assert!(queue.is_empty());
+        assert_eq!(queue.delivery("ev").unwrap().accepted, 1);
+    }
+}
@@ -1,11 +1,18 @@
-use std::collections::{HashMap, HashSet};
-use std::rc::Rc;
-use std::cell::RefCell;
-use web_sys::{WebSocket, MessageEvent, ErrorEvent, CloseEvent};
-use wasm_bindgen::{JsCast, closure::Closure};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
 use serde_json;
 
+use crate::clock::Clock;
 use crate::error::{Result, CoreError};
+use crate::filter::Filter;
+use crate::supervisor::{ConnectionSupervisor, DisconnectReason, SupervisorAction, SupervisorConfig, PING_FILTER, PING_SUB_ID};
+use crate::types::RelayConfig;
+use crate::event::NostrEvent;
+use crate::transport::{Transport, TransportConnection, TransportEvent, TransportSink};
+
+/// 検証に失敗したイベントを数える期間（秒）
+const INVALID_EVENT_WINDOW_SECS: i64 = 10 * 60;
 
 /// 接続状態
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -23,6 +30,12 @@ pub struct ExponentialBackoff {
     min_delay: u32,
 }
 
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ExponentialBackoff {
     pub fn new() -> Self {
         Self {
@@ -43,137 +56,405 @@ impl ExponentialBackoff {
     }
 }
 
+/// NIP-42 認証ポリシー
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AuthPolicy {
+    /// チャレンジを受けたら常に認証する
+    Always,
+    /// ユーザーに確認する
+    #[default]
+    Ask,
+    /// 認証しない
+    Never,
+}
+
+/// NIP-42 認証状態
+#[derive(Debug, Clone, Default)]
+struct AuthState {
+    challenge: Option<String>,
+    event_id: Option<String>,
+    authenticated: bool,
+    denied: bool,
+    // 認証後に再送するメッセージ
+    retry: Vec<String>,
+}
+
+/// 一時的な理由でRelayに終了された購読の再送
+#[derive(Debug, Clone, Default)]
+struct SubscriptionRetry {
+    /// 次に再送する時刻（再送済みならNone）
+    retry_at: Option<f64>,
+    backoff: ExponentialBackoff,
+}
+
 /// Relay接続
 pub struct RelayConnection {
     pub url: String,
-    ws: Option<WebSocket>,
-    state: Rc<RefCell<ConnectionState>>,
-    backoff: ExponentialBackoff,
-    subscriptions: HashMap<String, String>, // sub_id -> filter_json
+    transport: Arc<dyn Transport>,
+    clock: Arc<dyn Clock>,
+    conn: Option<Box<dyn TransportConnection>>,
+    sink: TransportSink,
+    state: ConnectionState,
+    supervisor: ConnectionSupervisor,
+    subscriptions: HashMap<String, Filter>,
+    queued_subscriptions: VecDeque<(String, Filter)>, // 購読数の上限を超えたため空きを待つ購読
+    max_subscriptions: Option<usize>,
+    subscription_retries: HashMap<String, SubscriptionRetry>, // 一時的な理由で終了され、再送を待つ購読
+    last_event_at: HashMap<String, i64>,    // sub_id -> 最後に受信したイベントのcreated_at
     eose_received: HashSet<String>,
-    last_connect_attempt: f64,
-    message_queue: Rc<RefCell<Vec<RelayMessage>>>,
-    // クロージャを保持してドロップされないようにする
-    _on_open: Option<Closure<dyn FnMut()>>,
-    _on_message: Option<Closure<dyn FnMut(MessageEvent)>>,
-    _on_error: Option<Closure<dyn FnMut(ErrorEvent)>>,
-    _on_close: Option<Closure<dyn FnMut(CloseEvent)>>,
+    message_queue: Vec<RelayMessage>,
+    opened: bool,
+    pending: Vec<String>, // 接続確立後に送るメッセージ
+    invalid_events: VecDeque<i64>, // 検証に失敗したイベントを受信した時刻
+    read: bool,
+    write: bool,
+    auth_policy: AuthPolicy,
+    auth: AuthState,
 }
 
 impl RelayConnection {
-    pub fn new(url: String) -> Self {
+    pub fn new(url: String, transport: Arc<dyn Transport>, clock: Arc<dyn Clock>) -> Self {
+        Self::with_config(url, transport, clock, SupervisorConfig::default())
+    }
+
+    /// Supervisorの設定を指定して作成
+    pub fn with_config(
+        url: String,
+        transport: Arc<dyn Transport>,
+        clock: Arc<dyn Clock>,
+        config: SupervisorConfig,
+    ) -> Self {
         Self {
             url,
-            ws: None,
-            state: Rc::new(RefCell::new(ConnectionState::Disconnected)),
-            backoff: ExponentialBackoff::new(),
+            transport,
+            clock,
+            conn: None,
+            sink: TransportSink::new(),
+            state: ConnectionState::Disconnected,
+            supervisor: ConnectionSupervisor::new(config),
             subscriptions: HashMap::new(),
+            queued_subscriptions: VecDeque::new(),
+            max_subscriptions: None,
+            subscription_retries: HashMap::new(),
+            last_event_at: HashMap::new(),
             eose_received: HashSet::new(),
-            last_connect_attempt: 0.0,
-            message_queue: Rc::new(RefCell::new(Vec::new())),
-            _on_open: None,
-            _on_message: None,
-            _on_error: None,
-            _on_close: None,
+            message_queue: Vec::new(),
+            opened: false,
+            pending: Vec::new(),
+            invalid_events: VecDeque::new(),
+            read: true,
+            write: true,
+            auth_policy: AuthPolicy::default(),
+            auth: AuthState::default(),
         }
     }
 
     pub fn state(&self) -> ConnectionState {
-        *self.state.borrow()
+        self.state
     }
 
     pub fn is_connected(&self) -> bool {
-        *self.state.borrow() == ConnectionState::Connected
+        self.state == ConnectionState::Connected
     }
 
     /// 接続試行
     pub async fn connect(&mut self) -> Result<()> {
-        let current_state = *self.state.borrow();
-        if current_state == ConnectionState::Connecting || current_state == ConnectionState::Connected {
+        if self.state == ConnectionState::Connecting || self.state == ConnectionState::Connected {
             return Ok(());
         }
 
-        *self.state.borrow_mut() = ConnectionState::Connecting;
-        self.last_connect_attempt = now();
+        self.state = ConnectionState::Connecting;
+        let now = self.now();
+        self.supervisor.on_connecting(now);
 
-        let ws = WebSocket::new(&self.url)?;
-        ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+        // 接続ごとに新しいsinkを使い、古いソケットのイベントを混ぜない
+        let sink = TransportSink::new();
+        match self.transport.connect(&self.url, sink.clone()) {
+            Ok(conn) => {
+                self.sink = sink;
+                self.conn = Some(conn);
+                Ok(())
+            }
+            Err(e) => {
+                self.state = ConnectionState::Disconnected;
+                self.supervisor.on_disconnect(now, DisconnectReason::ConnectFailed(e.to_string()));
+                Err(e)
+            }
+        }
+    }
 
-        // 状態を共有
-        let state = self.state.clone();
-        let message_queue = self.message_queue.clone();
-        let url = self.url.clone();
+    /// こちらから切断する（自動再接続はバックオフ後に行われる）
+    pub fn disconnect(&mut self) {
+        self.force_close(DisconnectReason::Manual);
+    }
 
-        // onopen ハンドラー
-        {
-            let state = state.clone();
-            let url = url.clone();
-            let on_open = Closure::wrap(Box::new(move || {
-                log::info!("WebSocket connected to {}", url);
-                *state.borrow_mut() = ConnectionState::Connected;
-            }) as Box<dyn FnMut()>);
-            ws.set_onopen(Some(on_open.as_ref().unchecked_ref()));
-            self._on_open = Some(on_open);
+    /// 接続を閉じて切断扱いにする
+    fn force_close(&mut self, reason: DisconnectReason) {
+        if let Some(conn) = self.conn.take() {
+            conn.close();
         }
+        // 古いソケットから遅れて届くイベントは捨てる
+        self.sink = TransportSink::new();
+        if self.state != ConnectionState::Disconnected {
+            self.on_close(reason);
+        }
+    }
 
-        // onmessage ハンドラー
-        {
-            let message_queue = message_queue.clone();
-            let on_message = Closure::wrap(Box::new(move |event: MessageEvent| {
-                if let Some(text) = event.data().as_string() {
+    /// トランスポートから届いたイベントを処理し、接続の健全性をチェック
+    pub fn poll(&mut self) {
+        for event in self.sink.drain() {
+            match event {
+                TransportEvent::Open => self.on_open(),
+                TransportEvent::Message(text) => {
+                    let now = self.now();
+                    self.supervisor.on_activity(now);
                     match RelayMessage::parse(&text) {
-                        Ok(msg) => {
-                            message_queue.borrow_mut().push(msg);
-                        }
-                        Err(e) => {
-                            log::warn!("Failed to parse relay message: {:?}", e);
+                        Ok(msg) => self.on_message(msg),
+                        // 不正な形式のイベントは検証失敗と同じく数える
+                        Err(CoreError::InvalidEvent(e)) => {
+                            log::warn!("Dropping malformed event from {}: {}", self.url, e);
+                            self.record_invalid_event();
                         }
+                        Err(e) => log::warn!("Failed to parse relay message: {:?}", e),
                     }
                 }
-            }) as Box<dyn FnMut(MessageEvent)>);
-            ws.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
-            self._on_message = Some(on_message);
-        }
-
-        // onerror ハンドラー
-        {
-            let url = url.clone();
-            let on_error = Closure::wrap(Box::new(move |_event: ErrorEvent| {
-                log::error!("WebSocket error on {}", url);
-            }) as Box<dyn FnMut(ErrorEvent)>);
-            ws.set_onerror(Some(on_error.as_ref().unchecked_ref()));
-            self._on_error = Some(on_error);
-        }
-
-        // onclose ハンドラー
-        {
-            let state = state.clone();
-            let url = url.clone();
-            let on_close = Closure::wrap(Box::new(move |_event: CloseEvent| {
-                log::info!("WebSocket closed for {}", url);
-                *state.borrow_mut() = ConnectionState::Disconnected;
-            }) as Box<dyn FnMut(CloseEvent)>);
-            ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
-            self._on_close = Some(on_close);
-        }
-
-        self.ws = Some(ws);
-        Ok(())
+                TransportEvent::Error(e) => self.on_error(&e),
+                TransportEvent::Closed { code, reason } => {
+                    self.on_close(DisconnectReason::Closed { code, reason });
+                }
+            }
+        }
+        self.send_due_subscription_retries();
+        self.supervise();
+    }
+
+    /// Supervisorのチェック結果を実行
+    fn supervise(&mut self) {
+        let now = self.now();
+        match self.supervisor.check(now, self.is_connected()) {
+            SupervisorAction::None => {}
+            SupervisorAction::SendPing => {
+                if let Some(conn) = &self.conn {
+                    let req = format!(r#"["REQ","{}",{}]"#, PING_SUB_ID, PING_FILTER);
+                    if let Err(e) = conn.send(&req) {
+                        log::warn!("Failed to send ping to {}: {:?}", self.url, e);
+                    }
+                }
+            }
+            SupervisorAction::Disconnect(reason) => {
+                log::warn!("Closing stale connection to {}: {:?}", self.url, reason);
+                self.force_close(reason);
+            }
+        }
+    }
+
+    /// 受信メッセージを処理
+    fn on_message(&mut self, msg: RelayMessage) {
+        match &msg {
+            // キープアライブの応答は上位に渡さない
+            RelayMessage::Eose { sub_id } if sub_id == PING_SUB_ID => {
+                if let Some(conn) = &self.conn {
+                    let _ = conn.send(&format!(r#"["CLOSE","{}"]"#, PING_SUB_ID));
+                }
+                return;
+            }
+            RelayMessage::Event { sub_id, .. } if sub_id == PING_SUB_ID => return,
+            // 再送した購読が受け付けられたらバックオフを戻す
+            RelayMessage::Eose { sub_id } if self.subscription_retries.get(sub_id).is_some_and(|r| r.retry_at.is_none()) => {
+                self.subscription_retries.remove(sub_id);
+            }
+            _ => {}
+        }
+        self.message_queue.push(msg);
     }
 
     /// メッセージ送信
     pub async fn send(&self, msg: &str) -> Result<()> {
-        if let Some(ws) = &self.ws {
-            if *self.state.borrow() == ConnectionState::Connected {
-                ws.send_with_str(msg)?;
+        if let Some(conn) = &self.conn {
+            if self.state == ConnectionState::Connected {
+                conn.send(msg)?;
             }
         }
         Ok(())
     }
 
+    /// 接続中なら送信し、未接続なら接続確立後に送る
+    pub async fn send_or_queue(&mut self, msg: &str) -> Result<()> {
+        if self.is_connected() {
+            return self.send(msg).await;
+        }
+        if !self.pending.iter().any(|m| m == msg) {
+            self.pending.push(msg.to_string());
+        }
+        Ok(())
+    }
+
     /// 購読追加
-    pub fn add_subscription(&mut self, sub_id: String, filter_json: String) {
-        self.subscriptions.insert(sub_id, filter_json);
+    pub fn add_subscription(&mut self, sub_id: String, filter: Filter) {
+        self.subscriptions.insert(sub_id, filter);
+    }
+
+    /// 購読を登録してREQを送信
+    ///
+    /// 未接続の場合は登録だけ行い、接続確立時に送信される。
+    /// 購読数が上限に達していれば、他の購読が終わるまで待たせる。
+    pub async fn subscribe(&mut self, sub_id: &str, filter: &Filter) -> Result<()> {
+        if !self.subscriptions.contains_key(sub_id) && self.at_subscription_limit() {
+            match self.queued_subscriptions.iter_mut().find(|(id, _)| id == sub_id) {
+                Some(queued) => queued.1 = filter.clone(),
+                None => self.queued_subscriptions.push_back((sub_id.to_string(), filter.clone())),
+            }
+            log::info!("Subscription {} queued on {} (limit reached)", sub_id, self.url);
+            return Ok(());
+        }
+        self.add_subscription(sub_id.to_string(), filter.clone());
+        self.eose_received.remove(sub_id);
+        self.send(&req_message(sub_id, filter)).await
+    }
+
+    /// 購読を削除（空きができたら待っている購読を開始する）
+    pub fn remove_subscription(&mut self, sub_id: &str) {
+        self.subscriptions.remove(sub_id);
+        self.subscription_retries.remove(sub_id);
+        self.queued_subscriptions.retain(|(id, _)| id != sub_id);
+        self.last_event_at.remove(sub_id);
+        self.eose_received.remove(sub_id);
+        self.start_queued_subscriptions();
+    }
+
+    /// 購読を終了する。Relay上で開いている購読にはCLOSEを送る
+    pub async fn unsubscribe(&mut self, sub_id: &str) -> Result<()> {
+        if self.subscriptions.contains_key(sub_id) {
+            self.send(&format!(r#"["CLOSE","{}"]"#, sub_id)).await?;
+        }
+        self.remove_subscription(sub_id);
+        Ok(())
+    }
+
+    /// すべての購読を終了する
+    pub async fn unsubscribe_all(&mut self) -> Result<()> {
+        self.queued_subscriptions.clear();
+        let sub_ids: Vec<String> = self.subscriptions.keys().cloned().collect();
+        for sub_id in sub_ids {
+            self.unsubscribe(&sub_id).await?;
+        }
+        Ok(())
+    }
+
+    /// 同時に開く購読数の上限（NIP-11 の`limitation.max_subscriptions`）
+    pub fn max_subscriptions(&self) -> Option<usize> {
+        self.max_subscriptions
+    }
+
+    /// 同時に開く購読数の上限を設定
+    ///
+    /// NIP-11 のRelay情報は取得しないので、`limitation.max_subscriptions`の値を設定で与える。
+    /// キープアライブのping（`PING_SUB_ID`）用に1つ空けておく。
+    pub fn set_max_subscriptions(&mut self, max: Option<usize>) {
+        self.max_subscriptions = max;
+        self.start_queued_subscriptions();
+    }
+
+    /// 購読数の上限により待っているか
+    pub fn is_subscription_queued(&self, sub_id: &str) -> bool {
+        self.queued_subscriptions.iter().any(|(id, _)| id == sub_id)
+    }
+
+    /// 購読数が上限に達したか（pingの分を除く。上限が1なら購読を1つ開く）
+    fn at_subscription_limit(&self) -> bool {
+        self.max_subscriptions
+            .is_some_and(|max| self.subscriptions.len() >= max.saturating_sub(1).max(1))
+    }
+
+    /// 空きがある分だけ、待っている購読を開始する（未接続なら接続確立時に送信される）
+    fn start_queued_subscriptions(&mut self) {
+        while !self.at_subscription_limit() {
+            let Some((sub_id, filter)) = self.queued_subscriptions.pop_front() else {
+                break;
+            };
+            let req = req_message(&sub_id, &filter);
+            self.add_subscription(sub_id, filter);
+            let Some(conn) = self.conn.as_ref().filter(|_| self.state == ConnectionState::Connected) else {
+                continue;
+            };
+            if let Err(e) = conn.send(&req) {
+                log::error!("Failed to send queued subscription to {}: {:?}", self.url, e);
+            }
+        }
+    }
+
+    /// 一時的な理由（`rate-limited:`、`error:`）でRelayに終了された購読を、バックオフ後に再送する
+    pub fn retry_subscription_later(&mut self, sub_id: &str) {
+        if !self.subscriptions.contains_key(sub_id) {
+            return;
+        }
+        let now = self.now();
+        let retry = self.subscription_retries.entry(sub_id.to_string()).or_default();
+        let delay = retry.backoff.next_delay();
+        retry.retry_at = Some(now + delay as f64);
+        log::info!("Retrying subscription {} on {} in {}s", sub_id, self.url, delay);
+    }
+
+    /// 再送時刻になった購読を送る（sinceは最後に受信したイベントまで進める）
+    fn send_due_subscription_retries(&mut self) {
+        let Some(conn) = self.conn.as_ref().filter(|_| self.state == ConnectionState::Connected) else {
+            return;
+        };
+        let now = self.now();
+        for (sub_id, retry) in &mut self.subscription_retries {
+            if retry.retry_at.is_none_or(|at| at > now) {
+                continue;
+            }
+            retry.retry_at = None;
+            let Some(filter) = self.subscriptions.get(sub_id) else {
+                continue;
+            };
+            let filter = match self.last_event_at.get(sub_id) {
+                Some(&last) => filter_with_since(filter, last),
+                None => filter.clone(),
+            };
+            log::info!("Resending subscription {} on {}", sub_id, self.url);
+            if let Err(e) = conn.send(&req_message(sub_id, &filter)) {
+                log::error!("Failed to resend subscription {} on {}: {:?}", sub_id, self.url, e);
+            }
+        }
+    }
+
+    /// 登録済みの購読
+    pub fn subscriptions(&self) -> &HashMap<String, Filter> {
+        &self.subscriptions
+    }
+
+    /// 接続確立（再接続を含む）を検知したか。呼ぶとフラグはクリアされる
+    pub fn take_opened(&mut self) -> bool {
+        std::mem::take(&mut self.opened)
+    }
+
+    /// 登録済みの購読を再送信（sinceは最後に受信したイベントまで進める）
+    fn replay_subscriptions(&mut self) {
+        let Some(conn) = &self.conn else {
+            return;
+        };
+        for (sub_id, filter) in &self.subscriptions {
+            let filter = match self.last_event_at.get(sub_id) {
+                Some(&last) => filter_with_since(filter, last),
+                None => filter.clone(),
+            };
+            let req = req_message(sub_id, &filter);
+            log::info!("Replaying subscription {} on {}", sub_id, self.url);
+            if let Err(e) = conn.send(&req) {
+                log::error!("Failed to replay subscription {} on {}: {:?}", sub_id, self.url, e);
+            }
+        }
+    }
+
+    /// 購読ごとの最新イベント時刻を記録（署名を検証したイベントだけ。再接続時の`since`に使う）
+    pub fn record_event_time(&mut self, sub_id: &str, created_at: i64) {
+        if !self.subscriptions.contains_key(sub_id) {
+            return;
+        }
+        let last = self.last_event_at.entry(sub_id.to_string()).or_insert(created_at);
+        *last = (*last).max(created_at);
     }
 
     /// EOSE受信記録
@@ -188,23 +469,163 @@ impl RelayConnection {
 
     /// 受信メッセージを取得（キューをクリア）
     pub fn drain_messages(&mut self) -> Vec<RelayMessage> {
-        self.message_queue.borrow_mut().drain(..).collect()
+        self.poll();
+        self.message_queue.drain(..).collect()
     }
 
     /// 受信メッセージ数
     pub fn message_count(&self) -> usize {
-        self.message_queue.borrow().len()
+        self.message_queue.len()
     }
 
     /// 再接続が必要か
     pub fn needs_reconnect(&self) -> bool {
-        if *self.state.borrow() == ConnectionState::Connected {
+        if self.state != ConnectionState::Disconnected {
             return false;
         }
+        self.supervisor.should_reconnect(self.now())
+    }
+
+    /// 直近`INVALID_EVENT_WINDOW_SECS`秒に検証に失敗したイベントの数
+    pub fn invalid_event_count(&self) -> u32 {
+        let since = self.clock.now() - INVALID_EVENT_WINDOW_SECS;
+        self.invalid_events.iter().filter(|&&at| at > since).count() as u32
+    }
+
+    /// 検証失敗の数をリセット
+    pub fn reset_invalid_events(&mut self) {
+        self.invalid_events.clear();
+    }
+
+    /// 検証に失敗したイベントを記録（期間を過ぎたものは忘れる）
+    pub fn record_invalid_event(&mut self) {
+        let now = self.clock.now();
+        while self.invalid_events.front().is_some_and(|&at| at <= now - INVALID_EVENT_WINDOW_SECS) {
+            self.invalid_events.pop_front();
+        }
+        self.invalid_events.push_back(now);
+    }
+
+    /// 購読（REQ）に使うか
+    pub fn is_read(&self) -> bool {
+        self.read
+    }
+
+    /// 送信（EVENT）に使うか
+    pub fn is_write(&self) -> bool {
+        self.write
+    }
+
+    /// 読み書きフラグを設定
+    pub fn set_policy(&mut self, read: bool, write: bool) {
+        self.read = read;
+        self.write = write;
+    }
+
+    /// 設定を適用
+    pub fn apply_config(&mut self, config: &RelayConfig) {
+        self.set_policy(config.read, config.write);
+        self.set_auth_policy(config.auth);
+        self.set_max_subscriptions(config.max_subscriptions);
+    }
+
+    /// 現在の設定
+    pub fn config(&self) -> RelayConfig {
+        RelayConfig {
+            url: self.url.clone(),
+            read: self.read,
+            write: self.write,
+            auth: self.auth_policy,
+            max_subscriptions: self.max_subscriptions,
+        }
+    }
+
+    /// 認証ポリシー
+    pub fn auth_policy(&self) -> AuthPolicy {
+        self.auth_policy
+    }
+
+    /// 認証ポリシーを設定
+    pub fn set_auth_policy(&mut self, policy: AuthPolicy) {
+        self.auth_policy = policy;
+    }
+
+    /// AUTHチャレンジを受け取った
+    pub fn set_auth_challenge(&mut self, challenge: String) {
+        self.auth.challenge = Some(challenge);
+        self.auth.authenticated = false;
+        self.auth.denied = false;
+    }
+
+    /// 未応答のAUTHチャレンジ
+    pub fn auth_challenge(&self) -> Option<&str> {
+        if self.auth.authenticated || self.auth.denied {
+            return None;
+        }
+        self.auth.challenge.as_deref()
+    }
+
+    /// 認証済みか
+    pub fn is_authenticated(&self) -> bool {
+        self.auth.authenticated
+    }
+
+    /// AUTHイベントを送信
+    pub async fn send_auth(&mut self, event: &NostrEvent) -> Result<()> {
+        self.auth.event_id = Some(event.id.clone());
+        self.send(&format!(r#"["AUTH",{}]"#, event.to_json())).await
+    }
+
+    /// このRelayに送ったAUTHイベントのIDか
+    pub fn is_auth_event(&self, event_id: &str) -> bool {
+        self.auth.event_id.as_deref() == Some(event_id)
+    }
+
+    /// AUTHに対するOKを処理。成功時は保留していたメッセージを再送する
+    pub async fn on_auth_result(&mut self, accepted: bool, message: &RelayReason) {
+        self.auth.event_id = None;
+        if !accepted {
+            log::warn!("AUTH rejected by {}: {}", self.url, message);
+            return;
+        }
+        log::info!("Authenticated to {}", self.url);
+        self.auth.authenticated = true;
+        for msg in std::mem::take(&mut self.auth.retry) {
+            if let Err(e) = self.send(&msg).await {
+                log::error!("Failed to resend after AUTH to {}: {:?}", self.url, e);
+            }
+        }
+    }
+
+    /// 認証を拒否（チャレンジを破棄）
+    pub fn deny_auth(&mut self) {
+        self.auth.denied = true;
+        self.auth.retry.clear();
+    }
+
+    /// `auth-required:`で拒否されたメッセージを認証後の再送用に保持
+    pub fn queue_auth_retry(&mut self, msg: String) {
+        if !self.auth.retry.contains(&msg) {
+            self.auth.retry.push(msg);
+        }
+    }
+
+    /// `auth-required:`で終了された購読を認証後に再送する
+    pub fn queue_auth_retry_subscription(&mut self, sub_id: &str) {
+        if let Some(filter) = self.subscriptions.get(sub_id) {
+            let req = req_message(sub_id, filter);
+            self.queue_auth_retry(req);
+        }
+    }
+
+    /// 最後の切断理由
+    pub fn last_disconnect_reason(&self) -> Option<&DisconnectReason> {
+        self.supervisor.last_disconnect()
+    }
 
-        let elapsed = now() - self.last_connect_attempt;
-        let delay = self.backoff.current_delay as f64;
-        elapsed >= delay
+    /// 接続監視
+    pub fn supervisor(&self) -> &ConnectionSupervisor {
+        &self.supervisor
     }
 
     /// 再接続試行
@@ -219,36 +640,153 @@ impl RelayConnection {
     /// 接続成功時の処理
     pub fn on_open(&mut self) {
         log::info!("Connected to {}", self.url);
-        *self.state.borrow_mut() = ConnectionState::Connected;
-        self.backoff.reset();
+        self.state = ConnectionState::Connected;
+        let now = self.now();
+        self.supervisor.on_open(now);
+        // 認証は接続ごと
+        self.auth = AuthState::default();
+        self.opened = true;
+        // 終了された購読も含めてすべて再送する
+        self.subscription_retries.clear();
+        self.replay_subscriptions();
+        self.flush_pending();
+    }
+
+    /// 接続待ちだったメッセージを送信
+    fn flush_pending(&mut self) {
+        let Some(conn) = &self.conn else {
+            return;
+        };
+        for msg in std::mem::take(&mut self.pending) {
+            if let Err(e) = conn.send(&msg) {
+                log::error!("Failed to send queued message to {}: {:?}", self.url, e);
+            }
+        }
     }
 
     /// 切断時の処理
-    pub fn on_close(&mut self) {
-        log::info!("Disconnected from {}", self.url);
-        *self.state.borrow_mut() = ConnectionState::Disconnected;
-        self.ws = None;
+    pub fn on_close(&mut self, reason: DisconnectReason) {
+        log::info!("Disconnected from {}: {:?}", self.url, reason);
+        self.state = ConnectionState::Disconnected;
+        self.conn = None;
+        let now = self.now();
+        self.supervisor.on_disconnect(now, reason);
     }
 
-    /// エラー時の処理
+    /// エラー時の処理（切断はクローズイベントで扱う）
     pub fn on_error(&mut self, error: &str) {
         log::error!("WebSocket error on {}: {}", self.url, error);
-        *self.state.borrow_mut() = ConnectionState::Disconnected;
+        self.supervisor.on_error(error);
+    }
+
+    /// 現在時刻（秒）
+    fn now(&self) -> f64 {
+        self.clock.now_millis() as f64 / 1000.0
+    }
+}
+
+/// REQメッセージ
+fn req_message(sub_id: &str, filter: &Filter) -> String {
+    format!(r#"["REQ","{}",{}]"#, sub_id, filter.to_json())
+}
+
+/// フィルターの`since`を引き上げる（元の`since`より前には戻さない）
+fn filter_with_since(filter: &Filter, since: i64) -> Filter {
+    let mut filter = filter.clone();
+    filter.since = Some(filter.since.unwrap_or(0).max(since));
+    filter
+}
+
+/// OK/CLOSEDメッセージの機械可読プレフィックス（NIP-01）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReasonPrefix {
+    Duplicate,
+    Pow,
+    Blocked,
+    RateLimited,
+    Invalid,
+    Restricted,
+    Mute,
+    Error,
+    AuthRequired,
+}
+
+impl ReasonPrefix {
+    pub fn parse(prefix: &str) -> Option<Self> {
+        match prefix {
+            "duplicate" => Some(Self::Duplicate),
+            "pow" => Some(Self::Pow),
+            "blocked" => Some(Self::Blocked),
+            "rate-limited" => Some(Self::RateLimited),
+            "invalid" => Some(Self::Invalid),
+            "restricted" => Some(Self::Restricted),
+            "mute" => Some(Self::Mute),
+            "error" => Some(Self::Error),
+            "auth-required" => Some(Self::AuthRequired),
+            _ => None,
+        }
+    }
+
+    /// 再送しても結果が変わらない拒否か
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, Self::Pow | Self::Blocked | Self::Invalid | Self::Restricted | Self::Mute)
+    }
+}
+
+/// OK/CLOSEDメッセージの理由
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RelayReason {
+    pub prefix: Option<ReasonPrefix>,
+    /// Relayから届いた文字列そのもの
+    pub message: String,
+}
+
+impl RelayReason {
+    /// `prefix: detail` 形式をパース
+    pub fn parse(message: &str) -> Self {
+        let prefix = message
+            .split_once(':')
+            .and_then(|(prefix, _)| ReasonPrefix::parse(prefix.trim()));
+        Self {
+            prefix,
+            message: message.to_string(),
+        }
+    }
+
+    /// 指定のプレフィックスか
+    pub fn is(&self, prefix: ReasonPrefix) -> bool {
+        self.prefix == Some(prefix)
+    }
+
+    /// プレフィックスを除いた本文
+    pub fn detail(&self) -> &str {
+        match (self.prefix, self.message.split_once(':')) {
+            (Some(_), Some((_, detail))) => detail.trim(),
+            _ => &self.message,
+        }
     }
 }
 
-/// 現在時刻（秒）
-fn now() -> f64 {
-    js_sys::Date::now() / 1000.0
+impl std::fmt::Display for RelayReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
 }
 
 /// Relayメッセージ型
 #[derive(Debug, Clone)]
 pub enum RelayMessage {
-    Event { sub_id: String, event_json: String },
+    /// イベント（形式は検証済み、署名は未検証）
+    Event { sub_id: String, event: NostrEvent },
     Eose { sub_id: String },
-    Ok { event_id: String, accepted: bool, message: String },
+    Ok { event_id: String, accepted: bool, message: RelayReason },
     Notice { message: String },
+    /// NIP-42 認証チャレンジ
+    Auth { challenge: String },
+    /// 購読がRelay側で終了された
+    Closed { sub_id: String, message: RelayReason },
+    /// NIP-45 COUNTの結果
+    Count { sub_id: String, count: u64, approximate: bool },
 }
 
 impl RelayMessage {
@@ -268,8 +806,8 @@ impl RelayMessage {
                     return Err(CoreError::ParseError("Invalid EVENT message".to_string()));
                 }
                 let sub_id = arr[1].as_str().ok_or_else(|| CoreError::ParseError("sub_id not a string".to_string()))?.to_string();
-                let event_json = arr[2].to_string();
-                Ok(RelayMessage::Event { sub_id, event_json })
+                let event = NostrEvent::from_value(&arr[2])?;
+                Ok(RelayMessage::Event { sub_id, event })
             }
             "EOSE" => {
                 if arr.len() < 2 {
@@ -284,7 +822,7 @@ impl RelayMessage {
                 }
                 let event_id = arr[1].as_str().ok_or_else(|| CoreError::ParseError("event_id not a string".to_string()))?.to_string();
                 let accepted = arr[2].as_bool().ok_or_else(|| CoreError::ParseError("accepted not a bool".to_string()))?;
-                let message = arr[3].as_str().unwrap_or("").to_string();
+                let message = RelayReason::parse(arr[3].as_str().unwrap_or(""));
                 Ok(RelayMessage::Ok { event_id, accepted, message })
             }
             "NOTICE" => {
@@ -294,6 +832,30 @@ impl RelayMessage {
                 let message = arr[1].as_str().ok_or_else(|| CoreError::ParseError("message not a string".to_string()))?.to_string();
                 Ok(RelayMessage::Notice { message })
             }
+            "AUTH" => {
+                if arr.len() < 2 {
+                    return Err(CoreError::ParseError("Invalid AUTH message".to_string()));
+                }
+                let challenge = arr[1].as_str().ok_or_else(|| CoreError::ParseError("challenge not a string".to_string()))?.to_string();
+                Ok(RelayMessage::Auth { challenge })
+            }
+            "CLOSED" => {
+                if arr.len() < 2 {
+                    return Err(CoreError::ParseError("Invalid CLOSED message".to_string()));
+                }
+                let sub_id = arr[1].as_str().ok_or_else(|| CoreError::ParseError("sub_id not a string".to_string()))?.to_string();
+                let message = RelayReason::parse(arr.get(2).and_then(|v| v.as_str()).unwrap_or(""));
+                Ok(RelayMessage::Closed { sub_id, message })
+            }
+            "COUNT" => {
+                if arr.len() < 3 {
+                    return Err(CoreError::ParseError("Invalid COUNT message".to_string()));
+                }
+                let sub_id = arr[1].as_str().ok_or_else(|| CoreError::ParseError("sub_id not a string".to_string()))?.to_string();
+                let count = arr[2]["count"].as_u64().ok_or_else(|| CoreError::ParseError("count not a number".to_string()))?;
+                let approximate = arr[2]["approximate"].as_bool().unwrap_or(false);
+                Ok(RelayMessage::Count { sub_id, count, approximate })
+            }
             _ => Err(CoreError::ParseError(format!("Unknown message type: {}", msg_type))),
         }
     }
@@ -302,6 +864,195 @@ impl RelayMessage {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
+    use crate::transport::mock::MockTransport;
+    use crate::verify::signed_event_json;
+
+    const URL: &str = "wss://relay.example";
+
+    #[tokio::test]
+    async fn test_connection_lifecycle() {
+        let transport = MockTransport::new();
+        let clock = Arc::new(MockClock::new(1_000));
+        let mut relay = RelayConnection::new(URL.to_string(), Arc::new(transport.clone()), clock.clone());
+
+        relay.connect().await.unwrap();
+        assert_eq!(relay.state(), ConnectionState::Connecting);
+
+        // 接続前の送信は捨てられる
+        relay.send(r#"["REQ","a",{}]"#).await.unwrap();
+        assert!(transport.take_sent(URL).is_empty());
+
+        transport.open(URL);
+        transport.receive(URL, r#"["EOSE","a"]"#);
+        let messages = relay.drain_messages();
+        assert!(relay.is_connected());
+        assert!(matches!(&messages[..], [RelayMessage::Eose { sub_id }] if sub_id == "a"));
+
+        relay.send(r#"["REQ","b",{}]"#).await.unwrap();
+        assert_eq!(transport.take_sent(URL), vec![r#"["REQ","b",{}]"#.to_string()]);
+
+        transport.close(URL);
+        relay.poll();
+        assert_eq!(relay.state(), ConnectionState::Disconnected);
+
+        // 初期遅延(1秒±ジッター)が経過するまで再接続しない
+        assert!(!relay.needs_reconnect());
+        clock.advance(2);
+        assert!(relay.needs_reconnect());
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions_replayed_on_reconnect() {
+        let transport = MockTransport::new();
+        let clock = Arc::new(MockClock::new(1_000));
+        let mut relay = RelayConnection::new(URL.to_string(), Arc::new(transport.clone()), clock.clone());
+
+        // 未接続でも購読は登録され、接続時に送信される
+        relay.connect().await.unwrap();
+        relay.subscribe("sub1", &Filter::new().kinds([42]).since(100)).await.unwrap();
+        assert!(transport.take_sent(URL).is_empty());
+
+        transport.open(URL);
+        relay.poll();
+        assert!(relay.take_opened());
+        assert!(!relay.take_opened());
+        assert_eq!(transport.take_sent(URL), vec![r#"["REQ","sub1",{"kinds":[42],"since":100}]"#.to_string()]);
+
+        let keys = nostr::Keys::generate();
+        for created_at in [500, 300] {
+            let event = signed_event_json(&keys, 42, &[], "hi", created_at);
+            transport.receive(URL, &format!(r#"["EVENT","sub1",{}]"#, event));
+        }
+        // 形式の不正なイベントは上位に渡さない
+        transport.receive(URL, r#"["EVENT","sub1",{"id":"forged","created_at":900}]"#);
+        relay.poll();
+        let messages = relay.drain_messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(relay.invalid_event_count(), 1);
+        // sinceは上位が検証したイベントだけで進める
+        for msg in messages {
+            if let RelayMessage::Event { sub_id, event } = msg {
+                relay.record_event_time(&sub_id, event.created_at);
+            }
+        }
+
+        transport.close(URL);
+        relay.poll();
+        clock.advance(2);
+        assert!(relay.needs_reconnect());
+        relay.connect().await.unwrap();
+        transport.open(URL);
+        relay.poll();
+
+        // sinceは最後に受信したイベントまで進む
+        let sent = transport.take_sent(URL);
+        assert_eq!(sent.len(), 1);
+        let req: Vec<serde_json::Value> = serde_json::from_str(&sent[0]).unwrap();
+        assert_eq!(req[1], "sub1");
+        assert_eq!(req[2]["since"], 500);
+        assert_eq!(req[2]["kinds"][0], 42);
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_ping_and_idle_timeout() {
+        let transport = MockTransport::new();
+        let clock = Arc::new(MockClock::new(1_000));
+        let config = SupervisorConfig { jitter: 0.0, ..SupervisorConfig::default() };
+        let mut relay = RelayConnection::with_config(URL.to_string(), Arc::new(transport.clone()), clock.clone(), config);
+
+        relay.connect().await.unwrap();
+        transport.open(URL);
+        relay.poll();
+
+        // 無通信が続くとpingを送る
+        clock.advance(30);
+        relay.poll();
+        let sent = transport.take_sent(URL);
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].starts_with(&format!(r#"["REQ","{}""#, PING_SUB_ID)));
+
+        // pingの応答は上位に渡さずCLOSEする
+        transport.receive(URL, &format!(r#"["EOSE","{}"]"#, PING_SUB_ID));
+        assert!(relay.drain_messages().is_empty());
+        assert_eq!(transport.take_sent(URL), vec![format!(r#"["CLOSE","{}"]"#, PING_SUB_ID)]);
+        assert!(relay.is_connected());
+
+        // 応答がなければ切断
+        clock.advance(60);
+        relay.poll();
+        assert_eq!(relay.state(), ConnectionState::Disconnected);
+        assert_eq!(relay.last_disconnect_reason(), Some(&DisconnectReason::IdleTimeout));
+
+        // 古いソケットのクローズは二重に数えない
+        transport.close(URL);
+        relay.poll();
+        assert_eq!(relay.supervisor().disconnect_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_backoff_grows_until_open() {
+        let transport = MockTransport::new();
+        let clock = Arc::new(MockClock::new(1_000));
+        let config = SupervisorConfig { jitter: 0.0, ..SupervisorConfig::default() };
+        let mut relay = RelayConnection::with_config(URL.to_string(), Arc::new(transport.clone()), clock.clone(), config);
+
+        relay.connect().await.unwrap();
+        transport.close(URL);
+        relay.poll();
+        assert_eq!(relay.supervisor().next_attempt_at(), 1_001.0);
+
+        clock.advance(1);
+        relay.connect().await.unwrap();
+        transport.close(URL);
+        relay.poll();
+        assert_eq!(relay.supervisor().next_attempt_at(), 1_003.0);
+
+        clock.advance(2);
+        relay.connect().await.unwrap();
+        transport.open(URL);
+        relay.poll();
+        transport.close(URL);
+        relay.poll();
+        // 接続成功でバックオフはリセット
+        assert_eq!(relay.supervisor().next_attempt_at(), 1_004.0);
+        assert_eq!(
+            relay.last_disconnect_reason(),
+            Some(&DisconnectReason::Closed { code: 1006, reason: String::new() })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_subscription_limit_leaves_room_for_ping() {
+        let transport = MockTransport::new();
+        let clock = Arc::new(MockClock::new(1_000));
+        let mut relay = RelayConnection::new(URL.to_string(), Arc::new(transport.clone()), clock.clone());
+        relay.set_max_subscriptions(Some(3));
+        relay.connect().await.unwrap();
+        transport.open(URL);
+        relay.poll();
+
+        for sub_id in ["a", "b", "c"] {
+            relay.subscribe(sub_id, &Filter::new().kinds([1])).await.unwrap();
+        }
+        assert_eq!(relay.subscriptions().len(), 2);
+        assert!(relay.is_subscription_queued("c"));
+
+        // 開いている購読とpingで上限ちょうど
+        transport.take_sent(URL);
+        clock.advance(30);
+        relay.poll();
+        let sent = transport.take_sent(URL);
+        assert!(sent[0].starts_with(&format!(r#"["REQ","{}""#, PING_SUB_ID)));
+        assert_eq!(relay.subscriptions().len() + 1, relay.max_subscriptions().unwrap());
+    }
+
+    #[test]
+    fn test_filter_with_since_never_moves_back() {
+        let filter = filter_with_since(&Filter::new().since(200), 100);
+        assert_eq!(filter.since, Some(200));
+        assert_eq!(filter_with_since(&Filter::new(), 100).since, Some(100));
+    }
 
     #[test]
     fn test_exponential_backoff() {
@@ -317,13 +1068,20 @@ mod tests {
 
     #[test]
     fn test_relay_message_parse() {
-        let json = r#"["EVENT","sub1",{"id":"abc","kind":1}]"#;
-        let msg = RelayMessage::parse(json).unwrap();
+        let event = signed_event_json(&nostr::Keys::generate(), 1, &[], "hi", 0);
+        let msg = RelayMessage::parse(&format!(r#"["EVENT","sub1",{}]"#, event)).unwrap();
         match msg {
-            RelayMessage::Event { sub_id, .. } => assert_eq!(sub_id, "sub1"),
+            RelayMessage::Event { sub_id, event } => {
+                assert_eq!(sub_id, "sub1");
+                assert_eq!(event.content, "hi");
+            }
             _ => panic!("Expected EVENT message"),
         }
 
+        // 形式の不正なイベントはパースの時点で弾く
+        let json = r#"["EVENT","sub1",{"id":"abc","kind":1}]"#;
+        assert!(matches!(RelayMessage::parse(json), Err(CoreError::InvalidEvent(_))));
+
         let json = r#"["EOSE","sub1"]"#;
         let msg = RelayMessage::parse(json).unwrap();
         match msg {
@@ -340,6 +1098,54 @@ mod tests {
             }
             _ => panic!("Expected OK message"),
         }
+
+        let json = r#"["AUTH","challenge-abc"]"#;
+        match RelayMessage::parse(json).unwrap() {
+            RelayMessage::Auth { challenge } => assert_eq!(challenge, "challenge-abc"),
+            _ => panic!("Expected AUTH message"),
+        }
+
+        let json = r#"["CLOSED","sub1","auth-required: members only"]"#;
+        match RelayMessage::parse(json).unwrap() {
+            RelayMessage::Closed { sub_id, message } => {
+                assert_eq!(sub_id, "sub1");
+                assert!(message.is(ReasonPrefix::AuthRequired));
+                assert_eq!(message.detail(), "members only");
+                assert_eq!(message.to_string(), "auth-required: members only");
+            }
+            _ => panic!("Expected CLOSED message"),
+        }
+
+        let json = r#"["COUNT","cnt1",{"count":42,"approximate":true}]"#;
+        match RelayMessage::parse(json).unwrap() {
+            RelayMessage::Count { sub_id, count, approximate } => {
+                assert_eq!(sub_id, "cnt1");
+                assert_eq!(count, 42);
+                assert!(approximate);
+            }
+            _ => panic!("Expected COUNT message"),
+        }
+    }
+
+    #[test]
+    fn test_reason_prefix_parse() {
+        let cases = [
+            ("duplicate: already have this event", Some(ReasonPrefix::Duplicate)),
+            ("pow: difficulty 26 is less than 30", Some(ReasonPrefix::Pow)),
+            ("blocked: you are banned", Some(ReasonPrefix::Blocked)),
+            ("rate-limited: slow down", Some(ReasonPrefix::RateLimited)),
+            ("invalid: event creation date is too far off", Some(ReasonPrefix::Invalid)),
+            ("restricted: not allowed to write", Some(ReasonPrefix::Restricted)),
+            ("error: could not connect to the database", Some(ReasonPrefix::Error)),
+            ("something happened", None),
+            ("", None),
+        ];
+        for (message, expected) in cases {
+            let reason = RelayReason::parse(message);
+            assert_eq!(reason.prefix, expected, "{}", message);
+            assert_eq!(reason.to_string(), message);
+        }
+        assert_eq!(RelayReason::parse("unknown: x").detail(), "unknown: x");
     }
 }
 
@@ -0,0 +1,298 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::event::{is_addressable_kind, is_replaceable_kind};
+use crate::types::{Scope, StorageUsage, StoredEvent};
+
+/// 1日（秒）
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Storageに残すイベントの上限
+///
+/// 自分のイベント、DM、置き換え可能なメタデータ（`is_protected`）は上限に関わらず残す。
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetentionPolicy {
+    /// 会話ごとに残すイベント数（会話の分からないイベントはまとめて1つの会話と数える）
+    pub max_events_per_scope: Option<usize>,
+    /// これより古いイベントは消す（秒）
+    pub max_age_secs: Option<i64>,
+    /// イベントの合計サイズの上限（バイト、JSONの長さで見積もる）
+    pub max_bytes: Option<u64>,
+    /// Storageの上限（`navigator.storage.estimate()`の`quota`）のうち使ってよい割合
+    pub max_quota_ratio: f64,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            max_events_per_scope: Some(5_000),
+            max_age_secs: Some(90 * DAY_SECS),
+            max_bytes: Some(50 * 1024 * 1024),
+            max_quota_ratio: 0.8,
+        }
+    }
+}
+
+/// 消してよいイベント
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub id: String,
+    /// イベントの会話（分からなければNone）
+    pub scope: Option<Scope>,
+    pub created_at: i64,
+    /// 見積もりサイズ（`event_size`）
+    pub size: u64,
+    /// 会話を最後に見た時刻（見ていなければ0）
+    pub last_viewed: i64,
+}
+
+/// 保存済みイベントの見積もり（保存方針を超えたかを全件を読まずに判定するのに使う）
+///
+/// 最初に一度だけ全件から数え、その後は保存と削除のたびに増減させる。
+/// 置き換えで消えた古い版などは反映しないので、おおよその値になる。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoredTotals {
+    /// 合計サイズ（`event_size`）
+    pub bytes: u64,
+    /// 会話ごとの消してよいイベント数
+    pub per_scope: HashMap<Scope, usize>,
+    /// 会話の分からない消してよいイベント（IDと`created_at`。フィルターで引けないので覚えておく）
+    pub unscoped: HashMap<String, i64>,
+}
+
+impl StoredTotals {
+    /// 保存したイベントを数える（`scope`は`Candidate::scope`と同じ）
+    pub fn add(&mut self, event: &StoredEvent, scope: Option<Scope>, protected: bool) {
+        self.bytes += event_size(event);
+        if protected {
+            return;
+        }
+        match scope {
+            Some(scope) => *self.per_scope.entry(scope).or_default() += 1,
+            None => {
+                self.unscoped.insert(event.id.clone(), event.created_at);
+            }
+        }
+    }
+
+    /// 消したイベントを差し引く
+    pub fn remove(&mut self, candidate: &Candidate) {
+        self.bytes = self.bytes.saturating_sub(candidate.size);
+        match &candidate.scope {
+            Some(scope) => {
+                if let Some(count) = self.per_scope.get_mut(scope) {
+                    *count = count.saturating_sub(1);
+                    if *count == 0 {
+                        self.per_scope.remove(scope);
+                    }
+                }
+            }
+            None => {
+                self.unscoped.remove(&candidate.id);
+            }
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// 合計サイズと使用量が上限を超えている分（バイト）
+    pub fn excess_bytes(&self, total_bytes: u64, usage: Option<StorageUsage>) -> u64 {
+        let mut excess = self.max_bytes.map_or(0, |max| total_bytes.saturating_sub(max));
+        if let Some(StorageUsage { usage, quota: Some(quota) }) = usage {
+            let allowed = (quota as f64 * self.max_quota_ratio) as u64;
+            excess = excess.max(usage.saturating_sub(allowed));
+        }
+        excess
+    }
+
+    /// 消すイベントのIDを消す順に返す
+    ///
+    /// 期限切れと会話ごとの上限を超えたものを消し、それでも`total_bytes`か
+    /// `usage`が上限を超えていれば、最後に見たのが古い会話の古いイベントから消す。
+    pub fn plan(&self, candidates: &[Candidate], total_bytes: u64, usage: Option<StorageUsage>, now: i64) -> Vec<String> {
+        // 最後に見たのが古い会話から、会話の中では古いものから
+        let mut order: Vec<&Candidate> = candidates.iter().collect();
+        order.sort_by(|a, b| {
+            a.last_viewed
+                .cmp(&b.last_viewed)
+                .then_with(|| scope_key(&a.scope).cmp(&scope_key(&b.scope)))
+                .then_with(|| a.created_at.cmp(&b.created_at))
+                .then_with(|| a.id.cmp(&b.id))
+        });
+
+        let mut evicted: HashSet<&str> = HashSet::new();
+        if let Some(max_age) = self.max_age_secs {
+            let cutoff = now - max_age;
+            evicted.extend(order.iter().filter(|c| c.created_at < cutoff).map(|c| c.id.as_str()));
+        }
+        if let Some(max_events) = self.max_events_per_scope {
+            let mut per_scope: HashMap<Option<&Scope>, Vec<&Candidate>> = HashMap::new();
+            for candidate in &order {
+                per_scope.entry(candidate.scope.as_ref()).or_default().push(candidate);
+            }
+            for events in per_scope.values() {
+                // 古い順に並んでいるので、新しい`max_events`件より前を消す
+                let excess = events.len().saturating_sub(max_events);
+                evicted.extend(events[..excess].iter().map(|c| c.id.as_str()));
+            }
+        }
+
+        let excess = self.excess_bytes(total_bytes, usage);
+        let freed: u64 = order.iter().filter(|c| evicted.contains(c.id.as_str())).map(|c| c.size).sum();
+        let mut remaining = excess.saturating_sub(freed);
+        for candidate in &order {
+            if remaining == 0 {
+                break;
+            }
+            if evicted.insert(candidate.id.as_str()) {
+                remaining = remaining.saturating_sub(candidate.size);
+            }
+        }
+
+        order
+            .into_iter()
+            .filter(|c| evicted.contains(c.id.as_str()))
+            .map(|c| c.id.clone())
+            .collect()
+    }
+}
+
+/// 上限に関わらず残すイベントか（自分のイベント、DM、置き換え可能なメタデータ）
+pub fn is_protected(event: &StoredEvent, self_pubkey: Option<&str>) -> bool {
+    Some(event.pubkey.as_str()) == self_pubkey || event.kind == 4 || is_metadata_kind(event.kind)
+}
+
+/// チャンネル作成・情報と、置き換え可能・アドレス指定可能なイベントのkind
+fn is_metadata_kind(kind: u16) -> bool {
+    matches!(kind, 40 | 41) || is_replaceable_kind(kind) || is_addressable_kind(kind)
+}
+
+/// イベントの見積もりサイズ（保存する形のJSONの長さ）
+pub fn event_size(event: &StoredEvent) -> u64 {
+    serde_json::to_string(event).map_or(0, |json| json.len() as u64)
+}
+
+fn scope_key(scope: &Option<Scope>) -> String {
+    scope.as_ref().map(Scope::storage_key).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_000_000;
+
+    fn candidate(id: &str, channel: Option<&str>, created_at: i64, last_viewed: i64) -> Candidate {
+        Candidate {
+            id: id.to_string(),
+            scope: channel.map(|c| Scope::Channel(c.to_string())),
+            created_at,
+            size: 100,
+            last_viewed,
+        }
+    }
+
+    fn unlimited() -> RetentionPolicy {
+        RetentionPolicy { max_events_per_scope: None, max_age_secs: None, max_bytes: None, max_quota_ratio: 1.0 }
+    }
+
+    #[test]
+    fn test_plan_age_and_scope_limits() {
+        let candidates = vec![
+            candidate("a1", Some("a"), NOW - 10, 0),
+            candidate("a2", Some("a"), NOW - 20, 0),
+            candidate("a3", Some("a"), NOW - 30, 0),
+            candidate("b1", Some("b"), NOW - 5_000, 0),
+            candidate("x1", None, NOW - 10, 0),
+        ];
+        assert!(unlimited().plan(&candidates, 500, None, NOW).is_empty());
+
+        let by_age = RetentionPolicy { max_age_secs: Some(1_000), ..unlimited() };
+        assert_eq!(by_age.plan(&candidates, 500, None, NOW), vec!["b1"]);
+
+        let by_scope = RetentionPolicy { max_events_per_scope: Some(1), ..unlimited() };
+        assert_eq!(by_scope.plan(&candidates, 500, None, NOW), vec!["a3", "a2"]);
+    }
+
+    #[test]
+    fn test_plan_evicts_least_recently_viewed_first() {
+        let candidates = vec![
+            candidate("recent1", Some("recent"), NOW - 10, NOW),
+            candidate("recent2", Some("recent"), NOW - 20, NOW),
+            candidate("stale1", Some("stale"), NOW - 1, NOW - 100),
+            candidate("stale2", Some("stale"), NOW - 2, NOW - 100),
+            candidate("unknown", None, NOW, 0),
+        ];
+
+        // 合計サイズの上限を250バイト超えている
+        let by_bytes = RetentionPolicy { max_bytes: Some(250), ..unlimited() };
+        assert_eq!(by_bytes.plan(&candidates, 500, None, NOW), vec!["unknown", "stale2", "stale1"]);
+
+        // Storageの上限の半分まで
+        let by_quota = RetentionPolicy { max_quota_ratio: 0.5, ..unlimited() };
+        let usage = StorageUsage { usage: 1_150, quota: Some(2_000) };
+        assert_eq!(by_quota.plan(&candidates, 500, Some(usage), NOW), vec!["unknown", "stale2"]);
+        // 上限が分からなければ使用量では消さない
+        let usage = StorageUsage { usage: 1_150, quota: None };
+        assert!(by_quota.plan(&candidates, 500, Some(usage), NOW).is_empty());
+
+        // 期限切れで足りていればそれ以上消さない
+        let both = RetentionPolicy { max_bytes: Some(400), max_age_secs: Some(15), ..unlimited() };
+        assert_eq!(both.plan(&candidates, 500, None, NOW), vec!["recent2"]);
+    }
+
+    #[test]
+    fn test_stored_totals() {
+        let event = |id: &str| StoredEvent::new(
+            crate::event::NostrEvent {
+                id: id.to_string(),
+                pubkey: "alice".to_string(),
+                created_at: 100,
+                kind: 42,
+                tags: Vec::new(),
+                content: String::new(),
+                sig: String::new(),
+            },
+            0,
+        );
+        let chan = Scope::Channel("a".to_string());
+        let mut totals = StoredTotals::default();
+        totals.add(&event("a1"), Some(chan.clone()), false);
+        totals.add(&event("a2"), Some(chan.clone()), false);
+        totals.add(&event("x1"), None, false);
+        totals.add(&event("me"), Some(chan.clone()), true);
+        assert_eq!(totals.bytes, 4 * event_size(&event("a1")));
+        assert_eq!(totals.per_scope[&chan], 2);
+        assert_eq!(totals.unscoped, HashMap::from([("x1".to_string(), 100)]));
+
+        let size = event_size(&event("a1"));
+        let removed = |id: &str, scope: Option<Scope>| Candidate { id: id.to_string(), scope, created_at: 100, size, last_viewed: 0 };
+        totals.remove(&removed("a1", Some(chan.clone())));
+        totals.remove(&removed("a2", Some(chan.clone())));
+        totals.remove(&removed("x1", None));
+        assert_eq!(totals, StoredTotals { bytes: size, ..StoredTotals::default() });
+    }
+
+    #[test]
+    fn test_is_protected() {
+        let event = |kind: u16, pubkey: &str| StoredEvent {
+            id: "id".to_string(),
+            kind,
+            pubkey: pubkey.to_string(),
+            created_at: 0,
+            content: String::new(),
+            tags: Vec::new(),
+            sig: String::new(),
+            relay_hint: None,
+            inserted_at: 0,
+            seen_on: Vec::new(),
+            tag_keys: Vec::new(),
+        };
+        assert!(!is_protected(&event(42, "other"), Some("me")));
+        assert!(!is_protected(&event(1, "other"), None));
+        assert!(is_protected(&event(42, "me"), Some("me")));
+        assert!(is_protected(&event(4, "other"), Some("me")));
+        for kind in [0, 3, 40, 41, 10002, 30023] {
+            assert!(is_protected(&event(kind, "other"), Some("me")), "kind {}", kind);
+        }
+    }
+}
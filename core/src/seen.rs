@@ -0,0 +1,137 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::event::NostrEvent;
+
+/// 受信済みイベントIDのキャッシュの既定サイズ
+pub const SEEN_CACHE_CAPACITY: usize = 10_000;
+
+/// 受信済みイベントIDと、受信したRelayの集合
+///
+/// 古いものから捨てる。捨てたIDはStorageで確認する。
+#[derive(Debug, Clone)]
+pub struct SeenCache {
+    entries: HashMap<String, Entry>,
+    order: VecDeque<String>,
+    capacity: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    relays: Vec<String>,
+    /// 検証済みのコピーの`pubkey`、`created_at`、`sig`
+    pubkey: String,
+    created_at: i64,
+    sig: String,
+}
+
+impl Default for SeenCache {
+    fn default() -> Self {
+        Self::new(SEEN_CACHE_CAPACITY)
+    }
+}
+
+impl SeenCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// 受信済みか
+    pub fn contains(&self, event_id: &str) -> bool {
+        self.entries.contains_key(event_id)
+    }
+
+    /// 登録した（検証済みの）コピーと`pubkey`、`created_at`、`sig`が同じか
+    pub fn matches(&self, event: &NostrEvent) -> bool {
+        self.entries.get(&event.id).is_some_and(|entry| {
+            entry.pubkey == event.pubkey && entry.created_at == event.created_at && entry.sig == event.sig
+        })
+    }
+
+    /// 検証済みのイベントを登録（既に受信したRelayの集合があれば引き継ぐ）
+    pub fn insert(&mut self, event: &NostrEvent, seen_on: Vec<String>) {
+        let entry = Entry {
+            relays: seen_on,
+            pubkey: event.pubkey.clone(),
+            created_at: event.created_at,
+            sig: event.sig.clone(),
+        };
+        if self.entries.insert(event.id.clone(), entry).is_none() {
+            self.order.push_back(event.id.clone());
+        }
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    /// 受信したRelayを記録。新しいRelayならtrueを返す
+    pub fn add_relay(&mut self, event_id: &str, relay_url: &str) -> bool {
+        let Some(relays) = self.entries.get_mut(event_id).map(|entry| &mut entry.relays) else {
+            return false;
+        };
+        if relays.iter().any(|r| r == relay_url) {
+            return false;
+        }
+        relays.push(relay_url.to_string());
+        true
+    }
+
+    /// イベントを受信したRelay（受信順）
+    pub fn seen_on(&self, event_id: &str) -> Option<&[String]> {
+        self.entries.get(event_id).map(|entry| entry.relays.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: &str) -> NostrEvent {
+        NostrEvent {
+            id: id.to_string(),
+            pubkey: "alice".to_string(),
+            created_at: 100,
+            kind: 1,
+            tags: Vec::new(),
+            content: String::new(),
+            sig: "sig".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_records_relays_once() {
+        let mut cache = SeenCache::default();
+        cache.insert(&event("e1"), vec!["wss://a".to_string()]);
+        assert!(!cache.add_relay("e1", "wss://a"));
+        assert!(cache.add_relay("e1", "wss://b"));
+        assert_eq!(cache.seen_on("e1").unwrap(), ["wss://a", "wss://b"]);
+        assert!(!cache.add_relay("unknown", "wss://a"));
+    }
+
+    #[test]
+    fn test_matches_the_verified_copy() {
+        let mut cache = SeenCache::default();
+        cache.insert(&event("e1"), vec![]);
+        assert!(cache.matches(&event("e1")));
+        assert!(cache.matches(&NostrEvent { content: "ignored".to_string(), ..event("e1") }));
+        assert!(!cache.matches(&NostrEvent { sig: "forged".to_string(), ..event("e1") }));
+        assert!(!cache.matches(&NostrEvent { created_at: 200, ..event("e1") }));
+        assert!(!cache.matches(&event("e2")));
+    }
+
+    #[test]
+    fn test_evicts_oldest() {
+        let mut cache = SeenCache::new(2);
+        cache.insert(&event("e1"), vec![]);
+        cache.insert(&event("e2"), vec![]);
+        cache.insert(&event("e3"), vec![]);
+        assert!(!cache.contains("e1"));
+        assert!(cache.contains("e2"));
+        assert!(cache.contains("e3"));
+    }
+}
@@ -5,7 +5,8 @@ use web_sys::{CryptoKey, SubtleCrypto};
 use js_sys::{Uint8Array, Object, Reflect};
 use nostr::{Keys, EventBuilder, Kind, Tag};
 
-use super::{Signer, UnsignedEvent, SignedEvent};
+use super::{Signer, UnsignedEvent};
+use crate::event::NostrEvent;
 use crate::storage::Storage;
 use crate::error::{Result, CoreError};
 
@@ -55,7 +56,7 @@ impl Signer for InternalSigner {
         Ok(self.keys.public_key().to_hex())
     }
 
-    async fn sign_event(&self, unsigned: UnsignedEvent) -> Result<SignedEvent> {
+    async fn sign_event(&self, unsigned: UnsignedEvent) -> Result<NostrEvent> {
         // Tagsを変換
         let tags: Vec<Tag> = unsigned.tags.iter()
             .filter_map(|tag_vec| Tag::parse(tag_vec).ok())
@@ -68,18 +69,7 @@ impl Signer for InternalSigner {
             .await
             .map_err(|e| CoreError::SignerError(format!("Failed to sign event: {}", e)))?;
         
-        Ok(SignedEvent {
-            id: event.id.to_hex(),
-            pubkey: event.pubkey.to_hex(),
-            created_at: event.created_at.as_u64() as i64,
-            kind: event.kind.as_u16(),
-            tags: event.tags.iter().map(|t| {
-                let vec = t.clone().to_vec();
-                vec.iter().map(|s| s.to_string()).collect()
-            }).collect(),
-            content: event.content,
-            sig: event.sig.to_string(),
-        })
+        Ok(NostrEvent::from(event))
     }
 
     async fn nip04_encrypt(&self, pubkey: &str, plaintext: &str) -> Result<String> {
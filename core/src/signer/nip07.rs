@@ -2,9 +2,9 @@ use async_trait::async_trait;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
 use js_sys::{Object, Reflect};
-use serde_json;
 
-use super::{Signer, UnsignedEvent, SignedEvent};
+use super::{Signer, UnsignedEvent};
+use crate::event::NostrEvent;
 use crate::error::{Result, CoreError};
 
 /// NIP-07 Signer (window.nostr)
@@ -59,7 +59,7 @@ impl Signer for Nip07Signer {
         result.as_string().ok_or_else(|| CoreError::SignerError("Public key is not a string".to_string()))
     }
 
-    async fn sign_event(&self, unsigned: UnsignedEvent) -> Result<SignedEvent> {
+    async fn sign_event(&self, unsigned: UnsignedEvent) -> Result<NostrEvent> {
         let event_obj = js_sys::Object::new();
         
         Reflect::set(&event_obj, &"kind".into(), &JsValue::from_f64(unsigned.kind as f64))?;
@@ -84,17 +84,7 @@ impl Signer for Nip07Signer {
             .as_string()
             .ok_or_else(|| CoreError::SignerError("Failed to stringify result".to_string()))?;
         
-        let event: serde_json::Value = serde_json::from_str(&json_str)?;
-        
-        Ok(SignedEvent {
-            id: event["id"].as_str().ok_or_else(|| CoreError::ParseError("No id".to_string()))?.to_string(),
-            pubkey: event["pubkey"].as_str().ok_or_else(|| CoreError::ParseError("No pubkey".to_string()))?.to_string(),
-            created_at: event["created_at"].as_i64().ok_or_else(|| CoreError::ParseError("No created_at".to_string()))?,
-            kind: event["kind"].as_u64().ok_or_else(|| CoreError::ParseError("No kind".to_string()))? as u16,
-            tags: serde_json::from_value(event["tags"].clone())?,
-            content: event["content"].as_str().ok_or_else(|| CoreError::ParseError("No content".to_string()))?.to_string(),
-            sig: event["sig"].as_str().ok_or_else(|| CoreError::ParseError("No sig".to_string()))?.to_string(),
-        })
+        NostrEvent::from_json(&json_str)
     }
 
     async fn nip04_encrypt(&self, pubkey: &str, plaintext: &str) -> Result<String> {
@@ -0,0 +1,336 @@
+//! すべてのStorage実装が満たすべき振る舞いのテスト
+//!
+//! 各実装のテストから`check_all`に「空のStorageを作る関数」を渡して使う。
+
+use std::future::Future;
+
+use crate::event::NostrEvent;
+use crate::storage::Storage;
+use crate::types::{DeliveryStatus, OutboxItem, OutboxStatus, RelayConfig, RelayReceipt, StorageFilter, StoredEvent};
+
+/// すべての項目を、項目ごとに空のStorageで確認する
+pub(crate) async fn check_all<S, F, Fut>(mut fresh: F)
+where
+    S: Storage,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = S>,
+{
+    check_event_roundtrip(&fresh().await).await;
+    check_seen_on(&fresh().await).await;
+    check_filter_semantics(&fresh().await).await;
+    check_ordering_and_limit(&fresh().await).await;
+    check_long_scan(&fresh().await).await;
+    check_delete_events(&fresh().await).await;
+    check_replaceable_events(&fresh().await).await;
+    check_outbox(&fresh().await).await;
+    check_dm_threads(&fresh().await).await;
+    check_last_seen(&fresh().await).await;
+    check_relays_and_keypair(&fresh().await).await;
+}
+
+fn hex_id(n: u64) -> String {
+    format!("{:064x}", n)
+}
+
+fn event(n: u64, kind: u16, created_at: i64, tags: &[&[&str]], content: &str) -> NostrEvent {
+    NostrEvent {
+        id: hex_id(n),
+        pubkey: hex_id(1000 + n % 2),
+        created_at,
+        kind,
+        tags: tags.iter().map(|t| t.iter().map(|s| s.to_string()).collect()).collect(),
+        content: content.to_string(),
+        sig: "0".repeat(128),
+    }
+}
+
+async fn ids<S: Storage>(storage: &S, filter: StorageFilter) -> Vec<String> {
+    storage.get_events(&filter).await.unwrap().into_iter().map(|e| e.id).collect()
+}
+
+async fn check_event_roundtrip<S: Storage>(storage: &S) {
+    let saved = event(1, 42, 100, &[&["e", "chan", "", "root"]], "hello");
+    storage.save_event(&saved).await.unwrap();
+    let stored = storage.get_event(&saved.id).await.unwrap().expect("saved event");
+    assert_eq!(stored.event(), saved);
+    assert_eq!(stored.tag_keys, vec!["e:chan"]);
+    assert!(stored.seen_on.is_empty());
+    assert_eq!(storage.get_event(&hex_id(99)).await.unwrap().map(|e| e.id), None);
+
+    // 同じIDは置き換える
+    let inserted = StoredEvent::new(event(2, 1, 200, &[], "first"), 0);
+    storage.insert_event(&inserted).await.unwrap();
+    storage.insert_event(&StoredEvent::new(event(2, 1, 200, &[], "second"), 0)).await.unwrap();
+    storage.save_event(&saved).await.unwrap();
+    assert_eq!(ids(storage, StorageFilter::new()).await, vec![hex_id(2), hex_id(1)]);
+    assert_eq!(storage.get_event(&inserted.id).await.unwrap().unwrap().content, "second");
+}
+
+async fn check_seen_on<S: Storage>(storage: &S) {
+    let saved = event(1, 42, 100, &[], "");
+    storage.save_event(&saved).await.unwrap();
+    storage.add_seen_on(&saved.id, "wss://a").await.unwrap();
+    storage.add_seen_on(&saved.id, "wss://b").await.unwrap();
+    storage.add_seen_on(&saved.id, "wss://a").await.unwrap();
+    // 知らないイベントは何もしない
+    storage.add_seen_on(&hex_id(99), "wss://a").await.unwrap();
+
+    // 保存し直しても受信Relayの記録は残す
+    storage.save_event(&saved).await.unwrap();
+    let stored = storage.get_event(&saved.id).await.unwrap().unwrap();
+    assert_eq!(stored.seen_on, vec!["wss://a", "wss://b"]);
+    assert_eq!(stored.relay_hint.as_deref(), Some("wss://a"));
+    assert_eq!(storage.get_event(&hex_id(99)).await.unwrap().map(|e| e.id), None);
+}
+
+async fn check_filter_semantics<S: Storage>(storage: &S) {
+    for saved in [
+        event(1, 42, 100, &[&["e", "chan"]], "Hello world"),
+        event(2, 42, 200, &[&["e", "chan"], &["e", "other"]], "goodbye"),
+        event(3, 42, 300, &[&["e", "other"]], ""),
+        event(4, 4, 400, &[&["p", "bob"]], "secret"),
+        event(5, 40, 500, &[&["title", "chan"]], ""),
+    ] {
+        storage.save_event(&saved).await.unwrap();
+    }
+    let f = StorageFilter::new;
+
+    assert_eq!(ids(storage, f().ids([hex_id(3), hex_id(1), hex_id(99)])).await, vec![hex_id(3), hex_id(1)]);
+    assert_eq!(ids(storage, f().authors([hex_id(1000)])).await, vec![hex_id(4), hex_id(2)]);
+    assert_eq!(ids(storage, f().kinds([4, 40])).await, vec![hex_id(5), hex_id(4)]);
+    // 複数の値に一致しても1回だけ返す
+    assert_eq!(ids(storage, f().tag('e', ["chan", "other"])).await, vec![hex_id(3), hex_id(2), hex_id(1)]);
+    assert_eq!(ids(storage, f().kinds([42]).tag('e', ["chan"])).await, vec![hex_id(2), hex_id(1)]);
+    // 1文字でないタグ名は検索できない
+    assert!(ids(storage, f().tag('t', ["chan"])).await.is_empty());
+    // since/untilは両端を含む
+    assert_eq!(ids(storage, f().since(200).until(400)).await, vec![hex_id(4), hex_id(3), hex_id(2)]);
+    assert!(ids(storage, f().since(400).until(200)).await.is_empty());
+    assert_eq!(ids(storage, f().search("HELLO")).await, vec![hex_id(1)]);
+
+    // 空のリストは何にも一致しない
+    assert!(ids(storage, f().ids(Vec::<String>::new())).await.is_empty());
+    assert!(ids(storage, f().kinds([])).await.is_empty());
+    assert!(ids(storage, f().tag('e', Vec::<String>::new())).await.is_empty());
+}
+
+async fn check_ordering_and_limit<S: Storage>(storage: &S) {
+    // 同じ時刻はIDの小さい順
+    for (n, created_at) in [(4, 100), (2, 300), (6, 200), (1, 200), (5, 200), (3, 50)] {
+        storage.save_event(&event(n, 1, created_at, &[&["t", "x"]], "")).await.unwrap();
+    }
+    storage.save_event(&event(7, 2, 1000, &[&["t", "x"]], "")).await.unwrap();
+
+    let all = vec![hex_id(7), hex_id(2), hex_id(1), hex_id(5), hex_id(6), hex_id(4), hex_id(3)];
+    assert_eq!(ids(storage, StorageFilter::new()).await, all);
+    assert_eq!(ids(storage, StorageFilter::new().tag('t', ["x"])).await, all);
+
+    // limitは絞り込んだ後に新しい方から数える
+    let kind1 = StorageFilter::new().kinds([1]);
+    assert_eq!(ids(storage, kind1.clone().limit(3)).await, vec![hex_id(2), hex_id(1), hex_id(5)]);
+    assert_eq!(ids(storage, kind1.clone().until(200).limit(2)).await, vec![hex_id(1), hex_id(5)]);
+    assert_eq!(ids(storage, StorageFilter::new().kinds([1, 2]).limit(2)).await, vec![hex_id(7), hex_id(2)]);
+    assert_eq!(ids(storage, StorageFilter::new().tag('t', ["x"]).limit(1)).await, vec![hex_id(7)]);
+    assert!(ids(storage, kind1.limit(0)).await.is_empty());
+}
+
+async fn check_long_scan<S: Storage>(storage: &S) {
+    // 1回で読む件数（IndexedDBは100件ずつ）を超え、同じ時刻がバッチの境目をまたぐ
+    for n in 1..=250 {
+        storage.save_event(&event(n, 1, (n / 7) as i64, &[], "")).await.unwrap();
+    }
+    let mut expected: Vec<u64> = (1..=250).collect();
+    expected.sort_by_key(|&n| (std::cmp::Reverse(n / 7), n));
+    let expected: Vec<String> = expected.into_iter().map(hex_id).collect();
+
+    assert_eq!(ids(storage, StorageFilter::new()).await, expected);
+    assert_eq!(ids(storage, StorageFilter::new().kinds([1])).await, expected);
+    assert_eq!(ids(storage, StorageFilter::new().kinds([1]).limit(120)).await, expected[..120]);
+    let older: Vec<String> = expected.iter().filter(|id| id.as_str() < hex_id(140).as_str()).cloned().collect();
+    assert_eq!(ids(storage, StorageFilter::new().until(19).limit(200)).await, older);
+}
+
+async fn check_delete_events<S: Storage>(storage: &S) {
+    for n in 1..=3 {
+        storage.save_event(&event(n, 42, 100 * n as i64, &[&["e", "chan"]], "")).await.unwrap();
+    }
+    // 知らないIDは無視する
+    storage.delete_events(&[hex_id(1), hex_id(3), hex_id(99)]).await.unwrap();
+    storage.delete_events(&[]).await.unwrap();
+
+    assert_eq!(storage.get_event(&hex_id(1)).await.unwrap().map(|e| e.id), None);
+    assert_eq!(ids(storage, StorageFilter::new()).await, vec![hex_id(2)]);
+    // インデックスからも消える
+    assert_eq!(ids(storage, StorageFilter::new().tag('e', ["chan"])).await, vec![hex_id(2)]);
+    assert_eq!(ids(storage, StorageFilter::new().kinds([42])).await, vec![hex_id(2)]);
+}
+
+async fn check_replaceable_events<S: Storage>(storage: &S) {
+    let by = |author: u64, event: NostrEvent| NostrEvent { pubkey: hex_id(author), ..event };
+    let kept = |filter: StorageFilter| ids(storage, filter.authors([hex_id(1)]));
+
+    // 新しい版が古い版を置き換え、古い版が後から届いても残さない
+    storage.save_event(&by(1, event(1, 0, 100, &[], "v1"))).await.unwrap();
+    storage.save_event(&by(1, event(2, 0, 200, &[], "v2"))).await.unwrap();
+    storage.save_event(&by(1, event(3, 0, 150, &[], "late"))).await.unwrap();
+    storage.save_event(&by(2, event(4, 0, 50, &[], "other author"))).await.unwrap();
+    assert_eq!(kept(StorageFilter::new().kinds([0])).await, vec![hex_id(2)]);
+    assert!(storage.get_event(&hex_id(1)).await.unwrap().is_none());
+    assert!(storage.get_event(&hex_id(4)).await.unwrap().is_some());
+
+    // 同じ時刻ならIDの小さい方を残す
+    storage.save_event(&by(1, event(6, 10002, 100, &[], ""))).await.unwrap();
+    storage.save_event(&by(1, event(5, 10002, 100, &[], ""))).await.unwrap();
+    storage.save_event(&by(1, event(7, 10002, 100, &[], ""))).await.unwrap();
+    assert_eq!(kept(StorageFilter::new().kinds([10002])).await, vec![hex_id(5)]);
+
+    // アドレス指定可能なイベントはdタグごと、チャンネル情報はチャンネルごと
+    storage.save_event(&by(1, event(10, 30023, 100, &[&["d", "a"]], ""))).await.unwrap();
+    storage.save_event(&by(1, event(11, 30023, 100, &[&["d", "b"]], ""))).await.unwrap();
+    storage.save_event(&by(1, event(12, 30023, 200, &[&["d", "a"]], ""))).await.unwrap();
+    assert_eq!(kept(StorageFilter::new().kinds([30023])).await, vec![hex_id(12), hex_id(11)]);
+    storage.save_event(&by(1, event(13, 41, 100, &[&["e", "x"]], ""))).await.unwrap();
+    storage.save_event(&by(1, event(14, 41, 100, &[&["e", "y"]], ""))).await.unwrap();
+    storage.save_event(&by(1, event(15, 41, 200, &[&["e", "x", "", "root"]], ""))).await.unwrap();
+    assert_eq!(kept(StorageFilter::new().kinds([41])).await, vec![hex_id(15), hex_id(14)]);
+
+    // 同じ版を保存し直しても消えない
+    storage.save_event(&by(1, event(2, 0, 200, &[], "v2"))).await.unwrap();
+    storage.insert_event(&StoredEvent::new(by(1, event(2, 0, 200, &[], "v2")), 0)).await.unwrap();
+    assert_eq!(kept(StorageFilter::new().kinds([0])).await, vec![hex_id(2)]);
+    // insert_eventも古い版は残さない
+    storage.insert_event(&StoredEvent::new(by(1, event(16, 0, 100, &[], "old")), 0)).await.unwrap();
+    storage.insert_event(&StoredEvent::new(by(1, event(17, 0, 300, &[], "v3")), 0)).await.unwrap();
+    assert_eq!(kept(StorageFilter::new().kinds([0])).await, vec![hex_id(17)]);
+
+    // 一時的なイベントは保存しない
+    storage.save_event(&event(20, 20001, 100, &[], "")).await.unwrap();
+    storage.insert_event(&StoredEvent::new(event(21, 29999, 100, &[], ""), 0)).await.unwrap();
+    assert!(storage.get_event(&hex_id(20)).await.unwrap().is_none());
+    assert!(storage.get_event(&hex_id(21)).await.unwrap().is_none());
+}
+
+fn outbox_item(req_id: &str, n: u64, status: OutboxStatus) -> OutboxItem {
+    let event = event(n, 42, 100, &[&["e", "chan"]], "hi");
+    OutboxItem {
+        req_id: req_id.to_string(),
+        event_id: event.id.clone(),
+        event,
+        status,
+        last_try_at: 0,
+        retry_count: 0,
+        error: None,
+        receipts: Vec::new(),
+    }
+}
+
+async fn check_outbox<S: Storage>(storage: &S) {
+    let pending_ids = || async {
+        storage
+            .get_pending_outbox()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| item.req_id)
+            .collect::<Vec<_>>()
+    };
+
+    for (req_id, n) in [("req_3", 3), ("req_1", 1), ("req_2", 2)] {
+        assert_eq!(storage.enqueue_outbox(outbox_item(req_id, n, OutboxStatus::Queued)).await.unwrap(), req_id);
+    }
+    // req_id順に返す
+    assert_eq!(pending_ids().await, vec!["req_1", "req_2", "req_3"]);
+
+    // Sentは再送のために残り、OkとErrorは外れる
+    storage.update_outbox_status("req_1", OutboxStatus::Sent).await.unwrap();
+    storage.update_outbox_status("req_2", OutboxStatus::Ok).await.unwrap();
+    storage.update_outbox_status("req_3", OutboxStatus::Error).await.unwrap();
+    storage.update_outbox_status("req_9", OutboxStatus::Ok).await.unwrap();
+    let pending = storage.get_pending_outbox().await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].status, OutboxStatus::Sent);
+    assert_eq!(pending[0].event, outbox_item("req_1", 1, OutboxStatus::Sent).event);
+
+    // 丸ごと更新（知らないアイテムは追加しない）
+    let mut item = pending[0].clone();
+    item.retry_count = 2;
+    item.error = Some("timeout".to_string());
+    item.receipts.push(RelayReceipt {
+        relay_url: "wss://a".to_string(),
+        status: DeliveryStatus::Accepted,
+        sent_at: 5,
+        attempts: 1,
+    });
+    storage.update_outbox_item(&item).await.unwrap();
+    storage.update_outbox_item(&outbox_item("req_9", 9, OutboxStatus::Queued)).await.unwrap();
+    let pending = storage.get_pending_outbox().await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].retry_count, 2);
+    assert_eq!(pending[0].error.as_deref(), Some("timeout"));
+    assert_eq!(pending[0].receipts, item.receipts);
+
+    // 同じreq_idで入れ直すと置き換える
+    storage.enqueue_outbox(outbox_item("req_2", 2, OutboxStatus::Queued)).await.unwrap();
+    assert_eq!(pending_ids().await, vec!["req_1", "req_2"]);
+
+    // 全件は送り終えたものも含む
+    let all: Vec<(String, OutboxStatus)> = storage
+        .get_all_outbox()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|item| (item.req_id, item.status))
+        .collect();
+    assert_eq!(
+        all,
+        vec![
+            ("req_1".to_string(), OutboxStatus::Sent),
+            ("req_2".to_string(), OutboxStatus::Queued),
+            ("req_3".to_string(), OutboxStatus::Error),
+        ]
+    );
+}
+
+async fn check_dm_threads<S: Storage>(storage: &S) {
+    assert!(storage.get_dm_threads().await.unwrap().is_empty());
+    storage.upsert_dm_thread("alice", 100).await.unwrap();
+    storage.upsert_dm_thread("bob", 200).await.unwrap();
+    storage.upsert_dm_thread("alice", 300).await.unwrap();
+
+    let threads = storage.get_dm_threads().await.unwrap();
+    let peers: Vec<(&str, i64)> = threads.iter().map(|t| (t.peer.as_str(), t.last_msg_at)).collect();
+    assert_eq!(peers, vec![("alice", 300), ("bob", 200)]);
+    assert!(threads.iter().all(|t| t.last_seen == 0));
+}
+
+async fn check_last_seen<S: Storage>(storage: &S) {
+    assert_eq!(storage.get_last_seen("channel:a").await.unwrap(), 0);
+    storage.set_last_seen("channel:a", 100).await.unwrap();
+    storage.set_last_seen("dm:b", 50).await.unwrap();
+    storage.set_last_seen("channel:a", 150).await.unwrap();
+    assert_eq!(storage.get_last_seen("channel:a").await.unwrap(), 150);
+    assert_eq!(storage.get_last_seen("dm:b").await.unwrap(), 50);
+    assert_eq!(
+        storage.get_all_last_seen().await.unwrap(),
+        vec![("channel:a".to_string(), 150), ("dm:b".to_string(), 50)]
+    );
+}
+
+async fn check_relays_and_keypair<S: Storage>(storage: &S) {
+    assert!(storage.get_relays().await.unwrap().is_empty());
+    storage.upsert_relay(&RelayConfig::new("wss://a")).await.unwrap();
+    storage.upsert_relay(&RelayConfig::new("wss://b")).await.unwrap();
+    let updated = RelayConfig { write: false, max_subscriptions: Some(5), ..RelayConfig::new("wss://a") };
+    storage.upsert_relay(&updated).await.unwrap();
+    storage.remove_relay("wss://b").await.unwrap();
+    storage.remove_relay("wss://unknown").await.unwrap();
+    let relays = storage.get_relays().await.unwrap();
+    assert_eq!(relays.len(), 1);
+    assert!(!relays[0].write);
+    assert_eq!(relays[0].max_subscriptions, Some(5));
+
+    assert_eq!(storage.get_keypair().await.unwrap(), None);
+    storage.save_keypair(&[1, 2, 3]).await.unwrap();
+    storage.save_keypair(&[0, 255, 128]).await.unwrap();
+    assert_eq!(storage.get_keypair().await.unwrap(), Some(vec![0, 255, 128]));
+}
@@ -1,20 +1,74 @@
+use std::collections::{BTreeSet, HashSet};
+
 use async_trait::async_trait;
 use rexie::*;
 use serde_json;
 use wasm_bindgen::{JsValue, JsCast};
 
-use crate::storage::Storage;
-use crate::types::{StoredEvent, StorageFilter, DmThread, OutboxItem, OutboxStatus};
+use crate::storage::{sort_and_limit, supersedes, Storage};
+use crate::storage::migrations::{self, *};
+use crate::types::{StoredEvent, StorageFilter, DmThread, OutboxItem, OutboxStatus, RelayConfig, StorageUsage};
 use crate::error::{Result, CoreError};
+use crate::event::{is_ephemeral_kind, NostrEvent};
 
 const DB_NAME: &str = "rustr_db";
-const DB_VERSION: u32 = 1;
 
-const STORE_EVENTS: &str = "events";
-const STORE_DM_THREADS: &str = "dm_threads";
-const STORE_LAST_SEEN: &str = "last_seen";
-const STORE_OUTBOX: &str = "outbox";
-const STORE_KEYPAIR: &str = "keypair";
+/// インデックスを走査するときに1回で読む件数
+const SCAN_BATCH: u32 = 100;
+
+/// `get_events`の検索計画
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum QueryPlan {
+    /// 一致するイベントがない
+    Nothing,
+    /// 主キーで直接取得
+    Ids(Vec<String>),
+    /// タグ（`StoredEvent::tag_key`の形）ごとに`[タグ, created_at]`インデックスを新しい順に走査してまとめる
+    Tags { keys: Vec<String>, since: Option<i64>, until: Option<i64> },
+    /// kindごとに`[kind, created_at]`インデックスを新しい順に走査してまとめる
+    KindsByTime { kinds: Vec<u16>, since: Option<i64>, until: Option<i64> },
+    /// `created_at`インデックスを範囲指定で新しい順に走査
+    ByTime { since: Option<i64>, until: Option<i64> },
+}
+
+impl QueryPlan {
+    /// フィルターから最も絞り込めるインデックスを選ぶ
+    fn new(filter: &StorageFilter) -> Self {
+        if filter.since.zip(filter.until).is_some_and(|(since, until)| since > until) {
+            return QueryPlan::Nothing;
+        }
+        if let Some(ids) = &filter.ids {
+            let ids: BTreeSet<&String> = ids.iter().collect();
+            return match ids.is_empty() {
+                true => QueryPlan::Nothing,
+                false => QueryPlan::Ids(ids.into_iter().cloned().collect()),
+            };
+        }
+        // 値の少ないタグ条件を使う
+        if let Some((name, values)) = filter.tags.iter().min_by_key(|(_, values)| values.len()) {
+            let keys: BTreeSet<String> = values
+                .iter()
+                .map(|value| StoredEvent::tag_key(&name.to_string(), value))
+                .collect();
+            return match keys.is_empty() {
+                true => QueryPlan::Nothing,
+                false => QueryPlan::Tags { keys: keys.into_iter().collect(), since: filter.since, until: filter.until },
+            };
+        }
+        if let Some(kinds) = &filter.kinds {
+            let kinds: BTreeSet<u16> = kinds.iter().copied().collect();
+            return match kinds.is_empty() {
+                true => QueryPlan::Nothing,
+                false => QueryPlan::KindsByTime {
+                    kinds: kinds.into_iter().collect(),
+                    since: filter.since,
+                    until: filter.until,
+                },
+            };
+        }
+        QueryPlan::ByTime { since: filter.since, until: filter.until }
+    }
+}
 
 /// IndexedDB実装
 pub struct IndexedDbStorage {
@@ -23,28 +77,134 @@ pub struct IndexedDbStorage {
 
 impl IndexedDbStorage {
     async fn open_db() -> Result<Rexie> {
-        let rexie = Rexie::builder(DB_NAME)
-            .version(DB_VERSION)
-            .add_object_store(
-                ObjectStore::new(STORE_EVENTS)
-                    .key_path("id")
-                    .add_index(Index::new("kind", "kind"))
-                    .add_index(Index::new("pubkey", "pubkey"))
-                    .add_index(Index::new("created_at", "created_at")),
-            )
-            .add_object_store(ObjectStore::new(STORE_DM_THREADS).key_path("peer"))
-            .add_object_store(ObjectStore::new(STORE_LAST_SEEN).key_path("scope"))
-            .add_object_store(
-                ObjectStore::new(STORE_OUTBOX)
-                    .key_path("req_id")
-                    .add_index(Index::new("status", "status")),
-            )
-            .add_object_store(ObjectStore::new(STORE_KEYPAIR).key_path("id"))
-            .build()
-            .await?;
+        Self::open_named(DB_NAME).await
+    }
 
+    /// マイグレーションを適用してからDBを開く（スキーマは`migrations::MIGRATIONS`で定義）
+    async fn open_named(name: &str) -> Result<Rexie> {
+        let version = migrations::latest_version();
+        migrations::upgrade(name, version).await?;
+        let rexie = Rexie::builder(name).version(version).build().await?;
         Ok(rexie)
     }
+
+    /// `created_at`で終わるインデックスを新しい順に走査し、フィルターに一致したものを`limit`件まで返す
+    ///
+    /// `SCAN_BATCH`件ずつ読み、`limit`に達したら最後と同じ時刻のものだけ読み足して止める
+    /// （インデックスは同じ時刻をIDの大きい順に返すので、並べ直すと小さい順の`limit`件になる）。
+    /// 次のバッチは最後に読んだ時刻までに範囲を狭め、その時刻で読み終えた分だけ飛ばして続ける。
+    async fn scan_newest(
+        index: &StoreIndex,
+        prefix: Option<&JsValue>,
+        since: Option<i64>,
+        mut until: Option<i64>,
+        filter: &StorageFilter,
+    ) -> Result<Vec<StoredEvent>> {
+        let limit = filter.limit.map(|l| l as usize);
+        let mut events: Vec<StoredEvent> = Vec::new();
+        let mut boundary: Option<i64> = None;
+        // `until`の時刻で読み終えた件数
+        let mut skip = 0;
+        loop {
+            // advance(0)はIndexedDBがエラーにするので0は渡さない
+            let range = time_range(prefix, since, until)?;
+            let batch = index
+                .scan(range, Some(SCAN_BATCH), (skip > 0).then_some(skip), Some(Direction::Prev))
+                .await?;
+            let exhausted = batch.len() < SCAN_BATCH as usize;
+
+            for (key, value) in batch {
+                match key_time(&key) {
+                    Some(ts) if until != Some(ts) => {
+                        until = Some(ts);
+                        skip = 1;
+                    }
+                    _ => skip += 1,
+                }
+                if let Ok(event) = serde_wasm_bindgen::from_value::<StoredEvent>(value) {
+                    if boundary.is_some_and(|boundary| event.created_at < boundary) {
+                        return Ok(events);
+                    }
+                    if filter.matches(&event) {
+                        events.push(event);
+                        if boundary.is_none() && limit.is_some_and(|limit| events.len() >= limit) {
+                            boundary = events.last().map(|e| e.created_at);
+                        }
+                    }
+                }
+            }
+            if exhausted {
+                return Ok(events);
+            }
+        }
+    }
+
+    /// 同じアドレスの古い版を消してtrueを返す（一時的なイベントと、新しい版があるイベントはfalse）
+    async fn make_room(store: &Store, event: &StoredEvent) -> Result<bool> {
+        if is_ephemeral_kind(event.kind) {
+            return Ok(false);
+        }
+        let Some(key) = event.replace_key() else {
+            return Ok(true);
+        };
+
+        let index = store.index(INDEX_KIND_PUBKEY)?;
+        let kind_pubkey = js_sys::Array::of2(&JsValue::from_f64(event.kind as f64), &JsValue::from_str(&event.pubkey));
+        let range = KeyRange::only(&kind_pubkey.into()).map_err(rexie::Error::from)?;
+        let mut superseded = Vec::new();
+        for value in index.get_all(Some(range), None).await? {
+            let Ok(old) = serde_wasm_bindgen::from_value::<StoredEvent>(value) else {
+                continue;
+            };
+            if old.id == event.id || old.replace_key().as_ref() != Some(&key) {
+                continue;
+            }
+            if !supersedes(event, &old) {
+                return Ok(false);
+            }
+            superseded.push(old.id);
+        }
+        for id in superseded {
+            store.delete(JsValue::from_str(&id)).await?;
+        }
+        Ok(true)
+    }
+}
+
+/// イベントのレコード（タグ・時刻インデックス用の`tag_times`を付ける）
+fn event_record(event: &StoredEvent) -> Result<JsValue> {
+    let record = serde_wasm_bindgen::to_value(event)?;
+    let tag_times = serde_wasm_bindgen::to_value(&migrations::tag_times(&event.tags, event.created_at))?;
+    js_sys::Reflect::set(&record, &JsValue::from_str(FIELD_TAG_TIMES), &tag_times)
+        .map_err(|e| CoreError::StorageError(format!("Failed to build event record: {:?}", e)))?;
+    Ok(record)
+}
+
+/// `created_at`の範囲（`prefix`があれば複合キーの先頭に付ける）
+fn time_range(prefix: Option<&JsValue>, since: Option<i64>, until: Option<i64>) -> Result<Option<KeyRange>> {
+    let key = |ts: Option<i64>, missing: f64| -> JsValue {
+        let ts = JsValue::from_f64(ts.map(|t| t as f64).unwrap_or(missing));
+        match prefix {
+            Some(prefix) => js_sys::Array::of2(prefix, &ts).into(),
+            None => ts,
+        }
+    };
+    let range = match (prefix, since, until) {
+        (None, None, None) => return Ok(None),
+        (None, Some(since), None) => KeyRange::lower_bound(&key(Some(since), 0.0), None),
+        (None, None, Some(until)) => KeyRange::upper_bound(&key(Some(until), 0.0), None),
+        _ => KeyRange::bound(&key(since, f64::NEG_INFINITY), &key(until, f64::INFINITY), None, None),
+    };
+    Ok(Some(range.map_err(rexie::Error::from)?))
+}
+
+/// インデックスのキーの`created_at`（複合キーなら最後の要素）
+fn key_time(key: &JsValue) -> Option<i64> {
+    let ts = match key.dyn_ref::<js_sys::Array>() {
+        Some(array) => array.get(array.length().checked_sub(1)?),
+        None => key.clone(),
+    };
+    ts.as_f64().map(|ts| ts as i64)
 }
 
 #[async_trait(?Send)]
@@ -58,15 +218,19 @@ impl Storage for IndexedDbStorage {
         let tx = self.db.transaction(&[STORE_EVENTS], TransactionMode::ReadWrite)?;
         let store = tx.store(STORE_EVENTS)?;
 
-        let js_value = serde_wasm_bindgen::to_value(event)?;
-
-        store.put(&js_value, None).await?;
+        let mut event = event.clone();
+        event.tag_keys = StoredEvent::tag_keys(&event.tags);
+        if !Self::make_room(&store, &event).await? {
+            return Ok(());
+        }
+        store.put(&event_record(&event)?, None).await?;
         tx.done().await?;
 
         Ok(())
     }
 
-    async fn save_event(&self, event_id: &str, event_json: &str) -> Result<()> {
+    async fn save_event(&self, event: &NostrEvent) -> Result<()> {
+        let event_id = &event.id;
         let tx = self.db.transaction(&[STORE_EVENTS], TransactionMode::ReadWrite)
             .map_err(|e| {
                 log::error!("save_event: Failed to start transaction for {}: {:?}", event_id, e);
@@ -78,34 +242,21 @@ impl Storage for IndexedDbStorage {
                 e
             })?;
 
-        // JSON文字列をパースしてStoredEvent構造体に変換
-        let event: serde_json::Value = serde_json::from_str(event_json)
-            .map_err(|e| {
-                log::error!("save_event: Failed to parse JSON for {}: {:?}", event_id, e);
-                CoreError::ParseError(e.to_string())
-            })?;
-        
-        // StoredEvent構造体を作成（IndexedDBのkeyPathが正しく動作するように）
         let now = js_sys::Date::now() as i64;
-        let stored_event = StoredEvent {
-            id: event["id"].as_str().unwrap_or(event_id).to_string(),
-            kind: event["kind"].as_u64().unwrap_or(0) as u16,
-            pubkey: event["pubkey"].as_str().unwrap_or("").to_string(),
-            created_at: event["created_at"].as_i64().unwrap_or(0),
-            content: event["content"].as_str().unwrap_or("").to_string(),
-            tags: event["tags"].as_array().map(|arr| {
-                arr.iter().filter_map(|tag| {
-                    tag.as_array().map(|t| {
-                        t.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect()
-                    })
-                }).collect()
-            }).unwrap_or_default(),
-            sig: event["sig"].as_str().unwrap_or("").to_string(),
-            relay_hint: None,
-            inserted_at: now,
-        };
+        let mut stored_event = StoredEvent::new(event.clone(), now);
+        if !Self::make_room(&store, &stored_event).await? {
+            return Ok(());
+        }
+
+        // 受信Relayの記録は残す
+        if let Some(existing) = store.get(JsValue::from_str(&stored_event.id)).await? {
+            if let Ok(existing) = serde_wasm_bindgen::from_value::<StoredEvent>(existing) {
+                stored_event.seen_on = existing.seen_on;
+                stored_event.relay_hint = existing.relay_hint;
+            }
+        }
         
-        let js_value = serde_wasm_bindgen::to_value(&stored_event)
+        let js_value = event_record(&stored_event)
             .map_err(|e| {
                 log::error!("save_event: Failed to serialize event {}: {:?}", event_id, e);
                 e
@@ -125,53 +276,98 @@ impl Storage for IndexedDbStorage {
         Ok(())
     }
 
-    async fn get_events(&self, filter: &StorageFilter) -> Result<Vec<StoredEvent>> {
+    async fn get_event(&self, event_id: &str) -> Result<Option<StoredEvent>> {
         let tx = self.db.transaction(&[STORE_EVENTS], TransactionMode::ReadOnly)?;
         let store = tx.store(STORE_EVENTS)?;
 
-        // 全件取得してメモリ上でフィルタリング
-        // （IndexedDBのインデックスを使った最適化は将来の改善として）
-        let all = store.get_all(None, None).await?;
+        let value = store.get(JsValue::from_str(event_id)).await?;
+        let event = match value {
+            Some(v) => serde_wasm_bindgen::from_value::<StoredEvent>(v).ok(),
+            None => None,
+        };
+        Ok(event)
+    }
 
-        let mut events = Vec::new();
-        for value in all {
-            // JavaScriptオブジェクトとしてデシリアライズ
-            if let Ok(event) = serde_wasm_bindgen::from_value::<StoredEvent>(value) {
-                // フィルター適用
-                if let Some(kinds) = &filter.kinds {
-                    if !kinds.contains(&event.kind) {
-                        continue;
-                    }
+    async fn add_seen_on(&self, event_id: &str, relay_url: &str) -> Result<()> {
+        let tx = self.db.transaction(&[STORE_EVENTS], TransactionMode::ReadWrite)?;
+        let store = tx.store(STORE_EVENTS)?;
+
+        if let Some(value) = store.get(JsValue::from_str(event_id)).await? {
+            if let Ok(mut event) = serde_wasm_bindgen::from_value::<StoredEvent>(value) {
+                if event.add_seen_on(relay_url) {
+                    store.put(&event_record(&event)?, None).await?;
                 }
-                if let Some(authors) = &filter.authors {
-                    if !authors.contains(&event.pubkey) {
-                        continue;
+            }
+        }
+        tx.done().await?;
+
+        Ok(())
+    }
+
+    async fn get_events(&self, filter: &StorageFilter) -> Result<Vec<StoredEvent>> {
+        let tx = self.db.transaction(&[STORE_EVENTS], TransactionMode::ReadOnly)?;
+        let store = tx.store(STORE_EVENTS)?;
+
+        let mut events = match QueryPlan::new(filter) {
+            QueryPlan::Nothing => Vec::new(),
+            QueryPlan::Ids(ids) => {
+                let mut events = Vec::new();
+                for id in ids {
+                    if let Some(value) = store.get(JsValue::from_str(&id)).await? {
+                        if let Ok(event) = serde_wasm_bindgen::from_value::<StoredEvent>(value) {
+                            if filter.matches(&event) {
+                                events.push(event);
+                            }
+                        }
                     }
                 }
-                if let Some(since) = filter.since {
-                    if event.created_at < since {
-                        continue;
+                events
+            }
+            QueryPlan::Tags { keys, since, until } => {
+                // タグごとに`limit`件ずつ読み、複数の値に一致するイベントは1回だけ返す
+                let index = store.index(INDEX_TAG_CREATED_AT)?;
+                let mut seen: HashSet<String> = HashSet::new();
+                let mut events = Vec::new();
+                for key in keys {
+                    let key = JsValue::from_str(&key);
+                    for event in Self::scan_newest(&index, Some(&key), since, until, filter).await? {
+                        if seen.insert(event.id.clone()) {
+                            events.push(event);
+                        }
                     }
                 }
-                if let Some(until) = filter.until {
-                    if event.created_at > until {
-                        continue;
-                    }
+                events
+            }
+            QueryPlan::KindsByTime { kinds, since, until } => {
+                // kindごとに新しい方から`limit`件ずつ読めば、全体の上位`limit`件はその中にある
+                let index = store.index(INDEX_KIND_CREATED_AT)?;
+                let mut events = Vec::new();
+                for kind in kinds {
+                    let kind = JsValue::from_f64(kind as f64);
+                    events.extend(Self::scan_newest(&index, Some(&kind), since, until, filter).await?);
                 }
-                
-                events.push(event);
+                events
             }
-        }
+            QueryPlan::ByTime { since, until } => {
+                let index = store.index(INDEX_CREATED_AT)?;
+                Self::scan_newest(&index, None, since, until, filter).await?
+            }
+        };
 
-        // created_at降順でソート
-        events.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        sort_and_limit(&mut events, filter.limit);
+        Ok(events)
+    }
+
+    async fn delete_events(&self, event_ids: &[String]) -> Result<()> {
+        let tx = self.db.transaction(&[STORE_EVENTS], TransactionMode::ReadWrite)?;
+        let store = tx.store(STORE_EVENTS)?;
 
-        // limit適用
-        if let Some(limit) = filter.limit {
-            events.truncate(limit as usize);
+        for id in event_ids {
+            store.delete(JsValue::from_str(id)).await?;
         }
+        tx.done().await?;
 
-        Ok(events)
+        Ok(())
     }
 
     async fn upsert_dm_thread(&self, peer: &str, last_msg_at: i64) -> Result<()> {
@@ -207,7 +403,7 @@ impl Storage for IndexedDbStorage {
         }
 
         // last_msg_at降順でソート
-        threads.sort_by(|a, b| b.last_msg_at.cmp(&a.last_msg_at));
+        threads.sort_by_key(|t| std::cmp::Reverse(t.last_msg_at));
 
         Ok(threads)
     }
@@ -240,13 +436,28 @@ impl Storage for IndexedDbStorage {
             "ts": ts,
         });
 
-        let value = serde_wasm_bindgen::to_value(&data)?;
+        let value = to_js_object(&data)?;
         store.put(&value, None).await?;
         tx.done().await?;
 
         Ok(())
     }
 
+    async fn get_all_last_seen(&self) -> Result<Vec<(String, i64)>> {
+        let tx = self.db.transaction(&[STORE_LAST_SEEN], TransactionMode::ReadOnly)?;
+        let store = tx.store(STORE_LAST_SEEN)?;
+
+        // 主キー（scope）順に返る
+        let mut entries = Vec::new();
+        for value in store.get_all(None, None).await? {
+            let data = serde_wasm_bindgen::from_value::<serde_json::Value>(value)?;
+            if let (Some(scope), Some(ts)) = (data.get("scope").and_then(|v| v.as_str()), data.get("ts").and_then(|v| v.as_i64())) {
+                entries.push((scope.to_string(), ts));
+            }
+        }
+        Ok(entries)
+    }
+
     async fn enqueue_outbox(&self, item: OutboxItem) -> Result<String> {
         let req_id = item.req_id.clone();
         
@@ -303,30 +514,24 @@ impl Storage for IndexedDbStorage {
             })?;
 
         if let Some(v) = value {
-            // JavaScriptオブジェクトとしてデシリアライズ
-            if let Ok(mut item) = serde_wasm_bindgen::from_value::<OutboxItem>(v.clone()) {
-                item.status = status;
-                let js_value = serde_wasm_bindgen::to_value(&item)
-                    .map_err(|e| {
-                        log::error!("update_outbox_status: Failed to serialize updated item {}: {:?}", req_id, e);
-                        e
-                    })?;
-                // key_path("req_id")が設定されているので、キーはオブジェクトから自動取得される
-                store.put(&js_value, None).await
-                    .map_err(|e| {
-                        log::error!("update_outbox_status: Failed to put updated item {}: {:?}", req_id, e);
-                        e
-                    })?;
-            } else {
-                // 古い形式のデータは削除
-                log::warn!("update_outbox_status: Deleting old format outbox item: {}, value type: {:?}", req_id, v);
-                let delete_key = JsValue::from_str(req_id);
-                store.delete(delete_key).await
-                    .map_err(|e| {
-                        log::error!("update_outbox_status: Failed to delete old format item {}: {:?}", req_id, e);
-                        e
-                    })?;
-            }
+            // 古い形式のデータはマイグレーションで書き換え済み
+            let mut item = serde_wasm_bindgen::from_value::<OutboxItem>(v)
+                .map_err(|e| {
+                    log::error!("update_outbox_status: Failed to deserialize item {}: {:?}", req_id, e);
+                    e
+                })?;
+            item.status = status;
+            let js_value = serde_wasm_bindgen::to_value(&item)
+                .map_err(|e| {
+                    log::error!("update_outbox_status: Failed to serialize updated item {}: {:?}", req_id, e);
+                    e
+                })?;
+            // key_path("req_id")が設定されているので、キーはオブジェクトから自動取得される
+            store.put(&js_value, None).await
+                .map_err(|e| {
+                    log::error!("update_outbox_status: Failed to put updated item {}: {:?}", req_id, e);
+                    e
+                })?;
         } else {
             log::warn!("update_outbox_status: Item not found: {}", req_id);
         }
@@ -339,6 +544,44 @@ impl Storage for IndexedDbStorage {
         Ok(())
     }
 
+    async fn update_outbox_item(&self, item: &OutboxItem) -> Result<()> {
+        let req_id = &item.req_id;
+        let tx = self.db.transaction(&[STORE_OUTBOX], TransactionMode::ReadWrite)
+            .map_err(|e| {
+                log::error!("update_outbox_item: Failed to start transaction for {}: {:?}", req_id, e);
+                e
+            })?;
+        let store = tx.store(STORE_OUTBOX)
+            .map_err(|e| {
+                log::error!("update_outbox_item: Failed to get store for {}: {:?}", req_id, e);
+                e
+            })?;
+
+        // ないアイテムは追加しない
+        if store.get(JsValue::from_str(req_id)).await?.is_none() {
+            log::warn!("update_outbox_item: Item not found: {}", req_id);
+            return Ok(());
+        }
+
+        let js_value = serde_wasm_bindgen::to_value(item)
+            .map_err(|e| {
+                log::error!("update_outbox_item: Failed to serialize item {}: {:?}", req_id, e);
+                e
+            })?;
+        // key_path("req_id")が設定されているので、キーはオブジェクトから自動取得される
+        store.put(&js_value, None).await
+            .map_err(|e| {
+                log::error!("update_outbox_item: Failed to put item {}: {:?}", req_id, e);
+                e
+            })?;
+        tx.done().await
+            .map_err(|e| {
+                log::error!("update_outbox_item: Failed to commit transaction for {}: {:?}", req_id, e);
+                e
+            })?;
+        Ok(())
+    }
+
     async fn get_pending_outbox(&self) -> Result<Vec<OutboxItem>> {
         let tx = self.db.transaction(&[STORE_OUTBOX], TransactionMode::ReadOnly)
             .map_err(|e| {
@@ -358,21 +601,69 @@ impl Storage for IndexedDbStorage {
             })?;
 
         let mut items = Vec::new();
-        for (idx, value) in all.iter().enumerate() {
-            // JavaScriptオブジェクトとしてデシリアライズ
-            if let Ok(item) = serde_wasm_bindgen::from_value::<OutboxItem>(value.clone()) {
-                if matches!(item.status, OutboxStatus::Queued | OutboxStatus::Sent) {
-                    items.push(item);
-                }
-            } else {
-                // 古い形式のデータ（JSON文字列）は警告してスキップ
-                log::warn!("get_pending_outbox: Skipping old format outbox item at index {}, value type: {:?}", idx, value);
+        for value in all {
+            let item = serde_wasm_bindgen::from_value::<OutboxItem>(value)
+                .map_err(|e| {
+                    log::error!("get_pending_outbox: Failed to deserialize item: {:?}", e);
+                    e
+                })?;
+            if matches!(item.status, OutboxStatus::Queued | OutboxStatus::Sent) {
+                items.push(item);
             }
         }
 
         Ok(items)
     }
 
+    async fn get_all_outbox(&self) -> Result<Vec<OutboxItem>> {
+        let tx = self.db.transaction(&[STORE_OUTBOX], TransactionMode::ReadOnly)?;
+        let store = tx.store(STORE_OUTBOX)?;
+
+        // 主キー（req_id）順に返る
+        let mut items = Vec::new();
+        for value in store.get_all(None, None).await? {
+            items.push(serde_wasm_bindgen::from_value::<OutboxItem>(value)?);
+        }
+        Ok(items)
+    }
+
+    async fn upsert_relay(&self, relay: &RelayConfig) -> Result<()> {
+        let tx = self.db.transaction(&[STORE_RELAYS], TransactionMode::ReadWrite)?;
+        let store = tx.store(STORE_RELAYS)?;
+
+        let js_value = serde_wasm_bindgen::to_value(relay)?;
+        store.put(&js_value, None).await?;
+        tx.done().await?;
+
+        Ok(())
+    }
+
+    async fn remove_relay(&self, url: &str) -> Result<()> {
+        let tx = self.db.transaction(&[STORE_RELAYS], TransactionMode::ReadWrite)?;
+        let store = tx.store(STORE_RELAYS)?;
+
+        store.delete(JsValue::from_str(url)).await?;
+        tx.done().await?;
+
+        Ok(())
+    }
+
+    async fn get_relays(&self) -> Result<Vec<RelayConfig>> {
+        let tx = self.db.transaction(&[STORE_RELAYS], TransactionMode::ReadOnly)?;
+        let store = tx.store(STORE_RELAYS)?;
+
+        let all = store.get_all(None, None).await?;
+
+        let mut relays = Vec::new();
+        for value in all {
+            if let Ok(relay) = serde_wasm_bindgen::from_value::<RelayConfig>(value) {
+                relays.push(relay);
+            }
+        }
+
+        Ok(relays)
+    }
+
     async fn save_keypair(&self, encrypted_data: &[u8]) -> Result<()> {
         let tx = self.db.transaction(&[STORE_KEYPAIR], TransactionMode::ReadWrite)?;
         let store = tx.store(STORE_KEYPAIR)?;
@@ -382,7 +673,7 @@ impl Storage for IndexedDbStorage {
             "data": base64_encode(encrypted_data),
         });
 
-        let value = serde_wasm_bindgen::to_value(&data)?;
+        let value = to_js_object(&data)?;
         store.put(&value, None).await?;
         tx.done().await?;
 
@@ -409,6 +700,23 @@ impl Storage for IndexedDbStorage {
 
         Ok(None)
     }
+
+    async fn estimate_usage(&self) -> Result<Option<StorageUsage>> {
+        // `navigator.storage.estimate()`はオリジン全体の使用量と上限を返す
+        let window = web_sys::window().ok_or_else(|| CoreError::Other("No window".to_string()))?;
+        let promise = window.navigator().storage().estimate()?;
+        let estimate: web_sys::StorageEstimate = wasm_bindgen_futures::JsFuture::from(promise).await?.unchecked_into();
+        Ok(estimate.get_usage().map(|usage| StorageUsage {
+            usage: usage as u64,
+            quota: estimate.get_quota().map(|quota| quota as u64),
+        }))
+    }
+}
+
+/// JSONをJSのオブジェクトにする（`to_value`ではMapになり、key_pathで引けない）
+fn to_js_object(value: &serde_json::Value) -> Result<JsValue> {
+    use serde::Serialize;
+    Ok(value.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
 }
 
 // 簡易Base64エンコード/デコード
@@ -434,3 +742,142 @@ fn base64_decode(encoded: &str) -> Result<Vec<u8>> {
     Ok(decoded_str.bytes().collect())
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_plan() {
+        let filter = StorageFilter::new().kinds([42, 40, 42]).authors(["alice"]).since(100).limit(20);
+        assert_eq!(
+            QueryPlan::new(&filter),
+            QueryPlan::KindsByTime { kinds: vec![40, 42], since: Some(100), until: None }
+        );
+
+        // IDがあれば主キーで引く
+        let filter = StorageFilter::new().ids(["b", "a", "b"]).kinds([1]).tag('e', ["chan"]);
+        assert_eq!(QueryPlan::new(&filter), QueryPlan::Ids(vec!["a".to_string(), "b".to_string()]));
+
+        // タグ条件はkindより優先し、値の少ないものを使う
+        let filter = StorageFilter::new().kinds([4]).tag('p', ["x", "y"]).tag('e', ["chan"]);
+        assert_eq!(
+            QueryPlan::new(&filter),
+            QueryPlan::Tags { keys: vec!["e:chan".to_string()], since: None, until: None }
+        );
+
+        // `#e`と`limit`は時刻範囲付きでタグごとに新しい順に走査する（`limit`件で止まる）
+        let filter = StorageFilter::new().tag('e', ["b", "a"]).until(500).limit(50);
+        assert_eq!(
+            QueryPlan::new(&filter),
+            QueryPlan::Tags { keys: vec!["e:a".to_string(), "e:b".to_string()], since: None, until: Some(500) }
+        );
+
+        let filter = StorageFilter::new().authors(["alice"]).until(50);
+        assert_eq!(QueryPlan::new(&filter), QueryPlan::ByTime { since: None, until: Some(50) });
+
+        // 一致し得ない条件は読まない
+        assert_eq!(QueryPlan::new(&StorageFilter::new().kinds([])), QueryPlan::Nothing);
+        assert_eq!(QueryPlan::new(&StorageFilter::new().tag('e', Vec::<String>::new())), QueryPlan::Nothing);
+        assert_eq!(QueryPlan::new(&StorageFilter::new().ids(Vec::<String>::new())), QueryPlan::Nothing);
+        assert_eq!(QueryPlan::new(&StorageFilter::new().since(10).until(5)), QueryPlan::Nothing);
+    }
+
+    #[test]
+    fn test_tag_keys() {
+        let tags: Vec<Vec<String>> = [&["e", "chan", "", "root"][..], &["p", "x"], &["e", "chan"], &["title", "t"], &["t"]]
+            .iter()
+            .map(|t| t.iter().map(|s| s.to_string()).collect())
+            .collect();
+        assert_eq!(StoredEvent::tag_keys(&tags), vec!["e:chan", "p:x"]);
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod wasm_tests {
+    use super::*;
+    use serde_json::json;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const TEST_DB: &str = "rustr_migration_test";
+    const CONFORMANCE_DB: &str = "rustr_conformance_test";
+
+    fn to_js(value: &serde_json::Value) -> JsValue {
+        to_js_object(value).unwrap()
+    }
+
+    /// v1のスキーマとその頃の形式のレコードを持つDBを作る
+    async fn create_v1_fixture(event: &serde_json::Value) {
+        Rexie::delete(TEST_DB).await.unwrap();
+        migrations::upgrade(TEST_DB, 1).await.unwrap();
+
+        let db = Rexie::builder(TEST_DB).version(1).build().await.unwrap();
+        let tx = db.transaction(&[STORE_EVENTS, STORE_OUTBOX], TransactionMode::ReadWrite).unwrap();
+        let mut stored = event.clone();
+        stored["relay_hint"] = serde_json::Value::Null;
+        stored["inserted_at"] = json!(0);
+        tx.store(STORE_EVENTS).unwrap().put(&to_js(&stored), None).await.unwrap();
+        let outbox = tx.store(STORE_OUTBOX).unwrap();
+        let item = json!({
+            "req_id": "r1",
+            "event_json": event.to_string(),
+            "status": "Queued",
+            "last_try_at": 0,
+            "retry_count": 0,
+            "error": null,
+        });
+        outbox.put(&to_js(&item), None).await.unwrap();
+        let broken = json!({ "req_id": "r2", "event_json": "{", "status": "Queued" });
+        outbox.put(&to_js(&broken), None).await.unwrap();
+        tx.done().await.unwrap();
+        db.close();
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_v1_database_upgrades_cleanly() {
+        let event = json!({
+            "id": "a".repeat(64),
+            "pubkey": "b".repeat(64),
+            "created_at": 100,
+            "kind": 42,
+            "tags": [["e", "chan"]],
+            "content": "hi",
+            "sig": "c".repeat(128),
+        });
+        create_v1_fixture(&event).await;
+
+        let storage = IndexedDbStorage { db: IndexedDbStorage::open_named(TEST_DB).await.unwrap() };
+        assert_eq!(storage.db.version().unwrap(), migrations::latest_version());
+
+        // v6のタグ・時刻インデックスで古いイベントも引ける
+        let filter = StorageFilter::new().kinds([42]).tag('e', ["chan"]).limit(10);
+        let events = storage.get_events(&filter).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].tag_keys, vec!["e:chan"]);
+
+        // 配送記録が付き、送れないアイテムは消える
+        let pending = storage.get_pending_outbox().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].event_id, "a".repeat(64));
+        assert!(pending[0].receipts.is_empty());
+
+        // v2で追加したストア
+        assert!(storage.get_relays().await.unwrap().is_empty());
+
+        storage.db.close();
+        Rexie::delete(TEST_DB).await.unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_conformance() {
+        crate::storage::conformance::check_all(|| async {
+            Rexie::delete(CONFORMANCE_DB).await.unwrap();
+            IndexedDbStorage { db: IndexedDbStorage::open_named(CONFORMANCE_DB).await.unwrap() }
+        })
+        .await;
+        Rexie::delete(CONFORMANCE_DB).await.unwrap();
+    }
+}
@@ -0,0 +1,459 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use idb::{Database, DatabaseEvent, Event, Factory, IndexParams, KeyPath, ObjectStoreParams, Request, Transaction};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::error::{CoreError, Result};
+use crate::event::NostrEvent;
+use crate::types::StoredEvent;
+
+pub(crate) const STORE_EVENTS: &str = "events";
+pub(crate) const STORE_DM_THREADS: &str = "dm_threads";
+pub(crate) const STORE_LAST_SEEN: &str = "last_seen";
+pub(crate) const STORE_OUTBOX: &str = "outbox";
+pub(crate) const STORE_KEYPAIR: &str = "keypair";
+pub(crate) const STORE_RELAYS: &str = "relays";
+
+pub(crate) const INDEX_CREATED_AT: &str = "created_at";
+pub(crate) const INDEX_KIND_CREATED_AT: &str = "kind_created_at";
+pub(crate) const INDEX_TAG_KEYS: &str = "tag_keys";
+pub(crate) const INDEX_KIND_PUBKEY: &str = "kind_pubkey";
+pub(crate) const INDEX_TAG_CREATED_AT: &str = "tag_created_at";
+
+/// イベントのレコードに付けるタグ・時刻インデックス用のキー（`tag_times`）
+pub(crate) const FIELD_TAG_TIMES: &str = "tag_times";
+
+/// スキーマの変更
+#[derive(Debug)]
+pub(crate) enum SchemaChange {
+    CreateStore { name: &'static str, key_path: &'static str },
+    CreateIndex {
+        store: &'static str,
+        name: &'static str,
+        /// 2つ以上なら複合インデックス
+        key_path: &'static [&'static str],
+        multi_entry: bool,
+    },
+}
+
+/// レコードの書き換え（`None`を返したレコードは削除する）
+pub(crate) struct Rewrite {
+    pub store: &'static str,
+    pub rewrite: fn(Value) -> Option<Value>,
+}
+
+/// 同じキーのレコードを1つにまとめる（書き換えの後に行う）
+pub(crate) struct Dedup {
+    pub store: &'static str,
+    /// まとめるキー（Noneのレコードはまとめない）
+    pub key: fn(&Value) -> Option<String>,
+    /// 残すレコードが先に来る順序
+    pub order: fn(&Value, &Value) -> Ordering,
+}
+
+/// スキーマのバージョンを1つ上げるマイグレーション
+pub(crate) struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub schema: &'static [SchemaChange],
+    pub rewrites: &'static [Rewrite],
+    pub dedups: &'static [Dedup],
+}
+
+/// 全マイグレーション（バージョン順。追加するときは末尾に足す）
+pub(crate) const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "初期スキーマ",
+        schema: &[
+            SchemaChange::CreateStore { name: STORE_EVENTS, key_path: "id" },
+            SchemaChange::CreateIndex { store: STORE_EVENTS, name: "kind", key_path: &["kind"], multi_entry: false },
+            SchemaChange::CreateIndex { store: STORE_EVENTS, name: "pubkey", key_path: &["pubkey"], multi_entry: false },
+            SchemaChange::CreateIndex {
+                store: STORE_EVENTS,
+                name: INDEX_CREATED_AT,
+                key_path: &["created_at"],
+                multi_entry: false,
+            },
+            SchemaChange::CreateStore { name: STORE_DM_THREADS, key_path: "peer" },
+            SchemaChange::CreateStore { name: STORE_LAST_SEEN, key_path: "scope" },
+            SchemaChange::CreateStore { name: STORE_OUTBOX, key_path: "req_id" },
+            SchemaChange::CreateIndex { store: STORE_OUTBOX, name: "status", key_path: &["status"], multi_entry: false },
+            SchemaChange::CreateStore { name: STORE_KEYPAIR, key_path: "id" },
+        ],
+        rewrites: &[],
+        dedups: &[],
+    },
+    Migration {
+        version: 2,
+        description: "Relay設定のストアとOutboxの配送記録",
+        schema: &[SchemaChange::CreateStore { name: STORE_RELAYS, key_path: "url" }],
+        rewrites: &[Rewrite { store: STORE_OUTBOX, rewrite: outbox_receipts }],
+        dedups: &[],
+    },
+    Migration {
+        version: 3,
+        description: "kindごとの時刻順インデックス",
+        schema: &[SchemaChange::CreateIndex {
+            store: STORE_EVENTS,
+            name: INDEX_KIND_CREATED_AT,
+            key_path: &["kind", "created_at"],
+            multi_entry: false,
+        }],
+        rewrites: &[],
+        dedups: &[],
+    },
+    Migration {
+        version: 4,
+        description: "タグ検索用インデックス",
+        schema: &[SchemaChange::CreateIndex {
+            store: STORE_EVENTS,
+            name: INDEX_TAG_KEYS,
+            key_path: &["tag_keys"],
+            multi_entry: true,
+        }],
+        rewrites: &[Rewrite { store: STORE_EVENTS, rewrite: event_tag_keys }],
+        dedups: &[],
+    },
+    Migration {
+        version: 5,
+        description: "置き換え可能なイベントは最新版だけ残す",
+        schema: &[SchemaChange::CreateIndex {
+            store: STORE_EVENTS,
+            name: INDEX_KIND_PUBKEY,
+            key_path: &["kind", "pubkey"],
+            multi_entry: false,
+        }],
+        rewrites: &[Rewrite { store: STORE_EVENTS, rewrite: drop_ephemeral }],
+        dedups: &[Dedup { store: STORE_EVENTS, key: event_replace_key, order: newest_version_first }],
+    },
+    Migration {
+        version: 6,
+        description: "タグごとの時刻順インデックス",
+        schema: &[SchemaChange::CreateIndex {
+            store: STORE_EVENTS,
+            name: INDEX_TAG_CREATED_AT,
+            key_path: &[FIELD_TAG_TIMES],
+            multi_entry: true,
+        }],
+        rewrites: &[Rewrite { store: STORE_EVENTS, rewrite: event_tag_times }],
+        dedups: &[],
+    },
+];
+
+/// 最新のスキーマバージョン
+pub(crate) fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// `old_version`から`new_version`までに適用するマイグレーション
+pub(crate) fn pending(old_version: u32, new_version: u32) -> impl Iterator<Item = &'static Migration> {
+    MIGRATIONS
+        .iter()
+        .filter(move |m| m.version > old_version && m.version <= new_version)
+}
+
+/// v2: 配送記録のないOutboxアイテムを今の形にする
+///
+/// 送れないイベント（`event_json`が壊れている）は削除する。
+fn outbox_receipts(mut record: Value) -> Option<Value> {
+    let event = NostrEvent::from_json(record.get("event_json")?.as_str()?).ok()?;
+    let object = record.as_object_mut()?;
+    if object.get("event_id").and_then(Value::as_str).is_none_or(str::is_empty) {
+        object.insert("event_id".to_string(), json!(event.id));
+    }
+    object.entry("receipts").or_insert_with(|| json!([]));
+    Some(record)
+}
+
+/// v4: イベントにタグ検索用のキーを付ける
+fn event_tag_keys(mut record: Value) -> Option<Value> {
+    let tags: Vec<Vec<String>> = serde_json::from_value(record.get("tags")?.clone()).ok()?;
+    let object = record.as_object_mut()?;
+    object.insert("tag_keys".to_string(), json!(StoredEvent::tag_keys(&tags)));
+    Some(record)
+}
+
+/// `[タグ検索用のキー, created_at]`の組
+///
+/// 複合キーのインデックスはmultiEntryにできないので、組の配列をmultiEntryでインデックスする。
+pub(crate) fn tag_times(tags: &[Vec<String>], created_at: i64) -> Vec<(String, i64)> {
+    StoredEvent::tag_keys(tags).into_iter().map(|key| (key, created_at)).collect()
+}
+
+/// v6: イベントにタグ・時刻インデックス用のキーを付ける
+fn event_tag_times(mut record: Value) -> Option<Value> {
+    let tags: Vec<Vec<String>> = serde_json::from_value(record.get("tags")?.clone()).ok()?;
+    let created_at = record.get("created_at")?.as_i64()?;
+    let object = record.as_object_mut()?;
+    object.insert(FIELD_TAG_TIMES.to_string(), json!(tag_times(&tags, created_at)));
+    Some(record)
+}
+
+/// v5: 一時的なイベントは残さない
+fn drop_ephemeral(record: Value) -> Option<Value> {
+    let kind = record.get("kind")?.as_u64()?;
+    (!(20000..=29999).contains(&kind)).then_some(record)
+}
+
+/// v5: 置き換え可能なイベントのアドレス
+fn event_replace_key(record: &Value) -> Option<String> {
+    let event: StoredEvent = serde_json::from_value(record.clone()).ok()?;
+    event.replace_key()
+}
+
+/// v5: 新しい版（同じ時刻ならIDの小さい方）を先にする
+fn newest_version_first(a: &Value, b: &Value) -> Ordering {
+    let created_at = |v: &Value| v.get("created_at").and_then(Value::as_i64).unwrap_or(0);
+    let id = |v: &Value| v.get("id").and_then(Value::as_str).unwrap_or("").to_string();
+    created_at(b).cmp(&created_at(a)).then_with(|| id(a).cmp(&id(b)))
+}
+
+/// `dedup`で残さないレコードの位置
+pub(crate) fn duplicates(dedup: &Dedup, records: &[Value]) -> Vec<usize> {
+    let mut kept: HashMap<String, usize> = HashMap::new();
+    let mut removed = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        let Some(key) = (dedup.key)(record) else {
+            continue;
+        };
+        match kept.get(&key) {
+            Some(&current) if (dedup.order)(&records[current], record) != Ordering::Greater => removed.push(i),
+            _ => {
+                if let Some(previous) = kept.insert(key, i) {
+                    removed.push(previous);
+                }
+            }
+        }
+    }
+    removed.sort_unstable();
+    removed
+}
+
+/// DBを`version`まで上げる
+///
+/// `onupgradeneeded`で未適用のマイグレーションを順に適用する。
+/// 途中で失敗したら変更はすべて取り消され、エラーを返す。
+pub(crate) async fn upgrade(name: &str, version: u32) -> Result<()> {
+    let failure: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+    let factory = Factory::new().map_err(storage_error)?;
+    let mut request = factory.open(name, Some(version)).map_err(storage_error)?;
+
+    let upgrade_failure = failure.clone();
+    request.on_upgrade_needed(move |event| {
+        let result = (|| {
+            let old_version = event.old_version()?;
+            let database = event.database()?;
+            let transaction = event
+                .target()?
+                .transaction()
+                .ok_or(idb::Error::TransactionNotFound)?;
+            for migration in pending(old_version, version) {
+                log::info!("IndexedDB migration v{}: {}", migration.version, migration.description);
+                apply_schema(&database, &transaction, migration.schema)?;
+            }
+            let migrations: Vec<&'static Migration> = pending(old_version, version)
+                .filter(|m| !m.rewrites.is_empty() || !m.dedups.is_empty())
+                .collect();
+            if !migrations.is_empty() {
+                wasm_bindgen_futures::spawn_local(rewrite_records(transaction, migrations, upgrade_failure.clone()));
+            }
+            Ok::<(), idb::Error>(())
+        })();
+        if let Err(e) = result {
+            *upgrade_failure.borrow_mut() = Some(e.to_string());
+            if let Ok(transaction) = event.target().map(|r| r.transaction()) {
+                let _ = transaction.map(Transaction::abort);
+            }
+        }
+    });
+
+    let database = request.await.map_err(|e| match failure.borrow_mut().take() {
+        Some(reason) => CoreError::StorageError(format!("Migration failed: {}", reason)),
+        None => storage_error(e),
+    })?;
+    database.close();
+    Ok(())
+}
+
+fn apply_schema(database: &Database, transaction: &Transaction, changes: &[SchemaChange]) -> std::result::Result<(), idb::Error> {
+    for change in changes {
+        match change {
+            SchemaChange::CreateStore { name, key_path } => {
+                let mut params = ObjectStoreParams::new();
+                params.key_path(Some(KeyPath::new_single(key_path)));
+                database.create_object_store(name, params)?;
+            }
+            SchemaChange::CreateIndex { store, name, key_path, multi_entry } => {
+                let key_path = match key_path {
+                    [single] => KeyPath::new_single(single),
+                    array => KeyPath::new_array(array.iter().copied()),
+                };
+                let mut params = IndexParams::new();
+                params.multi_entry(*multi_entry);
+                transaction.object_store(store)?.create_index(name, key_path, Some(params))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// アップグレード中のトランザクションでレコードを書き換え、重複をまとめる（マイグレーション順に適用）
+async fn rewrite_records(transaction: Transaction, migrations: Vec<&'static Migration>, failure: Rc<RefCell<Option<String>>>) {
+    let result = async {
+        for migration in migrations {
+            for rewrite in migration.rewrites {
+                let store = transaction.object_store(rewrite.store)?;
+                let keys = store.get_all_keys(None, None)?.await?;
+                let values = store.get_all(None, None)?.await?;
+                for (key, value) in keys.into_iter().zip(values) {
+                    let rewritten = serde_wasm_bindgen::from_value::<Value>(value)
+                        .ok()
+                        .and_then(rewrite.rewrite)
+                        .and_then(|record| record.serialize(&serde_wasm_bindgen::Serializer::json_compatible()).ok());
+                    match rewritten {
+                        Some(record) => {
+                            store.put(&record, None)?.await?;
+                        }
+                        None => {
+                            log::warn!("IndexedDB migration: dropping record {:?} in {}", key, rewrite.store);
+                            store.delete(key)?.await?;
+                        }
+                    }
+                }
+            }
+            for dedup in migration.dedups {
+                let store = transaction.object_store(dedup.store)?;
+                let keys = store.get_all_keys(None, None)?.await?;
+                let records: Vec<Value> = store
+                    .get_all(None, None)?
+                    .await?
+                    .into_iter()
+                    .map(|value| serde_wasm_bindgen::from_value(value).unwrap_or(Value::Null))
+                    .collect();
+                for i in duplicates(dedup, &records) {
+                    store.delete(keys[i].clone())?.await?;
+                }
+            }
+        }
+        Ok::<(), idb::Error>(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        *failure.borrow_mut() = Some(e.to_string());
+        let _ = transaction.abort();
+    }
+}
+
+fn storage_error(error: idb::Error) -> CoreError {
+    CoreError::from(rexie::Error::from(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::OutboxItem;
+
+    fn event_json() -> String {
+        json!({
+            "id": "a".repeat(64),
+            "pubkey": "b".repeat(64),
+            "created_at": 100,
+            "kind": 42,
+            "tags": [["e", "chan"]],
+            "content": "hi",
+            "sig": "c".repeat(128),
+        })
+        .to_string()
+    }
+
+    fn rewrite(version: u32, store: &str, record: Value) -> Option<Value> {
+        let migration = MIGRATIONS.iter().find(|m| m.version == version).unwrap();
+        let rewrite = migration.rewrites.iter().find(|r| r.store == store).unwrap();
+        (rewrite.rewrite)(record)
+    }
+
+    #[test]
+    fn test_migrations_are_numbered_in_order() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as u32 + 1);
+        }
+        assert_eq!(latest_version(), MIGRATIONS.len() as u32);
+
+        let versions: Vec<u32> = pending(1, latest_version()).map(|m| m.version).collect();
+        assert_eq!(versions, (2..=latest_version()).collect::<Vec<_>>());
+        assert_eq!(pending(0, 1).count(), 1);
+        assert_eq!(pending(latest_version(), latest_version()).count(), 0);
+    }
+
+    #[test]
+    fn test_v1_outbox_item_gets_receipts() {
+        let v1 = json!({
+            "req_id": "r1",
+            "event_json": event_json(),
+            "status": "Queued",
+            "last_try_at": 0,
+            "retry_count": 0,
+            "error": null,
+        });
+        let item: OutboxItem = serde_json::from_value(rewrite(2, STORE_OUTBOX, v1).unwrap()).unwrap();
+        assert_eq!(item.event_id, "a".repeat(64));
+        assert!(item.receipts.is_empty());
+
+        // 送れないアイテムは削除する
+        let broken = json!({ "req_id": "r2", "event_json": "{", "status": "Queued" });
+        assert_eq!(rewrite(2, STORE_OUTBOX, broken), None);
+        assert_eq!(rewrite(2, STORE_OUTBOX, json!("old")), None);
+    }
+
+    #[test]
+    fn test_v1_event_gets_tag_keys() {
+        let mut v1: Value = serde_json::from_str(&event_json()).unwrap();
+        v1["relay_hint"] = Value::Null;
+        v1["inserted_at"] = json!(0);
+        let event: StoredEvent = serde_json::from_value(rewrite(4, STORE_EVENTS, v1).unwrap()).unwrap();
+        assert_eq!(event.tag_keys, vec!["e:chan"]);
+        assert!(event.seen_on.is_empty());
+
+        assert_eq!(rewrite(4, STORE_EVENTS, json!({ "id": "x", "tags": "e" })), None);
+    }
+
+    #[test]
+    fn test_v5_event_gets_tag_times() {
+        let v5 = json!({ "id": "x", "created_at": 100, "tags": [["e", "chan"], ["p", "x"], ["e", "chan"]] });
+        let record = rewrite(6, STORE_EVENTS, v5).unwrap();
+        assert_eq!(record[FIELD_TAG_TIMES], json!([["e:chan", 100], ["p:x", 100]]));
+
+        assert_eq!(rewrite(6, STORE_EVENTS, json!({ "id": "x", "tags": [] })), None);
+    }
+
+    #[test]
+    fn test_v5_keeps_newest_replaceable_versions() {
+        let record = |id: &str, kind: u16, created_at: i64, tags: Value| {
+            json!({
+                "id": id, "kind": kind, "pubkey": "alice", "created_at": created_at, "content": "",
+                "tags": tags, "sig": "", "relay_hint": null, "inserted_at": 0,
+            })
+        };
+        assert_eq!(rewrite(5, STORE_EVENTS, record("e", 20001, 100, json!([]))), None);
+        assert!(rewrite(5, STORE_EVENTS, record("m", 42, 100, json!([]))).is_some());
+
+        let records = vec![
+            record("p2", 0, 200, json!([])),
+            record("m1", 42, 100, json!([])),
+            record("p1", 0, 100, json!([])),
+            record("p4", 0, 200, json!([])),
+            record("p3", 0, 200, json!([])),
+            record("a1", 30023, 100, json!([["d", "x"]])),
+            record("a2", 30023, 100, json!([["d", "y"]])),
+        ];
+        let migration = MIGRATIONS.iter().find(|m| m.version == 5).unwrap();
+        // 新しい版のうちIDの小さいp2だけ残す
+        assert_eq!(duplicates(&migration.dedups[0], &records), vec![2, 3, 4]);
+    }
+}
@@ -2,18 +2,30 @@ use async_trait::async_trait;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
-use crate::storage::Storage;
-use crate::types::{StoredEvent, StorageFilter, DmThread, OutboxItem, OutboxStatus};
-use crate::error::Result;
+use crate::storage::{sort_and_limit, supersedes, Storage};
+use crate::types::{StoredEvent, StorageFilter, DmThread, OutboxItem, OutboxStatus, RelayConfig, StorageUsage};
+use crate::error::{CoreError, Result};
+use crate::event::{is_ephemeral_kind, NostrEvent};
 
-/// テスト用のモックStorage実装
+/// テスト用のメモリ上のStorage実装
+///
+/// 他の実装と同じ振る舞いの基準（`storage::conformance`で確認する）。
 #[derive(Clone)]
 pub struct MockStorage {
     events: Arc<Mutex<Vec<StoredEvent>>>,
     dm_threads: Arc<Mutex<Vec<DmThread>>>,
     last_seen: Arc<Mutex<HashMap<String, i64>>>,
     outbox: Arc<Mutex<Vec<OutboxItem>>>,
+    relays: Arc<Mutex<Vec<RelayConfig>>>,
     keypair: Arc<Mutex<Option<Vec<u8>>>>,
+    usage: Arc<Mutex<Option<StorageUsage>>>,
+    fail_saves: Arc<Mutex<bool>>,
+}
+
+impl Default for MockStorage {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MockStorage {
@@ -23,8 +35,37 @@ impl MockStorage {
             dm_threads: Arc::new(Mutex::new(Vec::new())),
             last_seen: Arc::new(Mutex::new(HashMap::new())),
             outbox: Arc::new(Mutex::new(Vec::new())),
+            relays: Arc::new(Mutex::new(Vec::new())),
             keypair: Arc::new(Mutex::new(None)),
+            usage: Arc::new(Mutex::new(None)),
+            fail_saves: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    /// 保存するなら同じアドレスの古い版を消してtrueを返す（一時的なイベントと古い版はfalse）
+    fn make_room(events: &mut Vec<StoredEvent>, event: &StoredEvent) -> bool {
+        if is_ephemeral_kind(event.kind) {
+            return false;
         }
+        let Some(key) = event.replace_key() else {
+            return true;
+        };
+        let same_address = |e: &StoredEvent| e.id != event.id && e.replace_key().as_ref() == Some(&key);
+        if events.iter().any(|e| same_address(e) && !supersedes(event, e)) {
+            return false;
+        }
+        events.retain(|e| !same_address(e));
+        true
+    }
+
+    /// `estimate_usage`が返す使用量を設定
+    pub fn set_usage(&self, usage: Option<StorageUsage>) {
+        *self.usage.lock().unwrap() = usage;
+    }
+
+    /// `save_event`を容量不足で失敗させる
+    pub fn set_fail_saves(&self, fail: bool) {
+        *self.fail_saves.lock().unwrap() = fail;
     }
 }
 
@@ -36,44 +77,61 @@ impl Storage for MockStorage {
 
     async fn insert_event(&self, event: &StoredEvent) -> Result<()> {
         let mut events = self.events.lock().unwrap();
+        if !Self::make_room(&mut events, event) {
+            return Ok(());
+        }
+        events.retain(|e| e.id != event.id);
         events.push(event.clone());
         Ok(())
     }
 
-    async fn save_event(&self, _event_id: &str, _event_json: &str) -> Result<()> {
-        // Mock implementation: do nothing
-        Ok(())
-    }
-
-    async fn get_events(&self, filter: &StorageFilter) -> Result<Vec<StoredEvent>> {
-        let events = self.events.lock().unwrap();
-        let mut result: Vec<_> = events.iter().cloned().collect();
-
-        // フィルター適用
-        if let Some(kinds) = &filter.kinds {
-            result.retain(|e| kinds.contains(&e.kind));
-        }
-        if let Some(authors) = &filter.authors {
-            result.retain(|e| authors.contains(&e.pubkey));
+    async fn save_event(&self, event: &NostrEvent) -> Result<()> {
+        if *self.fail_saves.lock().unwrap() {
+            return Err(CoreError::StorageError("QuotaExceededError".to_string()));
         }
-        if let Some(since) = filter.since {
-            result.retain(|e| e.created_at >= since);
+        let mut event = StoredEvent::new(event.clone(), 0);
+        let mut events = self.events.lock().unwrap();
+        if !Self::make_room(&mut events, &event) {
+            return Ok(());
         }
-        if let Some(until) = filter.until {
-            result.retain(|e| e.created_at <= until);
+        match events.iter_mut().find(|e| e.id == event.id) {
+            // 受信Relayの記録は残す
+            Some(existing) => {
+                event.seen_on = std::mem::take(&mut existing.seen_on);
+                event.relay_hint = existing.relay_hint.take();
+                *existing = event;
+            }
+            None => events.push(event),
         }
+        Ok(())
+    }
 
-        // created_at降順でソート
-        result.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    async fn get_event(&self, event_id: &str) -> Result<Option<StoredEvent>> {
+        let events = self.events.lock().unwrap();
+        Ok(events.iter().find(|e| e.id == event_id).cloned())
+    }
 
-        // limit適用
-        if let Some(limit) = filter.limit {
-            result.truncate(limit as usize);
+    async fn add_seen_on(&self, event_id: &str, relay_url: &str) -> Result<()> {
+        let mut events = self.events.lock().unwrap();
+        if let Some(event) = events.iter_mut().find(|e| e.id == event_id) {
+            event.add_seen_on(relay_url);
         }
+        Ok(())
+    }
 
+    async fn get_events(&self, filter: &StorageFilter) -> Result<Vec<StoredEvent>> {
+        let events = self.events.lock().unwrap();
+        let mut result: Vec<_> = events.iter().filter(|e| filter.matches(e)).cloned().collect();
+        sort_and_limit(&mut result, filter.limit);
         Ok(result)
     }
 
+    async fn delete_events(&self, event_ids: &[String]) -> Result<()> {
+        let mut events = self.events.lock().unwrap();
+        events.retain(|e| !event_ids.contains(&e.id));
+        Ok(())
+    }
+
     async fn upsert_dm_thread(&self, peer: &str, last_msg_at: i64) -> Result<()> {
         let mut threads = self.dm_threads.lock().unwrap();
         if let Some(thread) = threads.iter_mut().find(|t| t.peer == peer) {
@@ -91,7 +149,7 @@ impl Storage for MockStorage {
     async fn get_dm_threads(&self) -> Result<Vec<DmThread>> {
         let threads = self.dm_threads.lock().unwrap();
         let mut result = threads.clone();
-        result.sort_by(|a, b| b.last_msg_at.cmp(&a.last_msg_at));
+        result.sort_by_key(|t| std::cmp::Reverse(t.last_msg_at));
         Ok(result)
     }
 
@@ -106,9 +164,16 @@ impl Storage for MockStorage {
         Ok(())
     }
 
+    async fn get_all_last_seen(&self) -> Result<Vec<(String, i64)>> {
+        let mut entries: Vec<_> = self.last_seen.lock().unwrap().iter().map(|(k, v)| (k.clone(), *v)).collect();
+        entries.sort();
+        Ok(entries)
+    }
+
     async fn enqueue_outbox(&self, item: OutboxItem) -> Result<String> {
         let mut outbox = self.outbox.lock().unwrap();
         let req_id = item.req_id.clone();
+        outbox.retain(|i| i.req_id != req_id);
         outbox.push(item);
         Ok(req_id)
     }
@@ -121,13 +186,50 @@ impl Storage for MockStorage {
         Ok(())
     }
 
+    async fn update_outbox_item(&self, item: &OutboxItem) -> Result<()> {
+        let mut outbox = self.outbox.lock().unwrap();
+        if let Some(existing) = outbox.iter_mut().find(|i| i.req_id == item.req_id) {
+            *existing = item.clone();
+        }
+        Ok(())
+    }
+
     async fn get_pending_outbox(&self) -> Result<Vec<OutboxItem>> {
         let outbox = self.outbox.lock().unwrap();
-        Ok(outbox
+        let mut items: Vec<_> = outbox
             .iter()
             .filter(|i| matches!(i.status, OutboxStatus::Queued | OutboxStatus::Sent))
             .cloned()
-            .collect())
+            .collect();
+        items.sort_by(|a, b| a.req_id.cmp(&b.req_id));
+        Ok(items)
+    }
+
+    async fn get_all_outbox(&self) -> Result<Vec<OutboxItem>> {
+        let mut items = self.outbox.lock().unwrap().clone();
+        items.sort_by(|a, b| a.req_id.cmp(&b.req_id));
+        Ok(items)
+    }
+
+    async fn upsert_relay(&self, relay: &RelayConfig) -> Result<()> {
+        let mut relays = self.relays.lock().unwrap();
+        if let Some(existing) = relays.iter_mut().find(|r| r.url == relay.url) {
+            *existing = relay.clone();
+        } else {
+            relays.push(relay.clone());
+        }
+        Ok(())
+    }
+
+    async fn remove_relay(&self, url: &str) -> Result<()> {
+        let mut relays = self.relays.lock().unwrap();
+        relays.retain(|r| r.url != url);
+        Ok(())
+    }
+
+    async fn get_relays(&self) -> Result<Vec<RelayConfig>> {
+        let relays = self.relays.lock().unwrap();
+        Ok(relays.clone())
     }
 
     async fn save_keypair(&self, encrypted_data: &[u8]) -> Result<()> {
@@ -140,5 +242,19 @@ impl Storage for MockStorage {
         let keypair = self.keypair.lock().unwrap();
         Ok(keypair.clone())
     }
+
+    async fn estimate_usage(&self) -> Result<Option<StorageUsage>> {
+        Ok(*self.usage.lock().unwrap())
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_conformance() {
+        crate::storage::conformance::check_all(|| async { MockStorage::new() }).await;
+    }
+}
@@ -1,13 +1,19 @@
+#[cfg(test)]
+mod conformance;
 pub mod indexeddb;
+mod migrations;
 pub mod mock;
+#[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
+pub mod sqlite;
 
 use async_trait::async_trait;
 use crate::error::Result;
-use crate::types::{StoredEvent, StorageFilter, DmThread, OutboxItem, OutboxStatus};
+use crate::event::NostrEvent;
+use crate::types::{StoredEvent, StorageFilter, DmThread, OutboxItem, OutboxStatus, RelayConfig, StorageUsage};
 
 /// Storage抽象trait
 /// 
-/// 将来的にネイティブ実装（SQLite等）への移行を想定した抽象インターフェース
+/// ブラウザでは`IndexedDbStorage`、ネイティブでは`SqliteStorage`（`sqlite` feature）を使う
 /// WASM環境ではシングルスレッドのため、Send + Sync要件なし
 #[async_trait(?Send)]
 pub trait Storage {
@@ -16,15 +22,27 @@ pub trait Storage {
     where
         Self: Sized;
 
-    /// イベント挿入
+    /// イベント挿入（同じIDは置き換える。アドレスと一時的なイベントは`save_event`と同じ）
     async fn insert_event(&self, event: &StoredEvent) -> Result<()>;
 
-    /// イベント保存（JSON文字列から）
-    async fn save_event(&self, event_id: &str, event_json: &str) -> Result<()>;
+    /// イベント保存（同じIDのイベントがあれば受信Relayの記録を残して置き換える）
+    ///
+    /// 置き換え可能なイベントは同じアドレス（`StoredEvent::replace_key`）の最新版だけを残し、
+    /// 古い版が届いたら保存しない。一時的なイベント（kind 20000-29999）は保存しない。
+    async fn save_event(&self, event: &NostrEvent) -> Result<()>;
 
-    /// イベント取得
+    /// IDでイベント取得
+    async fn get_event(&self, event_id: &str) -> Result<Option<StoredEvent>>;
+
+    /// イベントを受信したRelayを記録（relay_hintも更新）
+    async fn add_seen_on(&self, event_id: &str, relay_url: &str) -> Result<()>;
+
+    /// イベント取得（新しい順、同じ時刻はIDの小さい順に`limit`件まで）
     async fn get_events(&self, filter: &StorageFilter) -> Result<Vec<StoredEvent>>;
 
+    /// イベント削除（ないIDは無視する）
+    async fn delete_events(&self, event_ids: &[String]) -> Result<()>;
+
     /// DMスレッド挿入/更新
     async fn upsert_dm_thread(&self, peer: &str, last_msg_at: i64) -> Result<()>;
 
@@ -37,19 +55,53 @@ pub trait Storage {
     /// 既読位置設定
     async fn set_last_seen(&self, scope: &str, ts: i64) -> Result<()>;
 
+    /// 既読位置一覧取得（`scope`順）
+    async fn get_all_last_seen(&self) -> Result<Vec<(String, i64)>>;
+
     /// Outboxにキューイング
     async fn enqueue_outbox(&self, item: OutboxItem) -> Result<String>;
 
     /// Outboxステータス更新
     async fn update_outbox_status(&self, req_id: &str, status: OutboxStatus) -> Result<()>;
 
-    /// 保留中のOutboxアイテム取得
+    /// Outboxアイテムを丸ごと更新（Relayごとの配送記録を含む。ないアイテムは追加しない）
+    async fn update_outbox_item(&self, item: &OutboxItem) -> Result<()>;
+
+    /// 保留中（QueuedとSent）のOutboxアイテム取得（`req_id`順）
     async fn get_pending_outbox(&self) -> Result<Vec<OutboxItem>>;
 
+    /// 送信済みを含むすべてのOutboxアイテム取得（`req_id`順）
+    async fn get_all_outbox(&self) -> Result<Vec<OutboxItem>>;
+
+    /// Relay設定の挿入/更新
+    async fn upsert_relay(&self, relay: &RelayConfig) -> Result<()>;
+
+    /// Relay設定の削除
+    async fn remove_relay(&self, url: &str) -> Result<()>;
+
+    /// Relay設定一覧取得
+    async fn get_relays(&self) -> Result<Vec<RelayConfig>>;
+
     /// 鍵ペア保存（内蔵Signer用）
     async fn save_keypair(&self, encrypted_data: &[u8]) -> Result<()>;
 
     /// 鍵ペア取得（内蔵Signer用）
     async fn get_keypair(&self) -> Result<Option<Vec<u8>>>;
+
+    /// 使用量の見積もり（分からなければNone）
+    async fn estimate_usage(&self) -> Result<Option<StorageUsage>>;
 }
 
+
+/// 同じアドレスの`old`を置き換える版か（新しい方、同じ時刻ならIDの小さい方を残す）
+pub(crate) fn supersedes(new: &StoredEvent, old: &StoredEvent) -> bool {
+    (new.created_at, std::cmp::Reverse(&new.id)) > (old.created_at, std::cmp::Reverse(&old.id))
+}
+
+/// `get_events`の順（新しい順、同じ時刻はIDの小さい順）に並べて`limit`件にする
+pub(crate) fn sort_and_limit(events: &mut Vec<StoredEvent>, limit: Option<u32>) {
+    events.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.id.cmp(&b.id)));
+    if let Some(limit) = limit {
+        events.truncate(limit as usize);
+    }
+}
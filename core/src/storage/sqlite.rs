@@ -0,0 +1,725 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+
+use crate::error::{CoreError, Result};
+use crate::event::{is_ephemeral_kind, NostrEvent};
+use crate::storage::{supersedes, Storage};
+use crate::types::{DmThread, OutboxItem, OutboxStatus, RelayConfig, StorageFilter, StorageUsage, StoredEvent};
+
+/// `init()`で開くファイル
+const DEFAULT_PATH: &str = "rustr.db";
+
+/// 1回のDELETEで指定するIDの数
+const DELETE_BATCH: usize = 500;
+
+/// スキーマのバージョン（`PRAGMA user_version`）
+///
+/// v2: 置き換え可能なイベントは同じアドレスの最新版だけ残す
+const SCHEMA_VERSION: i64 = 2;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+    id TEXT PRIMARY KEY,
+    kind INTEGER NOT NULL,
+    pubkey TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    tags TEXT NOT NULL,
+    sig TEXT NOT NULL,
+    relay_hint TEXT,
+    inserted_at INTEGER NOT NULL,
+    seen_on TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_created_at ON events (created_at);
+CREATE INDEX IF NOT EXISTS events_kind_created_at ON events (kind, created_at);
+CREATE INDEX IF NOT EXISTS events_pubkey_created_at ON events (pubkey, created_at);
+CREATE INDEX IF NOT EXISTS events_kind_pubkey ON events (kind, pubkey);
+
+CREATE TABLE IF NOT EXISTS event_tags (
+    event_id TEXT NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (event_id, name, value)
+);
+CREATE INDEX IF NOT EXISTS event_tags_name_value ON event_tags (name, value);
+
+CREATE TABLE IF NOT EXISTS dm_threads (
+    peer TEXT PRIMARY KEY,
+    last_seen INTEGER NOT NULL,
+    last_msg_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS last_seen (
+    scope TEXT PRIMARY KEY,
+    ts INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS outbox (
+    req_id TEXT PRIMARY KEY,
+    event_id TEXT NOT NULL,
+    event_json TEXT NOT NULL,
+    status TEXT NOT NULL,
+    last_try_at INTEGER NOT NULL,
+    retry_count INTEGER NOT NULL,
+    error TEXT,
+    receipts TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS outbox_status ON outbox (status);
+
+CREATE TABLE IF NOT EXISTS relays (
+    url TEXT PRIMARY KEY,
+    read INTEGER NOT NULL,
+    write INTEGER NOT NULL,
+    auth TEXT NOT NULL,
+    max_subscriptions INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS keypair (
+    id TEXT PRIMARY KEY,
+    data BLOB NOT NULL
+);
+";
+
+const EVENT_COLUMNS: &str = "id, kind, pubkey, created_at, content, tags, sig, relay_hint, inserted_at, seen_on";
+
+const OUTBOX_COLUMNS: &str = "req_id, event_id, event_json, status, last_try_at, retry_count, error, receipts";
+
+/// SQLite実装（ネイティブ向け、`sqlite` feature）
+///
+/// タグは`event_tags`に1文字のタグ名ごとに展開し、`#e`などの条件をインデックスで引く。
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// ファイルを開く（なければ作る）
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// メモリ上のDBを開く（テスト用）
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(CoreError::StorageError(format!(
+                "Database schema version {} is newer than supported version {}",
+                version, SCHEMA_VERSION
+            )));
+        }
+        conn.execute_batch(SCHEMA)?;
+        if version == 1 {
+            Self::remove_superseded(&conn)?;
+        }
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// v1で残っていた置き換え済みの版と一時的なイベントを消す
+    fn remove_superseded(conn: &Connection) -> Result<()> {
+        let tx = conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM events WHERE kind BETWEEN 20000 AND 29999", [])?;
+        let events = tx
+            .prepare(&format!(
+                "SELECT {} FROM events WHERE kind IN (0, 3, 41) OR kind BETWEEN 10000 AND 19999 OR kind BETWEEN 30000 AND 39999",
+                EVENT_COLUMNS
+            ))?
+            .query_map([], Self::event_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut newest: HashMap<String, StoredEvent> = HashMap::new();
+        let mut superseded = Vec::new();
+        for event in events {
+            let Some(key) = event.replace_key() else {
+                continue;
+            };
+            if newest.get(&key).is_some_and(|current| !supersedes(&event, current)) {
+                superseded.push(event.id);
+            } else if let Some(old) = newest.insert(key, event) {
+                superseded.push(old.id);
+            }
+        }
+        for id in superseded {
+            tx.execute("DELETE FROM events WHERE id = ?1", params![id])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// 同じアドレスの別のイベント
+    fn same_address(conn: &Connection, event: &StoredEvent, key: &str) -> Result<Vec<StoredEvent>> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM events WHERE kind = ?1 AND pubkey = ?2 AND id != ?3",
+            EVENT_COLUMNS
+        ))?;
+        let events = stmt
+            .query_map(params![event.kind, event.pubkey, event.id], Self::event_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(events.into_iter().filter(|e| e.replace_key().as_deref() == Some(key)).collect())
+    }
+
+    /// イベントとタグを書き込む（同じIDと、同じアドレスの古い版は置き換える）
+    ///
+    /// 一時的なイベントと、同じアドレスに新しい版があるイベントは書き込まない。
+    fn write_event(conn: &Connection, event: &StoredEvent) -> Result<()> {
+        if is_ephemeral_kind(event.kind) {
+            return Ok(());
+        }
+        let tx = conn.unchecked_transaction()?;
+        if let Some(key) = event.replace_key() {
+            let versions = Self::same_address(&tx, event, &key)?;
+            if versions.iter().any(|old| !supersedes(event, old)) {
+                return Ok(());
+            }
+            for old in versions {
+                tx.execute("DELETE FROM events WHERE id = ?1", params![old.id])?;
+            }
+        }
+        tx.execute(
+            &format!("INSERT OR REPLACE INTO events ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)", EVENT_COLUMNS),
+            params![
+                event.id,
+                event.kind,
+                event.pubkey,
+                event.created_at,
+                event.content,
+                serde_json::to_string(&event.tags)?,
+                event.sig,
+                event.relay_hint,
+                event.inserted_at,
+                serde_json::to_string(&event.seen_on)?,
+            ],
+        )?;
+        tx.execute("DELETE FROM event_tags WHERE event_id = ?1", params![event.id])?;
+        let mut insert = tx.prepare("INSERT OR IGNORE INTO event_tags (event_id, name, value) VALUES (?1, ?2, ?3)")?;
+        for tag in &event.tags {
+            if let (Some(name), Some(value)) = (tag.first(), tag.get(1)) {
+                if name.chars().count() == 1 {
+                    insert.execute(params![event.id, name, value])?;
+                }
+            }
+        }
+        drop(insert);
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn event_from_row(row: &Row) -> rusqlite::Result<StoredEvent> {
+        let tags: Vec<Vec<String>> = json_column(row, 5)?;
+        Ok(StoredEvent {
+            id: row.get(0)?,
+            kind: row.get(1)?,
+            pubkey: row.get(2)?,
+            created_at: row.get(3)?,
+            content: row.get(4)?,
+            tag_keys: StoredEvent::tag_keys(&tags),
+            tags,
+            sig: row.get(6)?,
+            relay_hint: row.get(7)?,
+            inserted_at: row.get(8)?,
+            seen_on: json_column(row, 9)?,
+        })
+    }
+
+    fn outbox_from_row(row: &Row) -> rusqlite::Result<OutboxItem> {
+        let event_json: String = row.get(2)?;
+        let status: String = row.get(3)?;
+        Ok(OutboxItem {
+            req_id: row.get(0)?,
+            event_id: row.get(1)?,
+            event: NostrEvent::from_json(&event_json).map_err(|e| conversion_error(2, e))?,
+            status: serde_json::from_value(serde_json::Value::String(status)).map_err(|e| conversion_error(3, e))?,
+            last_try_at: row.get(4)?,
+            retry_count: row.get(5)?,
+            error: row.get(6)?,
+            receipts: json_column(row, 7)?,
+        })
+    }
+
+    fn write_outbox(conn: &Connection, item: &OutboxItem) -> Result<()> {
+        conn.execute(
+            &format!("INSERT OR REPLACE INTO outbox ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)", OUTBOX_COLUMNS),
+            params![
+                item.req_id,
+                item.event_id,
+                item.event.to_json(),
+                status_name(item.status),
+                item.last_try_at,
+                item.retry_count,
+                item.error,
+                serde_json::to_string(&item.receipts)?,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+/// フィルターをWHERE句にする
+///
+/// 全文検索（`search`）はSQLiteの`LIKE`では大文字小文字の扱いが違うので、読み込んだ後に`Filter::matches`で絞る。
+fn where_clause(filter: &StorageFilter) -> (String, Vec<SqlValue>) {
+    let mut conditions = Vec::new();
+    let mut values = Vec::new();
+
+    if let Some(ids) = &filter.ids {
+        conditions.push(format!("id IN ({})", placeholders(ids.len())));
+        values.extend(ids.iter().cloned().map(SqlValue::Text));
+    }
+    if let Some(authors) = &filter.authors {
+        conditions.push(format!("pubkey IN ({})", placeholders(authors.len())));
+        values.extend(authors.iter().cloned().map(SqlValue::Text));
+    }
+    if let Some(kinds) = &filter.kinds {
+        conditions.push(format!("kind IN ({})", placeholders(kinds.len())));
+        values.extend(kinds.iter().map(|k| SqlValue::Integer(*k as i64)));
+    }
+    for (name, tag_values) in &filter.tags {
+        conditions.push(format!(
+            "EXISTS (SELECT 1 FROM event_tags WHERE event_id = events.id AND name = ? AND value IN ({}))",
+            placeholders(tag_values.len())
+        ));
+        values.push(SqlValue::Text(name.to_string()));
+        values.extend(tag_values.iter().cloned().map(SqlValue::Text));
+    }
+    if let Some(since) = filter.since {
+        conditions.push("created_at >= ?".to_string());
+        values.push(SqlValue::Integer(since));
+    }
+    if let Some(until) = filter.until {
+        conditions.push("created_at <= ?".to_string());
+        values.push(SqlValue::Integer(until));
+    }
+
+    if conditions.is_empty() {
+        (String::new(), values)
+    } else {
+        (format!(" WHERE {}", conditions.join(" AND ")), values)
+    }
+}
+
+/// `IN (...)`のプレースホルダー
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+fn json_column<T: serde::de::DeserializeOwned>(row: &Row, index: usize) -> rusqlite::Result<T> {
+    let json: String = row.get(index)?;
+    serde_json::from_str(&json).map_err(|e| conversion_error(index, e))
+}
+
+fn conversion_error(index: usize, error: impl std::error::Error + Send + Sync + 'static) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(error))
+}
+
+fn status_name(status: OutboxStatus) -> &'static str {
+    match status {
+        OutboxStatus::Queued => "Queued",
+        OutboxStatus::Sent => "Sent",
+        OutboxStatus::Ok => "Ok",
+        OutboxStatus::Error => "Error",
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[async_trait(?Send)]
+impl Storage for SqliteStorage {
+    async fn init() -> Result<Self> {
+        Self::open(DEFAULT_PATH)
+    }
+
+    async fn insert_event(&self, event: &StoredEvent) -> Result<()> {
+        Self::write_event(&self.conn(), event)
+    }
+
+    async fn save_event(&self, event: &NostrEvent) -> Result<()> {
+        let conn = self.conn();
+        let mut stored = StoredEvent::new(event.clone(), now_millis());
+
+        // 受信Relayの記録は残す
+        let existing = conn
+            .query_row("SELECT relay_hint, seen_on FROM events WHERE id = ?1", params![event.id], |row| {
+                Ok((row.get::<_, Option<String>>(0)?, json_column::<Vec<String>>(row, 1)?))
+            })
+            .optional()?;
+        if let Some((relay_hint, seen_on)) = existing {
+            stored.relay_hint = relay_hint;
+            stored.seen_on = seen_on;
+        }
+        Self::write_event(&conn, &stored)
+    }
+
+    async fn get_event(&self, event_id: &str) -> Result<Option<StoredEvent>> {
+        let conn = self.conn();
+        let event = conn
+            .query_row(
+                &format!("SELECT {} FROM events WHERE id = ?1", EVENT_COLUMNS),
+                params![event_id],
+                Self::event_from_row,
+            )
+            .optional()?;
+        Ok(event)
+    }
+
+    async fn add_seen_on(&self, event_id: &str, relay_url: &str) -> Result<()> {
+        let conn = self.conn();
+        let event = conn
+            .query_row(
+                &format!("SELECT {} FROM events WHERE id = ?1", EVENT_COLUMNS),
+                params![event_id],
+                Self::event_from_row,
+            )
+            .optional()?;
+        if let Some(mut event) = event {
+            if event.add_seen_on(relay_url) {
+                conn.execute(
+                    "UPDATE events SET relay_hint = ?2, seen_on = ?3 WHERE id = ?1",
+                    params![event_id, event.relay_hint, serde_json::to_string(&event.seen_on)?],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_events(&self, filter: &StorageFilter) -> Result<Vec<StoredEvent>> {
+        let (where_sql, mut values) = where_clause(filter);
+        let mut sql = format!("SELECT {} FROM events{} ORDER BY created_at DESC, id ASC", EVENT_COLUMNS, where_sql);
+        if let (Some(limit), None) = (filter.limit, &filter.search) {
+            sql.push_str(" LIMIT ?");
+            values.push(SqlValue::Integer(limit as i64));
+        }
+
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&sql)?;
+        let mut events = stmt
+            .query_map(params_from_iter(values), Self::event_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        events.retain(|e| filter.matches(e));
+        if let Some(limit) = filter.limit {
+            events.truncate(limit as usize);
+        }
+        Ok(events)
+    }
+
+    async fn delete_events(&self, event_ids: &[String]) -> Result<()> {
+        // event_tagsはON DELETE CASCADEで消える
+        let conn = self.conn();
+        let tx = conn.unchecked_transaction()?;
+        for chunk in event_ids.chunks(DELETE_BATCH) {
+            let sql = format!("DELETE FROM events WHERE id IN ({})", placeholders(chunk.len()));
+            tx.execute(&sql, params_from_iter(chunk))?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    async fn upsert_dm_thread(&self, peer: &str, last_msg_at: i64) -> Result<()> {
+        self.conn().execute(
+            "INSERT INTO dm_threads (peer, last_seen, last_msg_at) VALUES (?1, 0, ?2)
+             ON CONFLICT (peer) DO UPDATE SET last_msg_at = excluded.last_msg_at",
+            params![peer, last_msg_at],
+        )?;
+        Ok(())
+    }
+
+    async fn get_dm_threads(&self) -> Result<Vec<DmThread>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT peer, last_seen, last_msg_at FROM dm_threads ORDER BY last_msg_at DESC")?;
+        let threads = stmt
+            .query_map([], |row| {
+                Ok(DmThread { peer: row.get(0)?, last_seen: row.get(1)?, last_msg_at: row.get(2)? })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(threads)
+    }
+
+    async fn get_last_seen(&self, scope: &str) -> Result<i64> {
+        let ts = self
+            .conn()
+            .query_row("SELECT ts FROM last_seen WHERE scope = ?1", params![scope], |row| row.get(0))
+            .optional()?;
+        Ok(ts.unwrap_or(0))
+    }
+
+    async fn set_last_seen(&self, scope: &str, ts: i64) -> Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO last_seen (scope, ts) VALUES (?1, ?2)",
+            params![scope, ts],
+        )?;
+        Ok(())
+    }
+
+    async fn get_all_last_seen(&self) -> Result<Vec<(String, i64)>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT scope, ts FROM last_seen ORDER BY scope")?;
+        let entries = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    async fn enqueue_outbox(&self, item: OutboxItem) -> Result<String> {
+        Self::write_outbox(&self.conn(), &item)?;
+        Ok(item.req_id)
+    }
+
+    async fn update_outbox_status(&self, req_id: &str, status: OutboxStatus) -> Result<()> {
+        self.conn().execute(
+            "UPDATE outbox SET status = ?2 WHERE req_id = ?1",
+            params![req_id, status_name(status)],
+        )?;
+        Ok(())
+    }
+
+    async fn update_outbox_item(&self, item: &OutboxItem) -> Result<()> {
+        let conn = self.conn();
+        let exists = conn
+            .query_row("SELECT 1 FROM outbox WHERE req_id = ?1", params![item.req_id], |_| Ok(()))
+            .optional()?;
+        if exists.is_some() {
+            Self::write_outbox(&conn, item)?;
+        }
+        Ok(())
+    }
+
+    async fn get_pending_outbox(&self) -> Result<Vec<OutboxItem>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM outbox WHERE status IN ('Queued', 'Sent') ORDER BY req_id",
+            OUTBOX_COLUMNS
+        ))?;
+        let items = stmt
+            .query_map([], Self::outbox_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(items)
+    }
+
+    async fn get_all_outbox(&self) -> Result<Vec<OutboxItem>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM outbox ORDER BY req_id", OUTBOX_COLUMNS))?;
+        let items = stmt
+            .query_map([], Self::outbox_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(items)
+    }
+
+    async fn upsert_relay(&self, relay: &RelayConfig) -> Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO relays (url, read, write, auth, max_subscriptions) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                relay.url,
+                relay.read,
+                relay.write,
+                serde_json::to_string(&relay.auth)?,
+                relay.max_subscriptions.map(|m| m as i64),
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn remove_relay(&self, url: &str) -> Result<()> {
+        self.conn().execute("DELETE FROM relays WHERE url = ?1", params![url])?;
+        Ok(())
+    }
+
+    async fn get_relays(&self) -> Result<Vec<RelayConfig>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT url, read, write, auth, max_subscriptions FROM relays ORDER BY rowid")?;
+        let relays = stmt
+            .query_map([], |row| {
+                Ok(RelayConfig {
+                    url: row.get(0)?,
+                    read: row.get(1)?,
+                    write: row.get(2)?,
+                    auth: json_column(row, 3)?,
+                    max_subscriptions: row.get::<_, Option<i64>>(4)?.map(|m| m as usize),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(relays)
+    }
+
+    async fn save_keypair(&self, encrypted_data: &[u8]) -> Result<()> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO keypair (id, data) VALUES ('default', ?1)",
+            params![encrypted_data],
+        )?;
+        Ok(())
+    }
+
+    async fn get_keypair(&self) -> Result<Option<Vec<u8>>> {
+        let data = self
+            .conn()
+            .query_row("SELECT data FROM keypair WHERE id = 'default'", [], |row| row.get(0))
+            .optional()?;
+        Ok(data)
+    }
+
+    async fn estimate_usage(&self) -> Result<Option<StorageUsage>> {
+        // 空きページは再利用されるので使用中のページだけ数える
+        let usage: i64 = self.conn().query_row(
+            "SELECT (page_count - freelist_count) * page_size
+             FROM pragma_page_count(), pragma_freelist_count(), pragma_page_size()",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(Some(StorageUsage { usage: usage.max(0) as u64, quota: None }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{DeliveryStatus, RelayReceipt};
+
+    fn event(id: &str, kind: u16, created_at: i64, tags: &[&[&str]]) -> NostrEvent {
+        NostrEvent {
+            id: id.to_string(),
+            pubkey: "alice".to_string(),
+            created_at,
+            kind,
+            tags: tags.iter().map(|t| t.iter().map(|s| s.to_string()).collect()).collect(),
+            content: String::new(),
+            sig: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events_roundtrip_through_indexes() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        storage.save_event(&event("a", 42, 100, &[&["e", "chan", "", "root"]])).await.unwrap();
+        storage.save_event(&event("b", 42, 200, &[&["e", "chan"], &["p", "bob"]])).await.unwrap();
+        storage.save_event(&event("c", 42, 300, &[&["e", "other"]])).await.unwrap();
+        storage.save_event(&event("d", 4, 400, &[&["p", "bob"]])).await.unwrap();
+
+        let ids = |events: Vec<StoredEvent>| events.into_iter().map(|e| e.id).collect::<Vec<_>>();
+        let chan = StorageFilter::new().kinds([42]).tag('e', ["chan"]);
+        assert_eq!(ids(storage.get_events(&chan).await.unwrap()), vec!["b", "a"]);
+        assert_eq!(ids(storage.get_events(&chan.clone().limit(1)).await.unwrap()), vec!["b"]);
+        assert_eq!(ids(storage.get_events(&chan.until(150)).await.unwrap()), vec!["a"]);
+        assert_eq!(ids(storage.get_events(&StorageFilter::new().tag('p', ["bob"])).await.unwrap()), vec!["d", "b"]);
+        assert_eq!(ids(storage.get_events(&StorageFilter::new().ids(["c", "a"])).await.unwrap()), vec!["c", "a"]);
+        assert_eq!(storage.get_events(&StorageFilter::new().kinds([])).await.unwrap().len(), 0);
+
+        // 置き換えても受信Relayの記録は残る
+        storage.add_seen_on("a", "wss://r1").await.unwrap();
+        storage.save_event(&event("a", 42, 100, &[&["e", "moved"]])).await.unwrap();
+        let a = storage.get_event("a").await.unwrap().unwrap();
+        assert_eq!(a.seen_on, vec!["wss://r1"]);
+        assert_eq!(a.relay_hint.as_deref(), Some("wss://r1"));
+        assert_eq!(a.tag_keys, vec!["e:moved"]);
+        assert_eq!(ids(storage.get_events(&StorageFilter::new().tag('e', ["chan"])).await.unwrap()), vec!["b"]);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_relays_and_keypair() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        // Outboxのイベントは読み出すときに検証する
+        let signed = NostrEvent {
+            pubkey: "b".repeat(64),
+            sig: "c".repeat(128),
+            ..event(&"a".repeat(64), 42, 100, &[])
+        };
+        let item = OutboxItem {
+            req_id: "r1".to_string(),
+            event_id: signed.id.clone(),
+            event: signed,
+            status: OutboxStatus::Queued,
+            last_try_at: 0,
+            retry_count: 0,
+            error: None,
+            receipts: Vec::new(),
+        };
+        storage.enqueue_outbox(item.clone()).await.unwrap();
+        let receipt = RelayReceipt {
+            relay_url: "wss://r1".to_string(),
+            status: DeliveryStatus::Accepted,
+            sent_at: 5,
+            attempts: 1,
+        };
+        storage.update_outbox_item(&OutboxItem { receipts: vec![receipt], ..item }).await.unwrap();
+        let pending = storage.get_pending_outbox().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].accepted_count(), 1);
+        assert_eq!(pending[0].event.id, "a".repeat(64));
+        storage.update_outbox_status("r1", OutboxStatus::Ok).await.unwrap();
+        assert!(storage.get_pending_outbox().await.unwrap().is_empty());
+
+        let relay = RelayConfig { max_subscriptions: Some(10), ..RelayConfig::new("wss://r1") };
+        storage.upsert_relay(&relay).await.unwrap();
+        assert_eq!(storage.get_relays().await.unwrap()[0].max_subscriptions, Some(10));
+        storage.remove_relay("wss://r1").await.unwrap();
+        assert!(storage.get_relays().await.unwrap().is_empty());
+
+        storage.save_keypair(&[1, 2, 3]).await.unwrap();
+        assert_eq!(storage.get_keypair().await.unwrap(), Some(vec![1, 2, 3]));
+        storage.set_last_seen("chan", 42).await.unwrap();
+        assert_eq!(storage.get_last_seen("chan").await.unwrap(), 42);
+        storage.upsert_dm_thread("bob", 10).await.unwrap();
+        storage.upsert_dm_thread("bob", 20).await.unwrap();
+        assert_eq!(storage.get_dm_threads().await.unwrap()[0].last_msg_at, 20);
+    }
+
+    #[tokio::test]
+    async fn test_conformance() {
+        crate::storage::conformance::check_all(|| async { SqliteStorage::open_in_memory().unwrap() }).await;
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_from_v1_keeps_newest_versions() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        for (id, kind, created_at, tags) in [
+            ("p1", 0, 100, "[]"),
+            ("p2", 0, 200, "[]"),
+            ("p3", 0, 200, "[]"),
+            ("a1", 30023, 100, r#"[["d","x"]]"#),
+            ("a2", 30023, 100, r#"[["d","y"]]"#),
+            ("e1", 20001, 100, "[]"),
+            ("m1", 42, 100, "[]"),
+        ] {
+            conn.execute(
+                &format!("INSERT INTO events ({}) VALUES (?1, ?2, 'alice', ?3, '', ?4, '', NULL, 0, '[]')", EVENT_COLUMNS),
+                params![id, kind, created_at, tags],
+            )
+            .unwrap();
+        }
+        conn.pragma_update(None, "user_version", 1).unwrap();
+
+        let storage = SqliteStorage::from_connection(conn).unwrap();
+        let ids: Vec<String> = storage.get_events(&StorageFilter::new()).await.unwrap().into_iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec!["p2", "a1", "a2", "m1"]);
+    }
+
+    #[test]
+    fn test_reopens_file_and_rejects_newer_schema() {
+        let path = std::env::temp_dir().join(format!("rustr-sqlite-test-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        SqliteStorage::open(&path).unwrap();
+        let storage = SqliteStorage::open(&path).unwrap();
+        storage.conn().pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+        drop(storage);
+
+        assert!(matches!(SqliteStorage::open(&path), Err(CoreError::StorageError(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,32 +1,90 @@
 use std::collections::HashMap;
-use serde_json::{json, Value};
+use std::sync::Arc;
 
-use crate::types::TimeWindow;
+use crate::clock::{Clock, SystemClock};
+use crate::filter::Filter;
+use crate::types::{Scope, UiRow};
+
+/// 購読を開いたときに遡る時間（秒）
+const INITIAL_WINDOW_SECONDS: i64 = 600;
 
 /// 購読の状態
 #[derive(Debug, Clone)]
 pub struct ActiveSub {
     pub sub_id: String,
-    pub filter_json: String,
+    pub filter: Filter,
     pub eose_count: u32,
-    pub last_extended_at: i64,
+    /// この購読を使っている呼び出し元の数
+    pub refs: u32,
+    /// この購読をCLOSEDで終了したRelay
+    pub closed_by: Vec<String>,
+}
+
+/// 会話の過去ログ取得の状態
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HistoryState {
+    /// Relayに問い合わせ中
+    pub loading: bool,
+    /// これより古いイベントはない
+    pub reached_start: bool,
+}
+
+/// 過去ログ取得の1回分の問い合わせ（`until`+`limit`のREQ）
+#[derive(Debug, Clone)]
+struct HistoryRequest {
+    scope: Scope,
+    /// EOSEを待っているRelay
+    pending: Vec<String>,
+}
+
+/// 会話ごとの過去ログ取得の進行状況
+#[derive(Debug, Clone, Default)]
+struct HistoryProgress {
+    state: HistoryState,
+    /// 今回の取得で求めた件数
+    limit: u32,
+    /// 今回の取得で受け取った、会話にまだない行（終わったら新しい`limit`件だけ会話に加える）
+    rows: Vec<UiRow>,
+}
+
+/// 終わった過去ログ取得の1回分
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinishedHistory {
+    pub scope: Scope,
+    pub state: HistoryState,
+    /// 会話に加える行（新しい順）
+    pub rows: Vec<UiRow>,
 }
 
 /// 購読マネージャー
 pub struct SubscriptionManager {
     active_subs: HashMap<String, ActiveSub>,
-    channel_windows: HashMap<String, TimeWindow>,
-    dm_windows: HashMap<String, TimeWindow>,
+    history_requests: HashMap<String, HistoryRequest>,
+    history: HashMap<Scope, HistoryProgress>,
+    next_history_id: u64,
     self_pubkey: Option<String>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for SubscriptionManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SubscriptionManager {
     pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
             active_subs: HashMap::new(),
-            channel_windows: HashMap::new(),
-            dm_windows: HashMap::new(),
+            history_requests: HashMap::new(),
+            history: HashMap::new(),
+            next_history_id: 0,
             self_pubkey: None,
+            clock,
         }
     }
 
@@ -34,186 +92,175 @@ impl SubscriptionManager {
         self.self_pubkey = Some(pubkey);
     }
 
+    /// 会話のイベントに一致するフィルター（DMは送信・受信の2つ）
+    pub fn scope_filters(scope: &Scope, self_pubkey: &str) -> Vec<Filter> {
+        match scope {
+            // NIP-28 channel message
+            Scope::Channel(channel_id) => vec![Filter::new().kinds([42]).tag('e', [channel_id.as_str()])],
+            // NIP-04: 自分 -> peer と peer -> 自分
+            Scope::Dm(peer) => vec![
+                Filter::new().kinds([4]).authors([self_pubkey]).tag('p', [peer.as_str()]),
+                Filter::new().kinds([4]).authors([peer.as_str()]).tag('p', [self_pubkey]),
+            ],
+        }
+    }
+
     /// チャンネルを開く
-    pub fn open_channel(&mut self, channel_id: &str) -> Vec<(String, String)> {
-        let now = current_timestamp();
-        let since = now - 600; // 初回は10分前から
-
-        let window = TimeWindow::new(since);
-        self.channel_windows.insert(channel_id.to_string(), window);
-
-        // サブスクリプションIDを生成（任意の識別子）
-        let sub_id = format!("channel_{}", channel_id);
-        let filter = json!({
-            "kinds": [42], // NIP-28 channel message
-            "#e": [channel_id],
-            "since": since,
-        });
-
-        let filter_json = filter.to_string();
-        self.active_subs.insert(
-            sub_id.clone(),
-            ActiveSub {
-                sub_id: sub_id.clone(),
-                filter_json: filter_json.clone(),
-                eose_count: 0,
-                last_extended_at: now,
-            },
-        );
-
-        vec![(sub_id, filter_json)]
+    pub fn open_channel(&mut self, channel_id: &str) -> Vec<(String, Filter)> {
+        let scope = Scope::Channel(channel_id.to_string());
+        let sub_ids = [format!("channel_{}", channel_id)];
+        self.open_scope(&scope, "", &sub_ids)
     }
 
     /// DMスレッドを開く
-    pub fn open_dm(&mut self, peer: &str, self_pubkey: &str) -> Vec<(String, String)> {
-        let now = current_timestamp();
-        let since = now - 600; // 初回は10分前から
+    pub fn open_dm(&mut self, peer: &str, self_pubkey: &str) -> Vec<(String, Filter)> {
+        let scope = Scope::Dm(peer.to_string());
+        let sub_ids = [format!("dm_to_{}", peer), format!("dm_from_{}", peer)];
+        self.open_scope(&scope, self_pubkey, &sub_ids)
+    }
 
-        let window = TimeWindow::new(since);
-        self.dm_windows.insert(peer.to_string(), window);
+    /// 会話の新着イベントを購読する（初回は少し前から）
+    ///
+    /// 既に開いている購読は共有し、送信すべき新しい購読だけを返す。
+    fn open_scope(&mut self, scope: &Scope, self_pubkey: &str, sub_ids: &[String]) -> Vec<(String, Filter)> {
+        let since = self.clock.now() - INITIAL_WINDOW_SECONDS;
+        let mut opened = Vec::new();
+        for (filter, sub_id) in Self::scope_filters(scope, self_pubkey).into_iter().zip(sub_ids) {
+            if let Some(sub) = self.active_subs.get_mut(sub_id) {
+                sub.refs += 1;
+                continue;
+            }
+            let filter = filter.since(since);
+            self.active_subs.insert(
+                sub_id.clone(),
+                ActiveSub {
+                    sub_id: sub_id.clone(),
+                    filter: filter.clone(),
+                    eose_count: 0,
+                    refs: 1,
+                    closed_by: Vec::new(),
+                },
+            );
+            opened.push((sub_id.clone(), filter));
+        }
+        opened
+    }
 
-        // サブスクリプションIDを生成（任意の識別子）
-        let sub_id = format!("dm_to_{}", peer);
-        
-        // NIP-04: kind=4, authors=[self] OR #p=[self]
-        let filter = json!({
-            "kinds": [4],
-            "authors": [self_pubkey],
-            "#p": [peer],
-            "since": since,
-        });
-
-        let filter_json = filter.to_string();
-        self.active_subs.insert(
-            sub_id.clone(),
-            ActiveSub {
-                sub_id: sub_id.clone(),
-                filter_json: filter_json.clone(),
-                eose_count: 0,
-                last_extended_at: now,
-            },
-        );
-
-        // 逆方向のフィルター（peer -> self）
-        let sub_id2 = format!("dm_from_{}", peer);
-        let filter2 = json!({
-            "kinds": [4],
-            "authors": [peer],
-            "#p": [self_pubkey],
-            "since": since,
-        });
-
-        let filter_json2 = filter2.to_string();
-        self.active_subs.insert(
-            sub_id2.clone(),
-            ActiveSub {
-                sub_id: sub_id2.clone(),
-                filter_json: filter_json2.clone(),
-                eose_count: 0,
-                last_extended_at: now,
-            },
-        );
-
-        vec![(sub_id, filter_json), (sub_id2, filter_json2)]
+    /// 会話の購読を閉じる。誰も使わなくなった購読のIDを返す（Relayに`CLOSE`を送る）
+    pub fn close_scope(&mut self, scope: &Scope) -> Vec<String> {
+        let sub_ids = match scope {
+            Scope::Channel(channel_id) => vec![format!("channel_{}", channel_id)],
+            Scope::Dm(peer) => vec![format!("dm_to_{}", peer), format!("dm_from_{}", peer)],
+        };
+        sub_ids.into_iter().filter(|sub_id| self.close_subscription(sub_id)).collect()
     }
 
-    /// EOSE受信時の処理
-    pub fn on_eose(&mut self, sub_id: &str) -> Option<Vec<(String, String)>> {
-        if let Some(sub) = self.active_subs.get_mut(sub_id) {
-            sub.eose_count += 1;
+    /// 過去ログ取得の購読を作る（`until`以前を新しい順に`limit`件）
+    ///
+    /// 取得中や、既に最初まで取得した会話では何も返さない。
+    pub fn open_history(&mut self, scope: &Scope, self_pubkey: &str, until: i64, limit: u32) -> Vec<(String, Filter)> {
+        let progress = self.history.entry(scope.clone()).or_default();
+        if progress.state.loading || progress.state.reached_start {
+            return Vec::new();
+        }
+        progress.state.loading = true;
+        progress.limit = limit;
+        progress.rows.clear();
+
+        let mut requests = Vec::new();
+        for filter in Self::scope_filters(scope, self_pubkey) {
+            self.next_history_id += 1;
+            let sub_id = format!("history_{}", self.next_history_id);
+            self.history_requests.insert(
+                sub_id.clone(),
+                HistoryRequest { scope: scope.clone(), pending: Vec::new() },
+            );
+            requests.push((sub_id, filter.until(until).limit(limit)));
+        }
+        requests
+    }
 
-            // 段階的に窓を拡大
-            if self.should_extend_window(sub_id) {
-                return self.extend_window(sub_id);
-            }
+    /// 過去ログ取得のREQを送ったRelayを記録する。送り先がなければその場で終了する
+    ///
+    /// 会話の過去ログ取得が終わったら、その結果を返す。
+    pub fn on_history_sent(&mut self, sub_id: &str, relays: Vec<String>) -> Option<FinishedHistory> {
+        let request = self.history_requests.get_mut(sub_id)?;
+        request.pending = relays;
+        if request.pending.is_empty() {
+            return self.finish_history(sub_id);
         }
         None
     }
 
-    /// EOSEをマーク（on_eoseのエイリアス）
-    pub fn mark_eose(&mut self, sub_id: &str) {
-        if let Some(sub) = self.active_subs.get_mut(sub_id) {
-            sub.eose_count += 1;
-        }
+    /// 過去ログ取得の購読か
+    pub fn is_history(&self, sub_id: &str) -> bool {
+        self.history_requests.contains_key(sub_id)
     }
 
-    /// ウィンドウ拡張が必要か
-    pub fn needs_extension(&self, sub_id: &str) -> bool {
-        self.should_extend_window(sub_id)
+    /// 過去ログ取得で会話にまだないイベントを得た（会話に加えるのは取得が終わってから）
+    pub fn on_history_event(&mut self, sub_id: &str, row: UiRow) {
+        let Some(request) = self.history_requests.get(sub_id) else {
+            return;
+        };
+        if let Some(progress) = self.history.get_mut(&request.scope) {
+            progress.rows.push(row);
+        }
     }
 
-    /// 窓を拡大すべきか
-    pub fn should_extend_window(&self, sub_id: &str) -> bool {
-        if let Some(sub) = self.active_subs.get(sub_id) {
-            // 最大4段階まで拡大
-            sub.eose_count < 4
-        } else {
-            false
+    /// 過去ログ取得の購読がRelayで終わった（EOSEまたはCLOSED）
+    ///
+    /// 会話の過去ログ取得が終わったら、その結果を返す。
+    pub fn on_history_done(&mut self, sub_id: &str, relay_url: &str) -> Option<FinishedHistory> {
+        let request = self.history_requests.get_mut(sub_id)?;
+        request.pending.retain(|url| url != relay_url);
+        if request.pending.is_empty() {
+            return self.finish_history(sub_id);
         }
+        None
     }
 
-    /// 窓を拡大
-    pub fn extend_window(&mut self, sub_id: &str) -> Option<Vec<(String, String)>> {
-        let sub = self.active_subs.get(sub_id)?;
-        let eose_count = sub.eose_count;
-
-        // 拡大量: 1h -> 1d -> 7d -> 30d
-        let additional_seconds = match eose_count {
-            1 => 3600,        // 1時間
-            2 => 86400,       // 1日
-            3 => 604800,      // 7日
-            4 => 2592000,     // 30日
-            _ => return None,
-        };
+    /// 過去ログ取得の1回分を終える。新しいイベントが1件もなければ最初まで取得したとみなす
+    ///
+    /// フィルター（DMなら送信と受信）ごとに`limit`件ずつ届くので、まとめて新しい`limit`件だけ返す。
+    /// 切り捨てた行は返した最古の行より古いので、次の`until`で取り直せる。
+    fn finish_history(&mut self, sub_id: &str) -> Option<FinishedHistory> {
+        let request = self.history_requests.remove(sub_id)?;
+        if self.history_requests.values().any(|r| r.scope == request.scope) {
+            return None;
+        }
+        let progress = self.history.get_mut(&request.scope)?;
+        let mut rows = std::mem::take(&mut progress.rows);
+        rows.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.id.cmp(&b.id)));
+        rows.dedup_by(|a, b| a.id == b.id);
+        rows.truncate(progress.limit as usize);
+        progress.state.loading = false;
+        progress.state.reached_start = rows.is_empty();
+        Some(FinishedHistory { scope: request.scope, state: progress.state, rows })
+    }
 
-        // チャンネルまたはDMの窓を更新
-        let new_since = if sub_id.starts_with("channel:") {
-            let channel_id = sub_id.strip_prefix("channel:")?;
-            if let Some(window) = self.channel_windows.get_mut(channel_id) {
-                window.extend(additional_seconds);
-                Some(window.since)
-            } else {
-                None
-            }
-        } else if sub_id.starts_with("dm:") {
-            let parts: Vec<&str> = sub_id.splitn(2, ':').collect();
-            if parts.len() >= 2 {
-                let peer = parts[1].split(':').next()?;
-                if let Some(window) = self.dm_windows.get_mut(peer) {
-                    window.extend(additional_seconds);
-                    Some(window.since)
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+    /// 会話の過去ログ取得の状態
+    pub fn history_state(&self, scope: &Scope) -> HistoryState {
+        self.history.get(scope).map(|p| p.state).unwrap_or_default()
+    }
 
-        if let Some(since) = new_since {
-            return self.create_extended_filter(sub_id, since);
+    /// EOSE受信時の処理
+    pub fn mark_eose(&mut self, sub_id: &str) {
+        if let Some(sub) = self.active_subs.get_mut(sub_id) {
+            sub.eose_count += 1;
         }
-
-        None
     }
 
-    /// 拡大されたフィルターを作成
-    fn create_extended_filter(&mut self, sub_id: &str, new_since: i64) -> Option<Vec<(String, String)>> {
-        let sub = self.active_subs.get_mut(sub_id)?;
-        
-        // 既存のフィルターをパースして since を更新
-        if let Ok(mut filter) = serde_json::from_str::<Value>(&sub.filter_json) {
-            filter["since"] = json!(new_since);
-            let new_filter_json = filter.to_string();
-            
-            sub.filter_json = new_filter_json.clone();
-            sub.last_extended_at = current_timestamp();
-
-            Some(vec![(sub_id.to_string(), new_filter_json)])
+    /// 購読IDから会話を求める
+    pub fn scope_of(&self, sub_id: &str) -> Option<Scope> {
+        if let Some(request) = self.history_requests.get(sub_id) {
+            Some(request.scope.clone())
+        } else if let Some(channel_id) = sub_id.strip_prefix("channel_") {
+            Some(Scope::Channel(channel_id.to_string()))
         } else {
-            None
+            sub_id
+                .strip_prefix("dm_to_")
+                .or_else(|| sub_id.strip_prefix("dm_from_"))
+                .map(|peer| Scope::Dm(peer.to_string()))
         }
     }
 
@@ -222,67 +269,157 @@ impl SubscriptionManager {
         self.active_subs.values().collect()
     }
 
-    /// 購読をクローズ
-    pub fn close_subscription(&mut self, sub_id: &str) {
-        self.active_subs.remove(sub_id);
+    /// RelayからCLOSEDを受信した
+    pub fn on_closed(&mut self, sub_id: &str, relay_url: &str) {
+        if let Some(sub) = self.active_subs.get_mut(sub_id) {
+            if !sub.closed_by.iter().any(|url| url == relay_url) {
+                sub.closed_by.push(relay_url.to_string());
+            }
+        }
+    }
+
+    /// 購読をCLOSEDで終了したRelay一覧
+    pub fn closed_relays(&self, sub_id: &str) -> &[String] {
+        self.active_subs
+            .get(sub_id)
+            .map(|sub| sub.closed_by.as_slice())
+            .unwrap_or(&[])
     }
-}
 
-/// 現在のUNIXタイムスタンプ（秒）
-fn current_timestamp() -> i64 {
-    (js_sys::Date::now() / 1000.0) as i64
+    /// 購読の利用をやめる。誰も使わなくなって削除したらtrueを返す
+    pub fn close_subscription(&mut self, sub_id: &str) -> bool {
+        let Some(sub) = self.active_subs.get_mut(sub_id) else {
+            return false;
+        };
+        sub.refs = sub.refs.saturating_sub(1);
+        if sub.refs > 0 {
+            return false;
+        }
+        self.active_subs.remove(sub_id);
+        true
+    }
 }
 
-#[cfg(all(test, target_arch = "wasm32"))]
+#[cfg(test)]
 mod tests {
     use super::*;
-    use wasm_bindgen_test::*;
+    use crate::clock::MockClock;
 
-    wasm_bindgen_test_configure!(run_in_browser);
+    fn manager() -> SubscriptionManager {
+        SubscriptionManager::with_clock(Arc::new(MockClock::new(1_000)))
+    }
 
-    #[wasm_bindgen_test]
+    #[test]
     fn test_open_channel() {
-        let mut mgr = SubscriptionManager::new();
+        let mut mgr = manager();
         let filters = mgr.open_channel("test_channel");
         
         assert_eq!(filters.len(), 1);
-        assert!(filters[0].0.starts_with("channel:"));
+        assert_eq!(filters[0].0, "channel_test_channel");
         
-        let filter: Value = serde_json::from_str(&filters[0].1).unwrap();
-        assert_eq!(filter["kinds"][0], 42);
-        assert_eq!(filter["#e"][0], "test_channel");
+        let filter = &filters[0].1;
+        assert_eq!(filter.kinds, Some(vec![42]));
+        assert_eq!(filter.tag_values('e'), Some(&["test_channel".to_string()][..]));
+        assert_eq!(filter.since, Some(400));
+        assert_eq!(mgr.scope_of("channel_test_channel"), Some(Scope::Channel("test_channel".to_string())));
     }
 
-    #[wasm_bindgen_test]
+    #[test]
     fn test_open_dm() {
-        let mut mgr = SubscriptionManager::new();
+        let mut mgr = manager();
         let self_pubkey = "self123";
         let peer = "peer456";
         
         let filters = mgr.open_dm(peer, self_pubkey);
         
         assert_eq!(filters.len(), 2);
-        assert!(filters[0].0.starts_with("dm:"));
+        assert_eq!(filters[0].0, "dm_to_peer456");
+        assert_eq!(filters[1].0, "dm_from_peer456");
         
-        let filter1: Value = serde_json::from_str(&filters[0].1).unwrap();
-        assert_eq!(filter1["kinds"][0], 4);
-        assert_eq!(filter1["authors"][0], self_pubkey);
-        assert_eq!(filter1["#p"][0], peer);
+        let filter1 = &filters[0].1;
+        assert_eq!(filter1.kinds, Some(vec![4]));
+        assert_eq!(filter1.authors, Some(vec![self_pubkey.to_string()]));
+        assert_eq!(filter1.tag_values('p'), Some(&[peer.to_string()][..]));
+        assert_eq!(mgr.scope_of("dm_from_peer456"), Some(Scope::Dm(peer.to_string())));
     }
 
-    #[wasm_bindgen_test]
-    fn test_eose_extension() {
-        let mut mgr = SubscriptionManager::new();
-        mgr.open_channel("test");
-        
-        let sub_id = "channel:test";
-        
-        // 1回目のEOSE
-        let result = mgr.on_eose(sub_id);
-        assert!(result.is_some());
-        
-        let sub = mgr.active_subs.get(sub_id).unwrap();
-        assert_eq!(sub.eose_count, 1);
+    #[test]
+    fn test_shared_subscription_closes_with_last_user() {
+        let mut mgr = manager();
+        let scope = Scope::Channel("chan".to_string());
+        assert_eq!(mgr.open_channel("chan").len(), 1);
+        // 同じ購読は共有し、重ねて送らない
+        assert!(mgr.open_channel("chan").is_empty());
+
+        assert!(mgr.close_scope(&scope).is_empty());
+        assert_eq!(mgr.close_scope(&scope), vec!["channel_chan".to_string()]);
+        assert!(mgr.get_active_subs().is_empty());
+        assert!(mgr.close_scope(&scope).is_empty());
     }
-}
 
+    fn row(id: &str, created_at: i64) -> UiRow {
+        UiRow {
+            id: id.to_string(),
+            kind: 4,
+            pubkey: "peer".to_string(),
+            created_at,
+            content: String::new(),
+            image_url: None,
+            scope: Some(Scope::Dm("peer".to_string())),
+        }
+    }
+
+    #[test]
+    fn test_history_keeps_newest_limit_across_filters() {
+        let mut mgr = manager();
+        let scope = Scope::Dm("peer".to_string());
+        let requests = mgr.open_history(&scope, "self", 500, 2);
+        for (sub_id, _) in &requests {
+            mgr.on_history_sent(sub_id, vec!["wss://a".to_string()]);
+        }
+
+        // 送信側は新しい2件、受信側は古い1件しかない
+        mgr.on_history_event(&requests[0].0, row("sent2", 400));
+        mgr.on_history_event(&requests[0].0, row("sent1", 300));
+        mgr.on_history_event(&requests[1].0, row("received", 100));
+        mgr.on_history_event(&requests[1].0, row("sent2", 400));
+        mgr.on_history_done(&requests[0].0, "wss://a");
+        let finished = mgr.on_history_done(&requests[1].0, "wss://a").unwrap();
+        assert_eq!(finished.rows, vec![row("sent2", 400), row("sent1", 300)]);
+        assert_eq!(finished.state, HistoryState { loading: false, reached_start: false });
+    }
+
+    #[test]
+    fn test_history_paging() {
+        let mut mgr = manager();
+        let scope = Scope::Dm("peer".to_string());
+
+        let requests = mgr.open_history(&scope, "self", 500, 20);
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].1.until, Some(500));
+        assert_eq!(requests[0].1.limit, Some(20));
+        assert_eq!(mgr.scope_of(&requests[0].0), Some(scope.clone()));
+        // 取得中は重ねて問い合わせない
+        assert!(mgr.open_history(&scope, "self", 500, 20).is_empty());
+
+        mgr.on_history_sent(&requests[0].0, vec!["wss://a".to_string(), "wss://b".to_string()]);
+        mgr.on_history_sent(&requests[1].0, vec!["wss://a".to_string()]);
+        mgr.on_history_event(&requests[0].0, row("a", 300));
+        mgr.on_history_done(&requests[0].0, "wss://a");
+        mgr.on_history_done(&requests[0].0, "wss://b");
+        assert!(mgr.history_state(&scope).loading);
+        let finished = mgr.on_history_done(&requests[1].0, "wss://a").unwrap();
+        assert_eq!(finished.rows, vec![row("a", 300)]);
+        assert_eq!(mgr.history_state(&scope), HistoryState { loading: false, reached_start: false });
+        assert!(!mgr.is_history(&requests[0].0));
+
+        // 新しいイベントがなければ最初まで取得した
+        let requests = mgr.open_history(&scope, "self", 400, 20);
+        for (sub_id, _) in &requests {
+            mgr.on_history_sent(sub_id, vec!["wss://a".to_string()]);
+            mgr.on_history_done(sub_id, "wss://a");
+        }
+        assert_eq!(mgr.history_state(&scope), HistoryState { loading: false, reached_start: true });
+        assert!(mgr.open_history(&scope, "self", 300, 20).is_empty());
+    }
+}
@@ -0,0 +1,284 @@
+use crate::relay::ExponentialBackoff;
+
+/// キープアライブ用のpingで使う購読ID
+pub const PING_SUB_ID: &str = "rustr_ping";
+
+/// キープアライブ用のpingフィルター（存在しないidを問い合わせてEOSEだけ返させる）
+pub const PING_FILTER: &str = r#"{"ids":["0000000000000000000000000000000000000000000000000000000000000000"],"limit":1}"#;
+
+/// 切断理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// 接続開始に失敗
+    ConnectFailed(String),
+    /// 接続確立がタイムアウト
+    ConnectTimeout,
+    /// 一定時間応答がない
+    IdleTimeout,
+    /// トランスポートエラー
+    TransportError(String),
+    /// Relayまたはネットワークによるクローズ
+    Closed { code: u16, reason: String },
+    /// こちらから切断
+    Manual,
+}
+
+/// Supervisorの設定
+#[derive(Debug, Clone, Copy)]
+pub struct SupervisorConfig {
+    /// バックオフ遅延に加えるジッター（0.0〜1.0、遅延に対する割合）
+    pub jitter: f64,
+    /// 接続確立のタイムアウト（秒）
+    pub connect_timeout: f64,
+    /// 無通信がこの時間続いたらpingを送る（秒）
+    pub ping_interval: f64,
+    /// 無通信がこの時間続いたら切断とみなす（秒）
+    pub idle_timeout: f64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            jitter: 0.2,
+            connect_timeout: 10.0,
+            ping_interval: 30.0,
+            idle_timeout: 60.0,
+        }
+    }
+}
+
+/// Supervisorが要求するアクション
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SupervisorAction {
+    /// 何もしない
+    None,
+    /// キープアライブのpingを送る
+    SendPing,
+    /// 接続を切る
+    Disconnect(DisconnectReason),
+}
+
+/// 接続監視（バックオフ、ジッター、キープアライブ）
+///
+/// 時刻は呼び出し側から秒で渡す。
+#[derive(Debug, Clone)]
+pub struct ConnectionSupervisor {
+    config: SupervisorConfig,
+    backoff: ExponentialBackoff,
+    next_attempt_at: f64,
+    connect_started_at: Option<f64>,
+    last_activity_at: f64,
+    ping_sent_at: Option<f64>,
+    last_error: Option<String>,
+    last_disconnect: Option<DisconnectReason>,
+    disconnect_count: u32,
+}
+
+impl Default for ConnectionSupervisor {
+    fn default() -> Self {
+        Self::new(SupervisorConfig::default())
+    }
+}
+
+impl ConnectionSupervisor {
+    pub fn new(config: SupervisorConfig) -> Self {
+        Self {
+            config,
+            backoff: ExponentialBackoff::new(),
+            next_attempt_at: 0.0,
+            connect_started_at: None,
+            last_activity_at: 0.0,
+            ping_sent_at: None,
+            last_error: None,
+            last_disconnect: None,
+            disconnect_count: 0,
+        }
+    }
+
+    pub fn config(&self) -> &SupervisorConfig {
+        &self.config
+    }
+
+    /// 再接続を試みてよいか
+    pub fn should_reconnect(&self, now: f64) -> bool {
+        now >= self.next_attempt_at
+    }
+
+    /// 次の接続試行時刻
+    pub fn next_attempt_at(&self) -> f64 {
+        self.next_attempt_at
+    }
+
+    /// 接続開始
+    pub fn on_connecting(&mut self, now: f64) {
+        self.connect_started_at = Some(now);
+        self.last_error = None;
+    }
+
+    /// 接続確立。バックオフをリセットする
+    pub fn on_open(&mut self, now: f64) {
+        self.connect_started_at = None;
+        self.last_activity_at = now;
+        self.ping_sent_at = None;
+        self.backoff.reset();
+    }
+
+    /// 受信があった
+    pub fn on_activity(&mut self, now: f64) {
+        self.last_activity_at = now;
+        self.ping_sent_at = None;
+    }
+
+    /// トランスポートエラーを記録（直後のクローズの理由として使う）
+    pub fn on_error(&mut self, error: &str) {
+        self.last_error = Some(error.to_string());
+    }
+
+    /// 切断。次の接続試行をバックオフ + ジッター後に予約する
+    pub fn on_disconnect(&mut self, now: f64, reason: DisconnectReason) {
+        let reason = match (reason, self.last_error.take()) {
+            // エラー直後のクローズはエラーを理由とする
+            (DisconnectReason::Closed { .. }, Some(error)) => DisconnectReason::TransportError(error),
+            (reason, _) => reason,
+        };
+
+        let delay = self.backoff.next_delay() as f64;
+        self.next_attempt_at = now + apply_jitter(delay, self.config.jitter, random_unit());
+        self.connect_started_at = None;
+        self.ping_sent_at = None;
+        self.last_disconnect = Some(reason);
+        self.disconnect_count += 1;
+    }
+
+    /// 最後の切断理由
+    pub fn last_disconnect(&self) -> Option<&DisconnectReason> {
+        self.last_disconnect.as_ref()
+    }
+
+    /// 切断回数
+    pub fn disconnect_count(&self) -> u32 {
+        self.disconnect_count
+    }
+
+    /// 接続の健全性をチェック
+    pub fn check(&mut self, now: f64, connected: bool) -> SupervisorAction {
+        if let Some(started) = self.connect_started_at {
+            if !connected && now - started >= self.config.connect_timeout {
+                return SupervisorAction::Disconnect(DisconnectReason::ConnectTimeout);
+            }
+            return SupervisorAction::None;
+        }
+
+        if !connected {
+            return SupervisorAction::None;
+        }
+
+        let idle = now - self.last_activity_at;
+        if idle >= self.config.idle_timeout {
+            return SupervisorAction::Disconnect(DisconnectReason::IdleTimeout);
+        }
+        if idle >= self.config.ping_interval && self.ping_sent_at.is_none() {
+            self.ping_sent_at = Some(now);
+            return SupervisorAction::SendPing;
+        }
+        SupervisorAction::None
+    }
+}
+
+/// 遅延にジッターを加える（`unit`は0.0〜1.0の乱数）
+fn apply_jitter(delay: f64, jitter: f64, unit: f64) -> f64 {
+    let jitter = jitter.clamp(0.0, 1.0);
+    (delay * (1.0 + jitter * (unit * 2.0 - 1.0))).max(0.0)
+}
+
+/// 0.0〜1.0の乱数
+fn random_unit() -> f64 {
+    let mut buf = [0u8; 4];
+    match getrandom::getrandom(&mut buf) {
+        Ok(()) => u32::from_le_bytes(buf) as f64 / u32::MAX as f64,
+        Err(_) => 0.5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_jitter() -> SupervisorConfig {
+        SupervisorConfig {
+            jitter: 0.0,
+            ..SupervisorConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff_grows_and_resets() {
+        let mut sup = ConnectionSupervisor::new(no_jitter());
+        assert!(sup.should_reconnect(0.0));
+
+        sup.on_disconnect(100.0, DisconnectReason::ConnectFailed("x".to_string()));
+        assert_eq!(sup.next_attempt_at(), 101.0);
+        sup.on_disconnect(101.0, DisconnectReason::ConnectFailed("x".to_string()));
+        assert_eq!(sup.next_attempt_at(), 103.0);
+        sup.on_disconnect(103.0, DisconnectReason::ConnectFailed("x".to_string()));
+        assert_eq!(sup.next_attempt_at(), 107.0);
+        assert!(!sup.should_reconnect(106.0));
+        assert!(sup.should_reconnect(107.0));
+
+        sup.on_open(108.0);
+        sup.on_disconnect(200.0, DisconnectReason::IdleTimeout);
+        assert_eq!(sup.next_attempt_at(), 201.0);
+        assert_eq!(sup.disconnect_count(), 4);
+    }
+
+    #[test]
+    fn test_jitter_bounds() {
+        assert_eq!(apply_jitter(10.0, 0.2, 0.0), 8.0);
+        assert_eq!(apply_jitter(10.0, 0.2, 0.5), 10.0);
+        assert_eq!(apply_jitter(10.0, 0.2, 1.0), 12.0);
+        assert_eq!(apply_jitter(10.0, 0.0, 1.0), 10.0);
+    }
+
+    #[test]
+    fn test_error_becomes_disconnect_reason() {
+        let mut sup = ConnectionSupervisor::new(no_jitter());
+        sup.on_error("connection reset");
+        sup.on_disconnect(0.0, DisconnectReason::Closed { code: 1006, reason: String::new() });
+        assert_eq!(
+            sup.last_disconnect(),
+            Some(&DisconnectReason::TransportError("connection reset".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_keepalive_and_idle_timeout() {
+        let mut sup = ConnectionSupervisor::new(no_jitter());
+        sup.on_connecting(0.0);
+        sup.on_open(1.0);
+
+        assert_eq!(sup.check(20.0, true), SupervisorAction::None);
+        assert_eq!(sup.check(31.0, true), SupervisorAction::SendPing);
+        // pingは応答待ちの間は再送しない
+        assert_eq!(sup.check(40.0, true), SupervisorAction::None);
+
+        // 応答があればタイマーはリセット
+        sup.on_activity(41.0);
+        assert_eq!(sup.check(60.0, true), SupervisorAction::None);
+
+        assert_eq!(
+            sup.check(101.0, true),
+            SupervisorAction::Disconnect(DisconnectReason::IdleTimeout)
+        );
+    }
+
+    #[test]
+    fn test_connect_timeout() {
+        let mut sup = ConnectionSupervisor::new(no_jitter());
+        sup.on_connecting(0.0);
+        assert_eq!(sup.check(5.0, false), SupervisorAction::None);
+        assert_eq!(
+            sup.check(10.0, false),
+            SupervisorAction::Disconnect(DisconnectReason::ConnectTimeout)
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::{Transport, TransportConnection, TransportEvent, TransportSink};
+use crate::error::{CoreError, Result};
+
+/// テスト用のモックTransport実装
+///
+/// 接続ごとのsinkを保持し、テストから任意の`TransportEvent`を注入できる。
+/// 送信されたメッセージはURLごとに記録される。
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    sinks: Arc<Mutex<HashMap<String, TransportSink>>>,
+    sent: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    connect_count: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+struct MockConnection {
+    url: String,
+    sink: TransportSink,
+    sent: Arc<Mutex<HashMap<String, Vec<String>>>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 接続成功を通知
+    pub fn open(&self, url: &str) {
+        self.push(url, TransportEvent::Open);
+    }
+
+    /// Relayからのメッセージを注入
+    pub fn receive(&self, url: &str, msg: &str) {
+        self.push(url, TransportEvent::Message(msg.to_string()));
+    }
+
+    /// 切断を通知
+    pub fn close(&self, url: &str) {
+        self.push(url, TransportEvent::Closed { code: 1006, reason: String::new() });
+    }
+
+    /// 任意のイベントを注入
+    pub fn push(&self, url: &str, event: TransportEvent) {
+        if let Some(sink) = self.sinks.lock().unwrap().get(url) {
+            sink.push(event);
+        }
+    }
+
+    /// 送信されたメッセージを取り出す
+    pub fn take_sent(&self, url: &str) -> Vec<String> {
+        self.sent.lock().unwrap().remove(url).unwrap_or_default()
+    }
+
+    /// 接続試行回数
+    pub fn connect_count(&self, url: &str) -> u32 {
+        *self.connect_count.lock().unwrap().get(url).unwrap_or(&0)
+    }
+}
+
+impl Transport for MockTransport {
+    fn connect(&self, url: &str, sink: TransportSink) -> Result<Box<dyn TransportConnection>> {
+        self.sinks.lock().unwrap().insert(url.to_string(), sink.clone());
+        *self.connect_count.lock().unwrap().entry(url.to_string()).or_insert(0) += 1;
+        Ok(Box::new(MockConnection {
+            url: url.to_string(),
+            sink,
+            sent: self.sent.clone(),
+        }))
+    }
+}
+
+impl TransportConnection for MockConnection {
+    fn send(&self, msg: &str) -> Result<()> {
+        let mut sent = self.sent.lock().unwrap();
+        sent.entry(self.url.clone()).or_default().push(msg.to_string());
+        Ok(())
+    }
+
+    fn close(&self) {
+        self.sink.push(TransportEvent::Closed { code: 1000, reason: String::new() });
+    }
+}
+
+/// 常に接続に失敗するTransport
+#[derive(Clone, Default)]
+pub struct FailingTransport;
+
+impl Transport for FailingTransport {
+    fn connect(&self, url: &str, _sink: TransportSink) -> Result<Box<dyn TransportConnection>> {
+        Err(CoreError::RelayError(format!("Cannot connect to {}", url)))
+    }
+}
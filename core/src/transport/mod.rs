@@ -0,0 +1,77 @@
+pub mod websocket;
+pub mod mock;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod native;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::error::Result;
+
+/// トランスポートから届くイベント
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportEvent {
+    /// 接続確立
+    Open,
+    /// テキストメッセージ受信
+    Message(String),
+    /// エラー発生
+    Error(String),
+    /// 切断
+    Closed { code: u16, reason: String },
+}
+
+/// トランスポートイベントの受け口
+///
+/// WebSocketのコールバックやネイティブのタスクからpushされ、
+/// `RelayConnection`がポーリングで取り出す。
+#[derive(Debug, Clone, Default)]
+pub struct TransportSink {
+    events: Arc<Mutex<VecDeque<TransportEvent>>>,
+}
+
+impl TransportSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// イベントを追加
+    pub fn push(&self, event: TransportEvent) {
+        self.events.lock().unwrap().push_back(event);
+    }
+
+    /// 溜まっているイベントを全て取り出す
+    pub fn drain(&self) -> Vec<TransportEvent> {
+        self.events.lock().unwrap().drain(..).collect()
+    }
+}
+
+/// 確立済み（または確立中）の接続
+pub trait TransportConnection {
+    /// テキストメッセージ送信
+    fn send(&self, msg: &str) -> Result<()>;
+
+    /// 接続を閉じる
+    fn close(&self);
+}
+
+/// Relayへの接続を作るトランスポート
+///
+/// WASMでは`web_sys::WebSocket`、ネイティブではtokio + tungstenite。
+/// WASM環境ではシングルスレッドのため、Send + Sync要件なし
+pub trait Transport {
+    /// 接続を開始する。結果は`sink`に`TransportEvent`として届く
+    fn connect(&self, url: &str, sink: TransportSink) -> Result<Box<dyn TransportConnection>>;
+}
+
+/// ターゲットに応じたデフォルトのトランスポート
+#[cfg(target_arch = "wasm32")]
+pub fn default_transport() -> Arc<dyn Transport> {
+    Arc::new(websocket::WebSocketTransport)
+}
+
+/// ターゲットに応じたデフォルトのトランスポート
+#[cfg(not(target_arch = "wasm32"))]
+pub fn default_transport() -> Arc<dyn Transport> {
+    Arc::new(native::TokioTransport)
+}
@@ -0,0 +1,98 @@
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio_tungstenite::tungstenite::Message;
+
+use super::{Transport, TransportConnection, TransportEvent, TransportSink};
+use crate::error::{CoreError, Result};
+
+/// tokio + tungstenite を使うネイティブ用トランスポート
+///
+/// `connect`はtokioランタイム上で呼ぶ必要がある。
+pub struct TokioTransport;
+
+/// 送信タスクへのコマンド
+enum Outgoing {
+    Text(String),
+    Close,
+}
+
+/// ネイティブ接続（実際のソケットはバックグラウンドタスクが保持）
+struct TokioConnection {
+    tx: UnboundedSender<Outgoing>,
+}
+
+impl Transport for TokioTransport {
+    fn connect(&self, url: &str, sink: TransportSink) -> Result<Box<dyn TransportConnection>> {
+        let runtime = tokio::runtime::Handle::try_current()
+            .map_err(|e| CoreError::RelayError(format!("No tokio runtime: {}", e)))?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        runtime.spawn(run_connection(url.to_string(), sink, rx));
+        Ok(Box::new(TokioConnection { tx }))
+    }
+}
+
+impl TransportConnection for TokioConnection {
+    fn send(&self, msg: &str) -> Result<()> {
+        self.tx
+            .send(Outgoing::Text(msg.to_string()))
+            .map_err(|_| CoreError::RelayError("Connection task has stopped".to_string()))
+    }
+
+    fn close(&self) {
+        let _ = self.tx.send(Outgoing::Close);
+    }
+}
+
+/// 接続タスク本体
+async fn run_connection(
+    url: String,
+    sink: TransportSink,
+    mut rx: mpsc::UnboundedReceiver<Outgoing>,
+) {
+    let stream = match tokio_tungstenite::connect_async(url.as_str()).await {
+        Ok((stream, _response)) => stream,
+        Err(e) => {
+            sink.push(TransportEvent::Error(e.to_string()));
+            sink.push(TransportEvent::Closed { code: 1006, reason: e.to_string() });
+            return;
+        }
+    };
+    sink.push(TransportEvent::Open);
+
+    let (mut write, mut read) = stream.split();
+    let (code, reason) = loop {
+        tokio::select! {
+            outgoing = rx.recv() => match outgoing {
+                Some(Outgoing::Text(text)) => {
+                    if let Err(e) = write.send(Message::text(text)).await {
+                        sink.push(TransportEvent::Error(e.to_string()));
+                        break (1006, e.to_string());
+                    }
+                }
+                // Closeコマンド、またはハンドルがドロップされた
+                Some(Outgoing::Close) | None => {
+                    let _ = write.close().await;
+                    break (1000, String::new());
+                }
+            },
+            incoming = read.next() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    sink.push(TransportEvent::Message(text.to_string()));
+                }
+                Some(Ok(Message::Close(frame))) => {
+                    break frame
+                        .map(|f| (u16::from(f.code), f.reason.to_string()))
+                        .unwrap_or((1005, String::new()));
+                }
+                // Ping/Pongはtungstenite側で処理される
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    sink.push(TransportEvent::Error(e.to_string()));
+                    break (1006, e.to_string());
+                }
+                None => break (1006, String::new()),
+            },
+        }
+    };
+    sink.push(TransportEvent::Closed { code, reason });
+}
@@ -0,0 +1,93 @@
+use web_sys::{WebSocket, MessageEvent, ErrorEvent, CloseEvent};
+use wasm_bindgen::{JsCast, closure::Closure};
+
+use super::{Transport, TransportConnection, TransportEvent, TransportSink};
+use crate::error::Result;
+
+/// ブラウザのWebSocketを使うトランスポート
+pub struct WebSocketTransport;
+
+/// WebSocket接続
+struct WebSocketConnection {
+    ws: WebSocket,
+    // クロージャを保持してドロップされないようにする
+    _on_open: Closure<dyn FnMut()>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_error: Closure<dyn FnMut(ErrorEvent)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+impl Transport for WebSocketTransport {
+    fn connect(&self, url: &str, sink: TransportSink) -> Result<Box<dyn TransportConnection>> {
+        let ws = WebSocket::new(url)?;
+        ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+
+        // onopen ハンドラー
+        let on_open = {
+            let sink = sink.clone();
+            Closure::wrap(Box::new(move || {
+                sink.push(TransportEvent::Open);
+            }) as Box<dyn FnMut()>)
+        };
+        ws.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+
+        // onmessage ハンドラー
+        let on_message = {
+            let sink = sink.clone();
+            Closure::wrap(Box::new(move |event: MessageEvent| {
+                if let Some(text) = event.data().as_string() {
+                    sink.push(TransportEvent::Message(text));
+                }
+            }) as Box<dyn FnMut(MessageEvent)>)
+        };
+        ws.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        // onerror ハンドラー
+        let on_error = {
+            let sink = sink.clone();
+            Closure::wrap(Box::new(move |_event: ErrorEvent| {
+                // WebSocketのerrorイベントは詳細を持たない
+                sink.push(TransportEvent::Error("WebSocket error".to_string()));
+            }) as Box<dyn FnMut(ErrorEvent)>)
+        };
+        ws.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+        // onclose ハンドラー
+        let on_close = Closure::wrap(Box::new(move |event: CloseEvent| {
+            sink.push(TransportEvent::Closed {
+                code: event.code(),
+                reason: event.reason(),
+            });
+        }) as Box<dyn FnMut(CloseEvent)>);
+        ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        Ok(Box::new(WebSocketConnection {
+            ws,
+            _on_open: on_open,
+            _on_message: on_message,
+            _on_error: on_error,
+            _on_close: on_close,
+        }))
+    }
+}
+
+impl TransportConnection for WebSocketConnection {
+    fn send(&self, msg: &str) -> Result<()> {
+        self.ws.send_with_str(msg)?;
+        Ok(())
+    }
+
+    fn close(&self) {
+        let _ = self.ws.close();
+    }
+}
+
+impl Drop for WebSocketConnection {
+    fn drop(&mut self) {
+        // クロージャ解放後にコールバックが呼ばれないようにハンドラーを外す
+        self.ws.set_onopen(None);
+        self.ws.set_onmessage(None);
+        self.ws.set_onerror(None);
+        self.ws.set_onclose(None);
+    }
+}
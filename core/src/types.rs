@@ -1,7 +1,12 @@
 use serde::{Deserialize, Serialize};
 
+use crate::event::NostrEvent;
+use crate::relay::{AuthPolicy, ConnectionState};
+use crate::subscription::HistoryState;
+use crate::supervisor::DisconnectReason;
+
 /// UI表示用の行データ
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UiRow {
     pub id: String,
     pub kind: u16,
@@ -9,17 +14,121 @@ pub struct UiRow {
     pub created_at: i64,
     pub content: String,
     pub image_url: Option<String>,
+    /// イベントが属する会話（チャンネルまたはDM）
+    #[serde(default)]
+    pub scope: Option<Scope>,
+}
+
+impl UiRow {
+    /// イベントから作成
+    pub fn from_event(event: &NostrEvent, scope: Option<Scope>) -> Self {
+        Self {
+            id: event.id.clone(),
+            kind: event.kind,
+            pubkey: event.pubkey.clone(),
+            created_at: event.created_at,
+            content: event.content.clone(),
+            image_url: None,
+            scope,
+        }
+    }
+
+    /// Storageのイベントから作成
+    pub fn from_stored(event: &StoredEvent, scope: Option<Scope>) -> Self {
+        Self {
+            id: event.id.clone(),
+            kind: event.kind,
+            pubkey: event.pubkey.clone(),
+            created_at: event.created_at,
+            content: event.content.clone(),
+            image_url: None,
+            scope,
+        }
+    }
+}
+
+/// 会話の単位
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Scope {
+    /// チャンネル（チャンネルID）
+    Channel(String),
+    /// DMスレッド（相手のpubkey）
+    Dm(String),
+}
+
+impl Scope {
+    /// Storageで既読位置などを引くキー（`channel:<ID>`、`dm:<pubkey>`）
+    pub fn storage_key(&self) -> String {
+        match self {
+            Scope::Channel(id) => format!("channel:{}", id),
+            Scope::Dm(peer) => format!("dm:{}", peer),
+        }
+    }
+}
+
+/// CoreHandleからUIへの通知（`CoreHandle::poll_events`または`CoreEvents`で受け取る）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoreEvent {
+    /// イベントを受信した
+    EventReceived(UiRow),
+    /// Relayの接続状態が変わった（切断時は`reason`に切断理由）
+    RelayStateChanged { url: String, state: ConnectionState, reason: Option<DisconnectReason> },
+    /// 送信したイベントの配送状況が変わった
+    OutboxUpdated { event_id: String, summary: DeliverySummary },
+    /// RelayからのNOTICE
+    Notice { url: String, message: String },
+    /// Relayが会話の保存済みイベントを送り終えた（EOSE）
+    EndOfStoredEvents { url: String, scope: Scope },
+    /// 会話の過去ログをRelayに問い合わせ始めた
+    HistoryLoading(Scope),
+    /// 会話の過去ログの問い合わせが終わった
+    HistoryLoaded(Scope),
+    /// 会話の最初まで取得した
+    HistoryExhausted(Scope),
+    /// 不正なイベントを送ってきたRelayを読み込みから外した（Relay設定は変えない）
+    RelayDemoted { url: String },
+    /// Relayが認証を求めており、ユーザーの確認を待っている
+    AuthRequested { url: String },
+    /// COUNTの結果（応答したRelayの最大値）
+    CountUpdated { sub_id: String, count: u64 },
+    /// 処理中のエラー
+    Error(String),
+    /// 会話の保持中のイベント（`CoreClient::load_conversation`への応答）
+    ConversationLoaded { scope: Scope, rows: Vec<UiRow>, history: HistoryState },
+    /// Relay設定が変わった
+    RelaysChanged(Vec<RelayConfig>),
 }
 
 /// 送信キューのアイテム
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutboxItem {
     pub req_id: String,
-    pub event_json: String,
+    /// イベントID（古いデータでは空）
+    #[serde(default)]
+    pub event_id: String,
+    /// 送信するイベント（StorageにはJSON文字列として保存）
+    #[serde(rename = "event_json", with = "crate::event::json_string")]
+    pub event: NostrEvent,
     pub status: OutboxStatus,
     pub last_try_at: i64,
+    /// 古い形式の再送回数（残すだけで使わない。再送はRelayごとの`RelayReceipt::attempts`で数える）
     pub retry_count: u32,
     pub error: Option<String>,
+    /// Relayごとの配送記録
+    #[serde(default)]
+    pub receipts: Vec<RelayReceipt>,
+}
+
+impl OutboxItem {
+    /// 受理したRelayの数
+    pub fn accepted_count(&self) -> usize {
+        self.receipts.iter().filter(|r| r.status == DeliveryStatus::Accepted).count()
+    }
+
+    /// 指定Relayの配送記録
+    pub fn receipt(&self, relay_url: &str) -> Option<&RelayReceipt> {
+        self.receipts.iter().find(|r| r.relay_url == relay_url)
+    }
 }
 
 /// 送信ステータス
@@ -31,6 +140,44 @@ pub enum OutboxStatus {
     Error,
 }
 
+/// Relayごとの配送状態
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryStatus {
+    /// 送信済み、OK待ち
+    Pending,
+    /// 受理された
+    Accepted,
+    /// 拒否された（`permanent`なら再送しない）
+    Rejected { reason: String, permanent: bool },
+    /// OKが返ってこなかった
+    TimedOut,
+    /// `auth-required:`で拒否され、認証を待っている（認証後に再送する）
+    AwaitingAuth,
+}
+
+/// Relayごとの配送記録
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayReceipt {
+    pub relay_url: String,
+    pub status: DeliveryStatus,
+    /// 最後に送信した時刻
+    pub sent_at: i64,
+    /// 送信回数
+    pub attempts: u32,
+}
+
+/// 配送状況（UI表示用）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliverySummary {
+    /// 受理したRelayの数
+    pub accepted: usize,
+    /// 送信先のRelayの数
+    pub total: usize,
+    /// 必要数のRelayに受理された
+    pub delivered: bool,
+    pub receipts: Vec<RelayReceipt>,
+}
+
 /// Storage用のイベント
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoredEvent {
@@ -43,16 +190,121 @@ pub struct StoredEvent {
     pub sig: String,
     pub relay_hint: Option<String>,
     pub inserted_at: i64,
+    /// このイベントを受信したRelay
+    #[serde(default)]
+    pub seen_on: Vec<String>,
+    /// タグ検索用のキー（`StoredEvent::tag_keys`）
+    #[serde(default)]
+    pub tag_keys: Vec<String>,
 }
 
-/// Storage検索フィルター
-#[derive(Debug, Clone, Default)]
-pub struct StorageFilter {
-    pub kinds: Option<Vec<u16>>,
-    pub authors: Option<Vec<String>>,
-    pub since: Option<i64>,
-    pub until: Option<i64>,
-    pub limit: Option<u32>,
+impl StoredEvent {
+    /// イベントから作成
+    pub fn new(event: NostrEvent, inserted_at: i64) -> Self {
+        Self {
+            id: event.id,
+            kind: event.kind,
+            pubkey: event.pubkey,
+            created_at: event.created_at,
+            content: event.content,
+            sig: event.sig,
+            relay_hint: None,
+            inserted_at,
+            seen_on: Vec::new(),
+            tag_keys: Self::tag_keys(&event.tags),
+            tags: event.tags,
+        }
+    }
+
+    /// 1文字のタグ名と値から作るタグ検索用のキー（`e:<値>`の形）
+    pub fn tag_keys(tags: &[Vec<String>]) -> Vec<String> {
+        let mut keys: Vec<String> = tags
+            .iter()
+            .filter_map(|tag| match (tag.first(), tag.get(1)) {
+                (Some(name), Some(value)) if name.chars().count() == 1 => Some(Self::tag_key(name, value)),
+                _ => None,
+            })
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    /// タグ検索用のキー
+    pub fn tag_key(name: &str, value: &str) -> String {
+        format!("{}:{}", name, value)
+    }
+
+    /// 新しい版で置き換えるイベントのアドレス（`event::replace_key`）
+    pub fn replace_key(&self) -> Option<String> {
+        crate::event::replace_key(self.kind, &self.pubkey, &self.tags)
+    }
+
+    /// 保存されたイベント本体
+    pub fn event(&self) -> NostrEvent {
+        NostrEvent {
+            id: self.id.clone(),
+            pubkey: self.pubkey.clone(),
+            created_at: self.created_at,
+            kind: self.kind,
+            tags: self.tags.clone(),
+            content: self.content.clone(),
+            sig: self.sig.clone(),
+        }
+    }
+
+    /// 受信したRelayを記録。最初のRelayをrelay_hintとする。追加したらtrueを返す
+    pub fn add_seen_on(&mut self, relay_url: &str) -> bool {
+        if self.seen_on.iter().any(|r| r == relay_url) {
+            return false;
+        }
+        self.seen_on.push(relay_url.to_string());
+        if self.relay_hint.is_none() {
+            self.relay_hint = Some(relay_url.to_string());
+        }
+        true
+    }
+}
+
+/// Storage検索フィルター（購読と同じフィルターを使う）
+pub type StorageFilter = crate::filter::Filter;
+
+/// Relay設定
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayConfig {
+    pub url: String,
+    /// 購読（REQ）に使う
+    pub read: bool,
+    /// 送信（EVENT）に使う
+    pub write: bool,
+    /// NIP-42 認証ポリシー
+    #[serde(default)]
+    pub auth: AuthPolicy,
+    /// 同時に開く購読数の上限（NIP-11 の`limitation.max_subscriptions`、なければ無制限）
+    ///
+    /// Relay情報は自動では取得しないので、`CoreHandle::set_max_subscriptions`で設定する。
+    #[serde(default)]
+    pub max_subscriptions: Option<usize>,
+}
+
+impl RelayConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            read: true,
+            write: true,
+            auth: AuthPolicy::default(),
+            max_subscriptions: None,
+        }
+    }
+}
+
+/// Storageの使用量（バイト）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageUsage {
+    pub usage: u64,
+    /// 使える上限（分からなければNone）
+    pub quota: Option<u64>,
 }
 
 /// DMスレッド情報
@@ -63,20 +315,3 @@ pub struct DmThread {
     pub last_msg_at: i64,
 }
 
-/// 時間窓
-#[derive(Debug, Clone, Copy)]
-pub struct TimeWindow {
-    pub since: i64,
-    pub until: Option<i64>,
-}
-
-impl TimeWindow {
-    pub fn new(since: i64) -> Self {
-        Self { since, until: None }
-    }
-
-    pub fn extend(&mut self, additional_seconds: i64) {
-        self.since -= additional_seconds;
-    }
-}
-
@@ -0,0 +1,69 @@
+use nostr::{Event, JsonUtil};
+
+use crate::error::{CoreError, Result};
+use crate::event::NostrEvent;
+
+/// イベントのIDハッシュとSchnorr署名を検証
+pub fn verify_event(event: &NostrEvent) -> Result<()> {
+    let event = Event::from_json(event.to_json())
+        .map_err(|e| CoreError::InvalidEvent(format!("Malformed event: {}", e)))?;
+    if !event.verify_id() {
+        return Err(CoreError::InvalidEvent(format!("Invalid id: {}", event.id)));
+    }
+    if !event.verify_signature() {
+        return Err(CoreError::InvalidEvent(format!("Invalid signature: {}", event.id)));
+    }
+    Ok(())
+}
+
+/// テスト用: 署名済みイベントのJSONを作る
+#[cfg(test)]
+pub(crate) fn signed_event_json(keys: &nostr::Keys, kind: u16, tags: &[&[&str]], content: &str, created_at: i64) -> String {
+    use nostr::{EventBuilder, Kind, Tag, Timestamp};
+
+    let tags: Vec<Tag> = tags.iter().map(|t| Tag::parse(t.iter().copied()).unwrap()).collect();
+    EventBuilder::new(Kind::from(kind), content)
+        .tags(tags)
+        .custom_created_at(Timestamp::from(created_at as u64))
+        .sign_with_keys(keys)
+        .unwrap()
+        .as_json()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::internal::InternalSigner;
+    use crate::signer::{Signer, UnsignedEvent};
+
+    async fn signed() -> NostrEvent {
+        let signer = InternalSigner::generate("").await.unwrap();
+        let unsigned = UnsignedEvent {
+            kind: 1,
+            content: "hello".to_string(),
+            tags: vec![],
+            created_at: 0,
+        };
+        signer.sign_event(unsigned).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_valid_event() {
+        assert!(verify_event(&signed().await).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_tampered_content_fails() {
+        let mut event = signed().await;
+        event.content = "forged".to_string();
+        assert!(verify_event(&event).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tampered_signature_fails() {
+        let mut event = signed().await;
+        let forged = format!("{}{}", if event.sig.starts_with('0') { "1" } else { "0" }, &event.sig[1..]);
+        event.sig = forged;
+        assert!(verify_event(&event).is_err());
+    }
+}
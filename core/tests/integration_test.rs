@@ -22,12 +22,24 @@ mod tests {
     fn test_relay_message_parse() {
         use rustr_core::relay::RelayMessage;
         
-        let json = r#"["EVENT","sub1",{"id":"abc","kind":1}]"#;
-        let msg = RelayMessage::parse(json).unwrap();
+        let json = format!(
+            r#"["EVENT","sub1",{{"id":"{}","pubkey":"{}","created_at":1,"kind":1,"tags":[],"content":"","sig":"{}"}}]"#,
+            "a".repeat(64),
+            "b".repeat(64),
+            "c".repeat(128),
+        );
+        let msg = RelayMessage::parse(&json).unwrap();
         match msg {
             RelayMessage::Event { sub_id, .. } => assert_eq!(sub_id, "sub1"),
             _ => panic!("Expected EVENT message"),
         }
+
+        // 形式の不正なイベントは弾く
+        let json = r#"["EVENT","sub1",{"id":"abc","kind":1}]"#;
+        assert!(matches!(
+            RelayMessage::parse(json),
+            Err(rustr_core::CoreError::InvalidEvent(_))
+        ));
     }
     
     #[test]
@@ -0,0 +1,77 @@
+// ネイティブTransportの統合テスト
+// ローカルにWebSocketサーバーを立ててRelayConnectionから接続する
+
+#![cfg(not(target_arch = "wasm32"))]
+
+// クレート名が`core`なので、明示的にエイリアスを使用
+extern crate core as rustr_core;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
+
+use rustr_core::clock::SystemClock;
+use rustr_core::relay::{RelayConnection, RelayMessage};
+use rustr_core::transport::native::TokioTransport;
+
+/// REQを受け取るとEOSEを返すだけのRelay
+async fn spawn_echo_relay() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        while let Some(Ok(msg)) = ws.next().await {
+            if let Message::Text(text) = msg {
+                let req: Vec<serde_json::Value> = serde_json::from_str(&text).unwrap();
+                let eose = serde_json::json!(["EOSE", req[1]]).to_string();
+                ws.send(Message::text(eose)).await.unwrap();
+            }
+        }
+    });
+    format!("ws://{}", addr)
+}
+
+/// 条件を満たすまでポーリング
+async fn wait_until(relay: &mut RelayConnection, mut cond: impl FnMut(&mut RelayConnection) -> bool) {
+    for _ in 0..200 {
+        relay.poll();
+        if cond(relay) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("timed out waiting for relay");
+}
+
+// `#[tokio::test]`は`::core`を参照するため、クレート名`core`と衝突する。
+// ランタイムを手動で構築する
+#[test]
+fn test_native_transport_round_trip() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(native_transport_round_trip());
+}
+
+async fn native_transport_round_trip() {
+    let url = spawn_echo_relay().await;
+    let mut relay = RelayConnection::new(url, Arc::new(TokioTransport), Arc::new(SystemClock));
+
+    relay.connect().await.unwrap();
+    wait_until(&mut relay, |r| r.is_connected()).await;
+
+    relay.send(r#"["REQ","sub1",{"kinds":[1]}]"#).await.unwrap();
+
+    let mut received = Vec::new();
+    wait_until(&mut relay, |r| {
+        received.extend(r.drain_messages());
+        !received.is_empty()
+    })
+    .await;
+    assert!(matches!(&received[0], RelayMessage::Eose { sub_id } if sub_id == "sub1"));
+}
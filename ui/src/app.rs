@@ -1,17 +1,22 @@
 use eframe::egui;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::rc::Rc;
 use std::cell::RefCell;
 
-use core::CoreHandle;
+use core::actor::{self, ActorMailbox};
+use core::{CoreClient, CoreEvents, CoreHandle};
+use core::relay::ConnectionState;
+use core::supervisor::DisconnectReason;
+use core::types::{CoreEvent, Scope};
 use core::storage::indexeddb::IndexedDbStorage;
 use core::signer::internal::InternalSigner;
 use core::signer::Signer;
 
-use crate::timeline::Timeline;
+use crate::timeline::{Timeline, TimelineAction};
 use crate::composer::Composer;
 use crate::onboarding::{Onboarding, OnboardingResult};
-use crate::settings::SettingsView;
+use crate::settings::{SettingsAction, SettingsView};
 use crate::i18n::I18n;
 
 #[cfg(feature = "debug-test")]
@@ -42,8 +47,11 @@ pub struct NostrApp {
     settings: SettingsView,
     i18n: I18n,
     
-    // Core (Rc<RefCell<>>でUIから変更可能にする)
-    core: Rc<RefCell<Option<CoreHandle>>>,
+    // Core（別タスクで動くCoreActorにコマンドを送る）
+    core: CoreClient,
+    core_events: CoreEvents,
+    /// 初期化後にCoreActorに渡すチャネル
+    core_mailbox: Option<ActorMailbox>,
     storage: Rc<RefCell<Option<Arc<IndexedDbStorage>>>>,
     
     // UI状態
@@ -52,10 +60,22 @@ pub struct NostrApp {
     show_channel_create: bool,
     channel_name_input: String,
     channel_about_input: String,
+    /// DMを開く相手の公開鍵の入力
+    dm_peer_input: String,
     sidebar_tab: SidebarTab,  // Public / DMs タブ
     current_channel: Option<String>,
     current_dm_peer: Option<String>,
     error_message: Option<String>,
+    /// Relayからのお知らせ（NOTICEなど）
+    notice: Option<String>,
+    /// 認証の確認待ちのRelay（承認か拒否を選ぶまで表示する）
+    auth_requests: Vec<String>,
+    /// 非同期の操作が終わったときのお知らせ（次のtickで`notice`に移す）
+    pending_notice: Rc<RefCell<Option<String>>>,
+    /// 作成が終わったチャンネル（次のtickで開く）
+    pending_channel: Rc<RefCell<Option<String>>>,
+    /// Relayごとの接続状態と最後の切断理由
+    relay_states: HashMap<String, (ConnectionState, Option<DisconnectReason>)>,
     
     // デバッグテスト
     #[cfg(feature = "debug-test")]
@@ -64,6 +84,12 @@ pub struct NostrApp {
 
 impl NostrApp {
     pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+        Self::default()
+    }
+}
+
+impl Default for NostrApp {
+    fn default() -> Self {
         let state = AppState::Onboarding;
         
         #[cfg(feature = "debug-test")]
@@ -74,6 +100,8 @@ impl NostrApp {
             log::info!("🧪 Debug test mode enabled!");
         }
         
+        let (core, core_events, core_mailbox) = actor::channel();
+        
         Self {
             state,
             onboarding: Onboarding::new(),
@@ -81,30 +109,42 @@ impl NostrApp {
             composer: Composer::new(),
             settings: SettingsView::new(),
             i18n: I18n::default(),
-            core: Rc::new(RefCell::new(None)),
+            core,
+            core_events,
+            core_mailbox: Some(core_mailbox),
             storage: Rc::new(RefCell::new(None)),
             show_composer: false,
             show_settings: false,
             show_channel_create: false,
             channel_name_input: String::new(),
             channel_about_input: String::new(),
+            dm_peer_input: String::new(),
             sidebar_tab: SidebarTab::Public,
             current_channel: None,
             current_dm_peer: None,
             error_message: None,
+            notice: None,
+            auth_requests: Vec::new(),
+            pending_notice: Rc::new(RefCell::new(None)),
+            pending_channel: Rc::new(RefCell::new(None)),
+            relay_states: HashMap::new(),
             #[cfg(feature = "debug-test")]
             debug_test,
         }
     }
-    
+}
+
+impl NostrApp {
     /// オンボーディング完了時の処理
     fn complete_onboarding(&mut self, result: OnboardingResult) {
         self.state = AppState::Main;
         
-        let core_ref = self.core.clone();
+        let Some(mailbox) = self.core_mailbox.take() else {
+            return;
+        };
         let storage_ref = self.storage.clone();
         
-        // CoreHandleを初期化（非同期）
+        // CoreHandleを初期化し、CoreActorとして動かす（初期化中に送ったコマンドはその後に処理される）
         wasm_bindgen_futures::spawn_local(async move {
             match Self::init_core_from_onboarding(result).await {
                 Ok((mut core, storage)) => {
@@ -115,8 +155,8 @@ impl NostrApp {
                         log::error!("Failed to connect to relays: {:?}", e);
                     }
                     
-                    *core_ref.borrow_mut() = Some(core);
                     *storage_ref.borrow_mut() = Some(storage);
+                    core.into_actor(mailbox).run().await;
                 }
                 Err(e) => {
                     log::error!("Failed to initialize core: {:?}", e);
@@ -152,7 +192,7 @@ impl NostrApp {
             }
         };
         
-        // デフォルトRelay一覧
+        // デフォルトRelay一覧（保存済みの一覧がない初回のみ使われる）
         let relay_urls = vec![
             "wss://x.kojira.io".to_string(),
             "wss://yabu.me".to_string(),
@@ -168,19 +208,23 @@ impl NostrApp {
     
     /// チャンネルを開く
     fn open_channel(&mut self, channel_id: String) {
+        let previous = self.current_scope().filter(|s| *s != Scope::Channel(channel_id.clone()));
         self.current_channel = Some(channel_id.clone());
         self.current_dm_peer = None;
-        self.timeline.load_channel(&channel_id);
+        self.load_timeline(Scope::Channel(channel_id.clone()));
         
-        // CoreHandleでチャンネルを購読
-        let core_ref = self.core.clone();
+        // CoreHandleでチャンネルを購読（前の画面の購読は閉じる）
+        let core = self.core.clone();
         let channel_id_clone = channel_id.clone();
         wasm_bindgen_futures::spawn_local(async move {
-            if let Some(core) = core_ref.borrow_mut().as_mut() {
-                if let Err(e) = core.open_channel(&channel_id_clone).await {
-                    log::error!("Failed to open channel: {:?}", e);
+            if let Some(previous) = previous {
+                if let Err(e) = core.close_scope(&previous).await {
+                    log::error!("Failed to close subscription: {:?}", e);
                 }
             }
+            if let Err(e) = core.open_channel(&channel_id_clone).await {
+                log::error!("Failed to open channel: {:?}", e);
+            }
         });
         
         log::info!("Opened channel: {}", channel_id);
@@ -188,41 +232,96 @@ impl NostrApp {
     
     /// DMを開く
     fn open_dm(&mut self, peer: String) {
+        let previous = self.current_scope().filter(|s| *s != Scope::Dm(peer.clone()));
         self.current_dm_peer = Some(peer.clone());
         self.current_channel = None;
-        self.timeline.load_dm(&peer);
+        self.load_timeline(Scope::Dm(peer.clone()));
         
-        // CoreHandleでDMを購読
-        let core_ref = self.core.clone();
+        // CoreHandleでDMを購読（前の画面の購読は閉じる）
+        let core = self.core.clone();
         let peer_clone = peer.clone();
         wasm_bindgen_futures::spawn_local(async move {
-            if let Some(core) = core_ref.borrow_mut().as_mut() {
-                if let Err(e) = core.open_dm(&peer_clone).await {
-                    log::error!("Failed to open DM: {:?}", e);
+            if let Some(previous) = previous {
+                if let Err(e) = core.close_scope(&previous).await {
+                    log::error!("Failed to close subscription: {:?}", e);
                 }
             }
+            if let Err(e) = core.open_dm(&peer_clone).await {
+                log::error!("Failed to open DM: {:?}", e);
+            }
         });
         
         log::info!("Opened DM with: {}", peer);
     }
     
+    /// Coreからの通知をUIに反映
+    fn handle_core_event(&mut self, event: CoreEvent) {
+        match event {
+            CoreEvent::EventReceived(row) => self.timeline.add_event(row),
+            CoreEvent::RelayStateChanged { url, state, reason } => {
+                self.relay_states.insert(url, (state, reason));
+            }
+            CoreEvent::OutboxUpdated { event_id, summary } => self.timeline.set_delivery(event_id, summary),
+            CoreEvent::Notice { url, message } => {
+                self.notice = Some(format!("{}: {}", url, message));
+            }
+            CoreEvent::EndOfStoredEvents { scope, .. } => self.timeline.on_end_of_stored_events(&scope),
+            CoreEvent::HistoryLoading(scope) => self.timeline.on_history_loading(&scope, true),
+            CoreEvent::HistoryLoaded(scope) => self.timeline.on_history_loading(&scope, false),
+            CoreEvent::HistoryExhausted(scope) => self.timeline.on_history_exhausted(&scope),
+            CoreEvent::RelayDemoted { url } => {
+                self.notice = Some(self.i18n.status_relay_demoted(&url));
+            }
+            CoreEvent::AuthRequested { url } => {
+                if !self.auth_requests.contains(&url) {
+                    self.auth_requests.push(url);
+                }
+            }
+            CoreEvent::CountUpdated { sub_id, count } => {
+                log::info!("COUNT {}: {}", sub_id, count);
+            }
+            CoreEvent::Error(message) => {
+                self.error_message = Some(message);
+            }
+            CoreEvent::ConversationLoaded { scope, rows, history } => {
+                self.timeline.on_conversation_loaded(&scope, rows, history);
+            }
+            CoreEvent::RelaysChanged(relays) => self.settings.set_relays(relays),
+        }
+    }
+    
+    /// 表示中の会話
+    fn current_scope(&self) -> Option<Scope> {
+        match (&self.current_channel, &self.current_dm_peer) {
+            (Some(channel_id), _) => Some(Scope::Channel(channel_id.clone())),
+            (None, Some(peer)) => Some(Scope::Dm(peer.clone())),
+            (None, None) => None,
+        }
+    }
+    
+    /// タイムラインを会話に切り替え、CoreHandleが保持するイベントを要求する
+    fn load_timeline(&mut self, scope: Scope) {
+        self.timeline.load_scope(scope.clone());
+        if let Err(e) = self.core.load_conversation(scope) {
+            log::error!("Failed to load conversation: {:?}", e);
+        }
+    }
+    
     /// メッセージ送信
     fn send_message(&mut self, content: String) {
-        let core_ref = self.core.clone();
+        let core = self.core.clone();
         
         if let Some(channel_id) = &self.current_channel {
             let channel_id = channel_id.clone();
             log::info!("Sending to channel {}: {}", channel_id, content);
             
             wasm_bindgen_futures::spawn_local(async move {
-                if let Some(core) = core_ref.borrow_mut().as_mut() {
-                    match core.send_public(&channel_id, &content).await {
-                        Ok(event_id) => {
-                            log::info!("Message sent: {}", event_id);
-                        }
-                        Err(e) => {
-                            log::error!("Failed to send message: {:?}", e);
-                        }
+                match core.send_public(&channel_id, &content).await {
+                    Ok(event_id) => {
+                        log::info!("Message sent: {}", event_id);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to send message: {:?}", e);
                     }
                 }
             });
@@ -231,14 +330,12 @@ impl NostrApp {
             log::info!("Sending DM to {}: {}", peer, content);
             
             wasm_bindgen_futures::spawn_local(async move {
-                if let Some(core) = core_ref.borrow_mut().as_mut() {
-                    match core.send_dm(&peer, &content).await {
-                        Ok(event_id) => {
-                            log::info!("DM sent: {}", event_id);
-                        }
-                        Err(e) => {
-                            log::error!("Failed to send DM: {:?}", e);
-                        }
+                match core.send_dm(&peer, &content).await {
+                    Ok(event_id) => {
+                        log::info!("DM sent: {}", event_id);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to send DM: {:?}", e);
                     }
                 }
             });
@@ -250,28 +347,20 @@ impl NostrApp {
 
     /// 定期処理（tick）
     fn tick(&mut self) {
-        // try_borrow_mut()を使って、借用できない場合はスキップ
-        if let Ok(mut core_borrow) = self.core.try_borrow_mut() {
-            if let Some(core) = core_borrow.as_mut() {
-                // poll_eventsを実行
-                let events = core.poll_events(50);
-                for event in events {
-                    self.timeline.add_event(event);
-                }
-            }
+        // 届いている通知をUIに反映
+        while let Ok(event) = self.core_events.try_recv() {
+            self.handle_core_event(event);
+        }
+        if let Some(notice) = self.pending_notice.borrow_mut().take() {
+            self.notice = Some(notice);
+        }
+        let created_channel = self.pending_channel.borrow_mut().take();
+        if let Some(channel_id) = created_channel {
+            self.open_channel(channel_id);
         }
         
-        // 非同期でtick()を実行（借用できない場合はスキップ）
-        let core_ref = self.core.clone();
-        wasm_bindgen_futures::spawn_local(async move {
-            if let Ok(mut core_borrow) = core_ref.try_borrow_mut() {
-                if let Some(core) = core_borrow.as_mut() {
-                    if let Err(e) = core.tick().await {
-                        log::error!("Tick error: {:?}", e);
-                    }
-                }
-            }
-        });
+        // CoreActorにtickを依頼（続けて届いたものはまとめて1回になる）
+        self.core.tick();
     }
     
     // === デバッグAPI ===
@@ -311,26 +400,22 @@ impl NostrApp {
     
     #[cfg(feature = "debug-test")]
     pub fn debug_create_channel(&mut self, name: String, about: String) {
-        let core_ref = self.core.clone();
+        let core = self.core.clone();
         
         wasm_bindgen_futures::spawn_local(async move {
-            if let Ok(mut core_borrow) = core_ref.try_borrow_mut() {
-                if let Some(core) = core_borrow.as_mut() {
-                    match core.create_channel(&name, &about, "").await {
-                        Ok(id) => {
-                            log::info!("✅ Channel created: {}", id);
-                            // チャンネルIDをローカルストレージに保存
-                            if let Some(window) = web_sys::window() {
-                                if let Ok(Some(storage)) = window.local_storage() {
-                                    let _ = storage.set_item("debug_channel_id", &id);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            log::error!("Failed to create channel: {:?}", e);
+            match core.create_channel(&name, &about, "").await {
+                Ok(id) => {
+                    log::info!("✅ Channel created: {}", id);
+                    // チャンネルIDをローカルストレージに保存
+                    if let Some(window) = web_sys::window() {
+                        if let Ok(Some(storage)) = window.local_storage() {
+                            let _ = storage.set_item("debug_channel_id", &id);
                         }
                     }
                 }
+                Err(e) => {
+                    log::error!("Failed to create channel: {:?}", e);
+                }
             }
         });
     }
@@ -508,8 +593,23 @@ impl NostrApp {
                     if ui.button(self.i18n.button_settings()).clicked() {
                         self.show_settings = !self.show_settings;
                     }
+                    self.show_relay_status(ui);
                 });
             });
+            
+            // エラーとお知らせ（クリックで閉じる）
+            if let Some(message) = &self.error_message {
+                let text = egui::RichText::new(format!("⚠ {}", message)).color(ui.visuals().error_fg_color);
+                if ui.add(egui::Label::new(text).sense(egui::Sense::click())).clicked() {
+                    self.error_message = None;
+                }
+            }
+            if let Some(notice) = &self.notice {
+                if ui.add(egui::Label::new(egui::RichText::new(notice).weak()).sense(egui::Sense::click())).clicked() {
+                    self.notice = None;
+                }
+            }
+            self.show_auth_requests(ui);
         });
         
         // 左サイドバー（チャンネル/DM一覧）
@@ -543,7 +643,9 @@ impl NostrApp {
                 .resizable(true)
                 .default_width(500.0)
                 .show(ctx, |ui| {
-                    self.settings.show(ctx, ui, &mut self.i18n);
+                    if let Some(action) = self.settings.show(ctx, ui, &mut self.i18n) {
+                        self.apply_settings_action(action);
+                    }
                     
                     ui.add_space(10.0);
                     if ui.button(self.i18n.button_close()).clicked() {
@@ -587,11 +689,133 @@ impl NostrApp {
         }
         
         // タイムライン（中央）
-        egui::CentralPanel::default().show(ctx, |ui| {
-            self.timeline.show(ui, &self.i18n);
+        let action = egui::CentralPanel::default()
+            .show(ctx, |ui| self.timeline.show(ui, &self.i18n))
+            .inner;
+        if let Some(action) = action {
+            self.apply_timeline_action(action);
+        }
+    }
+    
+    /// Relayの接続状況（接続中の数 / 全体）
+    fn show_relay_status(&self, ui: &mut egui::Ui) {
+        let connected = self.relay_states.values().filter(|(s, _)| *s == ConnectionState::Connected).count();
+        let mut urls: Vec<_> = self.relay_states.iter().collect();
+        urls.sort_by(|a, b| a.0.cmp(b.0));
+        let details = urls
+            .into_iter()
+            .map(|(url, (state, reason))| match reason {
+                Some(reason) => format!("{:?}: {} ({:?})", state, url, reason),
+                None => format!("{:?}: {}", state, url),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        ui.label(self.i18n.status_relays(connected, self.relay_states.len()))
+            .on_hover_text(details);
+    }
+    
+    /// 認証の確認待ちのRelayと、承認・拒否のボタン
+    fn show_auth_requests(&mut self, ui: &mut egui::Ui) {
+        let mut answered = None;
+        for url in &self.auth_requests {
+            ui.horizontal(|ui| {
+                ui.label(self.i18n.status_auth_requested(url));
+                if ui.button(self.i18n.button_auth_approve()).clicked() {
+                    answered = Some((url.clone(), true));
+                }
+                if ui.button(self.i18n.button_auth_deny()).clicked() {
+                    answered = Some((url.clone(), false));
+                }
+            });
+        }
+        if let Some((url, approve)) = answered {
+            self.answer_auth(url, approve);
+        }
+    }
+    
+    /// 確認待ちの認証を承認または拒否する
+    fn answer_auth(&mut self, url: String, approve: bool) {
+        self.auth_requests.retain(|u| *u != url);
+        if !approve {
+            if let Err(e) = self.core.deny_auth(&url) {
+                log::error!("Failed to deny AUTH for {}: {:?}", url, e);
+            }
+            return;
+        }
+        
+        let core = self.core.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(e) = core.approve_auth(&url).await {
+                log::error!("Failed to authenticate to {}: {:?}", url, e);
+            }
         });
     }
     
+    /// タイムラインの操作をCoreに反映
+    fn apply_timeline_action(&mut self, action: TimelineAction) {
+        let core = self.core.clone();
+        
+        wasm_bindgen_futures::spawn_local(async move {
+            match action {
+                TimelineAction::LoadOlder(scope, limit) => {
+                    if let Err(e) = core.load_older(&scope, limit).await {
+                        log::error!("Failed to load older messages: {:?}", e);
+                    }
+                }
+            }
+        });
+    }
+    
+    /// 設定画面の操作をCoreに反映
+    fn apply_settings_action(&mut self, action: SettingsAction) {
+        let core = self.core.clone();
+        let i18n = I18n::new(*self.i18n.language());
+        let pending_notice = self.pending_notice.clone();
+        
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = match action {
+                SettingsAction::AddRelay(url) => core.add_relay(&url).await,
+                SettingsAction::RemoveRelay(url) => core.remove_relay(&url).await,
+                SettingsAction::SetRelayPolicy { url, read, write } => {
+                    core.set_relay_policy(&url, read, write).await
+                }
+                SettingsAction::SetAuthPolicy { url, policy } => core.set_auth_policy(&url, policy).await,
+                SettingsAction::PublishRelayList => core.publish_relay_list().await.map(|_| ()),
+                SettingsAction::ExportBackup => {
+                    let result = Self::export_backup(&core, &i18n).await;
+                    Self::report_backup(&pending_notice, &i18n, result);
+                    return;
+                }
+                SettingsAction::ImportBackup(data) => {
+                    let result = core.import_backup(data).await
+                        .map(|summary| i18n.status_backup_imported(summary.imported, summary.skipped));
+                    Self::report_backup(&pending_notice, &i18n, result);
+                    return;
+                }
+            };
+            if let Err(e) = result {
+                log::error!("Failed to apply settings action: {:?}", e);
+            }
+        });
+    }
+    
+    /// バックアップを書き出してダウンロードさせる
+    async fn export_backup(core: &CoreClient, i18n: &I18n) -> core::error::Result<String> {
+        let data = core.export_backup().await?;
+        crate::backup_file::download(&crate::backup_file::backup_file_name(), &data)?;
+        let records = data.iter().filter(|b| **b == b'\n').count();
+        Ok(i18n.status_backup_exported(records))
+    }
+    
+    /// バックアップの結果をお知らせに出す
+    fn report_backup(pending_notice: &Rc<RefCell<Option<String>>>, i18n: &I18n, result: core::error::Result<String>) {
+        let notice = result.unwrap_or_else(|e| {
+            log::error!("Backup failed: {:?}", e);
+            i18n.status_backup_failed(&e.to_string())
+        });
+        *pending_notice.borrow_mut() = Some(notice);
+    }
+    
     /// チャンネル作成ダイアログ
     fn show_channel_create_dialog(&mut self, ctx: &egui::Context) {
         egui::Window::new(self.i18n.channel_create_title())
@@ -631,21 +855,18 @@ impl NostrApp {
     fn create_new_channel(&mut self) {
         let name = self.channel_name_input.clone();
         let about = self.channel_about_input.clone();
-        let core_ref = self.core.clone();
+        let core = self.core.clone();
+        let pending_channel = self.pending_channel.clone();
         
         wasm_bindgen_futures::spawn_local(async move {
-            if let Some(core) = core_ref.borrow_mut().as_mut() {
-                match core.create_channel(&name, &about, "").await {
-                    Ok(channel_id) => {
-                        log::info!("✅ Channel created: {}", channel_id);
-                        // チャンネルを開く
-                        if let Err(e) = core.open_channel(&channel_id).await {
-                            log::error!("Failed to open channel: {:?}", e);
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("Failed to create channel: {:?}", e);
-                    }
+            match core.create_channel(&name, &about, "").await {
+                Ok(channel_id) => {
+                    log::info!("✅ Channel created: {}", channel_id);
+                    // 次のtickでタイムラインごとチャンネルを開く
+                    *pending_channel.borrow_mut() = Some(channel_id);
+                }
+                Err(e) => {
+                    log::error!("Failed to create channel: {:?}", e);
                 }
             }
         });
@@ -674,9 +895,86 @@ impl NostrApp {
     /// DM一覧を表示
     fn show_dm_list(&mut self, ui: &mut egui::Ui) {
         ui.vertical(|ui| {
+            // 公開鍵を指定してDMを開く
+            ui.add(egui::TextEdit::singleline(&mut self.dm_peer_input).hint_text(self.i18n.dm_peer_placeholder()));
+            let peer = self.dm_peer_input.trim().to_string();
+            if ui.add_enabled(!peer.is_empty(), egui::Button::new(self.i18n.dm_open_button())).clicked() {
+                self.dm_peer_input.clear();
+                self.open_dm(peer);
+            }
+            
+            ui.separator();
+            
             // TODO: ストレージからDM一覧を取得して表示
             // 現在は仮実装
             ui.label(self.i18n.dm_list_empty());
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::subscription::HistoryState;
+    use core::types::UiRow;
+
+    fn row(id: &str, created_at: i64, scope: &Scope) -> UiRow {
+        UiRow {
+            id: id.to_string(),
+            kind: 42,
+            pubkey: "author".to_string(),
+            created_at,
+            content: id.to_string(),
+            image_url: None,
+            scope: Some(scope.clone()),
+        }
+    }
+
+    #[test]
+    fn test_created_channel_shows_its_rows() {
+        let mut app = NostrApp::default();
+        let scope = Scope::Channel("chan".to_string());
+
+        // 作成したチャンネルは次のtickでタイムラインごと開く
+        *app.pending_channel.borrow_mut() = Some("chan".to_string());
+        app.tick();
+        assert_eq!(app.current_scope(), Some(scope.clone()));
+
+        app.handle_core_event(CoreEvent::ConversationLoaded {
+            scope: scope.clone(),
+            rows: vec![row("stored", 100, &scope)],
+            history: HistoryState::default(),
+        });
+        app.handle_core_event(CoreEvent::EventReceived(row("live", 200, &scope)));
+        app.handle_core_event(CoreEvent::EventReceived(row("elsewhere", 300, &Scope::Channel("other".to_string()))));
+        assert_eq!(app.timeline.event_count(), 2);
+    }
+
+    #[test]
+    fn test_open_dm_switches_timeline() {
+        let mut app = NostrApp::default();
+        app.open_channel("chan".to_string());
+        app.handle_core_event(CoreEvent::EventReceived(row("channel", 100, &Scope::Channel("chan".to_string()))));
+
+        let scope = Scope::Dm("peer".to_string());
+        app.open_dm("peer".to_string());
+        assert_eq!(app.current_scope(), Some(scope.clone()));
+        assert_eq!(app.timeline.event_count(), 0);
+        app.handle_core_event(CoreEvent::ConversationLoaded { scope: scope.clone(), rows: Vec::new(), history: HistoryState::default() });
+        app.handle_core_event(CoreEvent::EventReceived(row("dm", 200, &scope)));
+        assert_eq!(app.timeline.event_count(), 1);
+    }
+
+    #[test]
+    fn test_auth_request_waits_for_answer() {
+        let mut app = NostrApp::default();
+        for _ in 0..2 {
+            app.handle_core_event(CoreEvent::AuthRequested { url: "wss://a".to_string() });
+        }
+        app.handle_core_event(CoreEvent::AuthRequested { url: "wss://b".to_string() });
+        assert_eq!(app.auth_requests, vec!["wss://a", "wss://b"]);
+
+        app.answer_auth("wss://a".to_string(), false);
+        assert_eq!(app.auth_requests, vec!["wss://b"]);
+    }
+}
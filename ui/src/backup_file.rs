@@ -0,0 +1,61 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+/// バックアップファイルの種類
+const BACKUP_MIME_TYPE: &str = "application/jsonl";
+
+/// バックアップファイルの名前（`rustr-backup-2024-01-31.jsonl`）
+pub fn backup_file_name() -> String {
+    let date: String = js_sys::Date::new_0().to_iso_string().into();
+    format!("rustr-backup-{}.jsonl", &date[..10])
+}
+
+/// データをファイルとしてダウンロードさせる
+pub fn download(file_name: &str, data: &[u8]) -> Result<(), JsValue> {
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or("No document")?;
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(data));
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(BACKUP_MIME_TYPE);
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+
+    let anchor: web_sys::HtmlAnchorElement = document.create_element("a")?.dyn_into()?;
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+    web_sys::Url::revoke_object_url(&url)
+}
+
+/// ファイル選択ダイアログを開き、選ばれたファイルの中身を`picked`に入れる
+///
+/// ブラウザはクリックの処理中にしかダイアログを開かないので、ボタンの処理から呼ぶ。
+pub fn pick_file(picked: Rc<RefCell<Option<Vec<u8>>>>) -> Result<(), JsValue> {
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or("No document")?;
+    let input: web_sys::HtmlInputElement = document.create_element("input")?.dyn_into()?;
+    input.set_type("file");
+    input.set_accept(".jsonl,application/jsonl");
+
+    let target = input.clone();
+    let on_change = Closure::once_into_js(move || {
+        let Some(file) = target.files().and_then(|files| files.get(0)) else {
+            return;
+        };
+        wasm_bindgen_futures::spawn_local(async move {
+            match JsFuture::from(file.array_buffer()).await {
+                Ok(buffer) => *picked.borrow_mut() = Some(js_sys::Uint8Array::new(&buffer).to_vec()),
+                Err(e) => log::error!("Failed to read {}: {:?}", file.name(), e),
+            }
+        });
+    });
+    input.set_onchange(Some(on_change.unchecked_ref()));
+    input.click();
+    Ok(())
+}
@@ -1,5 +1,6 @@
 /// 多言語対応リソース管理
 use serde::{Deserialize, Serialize};
+use core::relay::AuthPolicy;
 
 /// サポート言語
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -308,6 +309,63 @@ impl I18n {
         }
     }
     
+    pub fn timeline_delivered(&self, accepted: usize, total: usize) -> String {
+        match self.language {
+            Language::Japanese => format!("{}/{} リレーに配送済み", accepted, total),
+            Language::English => format!("Delivered to {}/{} relays", accepted, total),
+        }
+    }
+    
+    pub fn timeline_loading_older(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "以前のメッセージを読み込み中…",
+            Language::English => "Loading older messages…",
+        }
+    }
+    
+    pub fn timeline_start_of_history(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "これより前のメッセージはありません",
+            Language::English => "This is the beginning of the conversation",
+        }
+    }
+    
+    // ステータス
+    pub fn status_relays(&self, connected: usize, total: usize) -> String {
+        match self.language {
+            Language::Japanese => format!("🔌 {}/{} リレーに接続中", connected, total),
+            Language::English => format!("🔌 {}/{} relays connected", connected, total),
+        }
+    }
+    
+    pub fn status_auth_requested(&self, url: &str) -> String {
+        match self.language {
+            Language::Japanese => format!("{} が認証を求めています", url),
+            Language::English => format!("{} is asking for authentication", url),
+        }
+    }
+    
+    pub fn button_auth_approve(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "🔓 認証する",
+            Language::English => "🔓 Authenticate",
+        }
+    }
+    
+    pub fn button_auth_deny(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "拒否",
+            Language::English => "Deny",
+        }
+    }
+    
+    pub fn status_relay_demoted(&self, url: &str) -> String {
+        match self.language {
+            Language::Japanese => format!("{} から不正なイベントが続いたため、読み込みを停止しました", url),
+            Language::English => format!("Stopped reading from {} after repeated invalid events", url),
+        }
+    }
+    
     // 設定
     pub fn settings_title(&self) -> &'static str {
         match self.language {
@@ -389,6 +447,122 @@ impl I18n {
             Language::English => "Numbers: 0123456789",
         }
     }
+    
+    pub fn settings_relays(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "📡 リレー",
+            Language::English => "📡 Relays",
+        }
+    }
+    
+    pub fn settings_relay_read(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "読み込み",
+            Language::English => "Read",
+        }
+    }
+    
+    pub fn settings_relay_write(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "書き込み",
+            Language::English => "Write",
+        }
+    }
+    
+    pub fn settings_relay_auth(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "認証",
+            Language::English => "Auth",
+        }
+    }
+    
+    pub fn settings_auth_policy(&self, policy: AuthPolicy) -> &'static str {
+        match (self.language, policy) {
+            (Language::Japanese, AuthPolicy::Always) => "常にする",
+            (Language::Japanese, AuthPolicy::Ask) => "確認する",
+            (Language::Japanese, AuthPolicy::Never) => "しない",
+            (Language::English, AuthPolicy::Always) => "Always",
+            (Language::English, AuthPolicy::Ask) => "Ask",
+            (Language::English, AuthPolicy::Never) => "Never",
+        }
+    }
+    
+    pub fn settings_relay_add(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "➕ 追加",
+            Language::English => "➕ Add",
+        }
+    }
+    
+    pub fn settings_relay_remove(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "🗑 削除",
+            Language::English => "🗑 Remove",
+        }
+    }
+    
+    pub fn settings_relay_publish(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "📤 リレーリストを公開",
+            Language::English => "📤 Publish relay list",
+        }
+    }
+    
+    pub fn settings_backup(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "💾 バックアップ",
+            Language::English => "💾 Backup",
+        }
+    }
+    
+    pub fn settings_backup_description(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "メッセージ、既読位置、未送信のメッセージ、リレー、暗号化された鍵をファイルに保存します",
+            Language::English => "Saves messages, read positions, unsent messages, relays and the encrypted key to a file",
+        }
+    }
+    
+    pub fn settings_backup_export(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "⬇ ダウンロード",
+            Language::English => "⬇ Download",
+        }
+    }
+    
+    pub fn settings_backup_import(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "⬆ アップロードして復元",
+            Language::English => "⬆ Upload and restore",
+        }
+    }
+    
+    pub fn status_backup_exported(&self, records: usize) -> String {
+        match self.language {
+            Language::Japanese => format!("{} 件をバックアップしました", records),
+            Language::English => format!("Backed up {} records", records),
+        }
+    }
+    
+    pub fn status_backup_imported(&self, imported: usize, skipped: usize) -> String {
+        match self.language {
+            Language::Japanese => format!("{} 件を復元しました（{} 件は既にありました）", imported, skipped),
+            Language::English => format!("Restored {} records ({} already present)", imported, skipped),
+        }
+    }
+    
+    pub fn status_backup_failed(&self, error: &str) -> String {
+        match self.language {
+            Language::Japanese => format!("バックアップに失敗しました: {}", error),
+            Language::English => format!("Backup failed: {}", error),
+        }
+    }
+    
+    pub fn settings_relay_empty(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "リレーがありません",
+            Language::English => "No relays",
+        }
+    }
 }
 
 impl Default for I18n {
@@ -419,5 +593,19 @@ impl I18n {
             Language::English => "No DMs",
         }
     }
+    
+    pub fn dm_peer_placeholder(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "相手の公開鍵（hex）",
+            Language::English => "Peer public key (hex)",
+        }
+    }
+    
+    pub fn dm_open_button(&self) -> &'static str {
+        match self.language {
+            Language::Japanese => "💬 開く",
+            Language::English => "💬 Open",
+        }
+    }
 }
 
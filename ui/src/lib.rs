@@ -6,6 +6,7 @@ mod font_config;
 mod settings;
 mod emoji_label;
 mod i18n;
+mod backup_file;
 
 #[cfg(feature = "debug-test")]
 mod debug_test;
@@ -1,10 +1,34 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use crate::font_config::{FontConfig, FontFamily};
 use crate::i18n::{I18n, Language};
+use core::relay::AuthPolicy;
+use core::types::RelayConfig;
+
+/// 設定画面からCoreへの操作要求
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingsAction {
+    AddRelay(String),
+    RemoveRelay(String),
+    SetRelayPolicy { url: String, read: bool, write: bool },
+    /// NIP-42 の認証ポリシーを変える
+    SetAuthPolicy { url: String, policy: AuthPolicy },
+    PublishRelayList,
+    /// バックアップを書き出してダウンロードする
+    ExportBackup,
+    /// 選ばれたバックアップファイルを復元する
+    ImportBackup(Vec<u8>),
+}
 
 /// 設定画面
 pub struct SettingsView {
     font_config: FontConfig,
     font_changed: bool,
+    relays: Vec<RelayConfig>,
+    relay_input: String,
+    /// アップロードされたバックアップ（読み込み後に`ImportBackup`にする）
+    picked_backup: Rc<RefCell<Option<Vec<u8>>>>,
 }
 
 impl SettingsView {
@@ -12,14 +36,34 @@ impl SettingsView {
         Self {
             font_config: FontConfig::load(),
             font_changed: false,
+            relays: Vec::new(),
+            relay_input: String::new(),
+            picked_backup: Rc::new(RefCell::new(None)),
         }
     }
 
+    /// 表示するRelay一覧を更新
+    pub fn set_relays(&mut self, relays: Vec<RelayConfig>) {
+        self.relays = relays;
+    }
+
     /// 設定画面を表示
-    pub fn show(&mut self, _ctx: &egui::Context, ui: &mut egui::Ui, i18n: &mut I18n) {
+    pub fn show(&mut self, _ctx: &egui::Context, ui: &mut egui::Ui, i18n: &mut I18n) -> Option<SettingsAction> {
         crate::emoji_label::emoji_heading(ui, i18n.settings_title());
         ui.add_space(20.0);
 
+        // Relay設定
+        let mut action = self.show_relays(ui, i18n);
+
+        ui.add_space(20.0);
+
+        // バックアップ
+        if let Some(backup_action) = self.show_backup(ui, i18n) {
+            action = Some(backup_action);
+        }
+
+        ui.add_space(20.0);
+
         // 言語設定
         ui.group(|ui| {
             crate::emoji_label::emoji_label(ui, i18n.settings_language());
@@ -110,6 +154,103 @@ impl SettingsView {
             crate::emoji_label::emoji_label(ui, i18n.settings_preview_emoji());
             crate::emoji_label::emoji_label(ui, i18n.settings_preview_numbers());
         });
+
+        action
+    }
+
+    /// Relay一覧と追加フォームを表示
+    fn show_relays(&mut self, ui: &mut egui::Ui, i18n: &I18n) -> Option<SettingsAction> {
+        let mut action = None;
+
+        ui.group(|ui| {
+            crate::emoji_label::emoji_label(ui, i18n.settings_relays());
+            ui.add_space(10.0);
+
+            if self.relays.is_empty() {
+                ui.label(i18n.settings_relay_empty());
+            }
+
+            for relay in &self.relays {
+                ui.horizontal(|ui| {
+                    ui.label(&relay.url);
+
+                    let mut read = relay.read;
+                    let mut write = relay.write;
+                    let read_changed = ui.checkbox(&mut read, i18n.settings_relay_read()).changed();
+                    let write_changed = ui.checkbox(&mut write, i18n.settings_relay_write()).changed();
+                    if read_changed || write_changed {
+                        action = Some(SettingsAction::SetRelayPolicy {
+                            url: relay.url.clone(),
+                            read,
+                            write,
+                        });
+                    }
+
+                    let mut policy = relay.auth;
+                    egui::ComboBox::from_id_salt(("relay_auth", &relay.url))
+                        .selected_text(format!("{}: {}", i18n.settings_relay_auth(), i18n.settings_auth_policy(policy)))
+                        .show_ui(ui, |ui| {
+                            for option in [AuthPolicy::Always, AuthPolicy::Ask, AuthPolicy::Never] {
+                                ui.selectable_value(&mut policy, option, i18n.settings_auth_policy(option));
+                            }
+                        });
+                    if policy != relay.auth {
+                        action = Some(SettingsAction::SetAuthPolicy {
+                            url: relay.url.clone(),
+                            policy,
+                        });
+                    }
+
+                    if ui.button(i18n.settings_relay_remove()).clicked() {
+                        action = Some(SettingsAction::RemoveRelay(relay.url.clone()));
+                    }
+                });
+            }
+
+            ui.add_space(10.0);
+
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.relay_input).hint_text("wss://"));
+                if ui.button(i18n.settings_relay_add()).clicked() && !self.relay_input.trim().is_empty() {
+                    action = Some(SettingsAction::AddRelay(self.relay_input.trim().to_string()));
+                    self.relay_input.clear();
+                }
+            });
+
+            ui.add_space(10.0);
+
+            // NIP-65: 他のクライアントに読み書き先を知らせる
+            if ui.button(i18n.settings_relay_publish()).clicked() {
+                action = Some(SettingsAction::PublishRelayList);
+            }
+        });
+
+        action
+    }
+
+    /// バックアップのダウンロード・アップロードを表示
+    fn show_backup(&mut self, ui: &mut egui::Ui, i18n: &I18n) -> Option<SettingsAction> {
+        let mut action = self.picked_backup.borrow_mut().take().map(SettingsAction::ImportBackup);
+
+        ui.group(|ui| {
+            crate::emoji_label::emoji_label(ui, i18n.settings_backup());
+            ui.add_space(10.0);
+            ui.label(i18n.settings_backup_description());
+            ui.add_space(10.0);
+
+            ui.horizontal(|ui| {
+                if ui.button(i18n.settings_backup_export()).clicked() {
+                    action = Some(SettingsAction::ExportBackup);
+                }
+                if ui.button(i18n.settings_backup_import()).clicked() {
+                    if let Err(e) = crate::backup_file::pick_file(self.picked_backup.clone()) {
+                        log::error!("Failed to open file picker: {:?}", e);
+                    }
+                }
+            });
+        });
+
+        action
     }
 
     /// フォント設定を取得
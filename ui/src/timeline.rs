@@ -1,23 +1,95 @@
 use eframe::egui;
-use core::types::UiRow;
+use std::collections::HashMap;
+use core::subscription::HistoryState;
+use core::types::{DeliveryStatus, DeliverySummary, Scope, UiRow};
 use crate::i18n::I18n;
 
+/// 一度に読み込む過去ログの件数
+const LOAD_OLDER_LIMIT: u32 = 50;
+
+/// タイムラインでの操作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimelineAction {
+    /// 会話の過去ログを読み込む（会話, 件数）
+    LoadOlder(Scope, u32),
+}
+
 /// タイムライン表示
 pub struct Timeline {
+    /// 表示中の会話
+    scope: Option<Scope>,
+    /// 会話を切り替えた後、まだイベント一覧を読み込んでいない
+    stale: bool,
     events: Vec<UiRow>,
+    deliveries: HashMap<String, DeliverySummary>,
+    /// 表示中の会話の過去ログ取得の状態
+    history: HistoryState,
+    /// 過去ログの読み込みを要求し、結果をまだ受け取っていない
+    older_requested: bool,
+    /// 会話を開いてから、Relayの保存済みイベントを待っている
+    waiting_for_relays: bool,
 }
 
 impl Timeline {
     pub fn new() -> Self {
         Self {
+            scope: None,
+            stale: false,
             events: Vec::new(),
+            deliveries: HashMap::new(),
+            history: HistoryState::default(),
+            older_requested: false,
+            waiting_for_relays: false,
+        }
+    }
+    
+    /// Relayが会話の保存済みイベントを送り終えた
+    pub fn on_end_of_stored_events(&mut self, scope: &Scope) {
+        if self.scope.as_ref() == Some(scope) {
+            self.waiting_for_relays = false;
+        }
+    }
+    
+    /// 会話の過去ログの問い合わせが始まった・終わった
+    pub fn on_history_loading(&mut self, scope: &Scope, loading: bool) {
+        if self.scope.as_ref() == Some(scope) {
+            self.set_history_state(HistoryState { loading, ..self.history });
+        }
+    }
+    
+    /// 会話の最初まで取得した
+    pub fn on_history_exhausted(&mut self, scope: &Scope) {
+        if self.scope.as_ref() == Some(scope) {
+            self.set_history_state(HistoryState { reached_start: true, ..self.history });
         }
     }
     
-    /// イベントを追加
+    /// 表示中の会話の過去ログ取得の状態を更新
+    pub fn set_history_state(&mut self, history: HistoryState) {
+        if history.loading || history.reached_start {
+            self.older_requested = false;
+        }
+        self.history = history;
+    }
+    
+    /// 自分が送信したイベントの配送状況を更新
+    pub fn set_delivery(&mut self, event_id: String, summary: DeliverySummary) {
+        self.deliveries.insert(event_id, summary);
+    }
+    
+    /// イベントを追加（表示中の会話以外のイベントは無視）
     pub fn add_event(&mut self, event: UiRow) {
-        // 新しいイベントを先頭に追加（最新が上）
-        self.events.insert(0, event);
+        if self.scope.is_none() || event.scope != self.scope {
+            return;
+        }
+        if self.events.iter().any(|e| e.id == event.id) {
+            return;
+        }
+
+        // 新しい順に並べる（最新が上）
+        let pos = self.events.partition_point(|e| e.created_at > event.created_at);
+        self.events.insert(pos, event);
+        self.older_requested = false;
         
         // 最大1000件まで保持
         if self.events.len() > 1000 {
@@ -30,33 +102,74 @@ impl Timeline {
         self.events.len()
     }
     
-    /// チャンネルを読み込み（イベントをクリア）
-    pub fn load_channel(&mut self, _channel_id: &str) {
+    /// 会話を切り替える（イベント一覧は`on_conversation_loaded`で読み込む）
+    pub fn load_scope(&mut self, scope: Scope) {
+        self.scope = Some(scope);
+        self.stale = true;
         self.events.clear();
+        self.history = HistoryState::default();
+        self.older_requested = false;
+        self.waiting_for_relays = true;
     }
     
-    /// DMを読み込み（イベントをクリア）
-    pub fn load_dm(&mut self, _peer: &str) {
-        self.events.clear();
+    /// CoreHandleが保持する会話のイベント一覧（新しい順）を読み込む（表示中の会話以外は無視）
+    pub fn on_conversation_loaded(&mut self, scope: &Scope, rows: Vec<UiRow>, history: HistoryState) {
+        if !self.stale || self.scope.as_ref() != Some(scope) {
+            return;
+        }
+        self.events = rows;
+        self.stale = false;
+        self.older_requested = false;
+        self.set_history_state(history);
     }
     
     /// タイムライン表示
-    pub fn show(&mut self, ui: &mut egui::Ui, i18n: &I18n) {
+    ///
+    /// 古い側の端（一覧の末尾）までスクロールすると過去ログの読み込みを要求する。
+    pub fn show(&mut self, ui: &mut egui::Ui, i18n: &I18n) -> Option<TimelineAction> {
         egui::ScrollArea::vertical()
             .auto_shrink([false, false])
             .show(ui, |ui| {
-                if self.events.is_empty() {
+                if self.waiting_for_relays {
+                    ui.spinner();
+                }
+                
+                if self.events.is_empty() && self.history.reached_start {
                     ui.centered_and_justified(|ui| {
                         crate::emoji_label::emoji_label(ui, i18n.timeline_empty());
                     });
-                    return;
+                    return None;
                 }
                 
                 for event in &self.events {
                     self.show_event(ui, event, i18n);
                     ui.separator();
                 }
-            });
+                
+                self.show_history_end(ui, i18n)
+            })
+            .inner
+    }
+    
+    /// 一覧の末尾（過去ログの読み込み中、または会話の最初）
+    fn show_history_end(&mut self, ui: &mut egui::Ui, i18n: &I18n) -> Option<TimelineAction> {
+        let scope = self.scope.clone()?;
+        if self.history.reached_start {
+            ui.weak(i18n.timeline_start_of_history());
+            return None;
+        }
+        
+        let response = ui.horizontal(|ui| {
+            ui.spinner();
+            ui.weak(i18n.timeline_loading_older());
+        }).response;
+        
+        // 末尾が見えたら読み込む（要求中・取得中は重ねない）
+        if ui.is_rect_visible(response.rect) && !self.history.loading && !self.older_requested && !self.stale {
+            self.older_requested = true;
+            return Some(TimelineAction::LoadOlder(scope, LOAD_OLDER_LIMIT));
+        }
+        None
     }
     
     /// 個別イベント表示
@@ -76,6 +189,20 @@ impl Timeline {
                 // コンテンツ（カラー絵文字対応）
                 crate::emoji_label::emoji_label(ui, &event.content);
                 
+                // 配送状況（自分の投稿のみ）
+                if let Some(delivery) = self.deliveries.get(&event.id) {
+                    let text = egui::RichText::new(i18n.timeline_delivered(delivery.accepted, delivery.total))
+                        .small()
+                        .weak();
+                    let details = delivery
+                        .receipts
+                        .iter()
+                        .map(|r| format!("{}: {}", r.relay_url, delivery_status_text(&r.status)))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    ui.label(text).on_hover_text(details);
+                }
+                
                 // アクション
                 ui.horizontal(|ui| {
                     if ui.button(i18n.timeline_reply()).clicked() {
@@ -90,6 +217,17 @@ impl Timeline {
     }
 }
 
+/// 配送状態の表示用テキスト
+fn delivery_status_text(status: &DeliveryStatus) -> String {
+    match status {
+        DeliveryStatus::Pending => "⏳".to_string(),
+        DeliveryStatus::Accepted => "✅".to_string(),
+        DeliveryStatus::Rejected { reason, .. } => format!("❌ {}", reason),
+        DeliveryStatus::TimedOut => "⌛".to_string(),
+        DeliveryStatus::AwaitingAuth => "🔒".to_string(),
+    }
+}
+
 /// タイムスタンプをフォーマット
 fn format_timestamp(timestamp: i64) -> String {
     let now = js_sys::Date::now() / 1000.0;
@@ -105,3 +243,40 @@ fn format_timestamp(timestamp: i64) -> String {
         format!("{}d ago", diff / 86400)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1フレーム描画し、タイムラインでの操作を返す
+    fn show(timeline: &mut Timeline) -> Option<TimelineAction> {
+        let ctx = egui::Context::default();
+        let i18n = I18n::default();
+        let mut action = None;
+        let _ = ctx.run(egui::RawInput::default(), |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| action = timeline.show(ui, &i18n));
+        });
+        action
+    }
+
+    #[test]
+    fn test_history_end_requests_older_rows() {
+        let mut timeline = Timeline::new();
+        let scope = Scope::Channel("chan".to_string());
+        assert_eq!(show(&mut timeline), None);
+
+        // 会話のイベント一覧を読み込むまでは要求しない
+        timeline.load_scope(scope.clone());
+        assert_eq!(show(&mut timeline), None);
+        timeline.on_conversation_loaded(&scope, Vec::new(), HistoryState::default());
+        assert_eq!(show(&mut timeline), Some(TimelineAction::LoadOlder(scope.clone(), LOAD_OLDER_LIMIT)));
+        assert_eq!(show(&mut timeline), None);
+
+        // 取得が終わればまた要求でき、最初まで取得したら止める
+        timeline.on_history_loading(&scope, true);
+        timeline.on_history_loading(&scope, false);
+        assert_eq!(show(&mut timeline), Some(TimelineAction::LoadOlder(scope.clone(), LOAD_OLDER_LIMIT)));
+        timeline.on_history_exhausted(&scope);
+        assert_eq!(show(&mut timeline), None);
+    }
+}